@@ -0,0 +1,178 @@
+use crate::{
+    ruleset::{hex, hmac_sha256},
+    settings::Settings,
+    Error,
+};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Shortest PIN or passphrase accepted.
+const MIN_LENGTH: usize = 4;
+
+/// PBKDF2 rounds for new hashes. Stored with each hash, so it can be raised
+/// without invalidating existing ones.
+const ITERATIONS: u32 = 100_000;
+
+const SCHEME: &str = "pbkdf2-sha256";
+
+/// Wrong PINs in a row that are let through without a wait.
+const FREE_ATTEMPTS: u32 = 3;
+
+/// Wait after the first wrong PIN beyond `FREE_ATTEMPTS`, doubled with each
+/// further one up to `MAX_WAIT_SECS`.
+const FIRST_WAIT_SECS: i64 = 30;
+const MAX_WAIT_SECS: i64 = 60 * 60;
+
+#[derive(Serialize)]
+pub struct AppLockStatus {
+    /// Whether a PIN guards changes.
+    pub enabled: bool,
+    /// Whether changes are let through right now.
+    pub unlocked: bool,
+}
+
+/// PBKDF2-HMAC-SHA256 of `pin`, one block long.
+fn derive(pin: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut block = hmac_sha256(pin.as_bytes(), &[salt, &1u32.to_be_bytes()].concat());
+    let mut derived = block;
+    for _ in 1..iterations {
+        block = hmac_sha256(pin.as_bytes(), &block);
+        derived.iter_mut().zip(block).for_each(|(byte, next)| *byte ^= next);
+    }
+    derived
+}
+
+/// A salt that is unique rather than secret: the randomly keyed std hasher
+/// over the current time.
+fn salt() -> [u8; 16] {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let digest = Sha256::new()
+        .chain_update(RandomState::new().hash_one(nanos).to_le_bytes())
+        .chain_update(nanos.to_le_bytes())
+        .finalize();
+    let mut salt = [0u8; 16];
+    salt.copy_from_slice(&digest[..16]);
+    salt
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `pin` hashed for storage, as `pbkdf2-sha256$<iterations>$<salt>$<hash>`.
+fn hash(pin: &str) -> String {
+    let salt = salt();
+    format!("{}${}${}${}", SCHEME, ITERATIONS, hex(&salt), hex(&derive(pin, &salt, ITERATIONS)))
+}
+
+/// Whether `pin` is the one `stored` was hashed from.
+fn verify(stored: &str, pin: &str) -> bool {
+    let mut parts = stored.split('$');
+    let (Some(SCHEME), Some(iterations), Some(salt), Some(expected), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+    let (Ok(iterations), Some(salt), Some(expected)) = (iterations.parse(), unhex(salt), unhex(expected)) else {
+        return false;
+    };
+    // Compared in full, so the time taken doesn't tell how much matched
+    let derived = derive(pin, &salt, iterations);
+    expected.len() == derived.len() && expected.iter().zip(derived).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// How long to wait after `failures` wrong PINs in a row before the next
+/// one is checked.
+fn wait_secs(failures: u32) -> i64 {
+    match failures.checked_sub(FREE_ATTEMPTS) {
+        None => 0,
+        Some(extra) => FIRST_WAIT_SECS.saturating_mul(1 << extra.min(16)).min(MAX_WAIT_SECS),
+    }
+}
+
+/// Checks `pin` against the app lock in `conn`. Without a lock any PIN
+/// passes. Wrong PINs are recorded, and after `FREE_ATTEMPTS` of them in a
+/// row every further PIN waits longer, so PINs can't be tried one after
+/// the other; the wait outlasts restarts.
+pub fn check(conn: &Connection, pin: &str) -> Result<(), Error> {
+    let Some(stored) = Settings::load(conn)?.app_lock else {
+        return Ok(());
+    };
+    let now = chrono::Utc::now().timestamp();
+    let (failures, last): (u32, Option<i64>) =
+        conn.query_row("SELECT COUNT(*), MAX(failed_at) FROM pin_failures", [], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    if let Some(last) = last {
+        let until = last + wait_secs(failures);
+        if now < until {
+            return Err(Error::PinAttempts((until - now) as u64));
+        }
+    }
+    if !verify(&stored, pin) {
+        conn.execute("INSERT INTO pin_failures (failed_at) VALUES (?)", params![now])?;
+        return Err(Error::WrongPin);
+    }
+    conn.execute("DELETE FROM pin_failures", [])?;
+    Ok(())
+}
+
+/// Sets the app lock to `pin`, or removes it when unset. An existing lock
+/// is only changed with its `current` PIN.
+pub fn set(conn: &Connection, current: Option<&str>, pin: Option<&str>) -> Result<(), Error> {
+    if Settings::load(conn)?.app_lock.is_some() {
+        check(conn, current.ok_or(Error::WrongPin)?)?;
+    }
+    match pin {
+        Some(pin) if pin.chars().count() < MIN_LENGTH => Err(Error::InvalidSetting(format!(
+            "the PIN must be at least {} characters long",
+            MIN_LENGTH
+        ))),
+        Some(pin) => Settings::store_app_lock(conn, Some(&hash(pin))),
+        None => Settings::store_app_lock(conn, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_longer_after_each_wrong_pin_beyond_the_free_ones() {
+        assert_eq!((0..=FREE_ATTEMPTS - 1).map(wait_secs).max(), Some(0));
+        assert_eq!(wait_secs(FREE_ATTEMPTS), FIRST_WAIT_SECS);
+        assert_eq!(wait_secs(FREE_ATTEMPTS + 1), 2 * FIRST_WAIT_SECS);
+        assert_eq!(wait_secs(u32::MAX), MAX_WAIT_SECS);
+    }
+
+    #[test]
+    fn refuses_even_the_right_pin_while_waiting() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // A mapping in place before init keeps it from looking for the desktop
+        conn.execute("CREATE TABLE path_mappings (extension TEXT PRIMARY KEY, target_path TEXT NOT NULL)", [])
+            .unwrap();
+        conn.execute("INSERT INTO path_mappings (extension, target_path) VALUES ('.pdf', '/sorted')", [])
+            .unwrap();
+        crate::init_db(&mut conn).unwrap();
+        set(&conn, None, Some("1234")).unwrap();
+
+        for _ in 0..FREE_ATTEMPTS {
+            assert!(matches!(check(&conn, "0000"), Err(Error::WrongPin)));
+        }
+
+        assert!(matches!(check(&conn, "1234"), Err(Error::PinAttempts(secs)) if secs > 0));
+        conn.execute("UPDATE pin_failures SET failed_at = failed_at - ?", params![FIRST_WAIT_SECS]).unwrap();
+        assert!(check(&conn, "1234").is_ok());
+        assert!(matches!(check(&conn, "0000"), Err(Error::WrongPin)));
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{
+    execute_plan, history, quota::Quota, rules::MatchSource, settings::Settings, Error, PlannedMove, SortContext,
+    SortResult,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// A planned move waiting for the user to approve or reject it.
+#[derive(Serialize)]
+pub struct PendingMove {
+    pub id: i64,
+    pub source: String,
+    pub target_dir: String,
+    pub decided_by: String,
+    pub size: u64,
+    pub proposed_at: i64,
+}
+
+/// Queues `plan` for approval instead of carrying it out. Moves the user
+/// rejected before aren't proposed again unless their target changed; their
+/// sources are returned.
+pub fn propose(conn: &Connection, plan: &[PlannedMove]) -> Result<Vec<PathBuf>, Error> {
+    let now = chrono::Utc::now().timestamp();
+    let mut rejected = Vec::new();
+    for planned in plan {
+        if was_rejected(conn, planned)? {
+            rejected.push(planned.source.clone());
+            continue;
+        }
+        conn.execute(
+            "INSERT INTO pending_moves (source, target_dir, decided_by, matched, size, proposed_at, rejected)
+             VALUES (?, ?, ?, ?, ?, ?, 0)
+             ON CONFLICT(source) DO UPDATE SET
+                target_dir = excluded.target_dir,
+                decided_by = excluded.decided_by,
+                matched = excluded.matched,
+                size = excluded.size,
+                proposed_at = excluded.proposed_at,
+                rejected = 0",
+            params![
+                planned.source.to_string_lossy(),
+                planned.target_dir.to_string_lossy(),
+                planned.decided_by,
+                serde_json::to_string(&planned.matched).unwrap_or_default(),
+                planned.size,
+                now
+            ],
+        )?;
+    }
+    Ok(rejected)
+}
+
+/// The sources of the moves of `plan` the user rejected before, which
+/// `propose` would leave out, without queuing anything.
+pub fn rejected(conn: &Connection, plan: &[PlannedMove]) -> Result<Vec<PathBuf>, Error> {
+    let mut rejected = Vec::new();
+    for planned in plan {
+        if was_rejected(conn, planned)? {
+            rejected.push(planned.source.clone());
+        }
+    }
+    Ok(rejected)
+}
+
+fn was_rejected(conn: &Connection, planned: &PlannedMove) -> Result<bool, Error> {
+    Ok(conn
+        .query_row(
+            "SELECT 1 FROM pending_moves WHERE source = ? AND target_dir = ? AND rejected = 1",
+            params![planned.source.to_string_lossy(), planned.target_dir.to_string_lossy()],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+/// Drops entries the user has since moved or deleted themselves.
+pub fn prune(conn: &Connection) -> Result<(), Error> {
+    let sources: Vec<String> = conn
+        .prepare("SELECT source FROM pending_moves")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for source in sources.iter().filter(|source| !Path::new(source).exists()) {
+        conn.execute("DELETE FROM pending_moves WHERE source = ?", params![source])?;
+    }
+    Ok(())
+}
+
+/// Moves waiting for a decision, oldest first.
+pub fn load(conn: &Connection) -> Result<Vec<PendingMove>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT id, source, target_dir, decided_by, size, proposed_at
+         FROM pending_moves WHERE rejected = 0 ORDER BY proposed_at, id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(PendingMove {
+            id: row.get(0)?,
+            source: row.get(1)?,
+            target_dir: row.get(2)?,
+            decided_by: row.get(3)?,
+            size: row.get(4)?,
+            proposed_at: row.get(5)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Takes the moves `ids` off the queue to be carried out. `quota` gives the
+/// quota of the mapping behind a move. Simulated, they stay queued.
+pub fn take(conn: &Connection, ids: &[i64], quota: impl Fn(&MatchSource) -> Quota) -> Result<Vec<PlannedMove>, Error> {
+    let simulated = Settings::load(conn)?.simulation_mode;
+    let mut plan = Vec::new();
+    for id in ids {
+        let row: Option<(String, String, String, String, u64)> = conn
+            .query_row(
+                "SELECT source, target_dir, decided_by, matched, size FROM pending_moves
+                 WHERE id = ? AND rejected = 0",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)),
+            )
+            .optional()?;
+        let Some((source, target_dir, decided_by, matched, size)) = row else {
+            continue;
+        };
+        if !simulated {
+            conn.execute("DELETE FROM pending_moves WHERE id = ?", params![id])?;
+        }
+        let Ok(matched) = serde_json::from_str::<MatchSource>(&matched) else {
+            continue;
+        };
+        plan.push(PlannedMove {
+            source: PathBuf::from(source),
+            target_dir: PathBuf::from(target_dir),
+            quota: quota(&matched),
+            size,
+            decided_by: format!("{}, approved", decided_by),
+            matched,
+        });
+    }
+    Ok(plan)
+}
+
+/// Carries out the queued moves `ids`, to the targets they were proposed
+/// with.
+pub fn approve(conn: &Connection, ctx: &SortContext, ids: &[i64]) -> Result<SortResult, Error> {
+    let started = (std::time::Instant::now(), chrono::Utc::now().timestamp());
+    prune(conn)?;
+    let plan = take(conn, ids, |matched| ctx.mappings.quota(matched))?;
+    tracing::info!("Approved {} moves", plan.len());
+    execute_plan(
+        conn,
+        ctx,
+        plan,
+        history::Trigger::Approved,
+        started,
+        SortResult::new(Vec::new(), Vec::new()),
+    )
+}
+
+/// Turns down the moves `ids`. Their entries stay where they are, and later
+/// sorts don't propose the same moves again. Returns how many were rejected.
+pub fn reject(conn: &Connection, ids: &[i64]) -> Result<usize, Error> {
+    let mut rejected = 0;
+    for id in ids {
+        rejected += conn.execute(
+            "UPDATE pending_moves SET rejected = 1 WHERE id = ? AND rejected = 0",
+            params![id],
+        )?;
+    }
+    Ok(rejected)
+}
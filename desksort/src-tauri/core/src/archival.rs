@@ -0,0 +1,41 @@
+use crate::ScannedEntry;
+use chrono::{DateTime, Local};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use walkdir::WalkDir;
+
+/// When `path`, or for a folder anything inside it, was last modified.
+fn last_touched(path: &Path) -> Option<SystemTime> {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+        .max()
+}
+
+/// Whether `path` has gone at least `after` without changes.
+pub fn is_untouched_for(path: &Path, after: Duration) -> bool {
+    // A timestamp in the future counts as just touched
+    last_touched(path).is_some_and(|touched| {
+        SystemTime::now()
+            .duration_since(touched)
+            .is_ok_and(|idle| idle >= after)
+    })
+}
+
+/// The `<year-month>` folder below `root` of the last modification of `path`.
+pub fn month_dir(root: &Path, path: &Path) -> Option<PathBuf> {
+    let touched = last_touched(path)?;
+    Some(root.join(DateTime::<Local>::from(touched).format("%Y-%m").to_string()))
+}
+
+/// Where `entry` is archived below `root` if it has been left untouched for
+/// `after`: its `month_dir`. `None` while it is more recent than that.
+pub fn target_dir(root: &Path, entry: &ScannedEntry, after: Duration) -> Option<PathBuf> {
+    if !is_untouched_for(&entry.path, after) {
+        return None;
+    }
+    month_dir(root, &entry.path)
+}
@@ -0,0 +1,243 @@
+use crate::Error;
+use rusqlite::{types::Value as SqlValue, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+const FORMAT: &str = "desksort-export";
+
+/// Tables making up the user's configuration, in an order that satisfies
+/// references on import. Run state (queued moves, the conflict inbox) is
+/// left out.
+const EXPORTED_TABLES: &[&str] = &[
+    "settings",
+    "categories",
+    "path_mappings",
+    "rule_groups",
+    "rules",
+    "observed_moves",
+    "sessions",
+    "history",
+];
+
+/// The complete configuration as one portable JSON document.
+#[derive(Serialize, Deserialize)]
+pub struct Archive {
+    format: String,
+    /// `PRAGMA user_version` of the database it was taken from.
+    schema_version: i64,
+    exported_at: i64,
+    /// Rows by table, each row as column -> value.
+    tables: BTreeMap<String, Vec<Map<String, Value>>>,
+}
+
+/// What importing an archive changes in one table, by primary key.
+#[derive(Serialize, Default)]
+pub struct TableDiff {
+    table: String,
+    added: Vec<String>,
+    changed: Vec<String>,
+    removed: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ImportReport {
+    schema_version: i64,
+    tables: Vec<TableDiff>,
+    /// False for a dry run.
+    applied: bool,
+}
+
+fn schema_version(conn: &Connection) -> Result<i64, Error> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+/// Column names of `table`, and which of them form its primary key.
+fn columns(conn: &Connection, table: &str) -> Result<(Vec<String>, Vec<String>), Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(1)?, row.get::<_, i64>(5)?)))?;
+
+    let mut columns = Vec::new();
+    let mut key: Vec<(i64, String)> = Vec::new();
+    for row in rows {
+        let (name, pk) = row?;
+        if pk > 0 {
+            key.push((pk, name.clone()));
+        }
+        columns.push(name);
+    }
+    key.sort();
+    Ok((columns, key.into_iter().map(|(_, name)| name).collect()))
+}
+
+fn to_json(value: SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Integer(i) => i.into(),
+        SqlValue::Real(f) => f.into(),
+        SqlValue::Text(s) => s.into(),
+        SqlValue::Blob(bytes) => bytes.into(),
+    }
+}
+
+fn to_sql(table: &str, column: &str, value: &Value) -> Result<SqlValue, Error> {
+    Ok(match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        _ => {
+            return Err(Error::InvalidArchive(format!(
+                "unsupported value for {}.{}",
+                table, column
+            )))
+        }
+    })
+}
+
+fn read_table(conn: &Connection, table: &str) -> Result<Vec<Map<String, Value>>, Error> {
+    let (columns, _) = columns(conn, table)?;
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM {}", columns.join(", "), table))?;
+    let rows = stmt.query_map([], |row| {
+        let mut map = Map::new();
+        for (i, column) in columns.iter().enumerate() {
+            map.insert(column.clone(), to_json(row.get(i)?));
+        }
+        Ok(map)
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+pub fn export(conn: &Connection) -> Result<Archive, Error> {
+    let mut tables = BTreeMap::new();
+    for table in EXPORTED_TABLES {
+        tables.insert(table.to_string(), read_table(conn, table)?);
+    }
+    Ok(Archive {
+        format: FORMAT.to_string(),
+        schema_version: schema_version(conn)?,
+        exported_at: chrono::Utc::now().timestamp(),
+        tables,
+    })
+}
+
+/// Writes `export` of `conn` to `path` as one JSON file.
+pub fn write(conn: &Connection, path: &Path) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(&export(conn)?).map_err(|e| Error::InvalidArchive(e.to_string()))?;
+    fs::write(path, json)?;
+    Ok(())
+}
+
+/// Reads an export `write` made.
+pub fn read(path: &Path) -> Result<Archive, Error> {
+    serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| Error::InvalidArchive(e.to_string()))
+}
+
+/// Checks `archive` against this database and lists what importing it would
+/// change. Unless `dry_run`, the exported tables are then replaced with its
+/// contents in one transaction.
+pub fn import(conn: &mut Connection, archive: &Archive, dry_run: bool) -> Result<ImportReport, Error> {
+    if archive.format != FORMAT {
+        return Err(Error::InvalidArchive("not a DeskSort export".to_string()));
+    }
+    let current = schema_version(conn)?;
+    if archive.schema_version > current {
+        return Err(Error::InvalidArchive(format!(
+            "exported by a newer DeskSort (schema {}, this version has {})",
+            archive.schema_version, current
+        )));
+    }
+    if let Some(table) = archive.tables.keys().find(|t| !EXPORTED_TABLES.contains(&t.as_str())) {
+        return Err(Error::InvalidArchive(format!("unexpected table `{}`", table)));
+    }
+
+    let mut diffs = Vec::new();
+    for table in EXPORTED_TABLES {
+        let Some(incoming) = archive.tables.get(*table) else {
+            continue;
+        };
+        let (columns, key) = columns(conn, table)?;
+        if let Some(column) = incoming.iter().flat_map(|row| row.keys()).find(|c| !columns.contains(c)) {
+            return Err(Error::InvalidArchive(format!("unknown column {}.{}", table, column)));
+        }
+        diffs.push(diff(table, &key, &read_table(conn, table)?, incoming));
+    }
+
+    if !dry_run {
+        let tx = conn.transaction()?;
+        for table in EXPORTED_TABLES {
+            let Some(rows) = archive.tables.get(*table) else {
+                continue;
+            };
+            tx.execute(&format!("DELETE FROM {}", table), [])?;
+            for row in rows {
+                if row.is_empty() {
+                    return Err(Error::InvalidArchive(format!("empty row in {}", table)));
+                }
+                let columns: Vec<&String> = row.keys().collect();
+                let values = row
+                    .iter()
+                    .map(|(column, value)| to_sql(table, column, value))
+                    .collect::<Result<Vec<_>, _>>()?;
+                tx.execute(
+                    &format!(
+                        "INSERT INTO {} ({}) VALUES ({})",
+                        table,
+                        columns.iter().map(|c| c.as_str()).collect::<Vec<_>>().join(", "),
+                        vec!["?"; columns.len()].join(", ")
+                    ),
+                    rusqlite::params_from_iter(values),
+                )?;
+            }
+        }
+        tx.commit()?;
+    }
+
+    Ok(ImportReport {
+        schema_version: archive.schema_version,
+        tables: diffs,
+        applied: !dry_run,
+    })
+}
+
+fn diff(table: &str, key: &[String], current: &[Map<String, Value>], incoming: &[Map<String, Value>]) -> TableDiff {
+    let key_of = |row: &Map<String, Value>| {
+        key.iter()
+            .map(|column| match row.get(column) {
+                Some(Value::String(s)) => s.clone(),
+                Some(value) => value.to_string(),
+                None => String::new(),
+            })
+            .collect::<Vec<_>>()
+            .join(" / ")
+    };
+    let current: HashMap<String, &Map<String, Value>> = current.iter().map(|row| (key_of(row), row)).collect();
+
+    let mut result = TableDiff {
+        table: table.to_string(),
+        ..Default::default()
+    };
+    let mut seen = Vec::new();
+    for row in incoming {
+        let id = key_of(row);
+        match current.get(&id) {
+            None => result.added.push(id.clone()),
+            // Columns missing from an older export keep their defaults
+            Some(existing) if row.iter().any(|(column, value)| existing.get(column) != Some(value)) => {
+                result.changed.push(id.clone())
+            }
+            Some(_) => {}
+        }
+        seen.push(id);
+    }
+    result.removed = current.into_keys().filter(|id| !seen.contains(id)).collect();
+    result.removed.sort();
+    result
+}
@@ -0,0 +1,160 @@
+use std::path::Path;
+
+/// Carries the extended attributes and access control list of `from` over to
+/// its copy `to`. Returns what couldn't be kept, if anything.
+#[cfg(target_os = "linux")]
+pub fn copy(from: &Path, to: &Path) -> Option<String> {
+    use std::io;
+
+    let (from_c, to_c) = match (linux::c_path(from), linux::c_path(to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        _ => return Some(format!("{}: its name can't be passed to the system", from.display())),
+    };
+    let names = match linux::list(&from_c) {
+        Ok(names) => names,
+        // The source volume has no extended attributes, so there is nothing to lose
+        Err(e) if e.raw_os_error() == Some(linux::ENOTSUP) => return None,
+        Err(e) => return Some(format!("{}: can't read its extended attributes: {}", from.display(), e)),
+    };
+
+    let mut lost = Vec::new();
+    let mut error: Option<io::Error> = None;
+    for name in names {
+        let name_str = name.to_string_lossy();
+        // Security labels and trusted attributes need privileges, and new
+        // files get the labels that fit their new place anyway
+        if name_str.starts_with("security.") || name_str.starts_with("trusted.") {
+            continue;
+        }
+        if let Err(e) = linux::get(&from_c, &name).and_then(|value| linux::set(&to_c, &name, &value)) {
+            lost.push(if name_str.starts_with("system.posix_acl") {
+                "its access control list".to_string()
+            } else {
+                name_str.into_owned()
+            });
+            error.get_or_insert(e);
+        }
+    }
+    let error = error?;
+    Some(format!(
+        "{}: couldn't keep {}: {}",
+        to.display(),
+        lost.join(", "),
+        error
+    ))
+}
+
+/// Uses `copyfile`, which also covers Finder tags and the resource fork, and
+/// falls back to `._` files on volumes without extended attributes.
+#[cfg(target_os = "macos")]
+pub fn copy(from: &Path, to: &Path) -> Option<String> {
+    use std::{
+        ffi::{c_char, c_int, c_void, CString},
+        io,
+        os::unix::ffi::OsStrExt,
+    };
+
+    const COPYFILE_ACL: u32 = 1 << 0;
+    const COPYFILE_XATTR: u32 = 1 << 2;
+    extern "C" {
+        fn copyfile(from: *const c_char, to: *const c_char, state: *mut c_void, flags: u32) -> c_int;
+    }
+
+    let (Ok(from_c), Ok(to_c)) = (
+        CString::new(from.as_os_str().as_bytes()),
+        CString::new(to.as_os_str().as_bytes()),
+    ) else {
+        return Some(format!("{}: its name can't be passed to the system", from.display()));
+    };
+    let mut lost = Vec::new();
+    let mut error = None;
+    for (flag, what) in [(COPYFILE_XATTR, "its extended attributes and tags"), (COPYFILE_ACL, "its access control list")] {
+        // SAFETY: both paths are NUL-terminated and outlive the call, and no
+        // state object is used
+        if unsafe { copyfile(from_c.as_ptr(), to_c.as_ptr(), std::ptr::null_mut(), flag) } != 0 {
+            lost.push(what);
+            error.get_or_insert_with(io::Error::last_os_error);
+        }
+    }
+    let error = error?;
+    Some(format!("{}: couldn't keep {}: {}", to.display(), lost.join(" or "), error))
+}
+
+/// Explorer leaves a file copied to another volume with the permissions of
+/// its new folder, and so does DeskSort. Its download origin is copied by
+/// `origin::copy`.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn copy(_from: &Path, _to: &Path) -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        ffi::{c_char, c_int, c_void, CStr, CString},
+        io,
+        os::unix::ffi::OsStrExt,
+        path::Path,
+    };
+
+    pub const ENOTSUP: i32 = 95;
+    const ERANGE: i32 = 34;
+
+    extern "C" {
+        fn llistxattr(path: *const c_char, list: *mut c_char, size: usize) -> isize;
+        fn lgetxattr(path: *const c_char, name: *const c_char, value: *mut c_void, size: usize) -> isize;
+        fn lsetxattr(path: *const c_char, name: *const c_char, value: *const c_void, size: usize, flags: c_int)
+            -> c_int;
+    }
+
+    pub fn c_path(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// Calls `read` with a null buffer to learn the size, then with a buffer
+    /// that big, starting over if the value grew in between.
+    fn read_sized(read: impl Fn(*mut u8, usize) -> isize) -> io::Result<Vec<u8>> {
+        loop {
+            let size = read(std::ptr::null_mut(), 0);
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buffer = vec![0u8; size as usize];
+            let size = read(buffer.as_mut_ptr(), buffer.len());
+            if size < 0 {
+                let e = io::Error::last_os_error();
+                if e.raw_os_error() == Some(ERANGE) {
+                    continue;
+                }
+                return Err(e);
+            }
+            buffer.truncate(size as usize);
+            return Ok(buffer);
+        }
+    }
+
+    /// Names of the extended attributes of `path`.
+    pub fn list(path: &CStr) -> io::Result<Vec<CString>> {
+        // SAFETY: `path` is NUL-terminated and the buffer is `size` bytes long
+        let names = read_sized(|buffer, size| unsafe { llistxattr(path.as_ptr(), buffer.cast(), size) })?;
+        Ok(names
+            .split(|&byte| byte == 0)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| CString::new(name).ok())
+            .collect())
+    }
+
+    pub fn get(path: &CStr, name: &CStr) -> io::Result<Vec<u8>> {
+        // SAFETY: as in `list`
+        read_sized(|buffer, size| unsafe { lgetxattr(path.as_ptr(), name.as_ptr(), buffer.cast(), size) })
+    }
+
+    pub fn set(path: &CStr, name: &CStr, value: &[u8]) -> io::Result<()> {
+        // SAFETY: the strings are NUL-terminated and `value` is `value.len()` bytes long
+        let result = unsafe { lsetxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{
+    executor::{self, ExecutionOptions, ProgressSink},
+    filesystem::{FileSystem, OsFileSystem},
+    match_entries,
+    quota::Quota,
+    rules::{Candidate, Decision},
+    scan_entries,
+    settings::Settings,
+    sources::Source,
+    Error,
+};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+
+/// Extension given to a share of the generated files so the benchmark also
+/// covers lookups that miss.
+const UNMATCHED_EXTENSION: &str = ".desksortbench";
+
+/// Timings of one benchmark run, in milliseconds.
+#[derive(Serialize)]
+pub struct BenchmarkResult {
+    file_count: usize,
+    file_size: u64,
+    generate_ms: u128,
+    scan_ms: u128,
+    match_ms: u128,
+    move_ms: u128,
+    total_ms: u128,
+    moved: usize,
+    errors: usize,
+}
+
+struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn item_started(&self, _: usize, _: u64, _: &Path) {}
+    fn file_progress(&self, _: &Path, _: &Path, _: u64, _: u64) {}
+}
+
+/// Generates `file_count` dummy files in a temporary folder and sorts them with
+/// the configured mappings, redirected into that same folder so nothing outside
+/// it is touched. The folder is removed afterwards.
+pub fn run(conn: &Connection, file_count: usize, file_size: u64) -> Result<BenchmarkResult, Error> {
+    let settings = Settings::load(conn)?;
+    let root = std::env::temp_dir().join(format!(
+        "desksort-bench-{}",
+        chrono::Utc::now().timestamp_millis()
+    ));
+    let result = run_in(conn, &root, &settings, file_count, file_size);
+    if let Err(e) = fs::remove_dir_all(&root) {
+        tracing::warn!("Failed to clean up {}: {}", root.display(), e);
+    }
+    result
+}
+
+fn run_in(
+    conn: &Connection,
+    root: &Path,
+    settings: &Settings,
+    file_count: usize,
+    file_size: u64,
+) -> Result<BenchmarkResult, Error> {
+    let source_dir = root.join("source");
+    let targets_dir = root.join("targets");
+    fs::create_dir_all(&source_dir)?;
+
+    // Same extensions as the real mappings, each pointing at a scratch folder
+    // named after the real target
+    let mut mappings: HashMap<String, PathBuf> = HashMap::new();
+    let mut stmt = conn.prepare("SELECT extension, target_path FROM path_mappings WHERE extension != 'folder'")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+    for row in rows {
+        let (extension, target_path) = row?;
+        let category = Path::new(&target_path)
+            .file_name()
+            .map(|name| name.to_os_string())
+            .unwrap_or_else(|| "Other".into());
+        mappings.insert(extension, targets_dir.join(category));
+    }
+    let mut extensions: Vec<&String> = mappings.keys().collect();
+    extensions.sort();
+
+    let total_start = Instant::now();
+    let start = Instant::now();
+    let content = vec![b'x'; file_size as usize];
+    for i in 0..file_count {
+        let extension = match extensions.get(i % (extensions.len() + 1)) {
+            Some(extension) => extension.as_str(),
+            None => UNMATCHED_EXTENSION,
+        };
+        fs::write(source_dir.join(format!("bench_{:06}{}", i, extension)), &content)?;
+    }
+    let generate_ms = start.elapsed().as_millis();
+
+    let start = Instant::now();
+    let mut errors = Vec::new();
+    let fs: Arc<dyn FileSystem> = Arc::new(OsFileSystem);
+    let entries = scan_entries(&fs, &Source::at(&source_dir), &[], &mut errors, &mut Vec::new());
+    let scan_ms = start.elapsed().as_millis();
+
+    let start = Instant::now();
+    let plan = match_entries(entries, &mut |entry| {
+        Ok(match mappings.get(&entry.key) {
+            Some(dir) => Decision::Target(
+                Candidate::mapping(&entry.key, dir.clone(), Quota::default()),
+                "first match by priority",
+            ),
+            None => Decision::Unmatched,
+        })
+    })?;
+    let match_ms = start.elapsed().as_millis();
+
+    let start = Instant::now();
+    let report = executor::execute(&*fs, plan.moves, &ExecutionOptions::from_settings(settings), &NoProgress);
+    let move_ms = start.elapsed().as_millis();
+
+    Ok(BenchmarkResult {
+        file_count,
+        file_size,
+        generate_ms,
+        scan_ms,
+        match_ms,
+        move_ms,
+        total_ms: total_start.elapsed().as_millis(),
+        moved: report.moved.len(),
+        errors: errors.len() + report.errors.len(),
+    })
+}
@@ -0,0 +1,93 @@
+use crate::ScannedEntry;
+use chrono::{DateTime, Local, NaiveDate};
+use regex::Regex;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// Extensions cameras and phones write photos and clips with.
+const MEDIA_EXTENSIONS: &[&str] = &[
+    ".jpg", ".jpeg", ".heic", ".heif", ".png", ".dng", ".cr2", ".cr3", ".nef", ".arw", ".raf", ".orf", ".rw2",
+    ".mp4", ".mov", ".m4v", ".3gp", ".insv",
+];
+
+/// File names cameras and phones give their shots: `IMG_1234`, `DSC_0042`,
+/// `DSCF0042`, `PXL_20240501_101530123`, `GOPR0001`, `P1000123`...
+fn file_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"(?i)^(?:IMG|DSC[NF]?|_DSC|MVI|VID|PXL|GOPR|G[HX]\d{2}|DJI|SAM|P\d{3})_?\d{4}").unwrap()
+    })
+}
+
+/// `DCIM` itself, or one of the folders inside it named after the DCF
+/// standard, like `100CANON` or `101APPLE`.
+fn folder_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"(?i)^(?:DCIM|\d{3}[A-Z0-9_]{5})$").unwrap())
+}
+
+/// A `YYYYMMDD` date in a file name, as phones write them.
+fn date_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"(?:^|\D)((?:19|20)\d{2})(\d{2})(\d{2})(?:\D|$)").unwrap())
+}
+
+/// Whether `entry` looks like it was copied off a camera or phone.
+pub fn is_camera_import(entry: &ScannedEntry) -> bool {
+    let name = entry.name();
+    if entry.is_dir {
+        folder_pattern().is_match(&name)
+    } else {
+        MEDIA_EXTENSIONS.contains(&entry.key.as_str()) && file_pattern().is_match(&name)
+    }
+}
+
+/// The day `path` was shot: from its name when it carries a date, else its
+/// modification time, which copying off a card keeps.
+fn shot_on(path: &Path) -> Option<NaiveDate> {
+    let name = path.file_name()?.to_string_lossy();
+    let from_name = date_pattern().captures(&name).and_then(|captures| {
+        let part = |i: usize| captures[i].parse::<u32>().ok();
+        NaiveDate::from_ymd_opt(part(1)? as i32, part(2)?, part(3)?)
+    });
+    from_name.or_else(|| {
+        let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok()?;
+        Some(DateTime::<Local>::from(modified).date_naive())
+    })
+}
+
+/// The `<year>/<year-month-day>` folders below `root` that camera imports
+/// were filed in.
+pub fn day_dirs(root: &Path) -> Vec<PathBuf> {
+    let subdirs = |dir: &Path| -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect()
+    };
+    let name = |path: &Path| path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    subdirs(root)
+        .into_iter()
+        .filter(|year| {
+            let year = name(year);
+            year.len() == 4 && year.bytes().all(|byte| byte.is_ascii_digit())
+        })
+        .flat_map(|year| subdirs(&year))
+        .filter(|day| NaiveDate::parse_from_str(&name(day), "%Y-%m-%d").is_ok())
+        .collect()
+}
+
+/// Where the camera import `entry` goes below `root`: `<year>/<year-month-day>`
+/// of the day it was shot.
+pub fn target_dir(root: &Path, entry: &ScannedEntry) -> PathBuf {
+    match shot_on(&entry.path) {
+        Some(date) => root.join(date.format("%Y").to_string()).join(date.format("%Y-%m-%d").to_string()),
+        None => root.to_path_buf(),
+    }
+}
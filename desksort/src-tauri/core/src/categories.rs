@@ -0,0 +1,433 @@
+use crate::{
+    defaults, escape_like, filesystem::FileSystem, history, move_folder_contents, mover, quota::Quota,
+    settings::Settings, Error, SortContext, SortResult,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// A node of the category tree. Top-level categories are the ones extension
+/// mappings send files to; subcategories are folders inside them that rules
+/// can target, like `Images/Screenshots`.
+#[derive(Serialize)]
+pub struct Category {
+    /// Language-independent; a subcategory's id extends its parent's, e.g.
+    /// `images/screenshots`.
+    pub id: String,
+    pub parent_id: Option<String>,
+    /// The folder name.
+    pub name: String,
+    /// The folder, resolved through the parents.
+    pub target_path: String,
+    pub mapping_count: usize,
+    pub rule_count: usize,
+}
+
+/// A category with its subcategories nested inside.
+#[derive(Serialize)]
+pub struct CategoryNode {
+    #[serde(flatten)]
+    pub category: Category,
+    /// Rules targeting it or any of its subcategories.
+    pub total_rule_count: usize,
+    pub children: Vec<CategoryNode>,
+}
+
+/// Number of files `disk_usage` lists per category.
+const LARGEST_FILES: usize = 10;
+
+/// How much space a category's folder takes, subcategories included.
+#[derive(Serialize)]
+pub struct DiskUsage {
+    pub id: String,
+    pub name: String,
+    pub target_path: String,
+    pub bytes: u64,
+    pub files: u64,
+    /// Its biggest files, largest first.
+    pub largest: Vec<LargeFile>,
+}
+
+#[derive(Serialize)]
+pub struct LargeFile {
+    pub path: String,
+    pub size: u64,
+}
+
+/// Walks the folder of every category in `categories`. Missing folders take
+/// no space.
+pub fn disk_usage(categories: &[Category]) -> Vec<DiskUsage> {
+    categories
+        .iter()
+        .map(|category| {
+            let (mut bytes, mut files) = (0, 0);
+            // Min-heap of the largest files seen so far
+            let mut largest = BinaryHeap::new();
+            let walk = WalkDir::new(&category.target_path)
+                .into_iter()
+                .filter_map(|entry| entry.ok());
+            for entry in walk {
+                let Some(size) = entry.metadata().ok().filter(|m| m.is_file()).map(|m| m.len()) else {
+                    continue;
+                };
+                bytes += size;
+                files += 1;
+                largest.push(Reverse((size, entry.into_path())));
+                if largest.len() > LARGEST_FILES {
+                    largest.pop();
+                }
+            }
+            DiskUsage {
+                id: category.id.clone(),
+                name: category.name.clone(),
+                target_path: category.target_path.clone(),
+                bytes,
+                files,
+                largest: largest
+                    .into_sorted_vec()
+                    .into_iter()
+                    .map(|Reverse((size, path))| LargeFile {
+                        path: path.display().to_string(),
+                        size,
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// `LIKE` pattern matching the ids of every category below `id`.
+fn descendants_pattern(id: &str) -> String {
+    format!("{}/%", escape_like(id))
+}
+
+/// The whole tree, parents before their children.
+pub fn load(conn: &Connection) -> Result<Vec<Category>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT c.id, c.parent_id, c.name, c.target_path,
+                (SELECT COUNT(*) FROM path_mappings m WHERE m.category_id = c.id),
+                (SELECT COUNT(*) FROM rules r WHERE r.category_id = c.id)
+         FROM categories c ORDER BY c.id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(Category {
+            id: row.get(0)?,
+            parent_id: row.get(1)?,
+            name: row.get(2)?,
+            target_path: row.get(3)?,
+            mapping_count: row.get(4)?,
+            rule_count: row.get(5)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// The children of `parent` in `by_parent`, with theirs nested inside.
+fn nest(parent: Option<String>, by_parent: &mut HashMap<Option<String>, Vec<Category>>) -> Vec<CategoryNode> {
+    let categories = by_parent.remove(&parent).unwrap_or_default();
+    categories
+        .into_iter()
+        .map(|category| {
+            let children = nest(Some(category.id.clone()), by_parent);
+            CategoryNode {
+                total_rule_count: category.rule_count + children.iter().map(|c| c.total_rule_count).sum::<usize>(),
+                category,
+                children,
+            }
+        })
+        .collect()
+}
+
+/// The whole tree, nested, each level by id.
+pub fn tree(conn: &Connection) -> Result<Vec<CategoryNode>, Error> {
+    let mut by_parent: HashMap<Option<String>, Vec<Category>> = HashMap::new();
+    for category in load(conn)? {
+        by_parent.entry(category.parent_id.clone()).or_default().push(category);
+    }
+    Ok(nest(None, &mut by_parent))
+}
+
+/// The folder of category `id`, if it exists.
+pub fn target_path(conn: &Connection, id: &str) -> Result<Option<String>, Error> {
+    Ok(conn
+        .query_row("SELECT target_path FROM categories WHERE id = ?", params![id], |row| row.get(0))
+        .optional()?)
+}
+
+/// Adds the top-level category `id` unless it exists already.
+pub fn ensure(conn: &Connection, id: &str, name: &str, target_path: &str) -> Result<(), Error> {
+    conn.execute(
+        "INSERT OR IGNORE INTO categories (id, parent_id, name, target_path) VALUES (?, NULL, ?, ?)",
+        params![id, name, target_path],
+    )?;
+    Ok(())
+}
+
+/// `name` trimmed, if it can name a folder.
+fn folder_name(name: &str) -> Result<&str, Error> {
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(Error::InvalidCategory(format!("`{}` is not a folder name", name)));
+    }
+    Ok(name)
+}
+
+/// Adds a folder called `name` inside category `parent_id`.
+pub fn add_subcategory(conn: &Connection, parent_id: &str, name: &str) -> Result<Category, Error> {
+    let name = folder_name(name)?;
+    let parent = target_path(conn, parent_id)?
+        .ok_or_else(|| Error::InvalidCategory(format!("no category `{}`", parent_id)))?;
+
+    let id = format!("{}/{}", parent_id, defaults::category_id(name));
+    let target_path = Path::new(&parent).join(name).to_string_lossy().into_owned();
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO categories (id, parent_id, name, target_path) VALUES (?, ?, ?, ?)",
+        params![id, parent_id, name, target_path],
+    )?;
+    if inserted == 0 {
+        return Err(Error::InvalidCategory(format!("`{}` already has a folder called {}", parent_id, name)));
+    }
+    Ok(Category {
+        id,
+        parent_id: Some(parent_id.to_string()),
+        name: name.to_string(),
+        target_path,
+        mapping_count: 0,
+        rule_count: 0,
+    })
+}
+
+/// Adds category `id` from a shared ruleset unless it exists. A subcategory
+/// goes into its parent's folder, so `target_path` only counts for
+/// top-level categories. Returns whether it was added.
+pub fn import(
+    conn: &Connection,
+    id: &str,
+    parent_id: Option<&str>,
+    name: &str,
+    target_path: &str,
+) -> Result<bool, Error> {
+    let name = folder_name(name)?;
+    let target_path = match parent_id {
+        Some(parent_id) => {
+            let parent = self::target_path(conn, parent_id)?
+                .ok_or_else(|| Error::InvalidCategory(format!("no category `{}`", parent_id)))?;
+            Path::new(&parent).join(name).to_string_lossy().into_owned()
+        }
+        None => target_path.to_string(),
+    };
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO categories (id, parent_id, name, target_path) VALUES (?, ?, ?, ?)",
+        params![id, parent_id, name, target_path],
+    )?;
+    Ok(inserted > 0)
+}
+
+/// Renames category `id` to `name` and moves it to `to`, then re-resolves
+/// every subcategory and every mapping and rule targeting them. Targets the
+/// user pointed somewhere else are left alone. Returns the number of
+/// mappings and rules changed.
+pub fn relocate(conn: &Connection, id: &str, name: &str, to: &Path) -> Result<usize, Error> {
+    let Some(from) = target_path(conn, id)?.map(PathBuf::from) else {
+        return Ok(0);
+    };
+    let rebase = |path: &str| {
+        Path::new(path)
+            .strip_prefix(&from)
+            .ok()
+            .map(|rest| to.join(rest).to_string_lossy().into_owned())
+    };
+
+    conn.execute(
+        "UPDATE categories SET name = ?, target_path = ? WHERE id = ?",
+        params![name, to.to_string_lossy(), id],
+    )?;
+    conn.execute(
+        "UPDATE path_mappings SET category = ? WHERE category_id = ?",
+        params![name, id],
+    )?;
+
+    let pattern = descendants_pattern(id);
+    let mut changed = 0;
+    for (table, key) in [("categories", "id"), ("path_mappings", "extension"), ("rules", "id")] {
+        let column = if table == "categories" { "id" } else { "category_id" };
+        let rows: Vec<(rusqlite::types::Value, String)> = conn
+            .prepare(&format!(
+                "SELECT {key}, target_path FROM {table} WHERE {column} LIKE ?1 ESCAPE '\\' OR ({column} = ?2 AND ?3)",
+            ))?
+            .query_map(params![pattern, id, table != "categories"], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        for (key_value, target_path) in rows {
+            let Some(rebased) = rebase(&target_path) else {
+                continue;
+            };
+            let updated = conn.execute(
+                &format!("UPDATE {table} SET target_path = ? WHERE {key} = ?"),
+                params![rebased, key_value],
+            )?;
+            if table != "categories" {
+                changed += updated;
+            }
+        }
+    }
+    Ok(changed)
+}
+
+/// Renames category `id` and its folder to `name`. Subcategories, mappings
+/// and rules targeting the folder and history entries inside it follow.
+/// Nothing changes if the folder can't be renamed. Returns the new folder.
+pub fn rename(conn: &mut Connection, fs: &dyn FileSystem, id: &str, name: &str) -> Result<PathBuf, Error> {
+    let name = folder_name(name)?;
+    let from = PathBuf::from(
+        target_path(conn, id)?.ok_or_else(|| Error::InvalidCategory(format!("no category `{}`", id)))?,
+    );
+    let to = from.with_file_name(name);
+    // A change of case only is the same folder on most desktops
+    let same_folder = from.to_string_lossy().to_lowercase() == to.to_string_lossy().to_lowercase();
+    if !same_folder && fs.exists(&mover::long_path(&to)) {
+        return Err(Error::InvalidCategory(format!("{} already exists", to.display())));
+    }
+
+    let tx = conn.transaction()?;
+    relocate(&tx, id, name, &to)?;
+    // Simulated, what was sorted into the folder is still where history says
+    if !Settings::load(&tx)?.simulation_mode {
+        history::rebase(&tx, &from, &to)?;
+    }
+    let renamed = from != to && fs.exists(&mover::long_path(&from));
+    if renamed {
+        // Dropping the transaction rolls the database back
+        fs.rename(&mover::long_path(&from), &mover::long_path(&to))?;
+    }
+    if let Err(e) = tx.commit() {
+        if renamed {
+            let _ = fs.rename(&mover::long_path(&to), &mover::long_path(&from));
+        }
+        return Err(e.into());
+    }
+    Ok(to)
+}
+
+/// Folders a merge emptied, each with the one its entries belong in now.
+pub struct Merged {
+    pub folders: Vec<(PathBuf, PathBuf)>,
+    /// Mappings and rules repointed.
+    pub changed: usize,
+}
+
+/// Folds category `from` into `to`: its mappings and rules follow `to`, and
+/// its subcategories become subcategories of `to`, joining any of the same
+/// name. `from` is deleted; the files in its folders are left to the caller.
+pub fn merge(conn: &mut Connection, from: &str, to: &str) -> Result<Merged, Error> {
+    if from == to || to.starts_with(&format!("{}/", from)) {
+        return Err(Error::InvalidCategory(format!("can't merge `{}` into itself", from)));
+    }
+    let missing = |id: &str| Error::InvalidCategory(format!("no category `{}`", id));
+    let from_path = PathBuf::from(target_path(conn, from)?.ok_or_else(|| missing(from))?);
+    let to_path = PathBuf::from(target_path(conn, to)?.ok_or_else(|| missing(to))?);
+
+    let tx = conn.transaction()?;
+    // Parents before their children, so every new parent exists
+    let nodes: Vec<(String, String, String)> = tx
+        .prepare("SELECT id, name, target_path FROM categories WHERE id = ?1 OR id LIKE ?2 ESCAPE '\\' ORDER BY id")?
+        .query_map(params![from, descendants_pattern(from)], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+    let mut merged = Merged {
+        folders: Vec::new(),
+        changed: 0,
+    };
+    for (id, name, path) in nodes {
+        let new_id = format!("{}{}", to, &id[from.len()..]);
+        if id != from {
+            let new_parent = &new_id[..new_id.rfind('/').unwrap_or_default()];
+            let new_path = match Path::new(&path).strip_prefix(&from_path) {
+                Ok(rest) => to_path.join(rest),
+                Err(_) => to_path.join(&name),
+            };
+            tx.execute(
+                "INSERT OR IGNORE INTO categories (id, parent_id, name, target_path) VALUES (?, ?, ?, ?)",
+                params![new_id, new_parent, name, new_path.to_string_lossy()],
+            )?;
+        }
+        // A same-named subcategory of `to` keeps its own folder
+        let (new_name, new_path): (String, String) = tx.query_row(
+            "SELECT name, target_path FROM categories WHERE id = ?",
+            params![new_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        merged.changed += tx.execute(
+            "UPDATE path_mappings SET category_id = ?, category = ?, target_path = ? WHERE category_id = ?",
+            params![new_id, new_name, new_path, id],
+        )?;
+        merged.changed += tx.execute(
+            "UPDATE rules SET category_id = ?, target_path = ? WHERE category_id = ?",
+            params![new_id, new_path, id],
+        )?;
+        merged.folders.push((PathBuf::from(path), PathBuf::from(new_path)));
+    }
+    tx.execute(
+        "DELETE FROM categories WHERE id = ?1 OR id LIKE ?2 ESCAPE '\\'",
+        params![from, descendants_pattern(from)],
+    )?;
+    tx.commit()?;
+    Ok(merged)
+}
+
+/// Creates the top-level categories of existing mappings, each at the folder
+/// most of its mappings use, and links rules targeting a category's folder.
+pub fn backfill(conn: &Connection) -> Result<(), Error> {
+    let mappings: Vec<(String, String, String)> = conn
+        .prepare(
+            "SELECT category_id, category, target_path FROM path_mappings
+             WHERE category_id IS NOT NULL AND category IS NOT NULL",
+        )?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+
+    let mut uses: HashMap<(&str, &str), (usize, &str)> = HashMap::new();
+    for (id, name, target_path) in &mappings {
+        uses.entry((id, target_path)).or_insert((0, name)).0 += 1;
+    }
+    let mut best: HashMap<&str, (usize, &str, &str)> = HashMap::new();
+    for ((id, target_path), (count, name)) in uses {
+        let current = best.entry(id).or_insert((0, name, target_path));
+        if count > current.0 {
+            *current = (count, name, target_path);
+        }
+    }
+    for (id, (_, name, target_path)) in best {
+        ensure(conn, id, name, target_path)?;
+    }
+
+    conn.execute(
+        "UPDATE rules SET category_id = (SELECT id FROM categories c WHERE c.target_path = rules.target_path)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Moves the entries of the folders `merged` emptied into the folders that
+/// replace them, names that are taken getting a suffix as in any sort, and
+/// removes the folders left empty.
+pub fn consolidate(conn: &Connection, ctx: &SortContext, merged: &Merged, decided_by: &str) -> Result<SortResult, Error> {
+    let folders: Vec<_> = merged
+        .folders
+        .iter()
+        .filter(|(old, new)| old != new)
+        .map(|(old, new)| (old.clone(), new.clone(), Quota::default()))
+        .collect();
+    let result = move_folder_contents(conn, ctx, &folders, &|_| true, decided_by)?;
+    // Deepest first; folders that still hold something stay
+    for (old, _, _) in folders.iter().rev() {
+        let old = mover::long_path(old);
+        if ctx.fs.read_dir(&old).is_ok_and(|entries| entries.is_empty()) {
+            let _ = ctx.fs.remove_dir_all(&old);
+        }
+    }
+    Ok(result)
+}
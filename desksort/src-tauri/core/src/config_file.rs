@@ -0,0 +1,232 @@
+use crate::{categories, category_for_target, defaults, extension_groups, get_db_path, settings::Settings, Error};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+pub const FILE_NAME: &str = "desksort.toml";
+
+/// Editors often save in several steps; wait this long for them to finish
+/// before reading the file.
+const SETTLE: Duration = Duration::from_millis(300);
+
+/// The setting choosing the backend lives in the database only, so the file
+/// can't switch itself off.
+const BACKEND_KEY: &str = "config_backend";
+
+const HEADER: &str = "# DeskSort configuration. Changes are picked up while DeskSort is running.\n\n";
+
+/// Where mappings and settings are edited.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigBackend {
+    /// Only through the app; `settings.db` is the source of truth.
+    #[default]
+    Database,
+    /// `desksort.toml` next to the database is the source of truth and is
+    /// kept in sync with changes made in the app.
+    Toml,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    settings: Map<String, Value>,
+    #[serde(default, rename = "mapping")]
+    mappings: Vec<MappingEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MappingEntry {
+    extension: String,
+    target: String,
+    /// Defaults to the target's folder name. Default categories are recognized
+    /// under their name in any language.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_files: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_size: Option<u64>,
+    /// Only written for disabled mappings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+pub fn path() -> Result<PathBuf, Error> {
+    Ok(get_db_path()?.with_file_name(FILE_NAME))
+}
+
+fn invalid(message: impl ToString) -> Error {
+    Error::InvalidConfigFile(message.to_string())
+}
+
+/// Writes the mappings and settings in `conn` to `path`.
+pub fn write(conn: &Connection, path: &Path) -> Result<(), Error> {
+    let mut settings = match serde_json::to_value(Settings::load(conn)?) {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    settings.remove(BACKEND_KEY);
+    // TOML has no null; unset settings are simply left out
+    settings.retain(|_, value| !value.is_null());
+
+    let mappings = conn
+        .prepare(
+            "SELECT extension, target_path, category, max_files, max_bytes, enabled, min_size, max_size, description
+             FROM path_mappings ORDER BY category, extension",
+        )?
+        .query_map([], |row| {
+            Ok(MappingEntry {
+                extension: row.get(0)?,
+                target: row.get(1)?,
+                category: row.get(2)?,
+                max_files: row.get(3)?,
+                max_bytes: row.get(4)?,
+                min_size: row.get(6)?,
+                max_size: row.get(7)?,
+                enabled: (!row.get::<_, bool>(5)?).then_some(false),
+                description: row.get(8)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+
+    let text = toml::to_string_pretty(&ConfigFile { settings, mappings }).map_err(invalid)?;
+    // Replace the file in one step so the watcher never reads half of it
+    let temp = path.with_extension("toml.tmp");
+    fs::write(&temp, format!("{}{}", HEADER, text))?;
+    fs::rename(temp, path)?;
+    Ok(())
+}
+
+/// Makes the mappings and settings in `conn` match the file at `path`. Settings
+/// the file leaves out get their defaults. Nothing is written unless the
+/// whole file is valid.
+pub fn apply(conn: &mut Connection, path: &Path) -> Result<Settings, Error> {
+    let file: ConfigFile = toml::from_str(&fs::read_to_string(path)?).map_err(invalid)?;
+
+    let mut settings = match serde_json::to_value(Settings::default()) {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    settings.remove(BACKEND_KEY);
+    for (key, value) in file.settings {
+        if !settings.contains_key(&key) {
+            return Err(invalid(format!("unknown setting `{}`", key)));
+        }
+        settings.insert(key, value);
+    }
+
+    let mut extensions = HashSet::new();
+    for mapping in &file.mappings {
+        if mapping.extension.is_empty() || mapping.target.is_empty() {
+            return Err(invalid("every mapping needs an extension and a target"));
+        }
+        if extension_groups::is_group(&mapping.extension) && extension_groups::extensions(&mapping.extension).is_none() {
+            return Err(invalid(format!("`{}` is not an extension group", mapping.extension)));
+        }
+        if !extensions.insert(mapping.extension.as_str()) {
+            return Err(invalid(format!("`{}` is mapped twice", mapping.extension)));
+        }
+    }
+
+    let tx = conn.transaction()?;
+    Settings::update(&tx, settings)?;
+
+    let existing: Vec<String> = tx
+        .prepare("SELECT extension FROM path_mappings")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for extension in existing.iter().filter(|e| !extensions.contains(e.as_str())) {
+        tx.execute("DELETE FROM path_mappings WHERE extension = ?", params![extension])?;
+    }
+    // Upserts keep the match counters of mappings that stay
+    for mapping in &file.mappings {
+        let category = mapping.category.clone().unwrap_or_else(|| category_for_target(&mapping.target));
+        tx.execute(
+            "INSERT INTO path_mappings (extension, target_path, category, category_id, max_files, max_bytes, enabled,
+                min_size, max_size, description)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT(extension) DO UPDATE SET
+                target_path = excluded.target_path,
+                category = excluded.category,
+                category_id = excluded.category_id,
+                max_files = excluded.max_files,
+                max_bytes = excluded.max_bytes,
+                enabled = excluded.enabled,
+                min_size = excluded.min_size,
+                max_size = excluded.max_size,
+                description = excluded.description",
+            params![
+                mapping.extension,
+                mapping.target,
+                category,
+                defaults::category_id(&category),
+                mapping.max_files,
+                mapping.max_bytes,
+                mapping.enabled.unwrap_or(true),
+                mapping.min_size,
+                mapping.max_size,
+                mapping.description
+            ],
+        )?;
+        categories::ensure(&tx, &defaults::category_id(&category), &category, &mapping.target)?;
+    }
+
+    let settings = Settings::load(&tx)?;
+    tx.commit()?;
+    Ok(settings)
+}
+
+/// Keeps the watcher running; dropping it stops watching.
+pub struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+/// Calls `on_change` whenever the file at `path` is written, created or
+/// replaced.
+pub fn watch(path: &Path, on_change: impl Fn() + Send + 'static) -> Result<ConfigWatcher, Error> {
+    let dir = path.parent().ok_or(Error::ConfigDirNotFound)?;
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)
+        .map_err(|e| Error::Io(std::io::Error::other(format!("Failed to start watcher: {}", e))))?;
+    // The directory rather than the file, since editors save by replacing it
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(|e| Error::Io(std::io::Error::other(format!("Failed to watch: {}", e))))?;
+
+    tracing::info!("Watching {} for changes", path.display());
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        let concerns_file = |event: notify::Result<notify::Event>| {
+            event.is_ok_and(|event| !event.kind.is_access() && event.paths.contains(&path))
+        };
+        while let Ok(event) = rx.recv() {
+            if !concerns_file(event) {
+                continue;
+            }
+            while rx.recv_timeout(SETTLE).is_ok() {}
+            if path.exists() {
+                on_change();
+            }
+        }
+    });
+
+    Ok(ConfigWatcher { _watcher: watcher })
+}
@@ -0,0 +1,204 @@
+use crate::{categories, Error};
+use rusqlite::{params, Connection};
+use std::path::{Path, PathBuf};
+
+/// Category ids and their extensions, mapped to `Sorted/<folder name>` on
+/// first run.
+type MappingSet = &'static [(&'static str, &'static [&'static str])];
+
+/// File types that look the same on every platform.
+const COMMON: MappingSet = &[
+    ("documents", &[".pdf", ".docx", ".doc", ".txt", ".odt", ".rtf"]),
+    ("spreadsheets", &[".xls", ".xlsx", ".csv", ".ods"]),
+    ("presentations", &[".pptx", ".odp", ".key"]),
+    ("images", &[".jpg", ".jpeg", ".png", ".gif", ".bmp", ".webp", ".tiff"]),
+    ("videos", &[".mp4", ".mkv", ".avi", ".mov", ".webm", ".flv", ".wmv"]),
+    ("audio", &[".mp3", ".wav", ".aac", ".ogg", ".flac"]),
+    ("archives", &[".zip", ".rar", ".7z", ".tar", ".gz", ".tar.gz"]),
+    ("code", &[".js", ".py", ".rs", ".cpp", ".java", ".html", ".css", ".json", ".ts"]),
+    ("folders", &["folder"]),
+];
+
+/// Programs, installers and disk images only make sense on their own platform.
+#[cfg(target_os = "windows")]
+const PLATFORM: MappingSet = &[
+    ("executables", &[".exe", ".bat", ".cmd", ".ps1"]),
+    ("installers", &[".msi", ".msix", ".appx"]),
+    ("disk_images", &[".iso", ".vhd", ".vhdx"]),
+];
+
+#[cfg(target_os = "macos")]
+const PLATFORM: MappingSet = &[
+    ("executables", &[".sh", ".command"]),
+    ("installers", &[".pkg", ".mpkg"]),
+    ("disk_images", &[".dmg", ".iso"]),
+];
+
+#[cfg(all(unix, not(target_os = "macos")))]
+const PLATFORM: MappingSet = &[
+    ("executables", &[".sh", ".run", ".appimage"]),
+    ("installers", &[".deb", ".rpm", ".apk", ".flatpakref", ".snap"]),
+    ("disk_images", &[".iso", ".img"]),
+];
+
+/// Languages default folders can be named in, as ISO 639-1 codes.
+pub const LANGUAGES: &[&str] = &["en", "de", "fr", "es", "it", "pt", "nl"];
+
+/// Folder name of each default category, in `LANGUAGES` order.
+const FOLDER_NAMES: &[(&str, [&str; 7])] = &[
+    (
+        "documents",
+        ["Documents", "Dokumente", "Documents", "Documentos", "Documenti", "Documentos", "Documenten"],
+    ),
+    (
+        "spreadsheets",
+        ["Spreadsheets", "Tabellen", "Feuilles de calcul", "Hojas de cálculo", "Fogli di calcolo", "Planilhas", "Spreadsheets"],
+    ),
+    (
+        "presentations",
+        ["Presentations", "Präsentationen", "Présentations", "Presentaciones", "Presentazioni", "Apresentações", "Presentaties"],
+    ),
+    ("images", ["Images", "Bilder", "Images", "Imágenes", "Immagini", "Imagens", "Afbeeldingen"]),
+    // Not a mapping category; camera imports go here
+    ("photos", ["Photos", "Fotos", "Photos", "Fotos", "Foto", "Fotos", "Foto's"]),
+    ("videos", ["Videos", "Videos", "Vidéos", "Vídeos", "Video", "Vídeos", "Video's"]),
+    ("audio", ["Audio", "Audio", "Audio", "Audio", "Audio", "Áudio", "Audio"]),
+    ("archives", ["Archives", "Archive", "Archives", "Archivos", "Archivi", "Arquivos", "Archieven"]),
+    ("code", ["Code", "Code", "Code", "Código", "Codice", "Código", "Code"]),
+    ("folders", ["Folders", "Ordner", "Dossiers", "Carpetas", "Cartelle", "Pastas", "Mappen"]),
+    (
+        "executables",
+        ["Executables", "Programme", "Exécutables", "Ejecutables", "Eseguibili", "Executáveis", "Programma's"],
+    ),
+    (
+        "installers",
+        ["Installers", "Installationsprogramme", "Installateurs", "Instaladores", "Installer", "Instaladores", "Installatiebestanden"],
+    ),
+    (
+        "disk_images",
+        ["Disk Images", "Datenträgerabbilder", "Images disque", "Imágenes de disco", "Immagini disco", "Imagens de disco", "Schijfkopieën"],
+    ),
+];
+
+/// The folder name of category `id` in `language`, for default categories.
+pub fn folder_name(id: &str, language: &str) -> Option<&'static str> {
+    let index = LANGUAGES.iter().position(|l| *l == language).unwrap_or(0);
+    FOLDER_NAMES.iter().find(|(known, _)| *known == id).map(|(_, names)| names[index])
+}
+
+/// The id of the default category called `name` in any language.
+pub fn known_category(name: &str) -> Option<&'static str> {
+    let name = name.trim();
+    FOLDER_NAMES
+        .iter()
+        .find(|(id, names)| *id == name || names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+        .map(|(id, _)| *id)
+}
+
+/// The language-independent id of the category called `name`. Default
+/// categories are recognized in every language; other names are slugged.
+pub fn category_id(name: &str) -> String {
+    match known_category(name) {
+        Some(id) => id.to_string(),
+        None => name.to_lowercase().split_whitespace().collect::<Vec<_>>().join("_"),
+    }
+}
+
+/// The language default folders are named in: `setting` if DeskSort has names
+/// for it, else the system language, else English.
+pub fn language(setting: Option<&str>) -> &'static str {
+    setting
+        .and_then(supported)
+        .or_else(|| system_locale().as_deref().and_then(supported))
+        .unwrap_or(LANGUAGES[0])
+}
+
+/// The entry of `LANGUAGES` for a locale such as `de_DE.UTF-8` or `pt-BR`.
+pub fn supported(locale: &str) -> Option<&'static str> {
+    let code = locale.split(['_', '-', '.', '@']).next()?.to_ascii_lowercase();
+    LANGUAGES.iter().find(|l| **l == code).copied()
+}
+
+#[cfg(target_os = "windows")]
+fn system_locale() -> Option<String> {
+    let output = std::process::Command::new("reg")
+        .args(["query", r"HKCU\Control Panel\International", "/v", "LocaleName"])
+        .output()
+        .ok()?;
+    // "    LocaleName    REG_SZ    de-DE"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().find(|line| line.contains("LocaleName"))?;
+    line.split_whitespace().last().map(str::to_string)
+}
+
+/// Apps started from Finder don't inherit `LANG`, so ask for the user's
+/// preference directly.
+#[cfg(target_os = "macos")]
+fn system_locale() -> Option<String> {
+    let output = std::process::Command::new("defaults")
+        .args(["read", "-g", "AppleLocale"])
+        .output()
+        .ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string()).filter(|locale| !locale.is_empty())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn system_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// A mapping a new database starts with.
+pub struct DefaultMapping {
+    pub extension: &'static str,
+    pub category_id: &'static str,
+    pub target: PathBuf,
+}
+
+/// The mappings a new database starts with on this platform, into folders
+/// below `sorted_dir` named in `language`.
+pub fn default_mappings(sorted_dir: &Path, language: &str) -> Vec<DefaultMapping> {
+    COMMON
+        .iter()
+        .chain(PLATFORM)
+        .flat_map(|(id, extensions)| {
+            let target = sorted_dir.join(folder_name(id, language).unwrap_or(id));
+            extensions.iter().map(move |extension| DefaultMapping {
+                extension,
+                category_id: id,
+                target: target.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Points mappings that still use a default folder named in `from` at the
+/// same folder named in `to`, along with the category and its subcategories.
+/// Mappings the user redirected are left alone.
+pub fn rename_default_folders(conn: &Connection, sorted_dir: &Path, from: &str, to: &str) -> Result<usize, Error> {
+    let mut renamed = 0;
+    for (id, _) in FOLDER_NAMES {
+        let (Some(old), Some(new)) = (folder_name(id, from), folder_name(id, to)) else {
+            continue;
+        };
+        if old == new {
+            continue;
+        }
+        if categories::target_path(conn, id)?.is_some_and(|path| Path::new(&path) == sorted_dir.join(old)) {
+            renamed += categories::relocate(conn, id, new, &sorted_dir.join(new))?;
+        }
+        renamed += conn.execute(
+            "UPDATE path_mappings SET target_path = ?, category = ?
+             WHERE category_id = ? AND target_path = ?",
+            params![
+                sorted_dir.join(new).to_string_lossy(),
+                new,
+                id,
+                sorted_dir.join(old).to_string_lossy()
+            ],
+        )?;
+    }
+    Ok(renamed)
+}
@@ -0,0 +1,194 @@
+use crate::Error;
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Folder the user set as their desktop, which takes the place of the one
+/// the system reports.
+static OVERRIDE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Shell known folders, looked up with `SHGetKnownFolderPath`. Unlike the
+/// environment or the profile path, it follows folders the user moved or
+/// Group Policy redirected, and returns paths as UTF-16, so profiles with
+/// non-ASCII names work.
+#[cfg(windows)]
+mod known_folders {
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt, path::PathBuf, ptr};
+
+    #[repr(C)]
+    pub struct Guid(u32, u16, u16, [u8; 8]);
+
+    pub const DESKTOP: Guid = Guid(0xB4BF_CC3A, 0xDB2C, 0x424C, [0xB0, 0x29, 0x7F, 0xE9, 0x9A, 0x87, 0xC6, 0x41]);
+    pub const DOWNLOADS: Guid = Guid(0x374D_E290, 0x123F, 0x4565, [0x91, 0x64, 0x39, 0xC4, 0x92, 0x5E, 0x46, 0x7B]);
+    pub const PUBLIC_DESKTOP: Guid =
+        Guid(0xC4AA_340D, 0xF20F, 0x4863, [0xAF, 0xEF, 0xF8, 0x7E, 0xF2, 0xE6, 0xBA, 0x25]);
+
+    #[link(name = "shell32")]
+    extern "system" {
+        fn SHGetKnownFolderPath(id: *const Guid, flags: u32, token: *mut u8, path: *mut *mut u16) -> i32;
+    }
+
+    #[link(name = "ole32")]
+    extern "system" {
+        fn CoTaskMemFree(memory: *mut u8);
+    }
+
+    pub fn path(id: &Guid) -> Option<PathBuf> {
+        let mut raw: *mut u16 = ptr::null_mut();
+        // SAFETY: `id` is a valid GUID and `raw` receives a buffer the
+        // shell allocates, which must be freed even when the call fails
+        let result = unsafe { SHGetKnownFolderPath(id, 0, ptr::null_mut(), &mut raw) };
+        let path = (result == 0 && !raw.is_null()).then(|| {
+            // SAFETY: on success `raw` is a NUL-terminated UTF-16 string
+            let wide = unsafe {
+                let len = (0..).take_while(|&i| *raw.add(i) != 0).count();
+                std::slice::from_raw_parts(raw, len)
+            };
+            PathBuf::from(OsString::from_wide(wide))
+        });
+        // SAFETY: `raw` came from SHGetKnownFolderPath, or is null
+        unsafe { CoTaskMemFree(raw.cast()) };
+        path
+    }
+}
+
+/// Environment variables the OneDrive client sets to its folders, work or
+/// school accounts first.
+#[cfg(windows)]
+const ONEDRIVE_VARIABLES: &[&str] = &["OneDriveCommercial", "OneDriveConsumer", "OneDrive"];
+
+/// The OneDrive folders of the signed-in accounts.
+#[cfg(windows)]
+fn onedrive_roots() -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for root in ONEDRIVE_VARIABLES.iter().filter_map(std::env::var_os).map(PathBuf::from) {
+        if !roots.contains(&root) {
+            roots.push(root);
+        }
+    }
+    roots
+}
+
+#[cfg(windows)]
+fn in_onedrive(path: &Path) -> bool {
+    onedrive_roots().iter().any(|root| path.starts_with(root))
+}
+
+/// The Desktop in OneDrive that Known Folder Move redirected the desktop to.
+#[cfg(windows)]
+fn onedrive_desktop() -> Option<PathBuf> {
+    onedrive_roots().into_iter().map(|root| root.join("Desktop")).find(|dir| dir.is_dir())
+}
+
+/// The desktop folder to sort, the known folder on Windows, so a desktop
+/// moved to another drive is found too. With Known Folder Move, the desktop is in
+/// OneDrive, but processes started before the move and some sessions still
+/// get the old local folder, which is then missing or left empty but for
+/// its `desktop.ini`; the one in OneDrive is taken instead.
+pub fn path() -> Result<PathBuf, Error> {
+    if let Some(dir) = OVERRIDE.lock().unwrap().clone() {
+        // A folder that went away doesn't fall back to the real desktop,
+        // which the user didn't want sorted
+        return if dir.is_dir() { Ok(dir) } else { Err(Error::DesktopNotFound) };
+    }
+    #[cfg(windows)]
+    let known = known_folders::path(&known_folders::DESKTOP).or_else(dirs::desktop_dir);
+    #[cfg(not(windows))]
+    let known = dirs::desktop_dir();
+    #[cfg(windows)]
+    if let Some(onedrive) = onedrive_desktop() {
+        let stale = |dir: &Path| {
+            !in_onedrive(dir)
+                && std::fs::read_dir(dir).map_or(true, |entries| {
+                    entries
+                        .filter_map(|entry| entry.ok())
+                        .all(|entry| entry.file_name().eq_ignore_ascii_case("desktop.ini"))
+                })
+        };
+        if known.as_deref().is_none_or(stale) {
+            return Ok(onedrive);
+        }
+    }
+    known.ok_or(Error::DesktopNotFound)
+}
+
+/// Checks that `path` can stand in for the desktop.
+pub fn validate_override(path: &Path) -> Result<(), Error> {
+    if !path.is_absolute() {
+        return Err(Error::InvalidSource(format!("{} is not an absolute path", path.display())));
+    }
+    if !path.is_dir() {
+        return Err(Error::InvalidSource(format!("{} is not a folder", path.display())));
+    }
+    Ok(())
+}
+
+/// Makes `path` the desktop, or goes back to the system's one when unset.
+/// Returns whether the desktop changed.
+pub fn set_override(path: Option<PathBuf>) -> bool {
+    let mut current = OVERRIDE.lock().unwrap();
+    let changed = *current != path;
+    *current = path;
+    changed
+}
+
+/// The user's Downloads folder, resolved like the desktop.
+pub fn downloads() -> Option<PathBuf> {
+    #[cfg(windows)]
+    if let Some(dir) = known_folders::path(&known_folders::DOWNLOADS) {
+        return Some(dir);
+    }
+    dirs::download_dir()
+}
+
+/// The desktop shared by every user of a Windows machine, whose entries
+/// appear on each user's desktop too. Other systems have none.
+#[cfg(windows)]
+pub fn public() -> Option<PathBuf> {
+    known_folders::path(&known_folders::PUBLIC_DESKTOP).filter(|dir| dir.is_dir())
+}
+
+#[cfg(not(windows))]
+pub fn public() -> Option<PathBuf> {
+    None
+}
+
+/// Whether moving the entry at `path` away takes administrator rights, as
+/// it does for most of the public desktop. Opening it for deletion checks
+/// that without touching it.
+#[cfg(windows)]
+pub fn needs_elevation(path: &Path) -> bool {
+    use std::os::windows::fs::OpenOptionsExt;
+    /// DELETE access, and FILE_FLAG_BACKUP_SEMANTICS, without which folders
+    /// can't be opened.
+    const DELETE: u32 = 0x0001_0000;
+    const BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    std::fs::OpenOptions::new()
+        .access_mode(DELETE)
+        .custom_flags(BACKUP_SEMANTICS)
+        .open(path)
+        .is_err_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied)
+}
+
+#[cfg(not(windows))]
+pub fn needs_elevation(_path: &Path) -> bool {
+    false
+}
+
+/// Where the system says the folders commonly sorted are.
+#[derive(Serialize)]
+pub struct SourcePaths {
+    pub desktop: Option<PathBuf>,
+    pub downloads: Option<PathBuf>,
+    pub public_desktop: Option<PathBuf>,
+}
+
+pub fn source_paths() -> SourcePaths {
+    SourcePaths {
+        desktop: path().ok(),
+        downloads: downloads(),
+        public_desktop: public(),
+    }
+}
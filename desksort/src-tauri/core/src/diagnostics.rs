@@ -0,0 +1,220 @@
+use crate::{
+    messages::Message,
+    mover::{self, long_path},
+};
+use serde::Serialize;
+use std::{
+    fs, io,
+    path::Path,
+};
+
+/// The part of sorting an entry that failed.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailedStep {
+    Scan,
+    FreeSpace,
+    CreateDir,
+    Rotate,
+    ReadSource,
+    RemoveSource,
+    WriteTarget,
+}
+
+/// Why a move failed, as kept in the retry queue.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// Timeouts and dropped network connections.
+    Transient,
+    /// Another program holds the file open or locked.
+    InUse,
+    PermissionDenied,
+    ReadOnly,
+    NotFound,
+    Other,
+}
+
+impl FailureKind {
+    pub fn classify(err: &io::Error) -> Self {
+        if mover::is_transient(err) {
+            return FailureKind::Transient;
+        }
+        // ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION, and
+        // ERROR_CLOUD_FILE_IN_USE and ERROR_CLOUD_FILE_PROPERTY_LOCK_CONFLICT
+        // while OneDrive syncs the file
+        #[cfg(windows)]
+        if matches!(err.raw_os_error(), Some(32 | 33 | 392 | 398)) {
+            return FailureKind::InUse;
+        }
+        // EBUSY, ETXTBSY
+        #[cfg(unix)]
+        if matches!(err.raw_os_error(), Some(16 | 26)) {
+            return FailureKind::InUse;
+        }
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => FailureKind::PermissionDenied,
+            io::ErrorKind::ReadOnlyFilesystem => FailureKind::ReadOnly,
+            io::ErrorKind::NotFound => FailureKind::NotFound,
+            _ => FailureKind::Other,
+        }
+    }
+
+    /// Worth retrying without the user changing anything.
+    pub fn is_transient(self) -> bool {
+        matches!(self, FailureKind::Transient | FailureKind::InUse)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FailureKind::Transient => "transient",
+            FailureKind::InUse => "in_use",
+            FailureKind::PermissionDenied => "permission_denied",
+            FailureKind::ReadOnly => "read_only",
+            FailureKind::NotFound => "not_found",
+            FailureKind::Other => "other",
+        }
+    }
+
+    pub fn parse(text: &str) -> Self {
+        match text {
+            "transient" => FailureKind::Transient,
+            "in_use" => FailureKind::InUse,
+            "permission_denied" => FailureKind::PermissionDenied,
+            "read_only" => FailureKind::ReadOnly,
+            "not_found" => FailureKind::NotFound,
+            _ => FailureKind::Other,
+        }
+    }
+}
+
+/// An error reported in `SortResult`, with enough detail for the UI to explain
+/// what went wrong and what the user can do about it.
+#[derive(Serialize, Debug)]
+pub struct SortError {
+    pub message: Message,
+    pub path: Option<String>,
+    pub step: FailedStep,
+    pub permission_denied: bool,
+    pub read_only_media: bool,
+    pub remediation: Option<Message>,
+}
+
+impl SortError {
+    pub fn new(step: FailedStep, message: Message) -> Self {
+        SortError {
+            message,
+            path: None,
+            step,
+            permission_denied: false,
+            read_only_media: false,
+            remediation: None,
+        }
+    }
+
+    /// Builds an error for an I/O failure at `step` on `path`, flagging denied
+    /// access and read-only media with a suggested fix.
+    pub fn from_io(step: FailedStep, path: &Path, err: &io::Error, message: Message) -> Self {
+        let permission_denied = err.kind() == io::ErrorKind::PermissionDenied;
+        let read_only_media = err.kind() == io::ErrorKind::ReadOnlyFilesystem || is_read_only_media(path);
+
+        SortError {
+            message,
+            path: Some(path.display().to_string()),
+            step,
+            permission_denied,
+            read_only_media,
+            remediation: (permission_denied || read_only_media)
+                .then(|| Message::new(remediation(step, read_only_media))),
+        }
+    }
+
+    /// Like [`SortError::from_io`] for a failed move of `source` into `target_dir`,
+    /// probing both sides to tell a read failure from a write failure.
+    pub fn from_move(source: &Path, target_dir: &Path, err: &io::Error, message: Message) -> Self {
+        let denied = matches!(
+            err.kind(),
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem
+        );
+        if !denied {
+            return Self::from_io(FailedStep::WriteTarget, target_dir, err, message);
+        }
+
+        let (step, path) = if !can_read(source) {
+            (FailedStep::ReadSource, source.to_path_buf())
+        } else if !can_write_dir(target_dir) {
+            (FailedStep::WriteTarget, target_dir.to_path_buf())
+        } else {
+            let parent = source.parent().map(Path::to_path_buf).unwrap_or_default();
+            (FailedStep::RemoveSource, parent)
+        };
+        Self::from_io(step, &path, err, message)
+    }
+}
+
+/// Key of the suggested fix for a denied `step`.
+fn remediation(step: FailedStep, read_only_media: bool) -> &'static str {
+    if read_only_media {
+        return match step {
+            FailedStep::ReadSource | FailedStep::RemoveSource => "remediation.read_only_source",
+            _ => "remediation.read_only_target",
+        };
+    }
+
+    match step {
+        FailedStep::ReadSource => "remediation.read_source",
+        FailedStep::RemoveSource => "remediation.remove_source",
+        FailedStep::CreateDir => "remediation.create_dir",
+        FailedStep::Rotate => "remediation.rotate",
+        _ => "remediation.write_target",
+    }
+}
+
+fn can_read(path: &Path) -> bool {
+    let path = long_path(path);
+    if path.is_dir() {
+        fs::read_dir(path).is_ok()
+    } else {
+        fs::File::open(path).is_ok()
+    }
+}
+
+fn can_write_dir(dir: &Path) -> bool {
+    let probe = long_path(&dir.join(format!(".desksort-write-probe-{}", std::process::id())));
+    match fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Whether `path` sits on a filesystem mounted read-only.
+#[cfg(target_os = "linux")]
+fn is_read_only_media(path: &Path) -> bool {
+    let Ok(mounts) = fs::read_to_string("/proc/mounts") else {
+        return false;
+    };
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mount_point = fields.nth(1)?.replace("\\040", " ");
+            let options = fields.nth(1)?;
+            Some((std::path::PathBuf::from(mount_point), options.split(',').any(|o| o == "ro")))
+        })
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.as_os_str().len())
+        .is_some_and(|(_, read_only)| read_only)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_read_only_media(path: &Path) -> bool {
+    // Without mount information, fall back to the read-only attribute of the
+    // closest existing folder, which is what write-protected media report.
+    path.ancestors()
+        .find(|p| p.exists())
+        .and_then(|p| fs::metadata(long_path(p)).ok())
+        .is_some_and(|m| m.is_dir() && m.permissions().readonly())
+}
@@ -0,0 +1,448 @@
+use crate::{
+    diagnostics::{FailedStep, SortError},
+    filesystem::{FileSystem, OsFileSystem},
+    history,
+    messages::Message,
+    mover, naming,
+    trash::Disposal,
+    Error, MappingCache, PlannedMove,
+};
+use image::{imageops::FilterType, ImageFormat};
+use regex::Regex;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::UNIX_EPOCH,
+};
+use walkdir::WalkDir;
+
+/// What a sort does with entries named like a copy of another, e.g.
+/// `report (1).pdf` next to `report.pdf`.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// Copies are sorted like any other file.
+    #[default]
+    Keep,
+    /// Copies identical to their original, on the desktop or already at the
+    /// target, are moved to the trash.
+    Discard,
+    /// Like `Discard`, and copies whose original is nowhere to be found take
+    /// its name.
+    Merge,
+}
+
+/// A copy that was thrown away because it was identical to its original.
+#[derive(Serialize)]
+pub struct Discarded {
+    pub copy: String,
+    pub original: String,
+    /// Where the copy is in the trash, on platforms that tell.
+    pub location: Option<String>,
+}
+
+/// Stems browsers and file managers give copies: `report (1)` and `report(2)`
+/// from browsers, `report - Copy` and `report - Copy (3)` from Explorer,
+/// `report copy` and `report copy 2` from Finder.
+fn copy_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"^(.+?)(?: ?\(\d+\)| - Copy(?: \(\d+\))?| copy(?: \d+)?)$").unwrap())
+}
+
+/// The name of the file `name` is a copy of, if it is named like one.
+pub fn original_name(name: &str) -> Option<String> {
+    let (stem, extension) = naming::split_name(name);
+    let original = copy_pattern().captures(stem)?.get(1)?.as_str();
+    Some(format!("{}{}", original, extension))
+}
+
+/// Whether `a` and `b` are files with the same contents.
+fn identical(fs: &dyn FileSystem, a: &Path, b: &Path) -> bool {
+    let (Ok(a_meta), Ok(b_meta)) = (fs.metadata(a), fs.metadata(b)) else {
+        return false;
+    };
+    if a_meta.is_dir || b_meta.is_dir || a_meta.len != b_meta.len {
+        return false;
+    }
+    match (mover::hash_file(fs, a), mover::hash_file(fs, b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Applies `policy` to the copies in `plan`, looking at and renaming them
+/// through `fs`. Copies identical to their original next to them or at
+/// their target are handed to `disposal` and returned, and with `Merge`,
+/// copies without an original are renamed to its name before they are
+/// moved. Copies that differ from their original are left alone.
+pub fn consolidate(
+    policy: DuplicatePolicy,
+    fs: &dyn FileSystem,
+    disposal: &dyn Disposal,
+    plan: &mut Vec<PlannedMove>,
+) -> Vec<Discarded> {
+    if policy == DuplicatePolicy::Keep {
+        return Vec::new();
+    }
+    let planned_names: HashSet<PathBuf> = plan
+        .iter()
+        .filter_map(|m| Some(m.target_dir.join(m.source.file_name()?)))
+        .collect();
+    let mut discarded = Vec::new();
+    plan.retain_mut(|planned| {
+        if fs.metadata(&planned.source).map_or(true, |metadata| metadata.is_dir) {
+            return true;
+        }
+        let Some(original) = planned
+            .source
+            .file_name()
+            .and_then(|name| original_name(&name.to_string_lossy()))
+        else {
+            return true;
+        };
+        let beside = planned.source.with_file_name(&original);
+        let at_target = planned.target_dir.join(&original);
+        let identical_to = [&beside, &at_target]
+            .into_iter()
+            .find(|path| identical(fs, &planned.source, path))
+            .cloned();
+        if let Some(original) = identical_to {
+            match disposal.dispose(&planned.source) {
+                Ok(location) => {
+                    discarded.push(Discarded {
+                        copy: planned.source.display().to_string(),
+                        original: original.display().to_string(),
+                        location: location.map(|location| location.display().to_string()),
+                    });
+                    return false;
+                }
+                Err(e) => tracing::warn!("Failed to move duplicate {} to the trash: {}", planned.source.display(), e),
+            }
+        } else if policy == DuplicatePolicy::Merge
+            && !fs.exists(&beside)
+            && !fs.exists(&at_target)
+            && !planned_names.contains(&at_target)
+        {
+            match fs.rename(&planned.source, &beside) {
+                Ok(()) => planned.source = beside,
+                Err(e) => tracing::warn!("Failed to rename {} to {}: {}", planned.source.display(), original, e),
+            }
+        }
+        true
+    });
+    discarded
+}
+
+/// Which file of a group of identical ones the duplicate finder suggests
+/// keeping.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Keep {
+    Oldest,
+    Newest,
+}
+
+/// A file in the target folders with the same contents as at least one other.
+#[derive(Serialize)]
+pub struct DuplicateFile {
+    pub path: String,
+    /// Unix time of its last modification.
+    pub modified: Option<i64>,
+}
+
+/// Files with the same contents, oldest first.
+#[derive(Serialize)]
+pub struct DuplicateGroup {
+    pub size: u64,
+    pub files: Vec<DuplicateFile>,
+    pub keep: String,
+    /// Every file but `keep`.
+    pub suggested_deletions: Vec<String>,
+}
+
+/// What the user chose to delete from one group.
+#[derive(Deserialize)]
+pub struct Deletion {
+    pub keep: PathBuf,
+    pub delete: Vec<PathBuf>,
+}
+
+/// Groups the files below the target folders of `mappings` by contents.
+/// Files are only hashed when another file has the same size, and empty
+/// files are left out.
+pub fn find_in_targets(mappings: &MappingCache, keep: Keep) -> Vec<DuplicateGroup> {
+    let targets: BTreeSet<&Path> = mappings.target_dirs().collect();
+    let mut files = BTreeSet::new();
+    for dir in targets {
+        let entries = WalkDir::new(dir).into_iter().filter_map(|entry| entry.ok());
+        files.extend(
+            entries
+                .filter(|entry| entry.file_type().is_file() && !mover::is_partial(&entry.file_name().to_string_lossy()))
+                .map(|entry| entry.into_path()),
+        );
+    }
+
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for path in files {
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.len() > 0 => by_size.entry(metadata.len()).or_default().push(path),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to read {}: {}", path.display(), e),
+        }
+    }
+
+    let mut groups = Vec::new();
+    for (size, paths) in by_size.into_iter().filter(|(_, paths)| paths.len() > 1) {
+        let mut by_hash: HashMap<[u8; 32], Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            match mover::hash_file(&OsFileSystem, &path) {
+                Ok(hash) => by_hash.entry(hash).or_default().push(path),
+                Err(e) => tracing::warn!("Failed to hash {}: {}", path.display(), e),
+            }
+        }
+        for paths in by_hash.into_values().filter(|paths| paths.len() > 1) {
+            let mut files: Vec<DuplicateFile> = paths
+                .iter()
+                .map(|path| DuplicateFile {
+                    path: path.display().to_string(),
+                    modified: fs::metadata(path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                        .map(|age| age.as_secs() as i64),
+                })
+                .collect();
+            files.sort_by(|a, b| a.modified.cmp(&b.modified).then_with(|| a.path.cmp(&b.path)));
+            let keep = match keep {
+                Keep::Oldest => files.first(),
+                Keep::Newest => files.last(),
+            }
+            .map(|file| file.path.clone())
+            .unwrap_or_default();
+            let suggested_deletions = files.iter().map(|file| file.path.clone()).filter(|path| *path != keep).collect();
+            groups.push(DuplicateGroup {
+                size,
+                files,
+                keep,
+                suggested_deletions,
+            });
+        }
+    }
+    // Biggest savings first
+    groups.sort_by(|a, b| {
+        let saved = |group: &DuplicateGroup| group.size * group.suggested_deletions.len() as u64;
+        saved(b).cmp(&saved(a)).then_with(|| a.keep.cmp(&b.keep))
+    });
+    groups
+}
+
+/// Outcome of throwing duplicates away.
+#[derive(Serialize)]
+pub struct Cleanup {
+    pub discarded: Vec<Discarded>,
+    /// Files that couldn't be thrown away or changed since the search.
+    pub errors: Vec<SortError>,
+    pub session_id: i64,
+}
+
+/// Hands the files of `deletions` to `disposal` and records them in the
+/// history. A file is only thrown away while it is still identical, as `fs`
+/// sees it, to the file kept in its place, so nothing changed since the
+/// search is lost.
+pub fn delete(
+    conn: &Connection,
+    fs: &dyn FileSystem,
+    disposal: &dyn Disposal,
+    deletions: &[Deletion],
+) -> Result<Cleanup, Error> {
+    let started_at = chrono::Utc::now().timestamp();
+    let mut discarded = Vec::new();
+    let mut errors = Vec::new();
+    for deletion in deletions {
+        for path in &deletion.delete {
+            if *path == deletion.keep || !identical(fs, path, &deletion.keep) {
+                let message = Message::new("duplicates.changed")
+                    .arg("path", path.display())
+                    .arg("original", deletion.keep.display());
+                let mut error = SortError::new(FailedStep::RemoveSource, message);
+                error.path = Some(path.display().to_string());
+                errors.push(error);
+                continue;
+            }
+            match disposal.dispose(path) {
+                Ok(location) => discarded.push(Discarded {
+                    copy: path.display().to_string(),
+                    original: deletion.keep.display().to_string(),
+                    location: location.map(|location| location.display().to_string()),
+                }),
+                Err(e) => {
+                    let message = Message::new("sort.trash_failed").arg("path", path.display()).arg("detail", &e);
+                    errors.push(SortError::from_io(FailedStep::RemoveSource, path, &e, message));
+                }
+            }
+        }
+    }
+    let entries: Vec<history::Entry> = discarded
+        .iter()
+        .map(history::Entry::discarded)
+        .chain(errors.iter().map(history::Entry::failed))
+        .collect();
+    let session_id = history::record_session(conn, history::Trigger::Dedupe, started_at, &entries)?;
+    Ok(Cleanup {
+        discarded,
+        errors,
+        session_id,
+    })
+}
+
+/// Bits two image fingerprints may differ in for the images to count as
+/// similar, out of 64.
+pub const SIMILARITY_THRESHOLD: u32 = 10;
+
+/// An image in a cluster of similar ones.
+#[derive(Serialize)]
+pub struct SimilarImage {
+    pub path: String,
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Images that look alike, e.g. a photo and a resized or re-saved copy of
+/// it. Largest resolution first, as that is usually the one to keep.
+#[derive(Serialize)]
+pub struct SimilarImages {
+    pub images: Vec<SimilarImage>,
+}
+
+/// Difference hash of the image at `path`: the image shrunk to 9×8 grey
+/// pixels, one bit per pair of neighbours telling whether brightness goes
+/// up. Scaling, recompression and small edits flip few bits. `None` for
+/// files that aren't images in a format this build decodes.
+fn fingerprint(path: &Path) -> Option<(u64, u32, u32)> {
+    let format = ImageFormat::from_path(path).ok()?;
+    if !format.reading_enabled() {
+        return None;
+    }
+    let image = match image::open(path) {
+        Ok(image) => image,
+        Err(e) => {
+            tracing::warn!("Failed to decode {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let pixels = image.resize_exact(9, 8, FilterType::Triangle).into_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash = hash << 1 | (pixels.get_pixel(x + 1, y)[0] > pixels.get_pixel(x, y)[0]) as u64;
+        }
+    }
+    Some((hash, image.width(), image.height()))
+}
+
+/// The representative of the set `i` is in, for the union-find in
+/// `find_similar_images`.
+fn root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// Clusters the images below `dir` whose fingerprints differ in at most
+/// `max_distance` bits. Images that look like no other are left out.
+pub fn find_similar_images(dir: &Path, max_distance: u32) -> Vec<SimilarImages> {
+    let images: Vec<(u64, SimilarImage)> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let (hash, width, height) = fingerprint(entry.path())?;
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            Some((
+                hash,
+                SimilarImage {
+                    path: entry.path().display().to_string(),
+                    size,
+                    width,
+                    height,
+                },
+            ))
+        })
+        .collect();
+
+    // Union-find over every pair close enough
+    let mut parent: Vec<usize> = (0..images.len()).collect();
+    for i in 0..images.len() {
+        for j in i + 1..images.len() {
+            if (images[i].0 ^ images[j].0).count_ones() <= max_distance {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a] = b;
+            }
+        }
+    }
+
+    let mut clusters: HashMap<usize, Vec<SimilarImage>> = HashMap::new();
+    for (i, (_, image)) in images.into_iter().enumerate() {
+        clusters.entry(root(&mut parent, i)).or_default().push(image);
+    }
+    let mut clusters: Vec<SimilarImages> = clusters
+        .into_values()
+        .filter(|images| images.len() > 1)
+        .map(|mut images| {
+            images.sort_by(|a, b| {
+                (b.width as u64 * b.height as u64)
+                    .cmp(&(a.width as u64 * a.height as u64))
+                    .then_with(|| b.size.cmp(&a.size))
+                    .then_with(|| a.path.cmp(&b.path))
+            });
+            SimilarImages { images }
+        })
+        .collect();
+    clusters.sort_by(|a, b| b.images.len().cmp(&a.images.len()).then_with(|| a.images[0].path.cmp(&b.images[0].path)));
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::jpeg::JpegEncoder, DynamicImage, RgbImage};
+
+    fn save_jpeg(image: &DynamicImage, path: &Path, quality: u8) {
+        let mut out = fs::File::create(path).unwrap();
+        JpegEncoder::new_with_quality(&mut out, quality).encode_image(image).unwrap();
+    }
+
+    #[test]
+    fn clusters_a_resized_jpeg_with_its_original() {
+        let dir = std::env::temp_dir().join(format!("desksort-similar-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let photo = DynamicImage::ImageRgb8(RgbImage::from_fn(320, 240, |x, y| {
+            image::Rgb([(x * 255 / 320) as u8, (y * 255 / 240) as u8, ((x / 40 + y / 30) % 2 * 200) as u8])
+        }));
+        let other = DynamicImage::ImageRgb8(RgbImage::from_fn(320, 240, |x, y| {
+            let v = if (x / 20 + y / 60) % 2 == 0 { 240 } else { 10 };
+            image::Rgb([v, 255 - v, v])
+        }));
+        save_jpeg(&photo, &dir.join("photo.jpg"), 90);
+        save_jpeg(&photo.resize_exact(160, 120, FilterType::Triangle), &dir.join("photo small.jpg"), 60);
+        save_jpeg(&other, &dir.join("other.jpg"), 90);
+
+        let clusters = find_similar_images(&dir, SIMILARITY_THRESHOLD);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(clusters.len(), 1);
+        let paths: Vec<&str> = clusters[0].images.iter().map(|image| image.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [dir.join("photo.jpg").to_str().unwrap(), dir.join("photo small.jpg").to_str().unwrap()]
+        );
+        assert_eq!((clusters[0].images[0].width, clusters[0].images[1].width), (320, 160));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::Error;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{
+    fs,
+    io::Read,
+    path::Path,
+    sync::Mutex,
+};
+
+/// Passphrase of the open database, applied to every connection DeskSort
+/// opens (the main one and the move watcher's).
+static PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Every plaintext SQLite file starts with this; SQLCipher files look random.
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+#[derive(Serialize)]
+pub struct DatabaseStatus {
+    /// Whether this build links SQLCipher.
+    pub encryption_available: bool,
+    pub encrypted: bool,
+    /// Encrypted and waiting for the passphrase.
+    pub locked: bool,
+}
+
+pub fn is_available() -> bool {
+    cfg!(feature = "encryption")
+}
+
+/// Whether the file at `path` is an encrypted database. Missing and empty
+/// files are not.
+pub fn is_encrypted(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    match fs::File::open(path).and_then(|mut file| file.read_exact(&mut header)) {
+        Ok(()) => header != SQLITE_HEADER,
+        Err(_) => false,
+    }
+}
+
+pub fn set_passphrase(passphrase: Option<String>) {
+    *PASSPHRASE.lock().unwrap() = passphrase;
+}
+
+/// Keys `conn` with the current passphrase. Must run before anything else
+/// touches the connection.
+pub fn apply_key(conn: &Connection) -> Result<(), Error> {
+    match PASSPHRASE.lock().unwrap().as_deref() {
+        Some(passphrase) => key(conn, passphrase),
+        None => Ok(()),
+    }
+}
+
+/// Opens the encrypted database at `path` with `passphrase`, which becomes the
+/// current one if it is right.
+pub fn unlock(path: &Path, passphrase: &str) -> Result<Connection, Error> {
+    let conn = Connection::open(path)?;
+    key(&conn, passphrase)?;
+    // A wrong key only shows once the first page is read
+    if conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0)).is_err() {
+        return Err(Error::Encryption("wrong passphrase".to_string()));
+    }
+    set_passphrase(Some(passphrase.to_string()));
+    Ok(conn)
+}
+
+/// Copies the database behind `conn` to `target`, encrypted with `passphrase`
+/// or in plaintext when it is unset.
+pub fn export(conn: &Connection, target: &Path, passphrase: Option<&str>) -> Result<(), Error> {
+    let _ = fs::remove_file(target);
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    attach_export(conn, target, passphrase.unwrap_or(""))?;
+    conn.execute_batch(&format!(
+        "PRAGMA exported.user_version = {};
+         DETACH DATABASE exported;",
+        version
+    ))?;
+    Ok(())
+}
+
+/// Puts `replacement` in place of the database at `path`. The caller must
+/// have closed every connection to it.
+pub fn replace_file(replacement: &Path, path: &Path) -> Result<(), Error> {
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = path.as_os_str().to_os_string();
+        sidecar.push(suffix);
+        let _ = fs::remove_file(sidecar);
+    }
+    fs::rename(replacement, path)?;
+    Ok(())
+}
+
+#[cfg(feature = "encryption")]
+fn key(conn: &Connection, passphrase: &str) -> Result<(), Error> {
+    conn.pragma_update(None, "key", passphrase)?;
+    Ok(())
+}
+
+#[cfg(feature = "encryption")]
+fn attach_export(conn: &Connection, target: &Path, passphrase: &str) -> Result<(), Error> {
+    conn.execute(
+        "ATTACH DATABASE ?1 AS exported KEY ?2",
+        rusqlite::params![target.to_string_lossy(), passphrase],
+    )?;
+    conn.query_row("SELECT sqlcipher_export('exported')", [], |_| Ok(()))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "encryption"))]
+fn key(_: &Connection, _: &str) -> Result<(), Error> {
+    Err(unavailable())
+}
+
+#[cfg(not(feature = "encryption"))]
+fn attach_export(_: &Connection, _: &Path, _: &str) -> Result<(), Error> {
+    Err(unavailable())
+}
+
+#[cfg(not(feature = "encryption"))]
+fn unavailable() -> Error {
+    Error::Encryption("this build of DeskSort was made without SQLCipher".to_string())
+}
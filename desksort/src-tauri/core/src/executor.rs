@@ -0,0 +1,461 @@
+use crate::{
+    diagnostics::{FailedStep, FailureKind, SortError},
+    filesystem::FileSystem, folder_icons, messages::Message, mover, naming::CollisionNaming, nfc,
+    placeholders::{self, PlaceholderPolicy},
+    quota::QuotaTracker,
+    settings::Settings,
+    unique_target_path, Error, PlannedMove,
+};
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Minimum time between two file progress reports for the same item.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Receives progress from the executor's worker threads.
+pub trait ProgressSink: Sync {
+    /// Called before each item is processed; the counters cover the items
+    /// started before it.
+    fn item_started(&self, processed_items: usize, processed_bytes: u64, source: &Path);
+
+    /// Called while `file` (`source` itself, or a file inside it for folders) is
+    /// copied across volumes. Reports are rate limited, except the last one.
+    fn file_progress(&self, source: &Path, file: &Path, copied: u64, total: u64);
+}
+
+pub struct ExecutionOptions {
+    /// Number of items moved at the same time; 1 runs the plan sequentially.
+    pub workers: usize,
+    pub normalize_filenames: bool,
+    /// Bandwidth cap for copy fallbacks, set in low impact mode.
+    pub throttle: Option<mover::Throttle>,
+    /// Pause each worker takes after every item, set in low impact mode.
+    pub pause_between: Duration,
+    /// Decorate category folders created for the plan.
+    pub folder_icons: bool,
+    /// Leave moved files' download origin in place rather than stripping it.
+    pub keep_download_origin: bool,
+    /// How moved entries are renamed when their name is taken.
+    pub collision_naming: CollisionNaming,
+    /// Download online-only files before moving them.
+    pub hydrate_placeholders: bool,
+}
+
+impl ExecutionOptions {
+    /// In simulation mode, everything that would touch the disk beside the
+    /// file system is left out, and nothing is throttled as nothing is copied.
+    pub fn from_settings(settings: &Settings) -> Self {
+        let low_impact = settings.low_impact_mode && !settings.simulation_mode;
+        ExecutionOptions {
+            workers: if settings.parallel_moves {
+                settings.max_parallel_moves
+            } else {
+                1
+            },
+            normalize_filenames: settings.normalize_filenames,
+            throttle: low_impact.then(|| mover::Throttle::new(settings.low_impact_bytes_per_sec)),
+            pause_between: if low_impact {
+                Duration::from_millis(settings.low_impact_pause_ms)
+            } else {
+                Duration::ZERO
+            },
+            folder_icons: settings.folder_icons && !settings.simulation_mode,
+            keep_download_origin: settings.keep_download_origin || settings.simulation_mode,
+            collision_naming: settings.collision_naming,
+            hydrate_placeholders: settings.cloud_placeholders == PlaceholderPolicy::Hydrate
+                && !settings.simulation_mode,
+        }
+    }
+}
+
+/// Creates the target folder `dir` if it is missing, with its category icon
+/// when `folder_icons` is set. Returns whether it was created.
+pub fn create_target_dir(fs: &dyn FileSystem, dir: &Path, folder_icons: bool) -> io::Result<bool> {
+    let long_dir = &mover::long_path(dir);
+    let created = !fs.exists(long_dir);
+    if created {
+        fs.create_dir_all(long_dir)?;
+    }
+    // Cosmetic, so a failure doesn't hold up the move
+    if created && folder_icons {
+        if let Err(e) = folder_icons::decorate(dir) {
+            tracing::warn!("Failed to set the icon of {}: {}", dir.display(), e);
+        }
+    }
+    Ok(created)
+}
+
+/// Everything that happened while executing a plan, in completion order.
+#[derive(Default)]
+pub struct ExecutionReport {
+    /// (source, final path) of every item that was moved.
+    pub moved: Vec<(PathBuf, PathBuf)>,
+    /// Older files rolled into overflow folders to honor quotas.
+    pub rotated: Vec<(PathBuf, PathBuf)>,
+    /// (source, target folder) of items whose target share is offline.
+    pub queued: Vec<(PathBuf, PathBuf)>,
+    pub errors: Vec<SortError>,
+    /// Moves that went through but lost some attributes on the way.
+    pub warnings: Vec<String>,
+    /// Moves that failed, for the retry queue.
+    pub failed: Vec<FailedMove>,
+}
+
+pub struct FailedMove {
+    pub source: PathBuf,
+    pub target_dir: PathBuf,
+    pub kind: FailureKind,
+    pub message: String,
+}
+
+/// State shared by the workers while they prepare targets.
+#[derive(Default)]
+struct Targets {
+    quotas: QuotaTracker,
+    /// Final paths picked by moves that are still in flight.
+    reserved: HashSet<PathBuf>,
+}
+
+/// Runs `plan` on `options.workers` threads and collects the outcome.
+pub fn execute(
+    fs: &dyn FileSystem,
+    plan: Vec<PlannedMove>,
+    options: &ExecutionOptions,
+    sink: &dyn ProgressSink,
+) -> ExecutionReport {
+    let workers = options.workers.clamp(1, plan.len().max(1));
+    let queue = Mutex::new(plan.into_iter());
+    let started = Mutex::new((0usize, 0u64));
+    let targets = Mutex::new(Targets::default());
+    let report = Mutex::new(ExecutionReport::default());
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let Some(planned) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                {
+                    // Held while reporting so the counters arrive in order
+                    let mut started = started.lock().unwrap();
+                    sink.item_started(started.0, started.1, &planned.source);
+                    started.0 += 1;
+                    started.1 += planned.size;
+                }
+                execute_one(fs, planned, options, &targets, &report, sink);
+                if !options.pause_between.is_zero() {
+                    thread::sleep(options.pause_between);
+                }
+            });
+        }
+    });
+
+    report.into_inner().unwrap()
+}
+
+fn execute_one(
+    fs: &dyn FileSystem,
+    planned: PlannedMove,
+    options: &ExecutionOptions,
+    targets: &Mutex<Targets>,
+    report: &Mutex<ExecutionReport>,
+    sink: &dyn ProgressSink,
+) {
+    let PlannedMove { source, target_dir, quota, size, .. } = planned;
+    let _span = tracing::info_span!("move", source = %source.display()).entered();
+
+    // Park files headed to an unreachable share instead of failing each one
+    if mover::is_share_offline(&target_dir) {
+        tracing::debug!(target = %target_dir.display(), "target share offline, queued");
+        report.lock().unwrap().queued.push((source, target_dir));
+        return;
+    }
+
+    // Preparing the target is serialized, so parallel moves into the same
+    // folder agree on quotas and never pick the same name
+    let final_path = {
+        let mut targets = targets.lock().unwrap();
+
+        match create_target_dir(fs, &target_dir, options.folder_icons) {
+            Ok(_) => {}
+            Err(e) => {
+                let message = Message::new("sort.create_dir_failed")
+                    .arg("path", target_dir.display())
+                    .arg("detail", &e);
+                let mut report = report.lock().unwrap();
+                report.failed.push(FailedMove {
+                    kind: FailureKind::classify(&e),
+                    message: message.to_string(),
+                    source,
+                    target_dir: target_dir.clone(),
+                });
+                report
+                    .errors
+                    .push(SortError::from_io(FailedStep::CreateDir, &target_dir, &e, message));
+                return;
+            }
+        }
+
+        let made_room = targets.quotas.make_room(fs, &target_dir, &quota, &source, size, options.collision_naming);
+        record_rotation(report, &target_dir, made_room);
+
+        let file_name = source.file_name().unwrap();
+        let final_path = if options.normalize_filenames {
+            unique_target_path(
+                fs,
+                &target_dir,
+                nfc(&file_name.to_string_lossy()).as_ref(),
+                &targets.reserved,
+                options.collision_naming,
+            )
+        } else {
+            unique_target_path(fs, &target_dir, file_name, &targets.reserved, options.collision_naming)
+        };
+        targets.reserved.insert(final_path.clone());
+        final_path
+    };
+
+    let mut last_report: Option<Instant> = None;
+    let mut progress = |file: &Path, copied: u64, total: u64| {
+        if copied < total && last_report.is_some_and(|t| t.elapsed() < PROGRESS_INTERVAL) {
+            return;
+        }
+        last_report = Some(Instant::now());
+        sink.file_progress(&source, file, copied, total);
+    };
+    let hydrated = match options.hydrate_placeholders {
+        true => placeholders::hydrate(fs, &source),
+        false => Ok(()),
+    };
+    let outcome = hydrated
+        .and_then(|_| mover::move_path_with_retry(fs, &source, &final_path, &mut progress, options.throttle.as_ref()));
+
+    let mut targets = targets.lock().unwrap();
+    targets.reserved.remove(&final_path);
+    match outcome {
+        Ok(warning) => {
+            tracing::debug!(to = %final_path.display(), "moved");
+            if !options.keep_download_origin {
+                if let Err(e) = fs.strip_origin(&final_path) {
+                    tracing::warn!("Failed to strip the download origin of {}: {}", final_path.display(), e);
+                }
+            }
+            let recorded = targets.quotas.record(fs, &target_dir, &source, &final_path, options.collision_naming);
+            record_rotation(report, &target_dir, recorded);
+            let mut report = report.lock().unwrap();
+            report.warnings.extend(warning);
+            report.moved.push((source, final_path));
+        }
+        Err(e) => {
+            tracing::warn!(target = %target_dir.display(), error = %e, "move failed");
+            targets.quotas.release(&target_dir, &source);
+            let message = Message::new("sort.move_failed").arg("path", source.display()).arg("detail", &e);
+            let mut report = report.lock().unwrap();
+            report
+                .errors
+                .push(SortError::from_move(&source, &target_dir, &e, message.clone()));
+            report.failed.push(FailedMove {
+                source,
+                target_dir,
+                kind: FailureKind::classify(&e),
+                message: message.to_string(),
+            });
+        }
+    }
+}
+
+/// Adds what rotating `dir` into its overflow folder did to `report`.
+fn record_rotation(report: &Mutex<ExecutionReport>, dir: &Path, rotation: Result<Vec<(PathBuf, PathBuf)>, Error>) {
+    match rotation {
+        Ok(rotated) => report.lock().unwrap().rotated.extend(rotated),
+        Err(e) => {
+            let message = Message::new("sort.rotate_failed").arg("path", dir.display()).arg("detail", &e);
+            report.lock().unwrap().errors.push(match &e {
+                Error::Io(io) => SortError::from_io(FailedStep::Rotate, dir, io, message),
+                _ => SortError::new(FailedStep::Rotate, message),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filesystem::MemoryFileSystem, quota::Quota, rules::MatchSource};
+
+    struct NoProgress;
+
+    impl ProgressSink for NoProgress {
+        fn item_started(&self, _: usize, _: u64, _: &Path) {}
+        fn file_progress(&self, _: &Path, _: &Path, _: u64, _: u64) {}
+    }
+
+    fn options(workers: usize) -> ExecutionOptions {
+        ExecutionOptions {
+            workers,
+            normalize_filenames: false,
+            throttle: None,
+            pause_between: Duration::ZERO,
+            folder_icons: false,
+            keep_download_origin: true,
+            collision_naming: CollisionNaming::Counter,
+            hydrate_placeholders: false,
+        }
+    }
+
+    fn planned(source: &str, target_dir: &str, size: u64) -> PlannedMove {
+        PlannedMove {
+            source: PathBuf::from(source),
+            target_dir: PathBuf::from(target_dir),
+            quota: Quota::default(),
+            size,
+            decided_by: "test".to_string(),
+            matched: MatchSource::Mapping(".pdf".to_string()),
+        }
+    }
+
+    fn contents(fs: &MemoryFileSystem, path: &str) -> Option<String> {
+        fs.contents(Path::new(path)).map(|bytes| String::from_utf8(bytes).unwrap())
+    }
+
+    #[test]
+    fn renames_moves_whose_name_is_taken() {
+        let fs = MemoryFileSystem::new();
+        fs.add_file(Path::new("/sorted/Documents/report.pdf"), "old");
+        fs.add_file(Path::new("/desktop/report.pdf"), "desktop");
+        fs.add_file(Path::new("/downloads/report.pdf"), "downloads");
+        let plan = vec![
+            planned("/desktop/report.pdf", "/sorted/Documents", 7),
+            planned("/downloads/report.pdf", "/sorted/Documents", 9),
+        ];
+
+        let report = execute(&fs, plan, &options(2), &NoProgress);
+
+        assert!(report.errors.is_empty());
+        assert_eq!(report.moved.len(), 2);
+        assert_eq!(contents(&fs, "/sorted/Documents/report.pdf").as_deref(), Some("old"));
+        let mut renamed: Vec<_> = ["/sorted/Documents/report_1.pdf", "/sorted/Documents/report_2.pdf"]
+            .iter()
+            .filter_map(|path| contents(&fs, path))
+            .collect();
+        renamed.sort();
+        assert_eq!(renamed, ["desktop", "downloads"]);
+        assert!(!fs.exists(Path::new("/desktop/report.pdf")));
+        assert!(!fs.exists(Path::new("/downloads/report.pdf")));
+    }
+
+    #[test]
+    fn copies_moves_across_volumes() {
+        let fs = MemoryFileSystem::with_volumes([PathBuf::from("/desktop"), PathBuf::from("/archive")]);
+        fs.add_file(Path::new("/desktop/report.pdf"), "report");
+        fs.add_file(Path::new("/desktop/Project/notes.txt"), "notes");
+        let plan = vec![
+            planned("/desktop/report.pdf", "/archive/Documents", 6),
+            planned("/desktop/Project", "/archive/Folders", 5),
+        ];
+
+        let report = execute(&fs, plan, &options(1), &NoProgress);
+
+        assert!(report.errors.is_empty());
+        assert_eq!(contents(&fs, "/archive/Documents/report.pdf").as_deref(), Some("report"));
+        assert_eq!(contents(&fs, "/archive/Folders/Project/notes.txt").as_deref(), Some("notes"));
+        assert!(!fs.exists(Path::new("/desktop/report.pdf")));
+        assert!(!fs.exists(Path::new("/desktop/Project")));
+        assert!(fs.paths().iter().all(|path| !mover::is_partial(&path.to_string_lossy())));
+    }
+
+    #[test]
+    fn failed_copies_leave_the_source_in_place() {
+        let fs = MemoryFileSystem::with_volumes([PathBuf::from("/desktop"), PathBuf::from("/archive")]);
+        fs.add_file(Path::new("/desktop/report.pdf"), "a report too large to copy");
+        fs.fail_writes_after(Some(4));
+
+        let plan = vec![planned("/desktop/report.pdf", "/archive/Documents", 26)];
+        let report = execute(&fs, plan, &options(1), &NoProgress);
+
+        assert!(report.moved.is_empty());
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].source, PathBuf::from("/desktop/report.pdf"));
+        assert_eq!(contents(&fs, "/desktop/report.pdf").as_deref(), Some("a report too large to copy"));
+        assert!(!fs.exists(Path::new("/archive/Documents/report.pdf")));
+        assert!(fs.paths().iter().all(|path| !mover::is_partial(&path.to_string_lossy())));
+    }
+
+    #[test]
+    fn retries_replace_copies_left_by_a_crash() {
+        let fs = MemoryFileSystem::with_volumes([PathBuf::from("/desktop"), PathBuf::from("/archive")]);
+        fs.add_file(Path::new("/desktop/report.pdf"), "the whole report");
+        fs.add_file(Path::new("/archive/Documents/report.pdf.desksort.partial"), "the who");
+
+        let plan = vec![planned("/desktop/report.pdf", "/archive/Documents", 16)];
+        let report = execute(&fs, plan, &options(1), &NoProgress);
+
+        assert!(report.errors.is_empty());
+        assert_eq!(contents(&fs, "/archive/Documents/report.pdf").as_deref(), Some("the whole report"));
+        assert!(!fs.exists(Path::new("/archive/Documents/report.pdf.desksort.partial")));
+        assert!(!fs.exists(Path::new("/desktop/report.pdf")));
+    }
+
+    #[test]
+    fn parallel_moves_stay_within_quotas() {
+        let fs = MemoryFileSystem::new();
+        fs.add_file(Path::new("/sorted/Documents/old.pdf"), "old");
+        let quota = Quota {
+            max_files: Some(2),
+            max_bytes: None,
+        };
+        let plan: Vec<_> = (0..6)
+            .map(|i| {
+                let source = format!("/desktop/report-{}.pdf", i);
+                fs.add_file(Path::new(&source), "new");
+                PlannedMove {
+                    quota,
+                    ..planned(&source, "/sorted/Documents", 3)
+                }
+            })
+            .collect();
+
+        let report = execute(&fs, plan, &options(4), &NoProgress);
+
+        assert!(report.errors.is_empty());
+        let kept = fs.read_dir(Path::new("/sorted/Documents")).unwrap();
+        assert_eq!(kept.iter().filter(|entry| !entry.is_dir).count(), 2);
+        assert_eq!(report.rotated.len(), 5);
+    }
+
+    #[test]
+    fn failed_moves_give_back_their_quota_slot() {
+        let fs = MemoryFileSystem::with_volumes([PathBuf::from("/desktop"), PathBuf::from("/archive")]);
+        fs.add_file(Path::new("/archive/Documents/old.pdf"), "old");
+        fs.add_file(Path::new("/desktop/large.pdf"), "too large to copy");
+        fs.add_file(Path::new("/desktop/small.pdf"), "small");
+        fs.fail_writes_after(Some(8));
+        let quota = Quota {
+            max_files: Some(2),
+            max_bytes: None,
+        };
+        let plan = vec![
+            PlannedMove {
+                quota,
+                ..planned("/desktop/large.pdf", "/archive/Documents", 17)
+            },
+            PlannedMove {
+                quota,
+                ..planned("/desktop/small.pdf", "/archive/Documents", 5)
+            },
+        ];
+
+        let report = execute(&fs, plan, &options(1), &NoProgress);
+
+        assert_eq!(report.failed.len(), 1);
+        assert!(report.rotated.is_empty());
+        assert!(fs.exists(Path::new("/archive/Documents/old.pdf")));
+        assert!(fs.exists(Path::new("/archive/Documents/small.pdf")));
+    }
+}
@@ -0,0 +1,75 @@
+use serde::Serialize;
+
+/// Built-in groups a mapping or an extension condition can name instead of a
+/// single extension, e.g. `@images`. New formats are added here from release
+/// to release, so a mapping of `@images` picks up `.avif` without the user
+/// listing it. An extension's own mapping wins over its group's, and of two
+/// mapped groups the first here wins.
+const GROUPS: &[(&str, &[&str])] = &[
+    (
+        "@images",
+        &[
+            ".jpg", ".jpeg", ".png", ".gif", ".bmp", ".webp", ".tiff", ".tif", ".heic", ".heif", ".avif", ".jxl",
+            ".svg", ".ico",
+        ],
+    ),
+    ("@raw", &[".cr2", ".cr3", ".nef", ".arw", ".dng", ".orf", ".raf", ".rw2"]),
+    (
+        "@video",
+        &[".mp4", ".mkv", ".avi", ".mov", ".webm", ".flv", ".wmv", ".m4v", ".mpg", ".mpeg", ".3gp"],
+    ),
+    ("@audio", &[".mp3", ".wav", ".aac", ".ogg", ".flac", ".m4a", ".opus", ".wma", ".aiff"]),
+    (
+        "@office",
+        &[
+            ".doc", ".docx", ".xls", ".xlsx", ".ppt", ".pptx", ".odt", ".ods", ".odp", ".rtf", ".pages", ".numbers",
+            ".key",
+        ],
+    ),
+    ("@ebooks", &[".epub", ".mobi", ".azw3", ".fb2"]),
+    (
+        "@archives",
+        &[".zip", ".rar", ".7z", ".tar", ".gz", ".tar.gz", ".bz2", ".xz", ".tar.xz", ".zst"],
+    ),
+    ("@fonts", &[".ttf", ".otf", ".woff", ".woff2"]),
+];
+
+/// A group as the settings window lists it.
+#[derive(Serialize)]
+pub struct ExtensionGroup {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+}
+
+pub fn all() -> Vec<ExtensionGroup> {
+    GROUPS
+        .iter()
+        .map(|(name, extensions)| ExtensionGroup { name, extensions })
+        .collect()
+}
+
+/// Whether `key` names a group, known or not.
+pub fn is_group(key: &str) -> bool {
+    key.starts_with('@')
+}
+
+/// The extensions of the group `name`, e.g. `@images`.
+pub fn extensions(name: &str) -> Option<&'static [&'static str]> {
+    GROUPS
+        .iter()
+        .find(|(group, _)| group.eq_ignore_ascii_case(name))
+        .map(|(_, extensions)| *extensions)
+}
+
+/// The groups holding the mapping key `key`, in order of precedence.
+pub fn groups_of(key: &str) -> impl Iterator<Item = &'static str> + '_ {
+    GROUPS
+        .iter()
+        .filter(move |(_, extensions)| extensions.contains(&key))
+        .map(|(name, _)| *name)
+}
+
+/// Whether a mapping keyed `mapping_key` covers entries keyed `key`.
+pub fn covers(mapping_key: &str, key: &str) -> bool {
+    mapping_key == key || extensions(mapping_key).is_some_and(|extensions| extensions.contains(&key))
+}
@@ -0,0 +1,966 @@
+use crate::{attributes, origin, placeholders};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// An entry directly inside a folder. `is_dir` doesn't follow symlinks, so a
+/// link to a folder is treated like a file.
+pub struct DirEntry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+pub struct Metadata {
+    pub is_dir: bool,
+    /// Size in bytes; 0 for folders.
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// The file operations the scanner and the mover are built on, so a sort can
+/// run against the real disk, a [`SimulatedFileSystem`] over it, or a
+/// [`MemoryFileSystem`].
+pub trait FileSystem: Send + Sync {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<DirEntry>>;
+
+    /// Follows symlinks, like `fs::metadata`.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Describes a symlink itself rather than what it points to, like
+    /// `fs::symlink_metadata`, on file systems that have them.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.metadata(path)
+    }
+
+    /// Fails with `CrossesDevices` when `from` and `to` are on different volumes.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>>;
+
+    /// Creates or truncates the file at `path`. Flushing the writer makes what
+    /// was written durable.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + '_>>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Gives the copy `to` what `from` carries besides its contents. Returns
+    /// what couldn't be kept, one message each.
+    fn copy_attributes(&self, from: &Path, to: &Path) -> io::Result<Vec<String>>;
+
+    fn is_case_sensitive(&self, dir: &Path) -> bool;
+
+    /// The domain the file at `path` was downloaded from, if that was
+    /// recorded.
+    fn source_domain(&self, path: &Path) -> Option<String>;
+
+    /// Whether `path` is an online-only file of a sync client.
+    fn is_placeholder(&self, path: &Path) -> bool;
+
+    /// Forgets where `path`, or everything inside it, was downloaded from.
+    fn strip_origin(&self, path: &Path) -> io::Result<()>;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|m| m.is_dir)
+    }
+}
+
+/// The disk, through `std::fs`.
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            entries.push(DirEntry {
+                is_dir: entry.file_type()?.is_dir(),
+                path: entry.path(),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(Metadata::from(fs::metadata(path)?))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(Metadata::from(fs::symlink_metadata(path)?))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + '_>> {
+        Ok(Box::new(SyncedFile(fs::File::create(path)?)))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn copy_attributes(&self, from: &Path, to: &Path) -> io::Result<Vec<String>> {
+        let metadata = fs::metadata(from)?;
+        if metadata.is_file() {
+            if let Err(e) = origin::copy(from, to) {
+                tracing::warn!("Failed to copy the download origin of {}: {}", from.display(), e);
+            }
+            fs::set_permissions(to, metadata.permissions())?;
+        }
+        // After the contents, so an ACL denying writes doesn't get in the way
+        let mut lost: Vec<String> = attributes::copy(from, to).into_iter().collect();
+        // Last, since everything before touches a folder's modification time
+        if let Err(e) = copy_times(from, to) {
+            lost.push(format!("{}: couldn't keep its timestamps: {}", to.display(), e));
+        }
+        Ok(lost)
+    }
+
+    fn is_case_sensitive(&self, dir: &Path) -> bool {
+        crate::mover::is_case_sensitive(dir)
+    }
+
+    fn source_domain(&self, path: &Path) -> Option<String> {
+        origin::source_domain(path)
+    }
+
+    fn is_placeholder(&self, path: &Path) -> bool {
+        placeholders::is_placeholder(path)
+    }
+
+    fn strip_origin(&self, path: &Path) -> io::Result<()> {
+        origin::strip(path)
+    }
+}
+
+impl From<fs::Metadata> for Metadata {
+    fn from(metadata: fs::Metadata) -> Self {
+        Metadata {
+            is_dir: metadata.is_dir(),
+            len: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().ok(),
+        }
+    }
+}
+
+/// A file that is synced to disk when flushed.
+struct SyncedFile(fs::File);
+
+impl Write for SyncedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.0.sync_all()
+    }
+}
+
+/// Gives `to` the modification and access time of `from`, plus its creation
+/// time on Windows and macOS; Linux has no way to set one.
+fn copy_times(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(from)?;
+    let mut times = fs::FileTimes::new();
+    if let Ok(modified) = metadata.modified() {
+        times = times.set_modified(modified);
+    }
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    #[cfg(windows)]
+    if let Ok(created) = metadata.created() {
+        use std::os::windows::fs::FileTimesExt;
+        times = times.set_created(created);
+    }
+    #[cfg(target_os = "macos")]
+    if let Ok(created) = metadata.created() {
+        use std::os::macos::fs::FileTimesExt;
+        times = times.set_created(created);
+    }
+    open_for_times(to)?.set_times(times)
+}
+
+/// Opens `path`, file or folder, so its timestamps can be set: that takes
+/// FILE_WRITE_ATTRIBUTES, and folders only open with FILE_FLAG_BACKUP_SEMANTICS.
+#[cfg(windows)]
+fn open_for_times(path: &Path) -> io::Result<fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .access_mode(0x100)
+        .custom_flags(0x0200_0000)
+        .open(path)
+}
+
+#[cfg(not(windows))]
+fn open_for_times(path: &Path) -> io::Result<fs::File> {
+    fs::File::open(path)
+}
+
+enum Node {
+    Dir { modified: SystemTime },
+    File { contents: Vec<u8>, modified: SystemTime },
+}
+
+/// A filesystem held in memory, for running sorts deterministically. Paths
+/// below different volume roots behave like separate drives: renames between
+/// them fail with `CrossesDevices`, so moves take the copy fallback. Names are
+/// case-sensitive.
+#[derive(Default)]
+pub struct MemoryFileSystem {
+    nodes: Mutex<BTreeMap<PathBuf, Node>>,
+    volumes: Vec<PathBuf>,
+    /// Writes fail once a file holds this many bytes, as if the app died
+    /// halfway through a copy.
+    fail_writes_after: Mutex<Option<u64>>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A filesystem where each of `roots` is a volume of its own.
+    pub fn with_volumes(roots: impl IntoIterator<Item = PathBuf>) -> Self {
+        MemoryFileSystem {
+            volumes: roots.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Creates a file holding `contents`, and any folders above it.
+    pub fn add_file(&self, path: &Path, contents: impl Into<Vec<u8>>) {
+        let mut nodes = self.nodes.lock().unwrap();
+        add_ancestors(&mut nodes, path);
+        nodes.insert(
+            path.to_path_buf(),
+            Node::File {
+                contents: contents.into(),
+                modified: SystemTime::now(),
+            },
+        );
+    }
+
+    pub fn add_dir(&self, path: &Path) {
+        let mut nodes = self.nodes.lock().unwrap();
+        add_ancestors(&mut nodes, path);
+        nodes.insert(path.to_path_buf(), Node::Dir { modified: SystemTime::now() });
+    }
+
+    /// The contents of the file at `path`, if there is one.
+    pub fn contents(&self, path: &Path) -> Option<Vec<u8>> {
+        match self.nodes.lock().unwrap().get(path)? {
+            Node::File { contents, .. } => Some(contents.clone()),
+            Node::Dir { .. } => None,
+        }
+    }
+
+    /// Every file and folder, in path order.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.nodes.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Makes writes fail once a file holds `bytes` bytes, or lets them
+    /// through again with `None`.
+    pub fn fail_writes_after(&self, bytes: Option<u64>) {
+        *self.fail_writes_after.lock().unwrap() = bytes;
+    }
+
+    fn volume(&self, path: &Path) -> Option<&Path> {
+        self.volumes
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.as_os_str().len())
+            .map(PathBuf::as_path)
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} doesn't exist", path.display()))
+}
+
+fn add_ancestors(nodes: &mut BTreeMap<PathBuf, Node>, path: &Path) {
+    for dir in path.ancestors().skip(1).filter(|dir| !dir.as_os_str().is_empty()) {
+        nodes
+            .entry(dir.to_path_buf())
+            .or_insert(Node::Dir { modified: SystemTime::now() });
+    }
+}
+
+/// Checks that the folder `path` is to be created in exists.
+fn check_parent(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> io::Result<()> {
+    match path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        Some(parent) => match nodes.get(parent) {
+            Some(Node::Dir { .. }) => Ok(()),
+            Some(Node::File { .. }) => Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("{} is a file", parent.display()),
+            )),
+            None => Err(not_found(parent)),
+        },
+        None => Ok(()),
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<DirEntry>> {
+        let nodes = self.nodes.lock().unwrap();
+        match nodes.get(dir) {
+            Some(Node::Dir { .. }) => {}
+            Some(Node::File { .. }) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotADirectory,
+                    format!("{} is a file", dir.display()),
+                ))
+            }
+            None => return Err(not_found(dir)),
+        }
+        Ok(nodes
+            .iter()
+            .filter(|(path, _)| path.parent() == Some(dir))
+            .map(|(path, node)| DirEntry {
+                path: path.clone(),
+                is_dir: matches!(node, Node::Dir { .. }),
+            })
+            .collect())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self.nodes.lock().unwrap().get(path) {
+            Some(Node::Dir { modified }) => Ok(Metadata {
+                is_dir: true,
+                len: 0,
+                modified: Some(*modified),
+            }),
+            Some(Node::File { contents, modified }) => Ok(Metadata {
+                is_dir: false,
+                len: contents.len() as u64,
+                modified: Some(*modified),
+            }),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if self.volume(from) != self.volume(to) {
+            return Err(io::Error::new(
+                io::ErrorKind::CrossesDevices,
+                format!("{} and {} are on different volumes", from.display(), to.display()),
+            ));
+        }
+        let mut nodes = self.nodes.lock().unwrap();
+        if !nodes.contains_key(from) {
+            return Err(not_found(from));
+        }
+        check_parent(&nodes, to)?;
+        match nodes.get(to) {
+            Some(Node::Dir { .. }) if nodes.keys().any(|path| path.parent() == Some(to)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::DirectoryNotEmpty,
+                    format!("{} isn't empty", to.display()),
+                ))
+            }
+            _ => {}
+        }
+
+        let moved: Vec<PathBuf> = nodes.keys().filter(|path| path.starts_with(from)).cloned().collect();
+        for path in moved {
+            let node = nodes.remove(&path).unwrap();
+            let relative = path.strip_prefix(from).unwrap();
+            let path = if relative.as_os_str().is_empty() {
+                to.to_path_buf()
+            } else {
+                to.join(relative)
+            };
+            nodes.insert(path, node);
+        }
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        for dir in path.ancestors().filter(|dir| !dir.as_os_str().is_empty()) {
+            if let Some(Node::File { .. }) = nodes.get(dir) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a file", dir.display()),
+                ));
+            }
+        }
+        add_ancestors(&mut nodes, path);
+        nodes
+            .entry(path.to_path_buf())
+            .or_insert(Node::Dir { modified: SystemTime::now() });
+        Ok(())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        match self.nodes.lock().unwrap().get(path) {
+            Some(Node::File { contents, .. }) => Ok(Box::new(io::Cursor::new(contents.clone()))),
+            Some(Node::Dir { .. }) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{} is a folder", path.display()),
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + '_>> {
+        let mut nodes = self.nodes.lock().unwrap();
+        check_parent(&nodes, path)?;
+        if let Some(Node::Dir { .. }) = nodes.get(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{} is a folder", path.display()),
+            ));
+        }
+        nodes.insert(
+            path.to_path_buf(),
+            Node::File {
+                contents: Vec::new(),
+                modified: SystemTime::now(),
+            },
+        );
+        Ok(Box::new(MemoryFile {
+            fs: self,
+            path: path.to_path_buf(),
+        }))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        match nodes.get(path) {
+            Some(Node::File { .. }) => {
+                nodes.remove(path);
+                Ok(())
+            }
+            Some(Node::Dir { .. }) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{} is a folder", path.display()),
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.lock().unwrap();
+        if !nodes.contains_key(path) {
+            return Err(not_found(path));
+        }
+        nodes.retain(|node, _| !node.starts_with(path));
+        Ok(())
+    }
+
+    fn copy_attributes(&self, from: &Path, to: &Path) -> io::Result<Vec<String>> {
+        let modified = self.metadata(from)?.modified.unwrap_or_else(SystemTime::now);
+        match self.nodes.lock().unwrap().get_mut(to) {
+            Some(Node::Dir { modified: to_modified } | Node::File { modified: to_modified, .. }) => {
+                *to_modified = modified;
+                Ok(Vec::new())
+            }
+            None => Err(not_found(to)),
+        }
+    }
+
+    fn is_case_sensitive(&self, _dir: &Path) -> bool {
+        true
+    }
+
+    fn source_domain(&self, _path: &Path) -> Option<String> {
+        None
+    }
+
+    fn is_placeholder(&self, _path: &Path) -> bool {
+        false
+    }
+
+    fn strip_origin(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A file of a [`MemoryFileSystem`] being written.
+struct MemoryFile<'a> {
+    fs: &'a MemoryFileSystem,
+    path: PathBuf,
+}
+
+impl Write for MemoryFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let limit = *self.fs.fail_writes_after.lock().unwrap();
+        match self.fs.nodes.lock().unwrap().get_mut(&self.path) {
+            Some(Node::File { contents, modified }) => {
+                if limit.is_some_and(|limit| contents.len() as u64 + buf.len() as u64 > limit) {
+                    return Err(io::Error::other(format!(
+                        "simulated failure writing {}",
+                        self.path.display()
+                    )));
+                }
+                contents.extend_from_slice(buf);
+                *modified = SystemTime::now();
+                Ok(buf.len())
+            }
+            _ => Err(not_found(&self.path)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Where a path of a [`SimulatedFileSystem`] is.
+enum Simulated {
+    /// On the disk, at this path.
+    Disk(PathBuf),
+    CreatedDir,
+    /// Created by the simulation, and always empty.
+    CreatedFile,
+}
+
+/// What a simulation changed, by the path as it would be seen afterwards.
+/// A key stands for its whole subtree; the deepest key above a path decides
+/// where it is.
+#[derive(Default)]
+struct Overlay {
+    /// Moved here from the disk path.
+    moved: BTreeMap<PathBuf, PathBuf>,
+    /// Created here, a folder when `true`.
+    created: BTreeMap<PathBuf, bool>,
+    /// Moved away or removed.
+    removed: BTreeSet<PathBuf>,
+}
+
+impl Overlay {
+    fn resolve(&self, path: &Path) -> Option<Simulated> {
+        for ancestor in path.ancestors() {
+            let rest = path.strip_prefix(ancestor).unwrap();
+            if self.removed.contains(ancestor) {
+                return None;
+            }
+            if let Some(source) = self.moved.get(ancestor) {
+                // Joining an empty `rest` would add a trailing separator,
+                // which only folders resolve with
+                return Some(Simulated::Disk(match rest.as_os_str().is_empty() {
+                    true => source.clone(),
+                    false => source.join(rest),
+                }));
+            }
+            if let Some(&is_dir) = self.created.get(ancestor) {
+                return match (rest.as_os_str().is_empty(), is_dir) {
+                    (true, true) => Some(Simulated::CreatedDir),
+                    (true, false) => Some(Simulated::CreatedFile),
+                    (false, _) => None,
+                };
+            }
+        }
+        Some(Simulated::Disk(path.to_path_buf()))
+    }
+
+    /// Forgets everything recorded at and below `path`.
+    fn clear(&mut self, path: &Path) {
+        self.moved.retain(|key, _| !key.starts_with(path));
+        self.created.retain(|key, _| !key.starts_with(path));
+        self.removed.retain(|key| !key.starts_with(path));
+    }
+
+    /// Paths recorded directly inside `dir`, and whether they are folders.
+    fn children(&self, dir: &Path) -> Vec<DirEntry> {
+        let moved = self
+            .moved
+            .iter()
+            .map(|(path, source)| (path, fs::symlink_metadata(source).is_ok_and(|m| m.is_dir())));
+        let created = self.created.iter().map(|(path, &is_dir)| (path, is_dir));
+        moved
+            .chain(created)
+            .filter(|(path, _)| path.parent() == Some(dir))
+            .map(|(path, is_dir)| DirEntry { path: path.clone(), is_dir })
+            .collect()
+    }
+}
+
+/// `path` without the `\\?\` prefix `mover::long_path` adds, so the overlay
+/// finds a path in either form.
+fn plain(path: &Path) -> PathBuf {
+    if cfg!(windows) {
+        if let Some(text) = path.to_str() {
+            if let Some(rest) = text.strip_prefix(r"\\?\UNC\") {
+                return PathBuf::from(format!(r"\\{}", rest));
+            }
+            if let Some(rest) = text.strip_prefix(r"\\?\") {
+                return PathBuf::from(rest);
+            }
+        }
+    }
+    path.to_path_buf()
+}
+
+/// The disk as a sort would leave it, without changing anything: reads go
+/// to the disk, while moves, new folders and removals are only recorded and
+/// show in later reads. Renames never cross volumes, so moves don't copy.
+/// Sorts run on it in simulation mode.
+#[derive(Default)]
+pub struct SimulatedFileSystem {
+    overlay: Mutex<Overlay>,
+}
+
+impl SimulatedFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Where the entry a sort would leave at `path` is on the disk now, if
+    /// it is one that was there before.
+    fn on_disk(&self, path: &Path) -> Option<PathBuf> {
+        match self.overlay.lock().unwrap().resolve(&plain(path)) {
+            Some(Simulated::Disk(disk)) => Some(disk),
+            _ => None,
+        }
+    }
+}
+
+impl FileSystem for SimulatedFileSystem {
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<DirEntry>> {
+        let dir = plain(dir);
+        let overlay = self.overlay.lock().unwrap();
+        let mut entries = match overlay.resolve(&dir) {
+            Some(Simulated::Disk(disk)) => OsFileSystem
+                .read_dir(&disk)?
+                .into_iter()
+                .map(|entry| DirEntry {
+                    path: dir.join(entry.path.file_name().unwrap_or_default()),
+                    is_dir: entry.is_dir,
+                })
+                .filter(|entry| matches!(overlay.resolve(&entry.path), Some(Simulated::Disk(_))))
+                .collect(),
+            Some(Simulated::CreatedDir) => Vec::new(),
+            Some(Simulated::CreatedFile) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotADirectory,
+                    format!("{} is a file", dir.display()),
+                ))
+            }
+            None => return Err(not_found(&dir)),
+        };
+        for child in overlay.children(&dir) {
+            if !entries.iter().any(|entry| entry.path == child.path) {
+                entries.push(child);
+            }
+        }
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let resolved = self.overlay.lock().unwrap().resolve(&plain(path));
+        match resolved {
+            Some(Simulated::Disk(disk)) => OsFileSystem.metadata(&disk),
+            Some(Simulated::CreatedDir) => Ok(Metadata {
+                is_dir: true,
+                len: 0,
+                modified: Some(SystemTime::now()),
+            }),
+            Some(Simulated::CreatedFile) => Ok(Metadata {
+                is_dir: false,
+                len: 0,
+                modified: Some(SystemTime::now()),
+            }),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self.on_disk(path) {
+            Some(disk) => OsFileSystem.symlink_metadata(&disk),
+            None => self.metadata(path),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (plain(from), plain(to));
+        if !self.exists(&from) {
+            return Err(not_found(&from));
+        }
+        if to.parent().is_some_and(|parent| !self.is_dir(parent)) {
+            return Err(not_found(to.parent().unwrap()));
+        }
+        let mut overlay = self.overlay.lock().unwrap();
+        let resolved = overlay.resolve(&from);
+        // What was recorded below `from` moves along with it
+        let below_to = |path: &Path| {
+            let rest = path.strip_prefix(&from).ok().filter(|rest| !rest.as_os_str().is_empty())?;
+            Some(to.join(rest))
+        };
+        let below = Overlay {
+            moved: overlay.moved.iter().filter_map(|(path, source)| Some((below_to(path)?, source.clone()))).collect(),
+            created: overlay.created.iter().filter_map(|(path, &is_dir)| Some((below_to(path)?, is_dir))).collect(),
+            removed: overlay.removed.iter().filter_map(|path| below_to(path)).collect(),
+        };
+        overlay.clear(&from);
+        overlay.clear(&to);
+        match resolved {
+            Some(Simulated::Disk(disk)) => {
+                overlay.moved.insert(to.clone(), disk);
+            }
+            Some(Simulated::CreatedDir) => {
+                overlay.created.insert(to.clone(), true);
+            }
+            Some(Simulated::CreatedFile) => {
+                overlay.created.insert(to.clone(), false);
+            }
+            None => {}
+        }
+        overlay.moved.extend(below.moved);
+        overlay.created.extend(below.created);
+        overlay.removed.extend(below.removed);
+        overlay.removed.insert(from);
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let path = plain(path);
+        let mut overlay = self.overlay.lock().unwrap();
+        let missing: Vec<PathBuf> = path
+            .ancestors()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .collect();
+        for dir in missing.into_iter().rev() {
+            let is_dir = match overlay.resolve(&dir) {
+                Some(Simulated::Disk(disk)) => match fs::metadata(&disk) {
+                    Ok(metadata) => metadata.is_dir(),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        overlay.clear(&dir);
+                        overlay.created.insert(dir, true);
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                },
+                Some(Simulated::CreatedDir) => true,
+                Some(Simulated::CreatedFile) => false,
+                None => {
+                    overlay.clear(&dir);
+                    overlay.created.insert(dir, true);
+                    continue;
+                }
+            };
+            if !is_dir {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a file", dir.display()),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        let resolved = self.overlay.lock().unwrap().resolve(&plain(path));
+        match resolved {
+            Some(Simulated::Disk(disk)) => Ok(Box::new(fs::File::open(disk)?)),
+            Some(Simulated::CreatedFile) => Ok(Box::new(io::empty())),
+            Some(Simulated::CreatedDir) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{} is a folder", path.display()),
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + '_>> {
+        let path = plain(path);
+        if path.parent().is_some_and(|parent| !self.is_dir(parent)) {
+            return Err(not_found(path.parent().unwrap()));
+        }
+        let mut overlay = self.overlay.lock().unwrap();
+        overlay.clear(&path);
+        overlay.created.insert(path, false);
+        Ok(Box::new(io::sink()))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.remove_dir_all(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let path = plain(path);
+        if !self.exists(&path) {
+            return Err(not_found(&path));
+        }
+        let mut overlay = self.overlay.lock().unwrap();
+        overlay.clear(&path);
+        overlay.removed.insert(path);
+        Ok(())
+    }
+
+    fn copy_attributes(&self, _from: &Path, _to: &Path) -> io::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn is_case_sensitive(&self, dir: &Path) -> bool {
+        let resolved = self.overlay.lock().unwrap().resolve(&plain(dir));
+        match resolved {
+            Some(Simulated::Disk(disk)) if disk.exists() => OsFileSystem.is_case_sensitive(&disk),
+            _ => dir.parent().is_some_and(|parent| self.is_case_sensitive(parent)),
+        }
+    }
+
+    fn source_domain(&self, path: &Path) -> Option<String> {
+        OsFileSystem.source_domain(&self.on_disk(path)?)
+    }
+
+    fn is_placeholder(&self, path: &Path) -> bool {
+        self.on_disk(path).is_some_and(|disk| OsFileSystem.is_placeholder(&disk))
+    }
+
+    fn strip_origin(&self, path: &Path) -> io::Result<()> {
+        match self.exists(path) {
+            true => Ok(()),
+            false => Err(not_found(path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A folder of its own below the temp dir holding `a.txt` and
+    /// `docs/b.txt`, removed when dropped.
+    struct Disk(PathBuf);
+
+    impl Disk {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("desksort-{}-{}", name, std::process::id()));
+            fs::create_dir_all(root.join("docs")).unwrap();
+            fs::write(root.join("a.txt"), "alpha").unwrap();
+            fs::write(root.join("docs").join("b.txt"), "beta").unwrap();
+            Disk(root)
+        }
+
+        /// Every path below the folder and what the files hold.
+        fn snapshot(&self) -> Vec<(PathBuf, Option<Vec<u8>>)> {
+            let mut paths: Vec<(PathBuf, Option<Vec<u8>>)> = walkdir::WalkDir::new(&self.0)
+                .into_iter()
+                .map(|entry| entry.unwrap().into_path())
+                .map(|path| {
+                    let contents = fs::read(&path).ok();
+                    (path, contents)
+                })
+                .collect();
+            paths.sort();
+            paths
+        }
+    }
+
+    impl Drop for Disk {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn names(fs: &dyn FileSystem, dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs
+            .read_dir(dir)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn renames_show_in_later_reads_only() {
+        let disk = Disk::new("simulated-rename");
+        let before = disk.snapshot();
+        let simulated = SimulatedFileSystem::new();
+
+        simulated.rename(&disk.0.join("a.txt"), &disk.0.join("docs").join("a.txt")).unwrap();
+
+        assert_eq!(names(&simulated, &disk.0), ["docs"]);
+        assert_eq!(names(&simulated, &disk.0.join("docs")), ["a.txt", "b.txt"]);
+        assert!(!simulated.exists(&disk.0.join("a.txt")));
+        let moved = simulated.metadata(&disk.0.join("docs").join("a.txt")).unwrap();
+        assert!(!moved.is_dir);
+        assert_eq!(moved.len, 5);
+        let mut contents = String::new();
+        simulated.open(&disk.0.join("docs").join("a.txt")).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "alpha");
+        assert_eq!(disk.snapshot(), before);
+    }
+
+    #[test]
+    fn renamed_folders_take_their_contents_along() {
+        let disk = Disk::new("simulated-folder");
+        let before = disk.snapshot();
+        let simulated = SimulatedFileSystem::new();
+
+        simulated.rename(&disk.0.join("docs"), &disk.0.join("papers")).unwrap();
+
+        assert_eq!(names(&simulated, &disk.0), ["a.txt", "papers"]);
+        assert!(simulated.is_dir(&disk.0.join("papers")));
+        assert_eq!(simulated.metadata(&disk.0.join("papers").join("b.txt")).unwrap().len, 4);
+        assert!(simulated.read_dir(&disk.0.join("docs")).is_err());
+        assert_eq!(disk.snapshot(), before);
+    }
+
+    #[test]
+    fn removed_entries_are_gone() {
+        let disk = Disk::new("simulated-remove");
+        let before = disk.snapshot();
+        let simulated = SimulatedFileSystem::new();
+
+        simulated.remove_file(&disk.0.join("a.txt")).unwrap();
+        simulated.remove_dir_all(&disk.0.join("docs")).unwrap();
+
+        assert!(!simulated.exists(&disk.0.join("a.txt")));
+        assert!(!simulated.exists(&disk.0.join("docs").join("b.txt")));
+        assert!(names(&simulated, &disk.0).is_empty());
+        assert!(simulated.remove_file(&disk.0.join("a.txt")).is_err());
+        assert_eq!(disk.snapshot(), before);
+    }
+
+    #[test]
+    fn creates_folders_and_files_in_the_overlay() {
+        let disk = Disk::new("simulated-create");
+        let before = disk.snapshot();
+        let simulated = SimulatedFileSystem::new();
+        let nested = disk.0.join("new").join("nested");
+
+        simulated.create_dir_all(&nested).unwrap();
+        simulated.create(&nested.join("c.txt")).unwrap().write_all(b"gamma").unwrap();
+
+        assert!(simulated.is_dir(&disk.0.join("new")));
+        assert_eq!(names(&simulated, &disk.0), ["a.txt", "docs", "new"]);
+        assert_eq!(names(&simulated, &nested), ["c.txt"]);
+        assert!(simulated.exists(&nested.join("c.txt")));
+        assert!(simulated.create_dir_all(&disk.0.join("a.txt").join("below")).is_err());
+        assert_eq!(disk.snapshot(), before);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::{filesystem::FileSystem, key_for};
+use glob::{MatchOptions, Pattern};
+use std::{collections::HashMap, path::Path};
+
+/// Files counted before a folder's makeup is taken as known, so a folder
+/// holding a whole photo library doesn't stall the sort.
+const MAX_FILES: u64 = 10_000;
+
+const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// What a folder holds, for rules on folders.
+#[derive(Default)]
+pub struct FolderContents {
+    /// Names of the entries directly inside.
+    children: Vec<String>,
+    /// Files below the folder by mapping key, up to `MAX_FILES` in all.
+    /// Hidden folders like `.git` aren't looked into.
+    files_by_key: HashMap<String, u64>,
+    files: u64,
+}
+
+/// Looks through `dir` on `fs`. Folders that can't be read count as empty.
+pub fn inspect(fs: &dyn FileSystem, dir: &Path) -> FolderContents {
+    let mut contents = FolderContents::default();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(folder) = pending.pop() {
+        let Ok(entries) = fs.read_dir(&folder) else {
+            continue;
+        };
+        for entry in entries {
+            let name = entry.path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            if folder == dir {
+                contents.children.push(name.clone());
+            }
+            if entry.is_dir {
+                if !name.starts_with('.') {
+                    pending.push(entry.path);
+                }
+            } else if contents.files < MAX_FILES {
+                *contents.files_by_key.entry(key_for(&entry.path, false)).or_default() += 1;
+                contents.files += 1;
+            }
+        }
+    }
+    contents
+}
+
+impl FolderContents {
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Whether an entry directly inside is named like `pattern`.
+    pub fn contains(&self, pattern: &Pattern) -> bool {
+        self.children.iter().any(|name| pattern.matches_with(name, GLOB_OPTIONS))
+    }
+
+    /// Share of the files below that have one of the mapping keys `keys`,
+    /// from 0 to 1; 0 without any files.
+    pub fn share_of(&self, keys: &[String]) -> f64 {
+        if self.files == 0 {
+            return 0.0;
+        }
+        let matching: u64 = keys.iter().filter_map(|key| self.files_by_key.get(key)).sum();
+        matching as f64 / self.files as f64
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{defaults, Error};
+use std::{path::Path, process::Command};
+
+/// Marks the default category folder `dir` with its category's icon (Windows,
+/// Linux) or Finder color (macOS). Folders that aren't named after a default
+/// category are left alone.
+pub fn decorate(dir: &Path) -> Result<(), Error> {
+    let Some(id) = dir.file_name().and_then(|name| defaults::known_category(&name.to_string_lossy())) else {
+        return Ok(());
+    };
+    apply(dir, id)
+}
+
+fn run(command: &mut Command) -> Result<(), Error> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(Error::Io(std::io::Error::other(format!(
+            "{:?} failed: {}",
+            command.get_program(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))));
+    }
+    Ok(())
+}
+
+/// Icons of the shell's own library folders, from imageres.dll.
+#[cfg(target_os = "windows")]
+fn icon_resource(id: &str) -> Option<&'static str> {
+    Some(match id {
+        "documents" | "spreadsheets" | "presentations" => r"%SystemRoot%\system32\imageres.dll,-112",
+        "images" | "photos" => r"%SystemRoot%\system32\imageres.dll,-113",
+        "audio" => r"%SystemRoot%\system32\imageres.dll,-108",
+        "videos" => r"%SystemRoot%\system32\imageres.dll,-189",
+        "installers" | "executables" | "disk_images" | "archives" => r"%SystemRoot%\system32\imageres.dll,-184",
+        _ => return None,
+    })
+}
+
+/// Writes a hidden `desktop.ini` pointing at the icon. Explorer only reads it
+/// from folders with the read-only or system attribute.
+#[cfg(target_os = "windows")]
+fn apply(dir: &Path, id: &str) -> Result<(), Error> {
+    let Some(icon) = icon_resource(id) else {
+        return Ok(());
+    };
+    let ini = dir.join("desktop.ini");
+    std::fs::write(&ini, format!("[.ShellClassInfo]\r\nIconResource={}\r\n", icon))?;
+    run(Command::new("attrib").arg("+h").arg("+s").arg(&ini))?;
+    run(Command::new("attrib").arg("+r").arg(dir))
+}
+
+/// Finder label colors, as stored in the folder's FinderInfo flags.
+#[cfg(target_os = "macos")]
+fn label_color(id: &str) -> Option<u8> {
+    const GREEN: u8 = 2;
+    const PURPLE: u8 = 3;
+    const BLUE: u8 = 4;
+    const YELLOW: u8 = 5;
+    const RED: u8 = 6;
+    const ORANGE: u8 = 7;
+    Some(match id {
+        "documents" | "spreadsheets" | "presentations" => BLUE,
+        "images" | "photos" => PURPLE,
+        "videos" => RED,
+        "audio" => ORANGE,
+        "archives" | "disk_images" => YELLOW,
+        "code" | "executables" | "installers" => GREEN,
+        _ => return None,
+    })
+}
+
+/// Sets the Finder color label through the `com.apple.FinderInfo` attribute:
+/// 32 bytes, with the label in bits 1-3 of the tenth.
+#[cfg(target_os = "macos")]
+fn apply(dir: &Path, id: &str) -> Result<(), Error> {
+    let Some(color) = label_color(id) else {
+        return Ok(());
+    };
+    let mut info = [0u8; 32];
+    info[9] = color << 1;
+    let hex: Vec<String> = info.iter().map(|byte| format!("{:02x}", byte)).collect();
+    run(Command::new("xattr")
+        .args(["-wx", "com.apple.FinderInfo", &hex.join("")])
+        .arg(dir))
+}
+
+/// Icon names from the freedesktop icon naming spec and its common
+/// extensions, present in most themes.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn icon_name(id: &str) -> Option<&'static str> {
+    Some(match id {
+        "documents" | "spreadsheets" | "presentations" => "folder-documents",
+        "images" | "photos" => "folder-pictures",
+        "videos" => "folder-videos",
+        "audio" => "folder-music",
+        "code" => "folder-development",
+        "archives" | "installers" | "executables" | "disk_images" => "folder-download",
+        _ => return None,
+    })
+}
+
+/// Sets the icon through GVfs metadata, which GNOME Files, Nemo and Caja
+/// show. Other file managers ignore it.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn apply(dir: &Path, id: &str) -> Result<(), Error> {
+    let Some(icon) = icon_name(id) else {
+        return Ok(());
+    };
+    run(Command::new("gio")
+        .arg("set")
+        .arg(dir)
+        .args(["metadata::custom-icon-name", icon]))
+}
@@ -0,0 +1,116 @@
+use crate::{escape_like, Error, MappingCache};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+use walkdir::WalkDir;
+
+/// The state of one target folder, for the home screen.
+#[derive(Serialize, Clone)]
+pub struct FolderStats {
+    pub target_path: String,
+    pub exists: bool,
+    /// Files below the folder, leaving out target folders nested inside it.
+    pub files: usize,
+    pub bytes: u64,
+    /// What DeskSort moved into the folder last.
+    pub last_added: Option<LastAdded>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct LastAdded {
+    pub path: String,
+    pub at: i64,
+}
+
+/// Modification times of a folder and every folder below it. A file being
+/// added, removed or renamed anywhere inside changes one of them.
+type Signature = Vec<(PathBuf, Option<SystemTime>)>;
+
+/// Counts of each folder, reused while its signature stays the same.
+#[derive(Default)]
+pub struct StatsCache {
+    folders: Mutex<HashMap<PathBuf, (Signature, usize, u64)>>,
+}
+
+/// Stats for every target folder of `mappings`. Folders whose contents didn't
+/// change since the last call aren't walked again.
+pub fn folder_stats(conn: &Connection, mappings: &MappingCache, cache: &StatsCache) -> Result<Vec<FolderStats>, Error> {
+    let targets: BTreeSet<&Path> = mappings.target_dirs().collect();
+    let mut stats = Vec::new();
+    for dir in &targets {
+        // Nested targets have their own entry
+        let nested: Vec<&Path> = targets
+            .iter()
+            .copied()
+            .filter(|other| other != dir && other.starts_with(dir))
+            .collect();
+        let exists = dir.is_dir();
+        let (files, bytes) = if exists { count(dir, &nested, cache) } else { (0, 0) };
+        stats.push(FolderStats {
+            target_path: dir.display().to_string(),
+            exists,
+            files,
+            bytes,
+            last_added: last_added(conn, dir, &nested)?,
+        });
+    }
+    Ok(stats)
+}
+
+fn count(dir: &Path, nested: &[&Path], cache: &StatsCache) -> (usize, u64) {
+    let walk = || {
+        WalkDir::new(dir)
+            .into_iter()
+            .filter_entry(|entry| !nested.contains(&entry.path()))
+            .filter_map(|entry| entry.ok())
+    };
+    let signature: Signature = walk()
+        .filter(|entry| entry.file_type().is_dir())
+        .map(|entry| {
+            let modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+            (entry.into_path(), modified)
+        })
+        .collect();
+    if let Some((cached, files, bytes)) = cache.folders.lock().unwrap().get(dir) {
+        if *cached == signature {
+            return (*files, *bytes);
+        }
+    }
+
+    let (mut files, mut bytes) = (0, 0);
+    for entry in walk() {
+        if let Some(metadata) = entry.metadata().ok().filter(|m| m.is_file()) {
+            files += 1;
+            bytes += metadata.len();
+        }
+    }
+    cache
+        .folders
+        .lock()
+        .unwrap()
+        .insert(dir.to_path_buf(), (signature, files, bytes));
+    (files, bytes)
+}
+
+/// The newest move into `dir`, but not into `nested`, the history knows of.
+fn last_added(conn: &Connection, dir: &Path, nested: &[&Path]) -> Result<Option<LastAdded>, Error> {
+    let pattern = format!("{}%", escape_like(&dir.join("").to_string_lossy()));
+    let mut stmt = conn.prepare(
+        "SELECT destination, created_at FROM history
+         WHERE action = 'moved' AND destination LIKE ? ESCAPE '\\'
+         ORDER BY created_at DESC, id DESC",
+    )?;
+    let mut rows = stmt.query(params![pattern])?;
+    while let Some(row) = rows.next()? {
+        let path: String = row.get(0)?;
+        if !nested.iter().any(|nested| Path::new(&path).starts_with(nested)) {
+            return Ok(Some(LastAdded { path, at: row.get(1)? }));
+        }
+    }
+    Ok(None)
+}
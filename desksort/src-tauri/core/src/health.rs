@@ -0,0 +1,296 @@
+use crate::{
+    orphans::{self, OrphanReason, Owner},
+    rules, Error,
+};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Sorting will fail or do something wrong.
+    Error,
+    /// Probably not what the user meant.
+    Warning,
+    /// Worth knowing, nothing is broken.
+    Info,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    /// Another rule with the same condition and schedule sends entries to the
+    /// same target, so this one never matters.
+    DuplicateRule,
+    /// Another rule with the same condition and schedule sends entries
+    /// elsewhere; only the one tried first ever wins.
+    ConflictingRules,
+    /// The target is a folder that gets sorted, or sits directly in one.
+    TargetInsideSource,
+    /// Entries can't be written to the target.
+    Unwritable,
+    /// The target is on a drive or share that isn't connected.
+    Unreachable,
+    /// The folder the target lives in was deleted.
+    ParentMissing,
+    /// A category, mapping or rule group is switched off.
+    Disabled,
+}
+
+#[derive(Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub problem: Problem,
+    /// What the finding is about. Unset for whole categories and rule groups,
+    /// named in `subject` instead.
+    pub owner: Option<Owner>,
+    pub subject: String,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct HealthReport {
+    pub checked_mappings: usize,
+    pub checked_rules: usize,
+    /// Errors first, then warnings, then notes.
+    pub findings: Vec<Finding>,
+}
+
+struct Target {
+    owner: Owner,
+    subject: String,
+    path: PathBuf,
+}
+
+/// Checks every mapping and rule against each other and against the file
+/// system. `source_dir` is the folder sorts scan, `sorted_dir` the one
+/// DeskSort creates category folders in.
+pub fn check(conn: &Connection, source_dir: &Path, sorted_dir: &Path) -> Result<HealthReport, Error> {
+    let mut findings = Vec::new();
+
+    let mappings: Vec<(String, String, Option<String>, bool)> = conn
+        .prepare("SELECT extension, target_path, category, enabled FROM path_mappings ORDER BY extension")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+        .collect::<Result<_, _>>()?;
+    let groups = rules::load_groups(conn)?;
+    let all_rules = rules::load_rules(conn)?;
+    let disabled_groups: HashMap<i64, &str> = groups
+        .iter()
+        .filter(|group| !group.enabled)
+        .filter_map(|group| Some((group.id?, group.name.as_str())))
+        .collect();
+    let active_rules: Vec<_> = all_rules
+        .iter()
+        .filter(|rule| rule.enabled && !rule.group_id.is_some_and(|id| disabled_groups.contains_key(&id)))
+        .collect();
+
+    check_duplicate_rules(&active_rules, &mut findings);
+    check_disabled(&mappings, &disabled_groups, &mut findings);
+
+    let targets = mappings
+        .iter()
+        .filter(|(_, _, _, enabled)| *enabled)
+        .map(|(extension, target_path, _, _)| Target {
+            owner: Owner::Mapping {
+                extension: extension.clone(),
+            },
+            subject: extension.clone(),
+            path: PathBuf::from(target_path),
+        })
+        // Rules that delete have no target to check
+        .chain(active_rules.iter().filter(|rule| rule.action == rules::RuleAction::Move).map(|rule| Target {
+            owner: Owner::Rule {
+                id: rule.id.unwrap_or_default(),
+                name: rule.name.clone(),
+            },
+            subject: format!("rule \"{}\"", rule.name),
+            path: PathBuf::from(&rule.target_path),
+        }));
+    // Each folder is probed once, however many mappings share it
+    let mut writable: HashMap<PathBuf, bool> = HashMap::new();
+    for target in targets {
+        if let Some((severity, problem, message)) = check_target(&target.path, source_dir, sorted_dir, &mut writable) {
+            findings.push(Finding {
+                severity,
+                problem,
+                owner: Some(target.owner),
+                subject: target.subject,
+                message,
+            });
+        }
+    }
+
+    findings.sort_by_key(|finding| finding.severity as u8);
+    Ok(HealthReport {
+        checked_mappings: mappings.len(),
+        checked_rules: all_rules.len(),
+        findings,
+    })
+}
+
+fn check_duplicate_rules(active_rules: &[&rules::Rule], findings: &mut Vec<Finding>) {
+    // Rules are listed in evaluation order, so the first with a condition wins
+    let mut first_by_condition: HashMap<(String, String), &rules::Rule> = HashMap::new();
+    for rule in active_rules {
+        let key = (
+            serde_json::to_string(&rule.condition).unwrap_or_default(),
+            serde_json::to_string(&rule.schedule).unwrap_or_default(),
+        );
+        let Some(first) = first_by_condition.get(&key) else {
+            first_by_condition.insert(key, rule);
+            continue;
+        };
+        let (severity, problem, message) = if Path::new(&first.target_path) == Path::new(&rule.target_path) {
+            (
+                Severity::Info,
+                Problem::DuplicateRule,
+                format!("Does the same as rule \"{}\" and can be deleted", first.name),
+            )
+        } else {
+            (
+                Severity::Warning,
+                Problem::ConflictingRules,
+                format!(
+                    "Has the same condition as rule \"{}\", which sends entries to {} instead",
+                    first.name, first.target_path
+                ),
+            )
+        };
+        findings.push(Finding {
+            severity,
+            problem,
+            owner: Some(Owner::Rule {
+                id: rule.id.unwrap_or_default(),
+                name: rule.name.clone(),
+            }),
+            subject: format!("rule \"{}\"", rule.name),
+            message,
+        });
+    }
+}
+
+fn check_disabled(
+    mappings: &[(String, String, Option<String>, bool)],
+    disabled_groups: &HashMap<i64, &str>,
+    findings: &mut Vec<Finding>,
+) {
+    let mut by_category: BTreeMap<&str, Vec<(&str, bool)>> = BTreeMap::new();
+    for (extension, _, category, enabled) in mappings {
+        by_category
+            .entry(category.as_deref().unwrap_or_default())
+            .or_default()
+            .push((extension, *enabled));
+    }
+    for (category, mappings) in by_category {
+        if mappings.iter().all(|(_, enabled)| !enabled) {
+            findings.push(Finding {
+                severity: Severity::Info,
+                problem: Problem::Disabled,
+                owner: None,
+                subject: category.to_string(),
+                message: format!("All {} mappings of this category are disabled", mappings.len()),
+            });
+            continue;
+        }
+        for (extension, _) in mappings.iter().filter(|(_, enabled)| !enabled) {
+            findings.push(Finding {
+                severity: Severity::Info,
+                problem: Problem::Disabled,
+                owner: Some(Owner::Mapping {
+                    extension: extension.to_string(),
+                }),
+                subject: extension.to_string(),
+                message: format!("Disabled, so {} files stay where they are", extension),
+            });
+        }
+    }
+    for name in disabled_groups.values() {
+        findings.push(Finding {
+            severity: Severity::Info,
+            problem: Problem::Disabled,
+            owner: None,
+            subject: format!("rule group \"{}\"", name),
+            message: "Disabled, so none of its rules apply".to_string(),
+        });
+    }
+}
+
+fn check_target(
+    target: &Path,
+    source_dir: &Path,
+    sorted_dir: &Path,
+    writable: &mut HashMap<PathBuf, bool>,
+) -> Option<(Severity, Problem, String)> {
+    if target == source_dir {
+        return Some((
+            Severity::Error,
+            Problem::TargetInsideSource,
+            "Sends entries to the folder they are sorted out of".to_string(),
+        ));
+    }
+    if target.parent() == Some(source_dir) {
+        return Some((
+            Severity::Warning,
+            Problem::TargetInsideSource,
+            format!(
+                "{} sits among the entries being sorted; a folder inside {} keeps it out of the way",
+                target.display(),
+                sorted_dir.display()
+            ),
+        ));
+    }
+
+    match orphans::check(target, sorted_dir) {
+        Some(OrphanReason::DriveMissing) => {
+            return Some((
+                Severity::Error,
+                Problem::Unreachable,
+                "The drive it is on isn't connected".to_string(),
+            ))
+        }
+        Some(OrphanReason::ShareOffline) => {
+            return Some((
+                Severity::Warning,
+                Problem::Unreachable,
+                "The network share it is on can't be reached; entries wait until it is back".to_string(),
+            ))
+        }
+        Some(OrphanReason::ParentMissing) => {
+            return Some((
+                Severity::Warning,
+                Problem::ParentMissing,
+                format!("{} no longer exists and would be created again", target.display()),
+            ))
+        }
+        None => {}
+    }
+
+    // Missing folders are created on the first sort, so it is the closest
+    // existing one that has to take writes
+    let existing = target.ancestors().find(|dir| dir.is_dir())?;
+    let ok = *writable.entry(existing.to_path_buf()).or_insert_with(|| can_write(existing));
+    (!ok).then(|| {
+        (
+            Severity::Error,
+            Problem::Unwritable,
+            format!("DeskSort can't write to {}", existing.display()),
+        )
+    })
+}
+
+/// Whether files can be created in `dir`, found by writing a probe file.
+fn can_write(dir: &Path) -> bool {
+    let probe = dir.join(format!(".desksort-write-probe-{}", std::process::id()));
+    match fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
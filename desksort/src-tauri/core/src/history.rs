@@ -0,0 +1,399 @@
+use crate::{diagnostics::SortError, duplicates::Discarded, escape_like, settings::Settings, trash::Trashed, Error};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// What started a session. Stored as text, so the feed passes triggers
+/// through as they were written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    /// The user pressed Sort, or asked for a sort through a link.
+    Manual,
+    /// Failed moves tried again, by the user or on a timer.
+    Retry,
+    /// Already sorted entries moved after the rules or mappings changed.
+    Resort,
+    /// Sorted entries moved along when their mapping got a new target.
+    Migration,
+    /// Queued moves the user approved.
+    Approved,
+    /// Entries moved to the staging folder, waiting for a commit.
+    Staged,
+    /// Staged entries put back on the desktop, or an entry taken back out
+    /// of the trash.
+    Restored,
+    /// Duplicates in the target folders the user threw away.
+    Dedupe,
+    /// Broken links the user threw away.
+    Cleanup,
+    /// Desktop entries the user archived after being reminded of them.
+    Archived,
+}
+
+impl Trigger {
+    fn as_str(self) -> &'static str {
+        match self {
+            Trigger::Manual => "manual",
+            Trigger::Retry => "retry",
+            Trigger::Resort => "resort",
+            Trigger::Migration => "migration",
+            Trigger::Approved => "approved",
+            Trigger::Staged => "staged",
+            Trigger::Restored => "restored",
+            Trigger::Dedupe => "dedupe",
+            Trigger::Cleanup => "cleanup",
+            Trigger::Archived => "archived",
+        }
+    }
+}
+
+/// What happened to one entry.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Moved,
+    /// Rolled into an overflow folder to make room under a quota.
+    Rotated,
+    /// Parked until its target share is reachable again.
+    Queued,
+    Failed,
+    /// Sent to the trash for being identical to the file it is a copy of.
+    Discarded,
+    /// Sent to the trash by a rule that deletes.
+    Trashed,
+}
+
+impl Action {
+    fn as_str(self) -> &'static str {
+        match self {
+            Action::Moved => "moved",
+            Action::Rotated => "rotated",
+            Action::Queued => "queued",
+            Action::Failed => "failed",
+            Action::Discarded => "discarded",
+            Action::Trashed => "trashed",
+        }
+    }
+
+    fn parse(text: &str) -> Self {
+        match text {
+            "rotated" => Action::Rotated,
+            "queued" => Action::Queued,
+            "failed" => Action::Failed,
+            "discarded" => Action::Discarded,
+            "trashed" => Action::Trashed,
+            _ => Action::Moved,
+        }
+    }
+}
+
+/// One line of history, before it is written.
+pub struct Entry<'a> {
+    action: Action,
+    source: Option<&'a Path>,
+    destination: Option<&'a Path>,
+    size: Option<u64>,
+    decided_by: Option<&'a str>,
+    message: Option<String>,
+    /// Where the entry is in the trash, for `restore_trashed`.
+    trash_location: Option<&'a Path>,
+}
+
+impl<'a> Entry<'a> {
+    pub fn moved(source: &'a Path, destination: &'a Path, size: u64, decided_by: &'a str) -> Self {
+        Entry {
+            action: Action::Moved,
+            source: Some(source),
+            destination: Some(destination),
+            size: Some(size),
+            decided_by: Some(decided_by),
+            message: None,
+            trash_location: None,
+        }
+    }
+
+    pub fn rotated(source: &'a Path, destination: &'a Path) -> Self {
+        Entry {
+            action: Action::Rotated,
+            source: Some(source),
+            destination: Some(destination),
+            size: None,
+            decided_by: None,
+            message: None,
+            trash_location: None,
+        }
+    }
+
+    /// `destination` is the folder the entry will go to.
+    pub fn queued(source: &'a Path, destination: &'a Path) -> Self {
+        Entry {
+            action: Action::Queued,
+            source: Some(source),
+            destination: Some(destination),
+            size: None,
+            decided_by: None,
+            message: None,
+            trash_location: None,
+        }
+    }
+
+    /// `original` is the file `copy` was identical to.
+    pub fn discarded(discarded: &'a Discarded) -> Self {
+        Entry {
+            action: Action::Discarded,
+            source: Some(Path::new(&discarded.copy)),
+            destination: Some(Path::new(&discarded.original)),
+            size: None,
+            decided_by: None,
+            message: None,
+            trash_location: discarded.location.as_deref().map(Path::new),
+        }
+    }
+
+    pub fn trashed(trashed: &'a Trashed) -> Self {
+        Entry {
+            action: Action::Trashed,
+            source: Some(Path::new(&trashed.path)),
+            destination: None,
+            size: Some(trashed.size),
+            decided_by: Some(&trashed.decided_by),
+            message: None,
+            trash_location: trashed.location.as_deref().map(Path::new),
+        }
+    }
+
+    pub fn failed(error: &'a SortError) -> Self {
+        Entry {
+            action: Action::Failed,
+            source: error.path.as_deref().map(Path::new),
+            destination: None,
+            size: None,
+            decided_by: None,
+            message: Some(error.message.to_string()),
+            trash_location: None,
+        }
+    }
+}
+
+/// One entry of the activity feed.
+#[derive(Serialize)]
+pub struct Activity {
+    pub id: i64,
+    pub session_id: i64,
+    /// What started the session, e.g. `manual`.
+    pub trigger: String,
+    pub action: Action,
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    pub size: Option<u64>,
+    pub decided_by: Option<String>,
+    pub message: Option<String>,
+    /// Unix time the session finished.
+    pub at: i64,
+    /// Whether `restore_trashed` can bring the entry back from the trash.
+    pub restorable: bool,
+    /// Unix time the entry is pruned from the history after, when it is
+    /// restorable and the history is kept for a number of days.
+    pub restorable_until: Option<i64>,
+    /// Whether the session ran in simulation mode, so nothing it lists
+    /// actually happened.
+    pub simulated: bool,
+}
+
+/// Stores a session that ran from `started_at` until now and what it did,
+/// flagged as simulated in simulation mode. Returns the session id.
+pub fn record_session(conn: &Connection, trigger: Trigger, started_at: i64, entries: &[Entry]) -> Result<i64, Error> {
+    let simulated = Settings::load(conn)?.simulation_mode;
+    let tx = conn.unchecked_transaction()?;
+    let finished_at = chrono::Utc::now().timestamp();
+    tx.execute(
+        "INSERT INTO sessions (trigger, started_at, finished_at, simulated) VALUES (?, ?, ?, ?)",
+        params![trigger.as_str(), started_at, finished_at, simulated],
+    )?;
+    let session_id = tx.last_insert_rowid();
+
+    let mut stmt = tx.prepare(
+        "INSERT INTO history (session_id, action, source, destination, size, decided_by, message, created_at,
+            trash_location)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )?;
+    for entry in entries {
+        stmt.execute(params![
+            session_id,
+            entry.action.as_str(),
+            entry.source.map(|p| p.to_string_lossy()),
+            entry.destination.map(|p| p.to_string_lossy()),
+            entry.size,
+            entry.decided_by,
+            entry.message,
+            finished_at,
+            entry.trash_location.map(|p| p.to_string_lossy())
+        ])?;
+    }
+    drop(stmt);
+    tx.commit()?;
+    Ok(session_id)
+}
+
+/// Points history entries at paths inside `from` to the same place inside
+/// `to`, after the folder was renamed. Returns how many entries changed.
+pub fn rebase(conn: &Connection, from: &Path, to: &Path) -> Result<usize, Error> {
+    let pattern = format!(
+        "{}{}%",
+        escape_like(&from.to_string_lossy()),
+        escape_like(std::path::MAIN_SEPARATOR_STR)
+    );
+    let rows: Vec<(i64, Option<String>, Option<String>)> = conn
+        .prepare(
+            "SELECT id, source, destination FROM history
+             WHERE source LIKE ?1 ESCAPE '\\' OR destination LIKE ?1 ESCAPE '\\'",
+        )?
+        .query_map(params![pattern], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+    let rebase = |path: &Option<String>| {
+        path.as_deref().map(|path| match Path::new(path).strip_prefix(from) {
+            Ok(rest) => to.join(rest).to_string_lossy().into_owned(),
+            Err(_) => path.to_string(),
+        })
+    };
+    for (id, source, destination) in &rows {
+        conn.execute(
+            "UPDATE history SET source = ?, destination = ? WHERE id = ?",
+            params![rebase(source), rebase(destination), id],
+        )?;
+    }
+    Ok(rows.len())
+}
+
+/// The latest `limit` entries across all sessions, newest first. Entries are
+/// restorable until they are `keep_days` old.
+pub fn recent(conn: &Connection, limit: usize, keep_days: Option<u32>) -> Result<Vec<Activity>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT h.id, h.session_id, s.trigger, h.action, h.source, h.destination, h.size,
+                h.decided_by, h.message, h.created_at, h.trash_location IS NOT NULL, s.simulated
+         FROM history h JOIN sessions s ON s.id = h.session_id
+         ORDER BY h.created_at DESC, h.id DESC
+         LIMIT ?",
+    )?;
+    let rows = stmt.query_map(params![limit as i64], |row| {
+        Ok(Activity {
+            id: row.get(0)?,
+            session_id: row.get(1)?,
+            trigger: row.get(2)?,
+            action: Action::parse(&row.get::<_, String>(3)?),
+            source: row.get(4)?,
+            destination: row.get(5)?,
+            size: row.get(6)?,
+            decided_by: row.get(7)?,
+            message: row.get(8)?,
+            at: row.get(9)?,
+            restorable: row.get(10)?,
+            restorable_until: None,
+            simulated: row.get(11)?,
+        })
+    })?;
+    let mut activity: Vec<Activity> = rows.collect::<Result<_, _>>()?;
+    if let Some(days) = keep_days {
+        for entry in activity.iter_mut().filter(|entry| entry.restorable) {
+            entry.restorable_until = Some(entry.at + i64::from(days) * 24 * 60 * 60);
+        }
+    }
+    Ok(activity)
+}
+
+/// Where history entry `id` was trashed from, its size and where it is in
+/// the trash, if it is there to be restored.
+pub fn trashed(conn: &Connection, id: i64) -> Result<Option<(PathBuf, u64, PathBuf)>, Error> {
+    let row: Option<(Option<String>, Option<u64>, Option<String>)> = conn
+        .query_row(
+            "SELECT source, size, trash_location FROM history WHERE id = ?",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+    Ok(match row {
+        Some((Some(source), size, Some(location))) => {
+            Some((PathBuf::from(source), size.unwrap_or_default(), PathBuf::from(location)))
+        }
+        _ => None,
+    })
+}
+
+/// Marks history entry `id` as taken back out of the trash.
+pub fn forget_trash_location(conn: &Connection, id: i64) -> Result<(), Error> {
+    conn.execute("UPDATE history SET trash_location = NULL WHERE id = ?", params![id])?;
+    Ok(())
+}
+
+/// Deletes the sessions that finished more than `keep_days` days ago and all
+/// but the latest `keep_sessions`, with their entries. Trashed entries of
+/// those sessions stay in the trash but can no longer be restored from
+/// DeskSort. Returns how many sessions were deleted.
+pub fn prune(conn: &Connection, keep_days: Option<u32>, keep_sessions: Option<u32>) -> Result<usize, Error> {
+    let cutoff = keep_days.map(|days| chrono::Utc::now().timestamp() - i64::from(days) * 24 * 60 * 60);
+    // A negative LIMIT has none
+    let limits = params![cutoff.unwrap_or(i64::MIN), keep_sessions.map_or(-1, i64::from)];
+    let pruned_sessions = "SELECT id FROM sessions
+         WHERE finished_at < ?1
+            OR id NOT IN (SELECT id FROM sessions ORDER BY finished_at DESC, id DESC LIMIT ?2)";
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(&format!("DELETE FROM history WHERE session_id IN ({})", pruned_sessions), limits)?;
+    let pruned = tx.execute(&format!("DELETE FROM sessions WHERE id IN ({})", pruned_sessions), limits)?;
+    tx.commit()?;
+    Ok(pruned)
+}
+
+/// A span of Unix time, both ends inclusive. A missing end is open.
+#[derive(Deserialize, Default)]
+pub struct DateRange {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+/// Writes the entries recorded within `range` to `out` as CSV, oldest first.
+/// Entries of simulated sorts are flagged in the last column. Returns how
+/// many rows it wrote.
+pub fn export_csv(conn: &Connection, out: &mut impl Write, range: &DateRange) -> Result<usize, Error> {
+    writeln!(out, "timestamp,action,source,destination,size,rule,simulated")?;
+    let mut stmt = conn.prepare(
+        "SELECT h.created_at, h.action, h.source, h.destination, h.size, h.decided_by, s.simulated
+         FROM history h JOIN sessions s ON s.id = h.session_id
+         WHERE h.created_at >= ?1 AND h.created_at <= ?2
+         ORDER BY h.created_at, h.id",
+    )?;
+    let mut rows = stmt.query(params![range.from.unwrap_or(i64::MIN), range.to.unwrap_or(i64::MAX)])?;
+    let mut written = 0;
+    while let Some(row) = rows.next()? {
+        let at = chrono::DateTime::from_timestamp(row.get(0)?, 0)
+            .map(|at| at.with_timezone(&chrono::Local).to_rfc3339())
+            .unwrap_or_default();
+        let size: Option<u64> = row.get(4)?;
+        let fields = [
+            at,
+            row.get(1)?,
+            row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+            size.map(|size| size.to_string()).unwrap_or_default(),
+            row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+            row.get::<_, bool>(6)?.to_string(),
+        ];
+        let line: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        writeln!(out, "{}", line.join(","))?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// `field` quoted when it needs to be. Leading characters spreadsheets treat
+/// as the start of a formula are escaped with an apostrophe.
+fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@']) { format!("'{}", field) } else { field.to_string() };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
@@ -0,0 +1,89 @@
+use crate::{sources::Source, MappingCache};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, BinaryHeap},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use walkdir::WalkDir;
+
+/// Most files `find` returns.
+const LIMIT: usize = 100;
+
+/// Where `find` looks.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// The enabled sources, leaving out target folders inside them.
+    Sources,
+    /// The target folders.
+    Sorted,
+    All,
+}
+
+#[derive(Serialize)]
+pub struct LargeFile {
+    pub path: String,
+    pub size: u64,
+    /// Unix time of its last modification.
+    pub modified: Option<i64>,
+    /// Whole days since then.
+    pub age_days: Option<u64>,
+    /// Whether it is below a target folder rather than in a source.
+    pub sorted: bool,
+}
+
+/// The biggest files of at least `min_size` bytes in `scope`, largest first.
+/// `sources` are the enabled ones.
+pub fn find(sources: &[Source], mappings: &MappingCache, min_size: u64, scope: Scope) -> Vec<LargeFile> {
+    let targets: BTreeSet<PathBuf> = mappings.target_dirs().map(Path::to_path_buf).collect();
+    let mut roots: Vec<(PathBuf, bool)> = Vec::new();
+    if scope != Scope::Sorted {
+        roots.extend(sources.iter().map(|source| (source.path.clone(), false)));
+    }
+    if scope != Scope::Sources {
+        roots.extend(targets.iter().map(|dir| (dir.clone(), true)));
+    }
+
+    let now = SystemTime::now();
+    let mut seen = BTreeSet::new();
+    // Min-heap of the largest files seen so far
+    let mut largest = BinaryHeap::new();
+    for (root, sorted) in &roots {
+        let walk = WalkDir::new(root)
+            .into_iter()
+            // Targets are walked on their own, or not at all
+            .filter_entry(|entry| *sorted || !targets.contains(entry.path()))
+            .filter_map(|entry| entry.ok());
+        for entry in walk {
+            let Some(metadata) = entry.metadata().ok().filter(|m| m.is_file() && m.len() >= min_size) else {
+                continue;
+            };
+            if !seen.insert(entry.path().to_path_buf()) {
+                continue;
+            }
+            let modified = metadata.modified().ok();
+            largest.push(Reverse((metadata.len(), entry.into_path(), modified, *sorted)));
+            if largest.len() > LIMIT {
+                largest.pop();
+            }
+        }
+    }
+
+    largest
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((size, path, modified, sorted))| LargeFile {
+            path: path.display().to_string(),
+            size,
+            modified: modified
+                .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|since| since.as_secs() as i64),
+            age_days: modified
+                .and_then(|time| now.duration_since(time).ok())
+                .map(|age| age.as_secs() / 86_400),
+            sorted,
+        })
+        .collect()
+}
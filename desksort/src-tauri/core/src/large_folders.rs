@@ -0,0 +1,58 @@
+use crate::{filesystem::FileSystem, settings::Settings, PlannedMove};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// What happens to folders over the size or file count limits. Moving one
+/// to another volume copies every file, which is a different operation from
+/// moving a PDF.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LargeFolderPolicy {
+    /// Left where they are.
+    Skip,
+    /// Queued for the user to approve, even when approval mode is off.
+    #[default]
+    Ask,
+}
+
+/// Files below `dir`, counting no further than `limit + 1`.
+fn count_files(fs: &dyn FileSystem, dir: &Path, limit: u64) -> u64 {
+    let mut count = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(folder) = pending.pop() {
+        for entry in fs.read_dir(&folder).unwrap_or_default() {
+            if entry.is_dir {
+                pending.push(entry.path);
+            } else {
+                count += 1;
+                if count > limit {
+                    return count;
+                }
+            }
+        }
+    }
+    count
+}
+
+/// Whether `planned` moves a folder over the limits of `settings`.
+fn exceeds(fs: &dyn FileSystem, settings: &Settings, planned: &PlannedMove) -> bool {
+    if !fs.is_dir(&planned.source) {
+        return false;
+    }
+    settings.max_folder_bytes.is_some_and(|max| planned.size > max)
+        || settings
+            .max_folder_files
+            .is_some_and(|max| count_files(fs, &planned.source, max) > max)
+}
+
+/// Takes the moves of folders over the limits out of `plan`.
+pub fn take(fs: &dyn FileSystem, settings: &Settings, plan: &mut Vec<PlannedMove>) -> Vec<PlannedMove> {
+    if settings.max_folder_bytes.is_none() && settings.max_folder_files.is_none() {
+        return Vec::new();
+    }
+    let (large, rest) = std::mem::take(plan)
+        .into_iter()
+        .partition(|planned| exceeds(fs, settings, planned));
+    *plan = rest;
+    large
+}
@@ -0,0 +1,183 @@
+use crate::{configure_connection, filesystem::OsFileSystem, get_db_path, mapping_key, Error};
+use notify::{
+    event::{ModifyKind, RenameMode},
+    EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long after leaving the desktop an entry that shows up in the sorted
+/// tree still counts as moved there by hand.
+const MOVE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Manual moves of one extension into the same folder needed before it is
+/// proposed as a mapping.
+const LEARN_THRESHOLD: i64 = 3;
+
+/// Events arriving this long after a sort are still attributed to it.
+const SORT_SETTLE_MS: i64 = 2000;
+
+/// Tells the watcher when DeskSort itself is moving files, so its own moves
+/// aren't learned from. Holds the time (ms) until which events are ignored.
+#[derive(Default, Clone)]
+pub struct SortActivity(Arc<AtomicI64>);
+
+impl SortActivity {
+    pub fn begin(&self) {
+        self.0.store(i64::MAX, Ordering::SeqCst);
+    }
+
+    pub fn end(&self) {
+        self.0
+            .store(chrono::Utc::now().timestamp_millis() + SORT_SETTLE_MS, Ordering::SeqCst);
+    }
+
+    fn is_busy(&self) -> bool {
+        chrono::Utc::now().timestamp_millis() < self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Keeps the watcher running; dropping it stops watching.
+pub struct MoveWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+/// Watches `desktop` and the `sorted` tree below it and records every entry
+/// the user drags from one into the other.
+pub fn watch(desktop: &Path, sorted: &Path, activity: SortActivity) -> Result<MoveWatcher, Error> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)
+        .map_err(|e| Error::Io(std::io::Error::other(format!("Failed to start watcher: {}", e))))?;
+    let watch_err = |e: notify::Error| Error::Io(std::io::Error::other(format!("Failed to watch: {}", e)));
+    watcher.watch(desktop, RecursiveMode::NonRecursive).map_err(watch_err)?;
+    if sorted.exists() {
+        watcher.watch(sorted, RecursiveMode::Recursive).map_err(watch_err)?;
+    }
+
+    let conn = Connection::open(get_db_path()?)?;
+    configure_connection(&conn)?;
+    tracing::info!("Watching {} for manual moves", desktop.display());
+    let desktop = desktop.to_path_buf();
+    let sorted = sorted.to_path_buf();
+    thread::spawn(move || {
+        // Desktop entries that just disappeared, by file name
+        let mut left_desktop: HashMap<std::ffi::OsString, Instant> = HashMap::new();
+
+        for event in rx {
+            let Ok(event) = event else {
+                continue;
+            };
+            if activity.is_busy() {
+                continue;
+            }
+
+            let mut arrived: Vec<&PathBuf> = Vec::new();
+            match event.kind {
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                    if event.paths[0].parent() == Some(desktop.as_path()) {
+                        arrived.push(&event.paths[1]);
+                    }
+                }
+                EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_)) => {
+                    for path in &event.paths {
+                        // Some platforms don't say which end of a rename a path is
+                        let gone = match event.kind {
+                            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => false,
+                            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => true,
+                            _ => !path.exists(),
+                        };
+                        if gone {
+                            if let Some(name) = path.file_name().filter(|_| path.parent() == Some(desktop.as_path())) {
+                                left_desktop.insert(name.to_os_string(), Instant::now());
+                            }
+                        } else if path
+                            .file_name()
+                            .and_then(|name| left_desktop.remove(name))
+                            .is_some_and(|left| left.elapsed() < MOVE_WINDOW)
+                        {
+                            arrived.push(path);
+                        }
+                    }
+                }
+                _ => continue,
+            }
+            left_desktop.retain(|_, left| left.elapsed() < MOVE_WINDOW);
+
+            for path in arrived.into_iter().filter(|p| p.starts_with(&sorted)) {
+                let Some(target_dir) = path.parent() else {
+                    continue;
+                };
+                if let Err(e) = record(&conn, &mapping_key(&OsFileSystem, path), target_dir) {
+                    tracing::warn!("Failed to record manual move of {}: {}", path.display(), e);
+                }
+            }
+        }
+    });
+
+    Ok(MoveWatcher { _watcher: watcher })
+}
+
+fn record(conn: &Connection, extension: &str, target_dir: &Path) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO observed_moves (extension, target_dir, count, last_seen) VALUES (?, ?, 1, ?)
+         ON CONFLICT(extension, target_dir) DO UPDATE SET
+            count = count + 1,
+            last_seen = excluded.last_seen",
+        params![
+            extension,
+            target_dir.to_string_lossy(),
+            chrono::Utc::now().timestamp()
+        ],
+    )?;
+    Ok(())
+}
+
+/// A mapping proposed because the user keeps filing an extension by hand.
+#[derive(Serialize)]
+pub struct LearnedMapping {
+    extension: String,
+    target_path: String,
+    /// How many manual moves back the proposal.
+    times_seen: i64,
+    last_seen: i64,
+}
+
+/// Forgets the manual moves of `extension` entries into `target_dir`, after
+/// the user turned down the mapping they suggested.
+pub fn dismiss(conn: &Connection, extension: &str, target_dir: &str) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM observed_moves WHERE extension = ? AND target_dir = ?",
+        params![extension, target_dir],
+    )?;
+    Ok(())
+}
+
+/// Observations that crossed [`LEARN_THRESHOLD`] and disagree with the
+/// current mapping of their extension.
+pub fn learned_mappings(conn: &Connection) -> Result<Vec<LearnedMapping>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT o.extension, o.target_dir, o.count, o.last_seen FROM observed_moves o
+         LEFT JOIN path_mappings m ON m.extension = o.extension
+         WHERE o.count >= ? AND (m.target_path IS NULL OR m.target_path != o.target_dir)
+         ORDER BY o.count DESC, o.extension",
+    )?;
+    let rows = stmt.query_map(params![LEARN_THRESHOLD], |row| {
+        Ok(LearnedMapping {
+            extension: row.get(0)?,
+            target_path: row.get(1)?,
+            times_seen: row.get(2)?,
+            last_seen: row.get(3)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
@@ -0,0 +1,2243 @@
+//! Scanning, matching, moving and persistence for DeskSort, free of the GUI
+//! so the app, a command line and tests can all drive a sort.
+
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::OnceCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    result::Result,
+    sync::Arc,
+    time::Duration,
+};
+use unicode_normalization::UnicodeNormalization;
+
+pub mod app_lock;
+pub mod approvals;
+pub mod archival;
+pub mod archive;
+pub mod attributes;
+pub mod benchmark;
+pub mod camera;
+pub mod categories;
+pub mod config_file;
+pub mod defaults;
+pub mod desktop;
+pub mod diagnostics;
+pub mod duplicates;
+pub mod encryption;
+pub mod executor;
+pub mod extension_groups;
+pub mod filesystem;
+pub mod folder_contents;
+pub mod folder_icons;
+pub mod folder_stats;
+pub mod health;
+pub mod history;
+pub mod large_files;
+pub mod large_folders;
+pub mod learning;
+pub mod library;
+pub mod logging;
+pub mod messages;
+pub mod mappings;
+pub mod mime;
+pub mod mover;
+pub mod naming;
+pub mod origin;
+pub mod orphans;
+pub mod placeholders;
+pub mod projects;
+pub mod quota;
+pub mod report;
+pub mod retries;
+pub mod rules;
+pub mod ruleset;
+pub mod settings;
+pub mod shortcuts;
+pub mod skipped;
+pub mod sources;
+pub mod staging;
+pub mod stale;
+pub mod suggestions;
+pub mod team_sync;
+pub mod trash;
+
+use diagnostics::{FailedStep, SortError};
+use executor::{ExecutionOptions, ProgressSink};
+use filesystem::FileSystem;
+use messages::Message;
+use quota::Quota;
+use rules::{Candidate, Decision, MatchSource, RuleSemantics, RuleSet};
+use settings::Settings;
+use skipped::{SkipReason, SkippedFile};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    Io(#[from] std::io::Error),
+    Db(#[from] rusqlite::Error),
+    DesktopNotFound,
+    ConfigDirNotFound,
+    InvalidSetting(String),
+    InvalidRule(String),
+    InvalidLink(String),
+    InvalidArchive(String),
+    Encryption(String),
+    AppLocked,
+    WrongPin,
+    /// Seconds until another PIN is accepted, after too many wrong ones.
+    PinAttempts(u64),
+    InvalidConfigFile(String),
+    InvalidCategory(String),
+    InvalidSource(String),
+    NotRestorable(String),
+    InvalidRuleset(String),
+}
+
+impl Error {
+    /// What went wrong, for the frontend to show in the user's language.
+    pub fn message(&self) -> Message {
+        match self {
+            Error::Io(e) => Message::new("error.io").arg("detail", e),
+            Error::Db(e) => Message::new("error.db").arg("detail", e),
+            Error::DesktopNotFound => Message::new("error.desktop_not_found"),
+            Error::ConfigDirNotFound => Message::new("error.config_dir_not_found"),
+            Error::InvalidSetting(detail) => Message::new("error.invalid_setting").arg("detail", detail),
+            Error::InvalidRule(detail) => Message::new("error.invalid_rule").arg("detail", detail),
+            Error::InvalidLink(detail) => Message::new("error.invalid_link").arg("detail", detail),
+            Error::InvalidArchive(detail) => Message::new("error.invalid_archive").arg("detail", detail),
+            Error::Encryption(detail) => Message::new("error.encryption").arg("detail", detail),
+            Error::AppLocked => Message::new("error.app_locked"),
+            Error::WrongPin => Message::new("error.wrong_pin"),
+            Error::PinAttempts(secs) => Message::new("error.pin_attempts").arg("secs", secs),
+            Error::InvalidConfigFile(detail) => Message::new("error.invalid_config_file")
+                .arg("file", config_file::FILE_NAME)
+                .arg("detail", detail),
+            Error::InvalidCategory(detail) => Message::new("error.invalid_category").arg("detail", detail),
+            Error::InvalidSource(detail) => Message::new("error.invalid_source").arg("detail", detail),
+            Error::NotRestorable(detail) => Message::new("error.not_restorable").arg("detail", detail),
+            Error::InvalidRuleset(detail) => Message::new("error.invalid_ruleset").arg("detail", detail),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.message().fmt(f)
+    }
+}
+
+impl serde::Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.message().serialize(serializer)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+pub struct PathMapping {
+    extension: String,
+    target_path: String,
+    #[serde(default)]
+    max_files: Option<u64>,
+    #[serde(default)]
+    max_bytes: Option<u64>,
+    /// Files smaller than `min_size` or larger than `max_size` bytes are left
+    /// unmatched.
+    #[serde(default)]
+    min_size: Option<u64>,
+    #[serde(default)]
+    max_size: Option<u64>,
+    #[serde(default)]
+    category: Option<String>,
+    /// Language-independent id of the category, e.g. `images` for a folder
+    /// named `Bilder`.
+    #[serde(default, skip_deserializing)]
+    category_id: Option<String>,
+    /// Number of entries this mapping has routed, and when it last did.
+    #[serde(default, skip_deserializing)]
+    match_count: u64,
+    #[serde(default, skip_deserializing)]
+    last_matched_at: Option<i64>,
+    /// Disabled mappings are kept but never match.
+    #[serde(default, skip_deserializing)]
+    enabled: bool,
+    /// The user's note on why the mapping exists.
+    #[serde(default)]
+    description: Option<String>,
+}
+
+/// Column `get_all_mappings` sorts by.
+#[derive(Default, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum MappingSort {
+    #[default]
+    Extension,
+    TargetPath,
+    Category,
+    MatchCount,
+    LastMatchedAt,
+}
+
+impl MappingSort {
+    fn column(self) -> &'static str {
+        match self {
+            MappingSort::Extension => "extension",
+            MappingSort::TargetPath => "target_path",
+            MappingSort::Category => "category",
+            MappingSort::MatchCount => "match_count",
+            MappingSort::LastMatchedAt => "last_matched_at",
+        }
+    }
+}
+
+/// Filters and paging for `get_all_mappings`; everything is optional.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct MappingQuery {
+    /// Matched against extension, target path and category.
+    search: Option<String>,
+    category: Option<String>,
+    sort_by: MappingSort,
+    descending: bool,
+    limit: Option<usize>,
+    offset: usize,
+}
+
+#[derive(Serialize)]
+pub struct MappingPage {
+    mappings: Vec<PathMapping>,
+    /// Number of mappings matching the filters, ignoring paging.
+    total: usize,
+}
+
+/// Escapes `%`, `_` and the escape character itself for a `LIKE ... ESCAPE '\'` pattern.
+pub fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// All mappings keyed by extension plus the enabled rules, so matching a scan
+/// needs no queries.
+pub struct MappingCache {
+    targets: HashMap<String, MappingTarget>,
+    /// Bounds of the mappings that have any.
+    size_bounds: HashMap<String, SizeBounds>,
+    rules: RuleSet,
+    /// Where camera imports are filed, unless they are sent through the
+    /// mappings like other files.
+    camera_import_dir: Option<PathBuf>,
+    /// Where entries nothing matches are archived, and after how long.
+    archive: Option<(PathBuf, Duration)>,
+    /// Mappings of single sources, by source folder, deepest folder first.
+    source_targets: Vec<(PathBuf, i64, HashMap<String, PathBuf>)>,
+    /// Mappings by MIME type or family, exact types first.
+    mime_targets: Vec<(String, PathBuf)>,
+    /// Recognizes development projects, unless no markers are set.
+    projects: Option<projects::ProjectDetector>,
+}
+
+impl MappingCache {
+    /// Every folder a mapping or rule sends entries to.
+    fn target_dirs(&self) -> impl Iterator<Item = &Path> {
+        self.targets
+            .values()
+            .map(|(target_dir, _)| target_dir.as_path())
+            .chain(self.rules.target_dirs())
+            .chain(self.camera_import_dir.as_deref())
+            .chain(self.archive.as_ref().map(|(dir, _)| dir.as_path()))
+            .chain(
+                self.source_targets
+                    .iter()
+                    .flat_map(|(_, _, targets)| targets.values().map(PathBuf::as_path)),
+            )
+            .chain(self.mime_targets.iter().map(|(_, target_dir)| target_dir.as_path()))
+            .chain(self.projects.as_ref().and_then(projects::ProjectDetector::target_dir))
+    }
+
+    /// Key of the mapping entries keyed `key` go by: their own, or that of
+    /// the first mapped extension group holding them.
+    fn mapping_key<'a>(&'a self, key: &'a str) -> Option<&'a str> {
+        if self.targets.contains_key(key) {
+            return Some(key);
+        }
+        extension_groups::groups_of(key).find(|group| self.targets.contains_key(*group))
+    }
+
+    /// The quota of the mapping behind `matched`; other targets have none.
+    fn quota(&self, matched: &MatchSource) -> Quota {
+        match matched {
+            MatchSource::Mapping(key) => self.targets.get(key).map(|(_, quota)| *quota).unwrap_or_default(),
+            _ => Quota::default(),
+        }
+    }
+
+    pub fn load(conn: &Connection) -> Result<Self, Error> {
+        let mut stmt = conn.prepare(
+            "SELECT extension, target_path, max_files, max_bytes, min_size, max_size
+             FROM path_mappings WHERE enabled = 1",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let target_dir: String = row.get(1)?;
+            let quota = Quota {
+                max_files: row.get(2)?,
+                max_bytes: row.get(3)?,
+            };
+            let bounds = SizeBounds {
+                min: row.get(4)?,
+                max: row.get(5)?,
+            };
+            Ok((row.get::<_, String>(0)?, (PathBuf::from(target_dir), quota), bounds))
+        })?;
+
+        let mut targets = HashMap::new();
+        let mut size_bounds = HashMap::new();
+        for row in rows {
+            let (extension, target, bounds) = row?;
+            if bounds.min.is_some() || bounds.max.is_some() {
+                size_bounds.insert(extension.clone(), bounds);
+            }
+            targets.insert(extension, target);
+        }
+
+        let mut source_targets: Vec<(PathBuf, i64, HashMap<String, PathBuf>)> = sources::load(conn)?
+            .into_iter()
+            .map(|source| (source.path, source.id, HashMap::new()))
+            .collect();
+        for mapping in sources::load_mappings(conn)? {
+            if let Some((_, _, targets)) = source_targets.iter_mut().find(|(_, id, _)| *id == mapping.source_id) {
+                targets.insert(mapping.extension, PathBuf::from(mapping.target_path));
+            }
+        }
+        source_targets.retain(|(_, _, targets)| !targets.is_empty());
+        // Entries of a source nested in another one belong to the inner one
+        source_targets.sort_by_key(|(path, _, _)| std::cmp::Reverse(path.components().count()));
+
+        let settings = Settings::load(conn)?;
+        let sorted_dir = get_desktop_path()?.join("Sorted");
+        let mappings = MappingCache {
+            targets,
+            size_bounds,
+            rules: RuleSet::load(conn)?,
+            camera_import_dir: settings.camera_import_dir(&sorted_dir),
+            archive: settings.archive(&sorted_dir),
+            projects: projects::ProjectDetector::new(&settings.project_markers, settings.project_dir(&sorted_dir)),
+            source_targets,
+            mime_targets: mime::load(conn)?
+                .into_iter()
+                .map(|mapping| (mapping.mime_type, PathBuf::from(mapping.target_path)))
+                .collect(),
+        };
+        tracing::info!(
+            "Loaded {} mappings and {} rules into cache",
+            mappings.targets.len(),
+            mappings.rules.len()
+        );
+        Ok(mappings)
+    }
+
+    /// Where `entry` goes at `now`. A mapping of the source it is in wins
+    /// outright. Otherwise rules are tried before project detection, then
+    /// camera import detection,
+    /// which comes before its extension mapping, or for files without an
+    /// extension the `noext` mapping and then the mapping of the extension
+    /// their content suggests. Only when there is no such
+    /// mapping is the content sniffed for a MIME mapping. Entries none of
+    /// them match are archived once they are old enough. Quotas belong to mappings, so
+    /// other targets have none.
+    fn lookup(&self, entry: &ScannedEntry, semantics: RuleSemantics, now: chrono::NaiveDateTime) -> Decision {
+        let source_mapping = self
+            .source_targets
+            .iter()
+            .find(|(path, _, _)| entry.path.starts_with(path))
+            .and_then(|(_, id, targets)| {
+                let target_dir = targets.get(&entry.key)?;
+                Some(Candidate::source_mapping(*id, &entry.key, target_dir.clone()))
+            });
+        if let Some(candidate) = source_mapping {
+            return Decision::Target(candidate, "source mapping overrides the others");
+        }
+        let project = || self.projects.as_ref()?.candidate(entry);
+        let camera = || {
+            let root = self.camera_import_dir.as_deref()?;
+            camera::is_camera_import(entry).then(|| Candidate::camera_import(camera::target_dir(root, entry)))
+        };
+        let mapping = || {
+            // Files without an extension nobody mapped go by the extension
+            // their content usually has
+            let key = self.mapping_key(&entry.key).or_else(|| {
+                let extension = (entry.key == NO_EXTENSION)
+                    .then(|| entry.mime_type())
+                    .flatten()
+                    .and_then(mime::extension)?;
+                self.mapping_key(extension)
+            })?;
+            self.targets
+                .get(key)
+                .filter(|_| {
+                    // Only sized when bounded, as folders need a full walk
+                    self.size_bounds
+                        .get(key)
+                        .is_none_or(|bounds| bounds.contains(entry.size()))
+                })
+                .map(|(target_dir, quota)| Candidate::mapping(key, target_dir.clone(), *quota))
+        };
+        let mime = || {
+            if self.mime_targets.is_empty() || entry.is_dir {
+                return None;
+            }
+            let mime_type = entry.mime_type()?;
+            self.mime_targets
+                .iter()
+                .find(|(pattern, _)| mime::matches(pattern, mime_type))
+                .map(|(pattern, target_dir)| Candidate::mime(pattern, target_dir.clone()))
+        };
+        let candidates = if semantics == RuleSemantics::FirstMatch {
+            // Later matches can't change the outcome, so don't evaluate them
+            self.rules
+                .first_match(entry, now)
+                .or_else(project)
+                .or_else(camera)
+                .or_else(mapping)
+                .or_else(mime)
+                .into_iter()
+                .collect()
+        } else {
+            let mut candidates = self.rules.all_matches(entry, now);
+            candidates.extend(project());
+            candidates.extend(camera());
+            match mapping() {
+                Some(candidate) => candidates.push(candidate),
+                None => candidates.extend(mime()),
+            }
+            candidates
+        };
+        match semantics.decide(candidates) {
+            Decision::Unmatched => {
+                let archived = self.archive.as_ref().and_then(|(root, after)| archival::target_dir(root, entry, *after));
+                match archived {
+                    Some(target_dir) => Decision::Target(Candidate::archive(target_dir), "left untouched"),
+                    None => Decision::Unmatched,
+                }
+            }
+            decision => decision,
+        }
+    }
+}
+
+/// How long a statement waits for a lock held by another connection before
+/// failing with `database is locked`.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connection-level settings, applied every time the database is opened. WAL
+/// lets readers proceed while a sort is writing.
+pub fn configure_connection(conn: &Connection) -> Result<(), Error> {
+    encryption::apply_key(conn)?;
+    let mode: String = conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+    if !mode.eq_ignore_ascii_case("wal") {
+        tracing::warn!("Database does not support WAL, staying in {} mode", mode);
+    }
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    Ok(())
+}
+
+pub fn init_db(conn: &mut Connection) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS path_mappings (
+            extension TEXT PRIMARY KEY,
+            target_path TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    migrate(conn)?;
+
+    // Check if we need to initialize default paths
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM path_mappings",
+        [],
+        |row| row.get(0),
+    )?;
+
+    if count == 0 {
+        tracing::info!("Initializing default paths...");
+        let desktop = get_desktop_path()?;
+        let sorted_dir = desktop.join("Sorted");
+        let language = Settings::load(conn)?.folder_language();
+
+        let tx = conn.transaction()?;
+        for mapping in defaults::default_mappings(&sorted_dir, language) {
+            let path = mapping.target.to_str().unwrap();
+            tx.execute(
+                "INSERT OR IGNORE INTO path_mappings (extension, target_path, category, category_id)
+                 VALUES (?, ?, ?, ?)",
+                params![mapping.extension, path, category_for_target(path), mapping.category_id],
+            )?;
+            categories::ensure(&tx, mapping.category_id, &category_for_target(path), path)?;
+        }
+        tx.commit()?;
+        tracing::info!("Default paths initialized");
+    }
+
+    Ok(())
+}
+
+/// One step of the schema history. Most are plain SQL; data backfills that
+/// need Rust (path handling, defaults) are functions.
+enum Migration {
+    Sql(&'static str),
+    Code(fn(&Connection) -> Result<(), Error>),
+}
+
+/// Schema changes applied in order on top of the base table; the index of the
+/// last applied entry is tracked in `PRAGMA user_version`.
+const MIGRATIONS: &[Migration] = &[
+    Migration::Sql(
+        "ALTER TABLE path_mappings ADD COLUMN max_files INTEGER;
+         ALTER TABLE path_mappings ADD COLUMN max_bytes INTEGER;",
+    ),
+    Migration::Sql(
+        "CREATE TABLE deferred_moves (
+            source TEXT PRIMARY KEY,
+            target_dir TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            queued_at INTEGER NOT NULL
+        );",
+    ),
+    Migration::Sql(
+        "CREATE TABLE settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );",
+    ),
+    Migration::Sql(
+        "CREATE INDEX idx_path_mappings_target_path ON path_mappings (target_path);
+         CREATE INDEX idx_deferred_moves_queued_at ON deferred_moves (queued_at);",
+    ),
+    Migration::Sql(
+        "ALTER TABLE path_mappings ADD COLUMN category TEXT;
+         CREATE INDEX idx_path_mappings_category ON path_mappings (category);",
+    ),
+    Migration::Code(backfill_mapping_categories),
+    Migration::Sql(
+        "CREATE TABLE rules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            condition TEXT NOT NULL,
+            target_path TEXT NOT NULL,
+            priority INTEGER NOT NULL DEFAULT 0,
+            enabled INTEGER NOT NULL DEFAULT 1
+        );
+         CREATE INDEX idx_rules_priority ON rules (priority);",
+    ),
+    Migration::Sql(
+        "CREATE TABLE conflicts (
+            source TEXT PRIMARY KEY,
+            candidates TEXT NOT NULL,
+            detected_at INTEGER NOT NULL
+        );",
+    ),
+    Migration::Sql(
+        "CREATE TABLE rule_groups (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE,
+            enabled INTEGER NOT NULL DEFAULT 1
+        );
+         ALTER TABLE rules ADD COLUMN group_id INTEGER REFERENCES rule_groups (id);
+         CREATE INDEX idx_rules_group_id ON rules (group_id);",
+    ),
+    Migration::Sql("ALTER TABLE rules ADD COLUMN schedule TEXT;"),
+    Migration::Sql(
+        "ALTER TABLE path_mappings ADD COLUMN match_count INTEGER NOT NULL DEFAULT 0;
+         ALTER TABLE path_mappings ADD COLUMN last_matched_at INTEGER;
+         ALTER TABLE rules ADD COLUMN match_count INTEGER NOT NULL DEFAULT 0;
+         ALTER TABLE rules ADD COLUMN last_matched_at INTEGER;",
+    ),
+    Migration::Sql(
+        "CREATE TABLE observed_moves (
+            extension TEXT NOT NULL,
+            target_dir TEXT NOT NULL,
+            count INTEGER NOT NULL,
+            last_seen INTEGER NOT NULL,
+            PRIMARY KEY (extension, target_dir)
+        );",
+    ),
+    Migration::Sql(
+        "ALTER TABLE path_mappings ADD COLUMN category_id TEXT;
+         CREATE INDEX idx_path_mappings_category_id ON path_mappings (category_id);",
+    ),
+    Migration::Code(backfill_category_ids),
+    Migration::Sql(
+        "CREATE TABLE sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            trigger TEXT NOT NULL,
+            started_at INTEGER NOT NULL,
+            finished_at INTEGER NOT NULL
+        );
+         CREATE TABLE history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            session_id INTEGER NOT NULL REFERENCES sessions (id),
+            action TEXT NOT NULL,
+            source TEXT,
+            destination TEXT,
+            size INTEGER,
+            decided_by TEXT,
+            message TEXT,
+            created_at INTEGER NOT NULL
+        );
+         CREATE INDEX idx_history_session_id ON history (session_id);
+         CREATE INDEX idx_history_created_at ON history (created_at);",
+    ),
+    Migration::Sql(
+        "CREATE TABLE pending_retries (
+            source TEXT PRIMARY KEY,
+            target_dir TEXT NOT NULL,
+            error_kind TEXT NOT NULL,
+            message TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 1,
+            last_attempt_at INTEGER NOT NULL
+        );",
+    ),
+    Migration::Sql("ALTER TABLE path_mappings ADD COLUMN enabled INTEGER NOT NULL DEFAULT 1;"),
+    Migration::Sql(
+        "CREATE TABLE categories (
+            id TEXT PRIMARY KEY,
+            parent_id TEXT REFERENCES categories (id),
+            name TEXT NOT NULL,
+            target_path TEXT NOT NULL
+        );
+         CREATE INDEX idx_categories_parent_id ON categories (parent_id);
+         ALTER TABLE rules ADD COLUMN category_id TEXT;",
+    ),
+    Migration::Code(categories::backfill),
+    Migration::Sql(
+        "CREATE TABLE pending_moves (
+            id INTEGER PRIMARY KEY,
+            source TEXT NOT NULL UNIQUE,
+            target_dir TEXT NOT NULL,
+            decided_by TEXT NOT NULL,
+            matched TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            proposed_at INTEGER NOT NULL,
+            rejected INTEGER NOT NULL DEFAULT 0
+        );",
+    ),
+    Migration::Sql(
+        "CREATE TABLE staged_moves (
+            staged_path TEXT PRIMARY KEY,
+            source TEXT NOT NULL,
+            target_dir TEXT NOT NULL,
+            decided_by TEXT NOT NULL,
+            matched TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            staged_at INTEGER NOT NULL
+        );",
+    ),
+    Migration::Sql(
+        "CREATE TABLE sources (
+            id INTEGER PRIMARY KEY,
+            path TEXT UNIQUE,
+            enabled INTEGER NOT NULL DEFAULT 1,
+            depth INTEGER NOT NULL DEFAULT 1,
+            include_hidden INTEGER NOT NULL DEFAULT 0,
+            ignore_patterns TEXT NOT NULL DEFAULT '[]'
+        );",
+    ),
+    Migration::Code(sources::seed),
+    Migration::Sql(
+        "CREATE TABLE source_mappings (
+            source_id INTEGER NOT NULL,
+            extension TEXT NOT NULL,
+            target_path TEXT NOT NULL,
+            PRIMARY KEY (source_id, extension)
+        );",
+    ),
+    Migration::Sql("ALTER TABLE sources ADD COLUMN include_patterns TEXT NOT NULL DEFAULT '[]';"),
+    Migration::Sql(
+        "ALTER TABLE path_mappings ADD COLUMN min_size INTEGER;
+         ALTER TABLE path_mappings ADD COLUMN max_size INTEGER;",
+    ),
+    Migration::Sql("ALTER TABLE rules ADD COLUMN action TEXT;"),
+    Migration::Sql(
+        "ALTER TABLE history ADD COLUMN trash_location TEXT;
+         UPDATE history SET trash_location = destination, destination = NULL WHERE action = 'trashed';",
+    ),
+    Migration::Sql(
+        "CREATE TABLE desktop_sightings (
+            path TEXT PRIMARY KEY,
+            first_seen INTEGER NOT NULL
+        );",
+    ),
+    Migration::Sql(
+        "CREATE TABLE ruleset_subscriptions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            url TEXT NOT NULL,
+            strategy TEXT NOT NULL,
+            sha256 TEXT,
+            secret TEXT,
+            last_hash TEXT,
+            checked_at INTEGER,
+            last_error TEXT
+        );",
+    ),
+    Migration::Sql(
+        "CREATE TABLE ruleset_sync (
+            location TEXT PRIMARY KEY,
+            base TEXT NOT NULL,
+            synced_at INTEGER NOT NULL
+        );",
+    ),
+    Migration::Sql("ALTER TABLE path_mappings ADD COLUMN description TEXT;"),
+    Migration::Sql(
+        "CREATE TABLE mime_mappings (
+            mime_type TEXT PRIMARY KEY,
+            target_path TEXT NOT NULL,
+            match_count INTEGER NOT NULL DEFAULT 0,
+            last_matched_at INTEGER
+        );",
+    ),
+    Migration::Sql("ALTER TABLE sessions ADD COLUMN simulated INTEGER NOT NULL DEFAULT 0;"),
+    Migration::Sql("CREATE TABLE weekly_reports (sent_at INTEGER NOT NULL);"),
+    Migration::Code(settings::keep_previous_defaults),
+    Migration::Sql("CREATE TABLE stale_reminders (reminded_at INTEGER NOT NULL);"),
+    Migration::Sql("CREATE TABLE pin_failures (failed_at INTEGER NOT NULL);"),
+];
+
+fn migrate(conn: &mut Connection) -> Result<(), Error> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tracing::info!("Applying migration {}...", i + 1);
+        let tx = conn.transaction()?;
+        match migration {
+            Migration::Sql(sql) => tx.execute_batch(sql)?,
+            Migration::Code(apply) => apply(&tx)?,
+        }
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Every target folder that was created up front, and the ones that couldn't be.
+#[derive(Serialize)]
+pub struct TargetDirsReport {
+    created: Vec<String>,
+    pub errors: Vec<SortError>,
+}
+
+/// Creates the target folders of `mappings` that don't exist yet. Targets on
+/// disconnected drives or shares are left for later.
+pub fn create_target_dirs(fs: &dyn FileSystem, mappings: &MappingCache, folder_icons: bool) -> TargetDirsReport {
+    let mut report = TargetDirsReport {
+        created: Vec::new(),
+        errors: Vec::new(),
+    };
+    let targets: BTreeSet<&Path> = mappings.target_dirs().collect();
+    for dir in targets {
+        if orphans::is_disconnected(dir) {
+            continue;
+        }
+        match executor::create_target_dir(fs, dir, folder_icons) {
+            Ok(true) => report.created.push(dir.display().to_string()),
+            Ok(false) => {}
+            Err(e) => report.errors.push(SortError::from_io(
+                FailedStep::CreateDir,
+                dir,
+                &e,
+                Message::new("sort.create_dir_failed").arg("path", dir.display()).arg("detail", &e),
+            )),
+        }
+    }
+    if !report.created.is_empty() {
+        tracing::info!("Created {} target folders", report.created.len());
+    }
+    report
+}
+
+/// Name of the category a target folder represents: its last path component.
+pub fn category_for_target(target_path: &str) -> String {
+    Path::new(target_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn backfill_category_ids(conn: &Connection) -> Result<(), Error> {
+    let mappings: Vec<(String, String)> = conn
+        .prepare("SELECT extension, category FROM path_mappings WHERE category IS NOT NULL")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+
+    for (extension, category) in mappings {
+        conn.execute(
+            "UPDATE path_mappings SET category_id = ? WHERE extension = ?",
+            params![defaults::category_id(&category), extension],
+        )?;
+    }
+    Ok(())
+}
+
+fn backfill_mapping_categories(conn: &Connection) -> Result<(), Error> {
+    let mappings: Vec<(String, String)> = conn
+        .prepare("SELECT extension, target_path FROM path_mappings WHERE category IS NULL")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+
+    for (extension, target_path) in mappings {
+        conn.execute(
+            "UPDATE path_mappings SET category = ? WHERE extension = ?",
+            params![category_for_target(&target_path), extension],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn get_db_path() -> Result<PathBuf, Error> {
+    let config_dir = dirs::config_dir().ok_or(Error::ConfigDirNotFound)?;
+    let db_dir = config_dir.join("desksort");
+    fs::create_dir_all(&db_dir)?;
+    Ok(db_dir.join("settings.db"))
+}
+
+/// Opens `dir` in the platform file manager.
+pub fn open_in_file_manager(dir: &Path) -> Result<(), Error> {
+    let opener = if cfg!(target_os = "windows") {
+        "explorer"
+    } else if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    // Not waited on: explorer exits with 1 even on success, and the file
+    // manager may keep running
+    std::process::Command::new(opener).arg(dir).spawn()?;
+    Ok(())
+}
+
+pub fn get_desktop_path() -> Result<PathBuf, Error> {
+    desktop::path()
+}
+
+/// Creates `path` if it is missing, and reports whether it did.
+pub fn ensure_dir_exists(path: &Path) -> std::io::Result<bool> {
+    let path = &mover::long_path(path);
+    if path.exists() {
+        return Ok(false);
+    }
+    fs::create_dir_all(path)?;
+    Ok(true)
+}
+
+fn nfc(name: &str) -> String {
+    name.nfc().collect()
+}
+
+/// Picks a free name for `file_name` inside `dir`, renaming it as `naming` says on
+/// collision. Names are compared in NFC so a decomposed `Café.pdf` collides with a composed one,
+/// and case-insensitively unless the target filesystem is known to be case-sensitive.
+/// `reserved` holds paths already promised to moves that haven't happened yet.
+pub fn unique_target_path(
+    fs: &dyn FileSystem,
+    dir: &Path,
+    file_name: &std::ffi::OsStr,
+    reserved: &HashSet<PathBuf>,
+    naming: naming::CollisionNaming,
+) -> PathBuf {
+    let case_sensitive = fs.is_case_sensitive(dir);
+    let name_key = |name: &std::ffi::OsStr| {
+        let name = nfc(&name.to_string_lossy());
+        if case_sensitive {
+            name
+        } else {
+            name.to_lowercase()
+        }
+    };
+
+    let mut existing: HashSet<String> = fs
+        .read_dir(&mover::long_path(dir))
+        .map(|entries| entries.iter().filter_map(|e| e.path.file_name()).map(name_key).collect())
+        .unwrap_or_default();
+    existing.extend(
+        reserved
+            .iter()
+            .filter(|path| path.parent() == Some(dir))
+            .filter_map(|path| path.file_name())
+            .map(name_key),
+    );
+    let is_taken = |path: &Path| {
+        fs.exists(&mover::long_path(path)) || existing.contains(&name_key(path.file_name().unwrap()))
+    };
+
+    let name = file_name.to_string_lossy();
+    let mut attempt = 1;
+    let mut final_path = dir.join(file_name);
+
+    while is_taken(&final_path) {
+        final_path = dir.join(naming.alternative(&name, attempt));
+        attempt += 1;
+    }
+
+    final_path
+}
+
+/// A top-level entry of a source folder and the mapping key it is looked up by.
+pub struct ScannedEntry {
+    path: PathBuf,
+    key: String,
+    is_dir: bool,
+    fs: Arc<dyn FileSystem>,
+    /// Computed on first use; folders need a full walk.
+    size: OnceCell<u64>,
+    source_domain: OnceCell<Option<String>>,
+    mime_type: OnceCell<Option<&'static str>>,
+    contents: OnceCell<folder_contents::FolderContents>,
+}
+
+impl ScannedEntry {
+    fn new(fs: &Arc<dyn FileSystem>, path: PathBuf) -> Self {
+        let is_dir = fs.is_dir(&path);
+        Self::with_kind(fs, path, is_dir)
+    }
+
+    fn with_kind(fs: &Arc<dyn FileSystem>, path: PathBuf, is_dir: bool) -> Self {
+        ScannedEntry {
+            key: key_for(&path, is_dir),
+            is_dir,
+            fs: fs.clone(),
+            size: OnceCell::new(),
+            source_domain: OnceCell::new(),
+            mime_type: OnceCell::new(),
+            contents: OnceCell::new(),
+            path,
+        }
+    }
+
+    fn name(&self) -> std::borrow::Cow<'_, str> {
+        self.path.file_name().unwrap_or_default().to_string_lossy()
+    }
+
+    fn size(&self) -> u64 {
+        *self.size.get_or_init(|| mover::entry_size(&*self.fs, &self.path))
+    }
+
+    /// The domain the entry was downloaded from, if that was recorded.
+    fn source_domain(&self) -> Option<&str> {
+        self.source_domain
+            .get_or_init(|| self.fs.source_domain(&self.path))
+            .as_deref()
+    }
+
+    /// The MIME type the first bytes of the file say it has.
+    fn mime_type(&self) -> Option<&'static str> {
+        *self.mime_type.get_or_init(|| {
+            let mut header = Vec::with_capacity(mime::HEADER_LEN);
+            let reader = self.fs.open(&self.path).ok()?;
+            reader.take(mime::HEADER_LEN as u64).read_to_end(&mut header).ok()?;
+            mime::sniff(&header)
+        })
+    }
+
+    /// What the folder holds, looked through on first use.
+    fn contents(&self) -> &folder_contents::FolderContents {
+        self.contents
+            .get_or_init(|| folder_contents::inspect(&*self.fs, &self.path))
+    }
+}
+
+/// Key of files without an extension, like `Makefile`.
+pub const NO_EXTENSION: &str = "noext";
+
+/// The key `path` is looked up by in `path_mappings`: `folder` for
+/// directories, the lowercased extension with its dot for files, or
+/// `noext` for files without one.
+fn mapping_key(fs: &dyn FileSystem, path: &Path) -> String {
+    key_for(path, fs.is_dir(path))
+}
+
+fn key_for(path: &Path, is_dir: bool) -> String {
+    if is_dir {
+        String::from("folder")
+    } else {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| format!(".{}", e.to_lowercase()))
+            .unwrap_or_else(|| NO_EXTENSION.to_string())
+    }
+}
+
+/// Lists the entries of `source` that are candidates for sorting, down to its
+/// depth. Folders holding a target in `targets` are never looked into. Entries
+/// that can't be read are reported in `errors`, hidden, ignored and not
+/// included ones in `skipped`.
+fn scan_entries(
+    fs: &Arc<dyn FileSystem>,
+    source: &sources::Source,
+    targets: &[&Path],
+    errors: &mut Vec<SortError>,
+    skipped: &mut Vec<SkippedFile>,
+) -> Vec<ScannedEntry> {
+    let _span = tracing::info_span!("scan", source = %source.path.display()).entered();
+    let mut entries = Vec::new();
+
+    // Depth-first, like a directory walk, with the level of each folder
+    let mut pending = vec![(source.path.clone(), 1)];
+    while let Some((dir, depth)) = pending.pop() {
+        let children = match fs.read_dir(&dir) {
+            Ok(children) => children,
+            Err(e) => {
+                errors.push(SortError::new(
+                    FailedStep::Scan,
+                    Message::new("sort.scan_failed").arg("path", dir.display()).arg("detail", e),
+                ));
+                continue;
+            }
+        };
+
+        let mut subdirs = Vec::new();
+        for entry in children {
+            let name = entry.path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            let reason = if !source.include_hidden && skipped::is_hidden(&entry.path) {
+                Some(SkipReason::Hidden)
+            } else if source.ignores(&name) {
+                Some(SkipReason::Ignored)
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                tracing::debug!(path = %entry.path.display(), ?reason, "skipped");
+                skipped.push(SkippedFile::new(&**fs, &entry.path, reason));
+                continue;
+            }
+            // Folders above the last level are looked into rather than moved
+            let holds_target = entry.is_dir && targets.iter().any(|target| target.starts_with(&entry.path));
+            if entry.is_dir && depth < source.depth as usize && !holds_target {
+                subdirs.push((entry.path, depth + 1));
+                continue;
+            }
+            if !source.includes(&name) {
+                tracing::debug!(path = %entry.path.display(), reason = ?SkipReason::NotIncluded, "skipped");
+                skipped.push(SkippedFile::new(&**fs, &entry.path, SkipReason::NotIncluded));
+                continue;
+            }
+            entries.push(ScannedEntry::with_kind(fs, entry.path, entry.is_dir));
+        }
+        pending.extend(subdirs.into_iter().rev());
+    }
+
+    tracing::debug!(entries = entries.len(), "scanned");
+    entries
+}
+
+/// Lists the entries of every enabled source, as `scan_entries` does. With
+/// `public_desktop` on, the public desktop is scanned with the desktop's
+/// options, leaving out what only an administrator could move.
+fn scan_sources(
+    conn: &Connection,
+    fs: &Arc<dyn FileSystem>,
+    mappings: &MappingCache,
+    errors: &mut Vec<SortError>,
+    skipped: &mut Vec<SkippedFile>,
+) -> Result<Vec<ScannedEntry>, Error> {
+    let targets: Vec<&Path> = mappings.target_dirs().collect();
+    let public_desktop = if Settings::load(conn)?.public_desktop { desktop::public() } else { None };
+    let mut entries = Vec::new();
+    for source in sources::enabled(conn)? {
+        if let Some(public) = public_desktop.as_ref().filter(|_| source.is_desktop) {
+            let public = sources::Source { path: public.clone(), ..source.clone() };
+            for entry in scan_entries(fs, &public, &targets, errors, skipped) {
+                if desktop::needs_elevation(&entry.path) {
+                    skipped.push(SkippedFile::new(&**fs, &entry.path, SkipReason::NeedsElevation));
+                } else {
+                    entries.push(entry);
+                }
+            }
+        }
+        entries.extend(scan_entries(fs, &source, &targets, errors, skipped));
+    }
+    Ok(entries)
+}
+
+/// Entries to sort and the errors hit while listing them.
+struct Scan {
+    entries: Vec<ScannedEntry>,
+    errors: Vec<SortError>,
+    skipped: Vec<SkippedFile>,
+    /// Whether `entries` is a whole source folder rather than a selection, so
+    /// the conflict inbox can be rebuilt from it.
+    complete: bool,
+}
+
+/// Target folder and quota of a mapping, as returned by a mapping lookup.
+type MappingTarget = (PathBuf, Quota);
+
+/// Sizes in bytes, both inclusive, a mapping is limited to.
+#[derive(Clone, Copy)]
+struct SizeBounds {
+    min: Option<u64>,
+    max: Option<u64>,
+}
+
+impl SizeBounds {
+    fn contains(&self, size: u64) -> bool {
+        self.min.is_none_or(|min| size >= min) && self.max.is_none_or(|max| size <= max)
+    }
+}
+
+/// The outcome of matching a scan.
+struct Plan {
+    moves: Vec<PlannedMove>,
+    /// Entries whose matches disagree, left for the user to decide.
+    conflicts: Vec<(PathBuf, Vec<Candidate>)>,
+    /// Entries no rule or mapping matched.
+    unmatched: Vec<PathBuf>,
+    /// Entries a rule that deletes matched, past their retention period.
+    deletions: Vec<PlannedMove>,
+    /// Entries a rule that deletes matched, still within it.
+    retained: Vec<PathBuf>,
+    /// Entries a rule that skips matched.
+    kept: Vec<PathBuf>,
+}
+
+/// Pairs every scanned entry that has a rule or mapping with its target.
+fn match_entries(
+    entries: Vec<ScannedEntry>,
+    lookup: &mut dyn FnMut(&ScannedEntry) -> Result<Decision, Error>,
+) -> Result<Plan, Error> {
+    let _span = tracing::info_span!("match", entries = entries.len()).entered();
+    let mut plan = Plan {
+        moves: Vec::new(),
+        conflicts: Vec::new(),
+        unmatched: Vec::new(),
+        deletions: Vec::new(),
+        retained: Vec::new(),
+        kept: Vec::new(),
+    };
+    for entry in entries {
+        match lookup(&entry)? {
+            Decision::Unmatched => {
+                tracing::debug!(path = %entry.path.display(), key = %entry.key, "no rule or mapping");
+                plan.unmatched.push(entry.path)
+            }
+            Decision::Target(candidate, _) if candidate.skips => {
+                tracing::debug!(path = %entry.path.display(), matched_by = %candidate.matched_by, "kept");
+                plan.kept.push(entry.path)
+            }
+            Decision::Target(candidate, reason) => {
+                tracing::debug!(
+                    path = %entry.path.display(),
+                    target = %candidate.target_dir.display(),
+                    matched_by = %candidate.matched_by,
+                    reason,
+                    "matched"
+                );
+                let planned = PlannedMove {
+                    size: entry.size(),
+                    source: entry.path,
+                    target_dir: candidate.target_dir,
+                    quota: candidate.quota,
+                    decided_by: format!("{}, {}", candidate.matched_by, reason),
+                    matched: candidate.source,
+                };
+                match candidate.retention {
+                    None => plan.moves.push(planned),
+                    Some(after) if archival::is_untouched_for(&planned.source, after) => plan.deletions.push(planned),
+                    Some(_) => plan.retained.push(planned.source),
+                }
+            }
+            Decision::Ambiguous(candidates) => {
+                tracing::debug!(path = %entry.path.display(), candidates = candidates.len(), "ambiguous");
+                plan.conflicts.push((entry.path, candidates))
+            }
+        }
+    }
+    Ok(plan)
+}
+
+/// Pairs every entry that has a rule or mapping with its target. Rule
+/// schedules are checked against the local time.
+fn plan_moves(mappings: &MappingCache, semantics: RuleSemantics, entries: Vec<ScannedEntry>) -> Result<Plan, Error> {
+    let now = chrono::Local::now().naive_local();
+    match_entries(entries, &mut |entry| Ok(mappings.lookup(entry, semantics, now)))
+}
+
+/// Bumps the match counters of the rules and mappings that routed `plan`.
+fn record_matches(conn: &Connection, plan: &[PlannedMove]) -> Result<(), Error> {
+    let mut counts: HashMap<&MatchSource, u64> = HashMap::new();
+    for planned in plan {
+        *counts.entry(&planned.matched).or_default() += 1;
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    for (source, count) in counts {
+        match source {
+            MatchSource::Rule(id) => conn.execute(
+                "UPDATE rules SET match_count = match_count + ?, last_matched_at = ? WHERE id = ?",
+                params![count, now, id],
+            )?,
+            MatchSource::Mapping(extension) => conn.execute(
+                "UPDATE path_mappings SET match_count = match_count + ?, last_matched_at = ?
+                 WHERE extension = ?",
+                params![count, now, extension],
+            )?,
+            MatchSource::Mime(pattern) => conn.execute(
+                "UPDATE mime_mappings SET match_count = match_count + ?, last_matched_at = ?
+                 WHERE mime_type = ?",
+                params![count, now, pattern],
+            )?,
+            MatchSource::CameraImport
+            | MatchSource::SourceMapping(..)
+            | MatchSource::Archive
+            | MatchSource::Project(_) => 0,
+        };
+    }
+    Ok(())
+}
+
+/// Moves the entries `extension` maps to out of `from`, its mapping's
+/// previous target, into its current one.
+pub fn migrate_mapping(
+    conn: &Connection,
+    ctx: &SortContext,
+    extension: &str,
+    from: &Path,
+) -> Result<SortResult, Error> {
+    let Some((target_dir, quota)) = ctx.mappings.targets.get(extension).cloned() else {
+        return Ok(SortResult::new(Vec::new(), Vec::new()));
+    };
+    tracing::info!("Migrating {} entries from {}", extension, from.display());
+    move_folder_contents(
+        conn,
+        ctx,
+        &[(from.to_path_buf(), target_dir, quota)],
+        &|entry| ctx.mappings.mapping_key(&entry.key) == Some(extension),
+        &format!("{} moved to its mapping's new target", extension),
+    )
+}
+
+/// Moves the entries `select` picks out of each `(from, to, quota)` folder
+/// into `to`, as a history session of its own. Folders holding targets or
+/// other `from` folders, and unfinished downloads, stay put.
+fn move_folder_contents(
+    conn: &Connection,
+    ctx: &SortContext,
+    folders: &[(PathBuf, PathBuf, Quota)],
+    select: &dyn Fn(&ScannedEntry) -> bool,
+    decided_by: &str,
+) -> Result<SortResult, Error> {
+    let started = (std::time::Instant::now(), chrono::Utc::now().timestamp());
+    let mappings = &ctx.mappings;
+    let mut skipped_files = Vec::new();
+    let mut plan = Vec::new();
+    for (from, to, quota) in folders {
+        let entries: Vec<ScannedEntry> = ctx
+            .fs
+            .read_dir(&mover::long_path(from))
+            .into_iter()
+            .flatten()
+            .map(|entry| ScannedEntry::new(&ctx.fs, from.join(entry.path.file_name().unwrap())))
+            .filter(|entry| !skipped::is_hidden(&entry.path) && select(entry))
+            .collect();
+        let targets = mappings.target_dirs().chain(folders.iter().map(|(from, _, _)| from.as_path()));
+        let filter = skipped::Filter::new(targets, Duration::ZERO, &entries);
+        for entry in entries {
+            if let Some(reason) = filter.check(&entry) {
+                skipped_files.push(SkippedFile::new(&*ctx.fs, &entry.path, reason));
+                continue;
+            }
+            plan.push(PlannedMove {
+                size: entry.size(),
+                target_dir: to.clone(),
+                quota: *quota,
+                decided_by: decided_by.to_string(),
+                // Counted under the category its mapping names
+                matched: MatchSource::Mapping(entry.key),
+                source: entry.path,
+            });
+        }
+    }
+
+    let mut result = SortResult::new(Vec::new(), skipped_files);
+    result.decisions.extend(plan.iter().map(|m| MatchDecision {
+        source: m.source.display().to_string(),
+        target_dir: Some(m.target_dir.display().to_string()),
+        semantics: RuleSemantics::FirstMatch,
+        decided_by: m.decided_by.clone(),
+    }));
+    execute_plan(conn, ctx, plan, history::Trigger::Migration, started, result)
+}
+
+/// Sorts the entries of every enabled source.
+pub fn sort_sources(conn: &Connection, ctx: &SortContext, trigger: history::Trigger) -> Result<SortResult, Error> {
+    let (mut errors, mut skipped) = (Vec::new(), Vec::new());
+    let entries = scan_sources(conn, &ctx.fs, &ctx.mappings, &mut errors, &mut skipped)?;
+    let scan = Scan {
+        entries,
+        errors,
+        skipped,
+        complete: true,
+    };
+    sort_entries(conn, ctx, scan, trigger)
+}
+
+/// Sorts the entries at `paths` and nothing else, wherever they are.
+pub fn sort_paths(
+    conn: &Connection,
+    ctx: &SortContext,
+    paths: impl IntoIterator<Item = PathBuf>,
+    trigger: history::Trigger,
+) -> Result<SortResult, Error> {
+    let scan = Scan {
+        entries: paths.into_iter().map(|path| ScannedEntry::new(&ctx.fs, path)).collect(),
+        errors: Vec::new(),
+        skipped: Vec::new(),
+        complete: false,
+    };
+    sort_entries(conn, ctx, scan, trigger)
+}
+
+/// Sorts the entries of the sources that have `extension`, e.g. right after
+/// it was mapped.
+pub fn sort_extension(conn: &Connection, ctx: &SortContext, extension: &str) -> Result<SortResult, Error> {
+    let mut errors = Vec::new();
+    let entries = scan_sources(conn, &ctx.fs, &ctx.mappings, &mut errors, &mut Vec::new())?
+        .into_iter()
+        .filter(|entry| extension_groups::covers(extension, &entry.key))
+        .collect();
+    let scan = Scan {
+        entries,
+        errors,
+        skipped: Vec::new(),
+        complete: false,
+    };
+    sort_entries(conn, ctx, scan, history::Trigger::Manual)
+}
+
+/// Plans and carries out the moves for `scan`, then records the session in
+/// the history.
+fn sort_entries(
+    conn: &Connection,
+    ctx: &SortContext,
+    scan: Scan,
+    trigger: history::Trigger,
+) -> Result<SortResult, Error> {
+    let _span = tracing::info_span!("sort", ?trigger, entries = scan.entries.len()).entered();
+    let started = std::time::Instant::now();
+    let started_at = chrono::Utc::now().timestamp();
+    let mut result = SortResult::new(scan.errors, scan.skipped);
+
+    let settings = Settings::load(conn)?;
+    let fs = &*ctx.fs;
+    // Drop queued items the user has since moved or deleted themselves
+    let queued: Vec<String> = conn
+        .prepare("SELECT source FROM deferred_moves")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for source in queued.iter().filter(|s| !ctx.fs.exists(Path::new(s))) {
+        conn.execute("DELETE FROM deferred_moves WHERE source = ?", params![source])?;
+    }
+    retries::prune(conn)?;
+    let retrying = retries::sources(conn)?;
+    let scanned: Vec<PathBuf> = scan
+        .entries
+        .iter()
+        .map(|entry| entry.path.clone())
+        .filter(|path| retrying.contains(path))
+        .collect();
+
+    // Planning pass: work out every move up front so totals are known
+    // before anything is touched
+    let mappings = &ctx.mappings;
+    let filter = skipped::Filter::new(
+        mappings.target_dirs(),
+        Duration::from_secs(settings.min_age_secs),
+        &scan.entries,
+    );
+    let mut entries = Vec::new();
+    let mut lock_files = Vec::new();
+    for entry in scan.entries {
+        match filter.check(&entry) {
+            // Only rules that delete may clear out stale locks
+            Some(SkipReason::Protected) if skipped::is_lock_file(&entry.name()) => lock_files.push(entry),
+            Some(reason) => result.skipped_files.push(SkippedFile::new(fs, &entry.path, reason)),
+            None => entries.push(entry),
+        }
+    }
+    let Plan {
+        moves: mut plan,
+        conflicts,
+        unmatched,
+        mut deletions,
+        retained,
+        kept,
+    } = plan_moves(mappings, settings.rule_semantics, entries)?;
+    let large_folders = large_folders::take(&*ctx.fs, &settings, &mut plan);
+    if settings.cloud_placeholders == placeholders::PlaceholderPolicy::Skip {
+        let (online_only, rest): (Vec<PlannedMove>, _) =
+            plan.into_iter().partition(|m| fs.is_placeholder(&m.source));
+        plan = rest;
+        result
+            .skipped_files
+            .extend(online_only.iter().map(|m| SkippedFile::new(fs, &m.source, SkipReason::OnlineOnly)));
+    }
+    let locks = plan_moves(mappings, settings.rule_semantics, lock_files)?;
+    let protected = locks.moves.iter().map(|m| m.source.as_path()).chain(locks.unmatched.iter().map(PathBuf::as_path));
+    result.skipped_files.extend(protected.map(|path| SkippedFile::new(fs, path, SkipReason::Protected)));
+    result
+        .skipped_files
+        .extend(locks.conflicts.iter().map(|(path, _)| SkippedFile::new(fs, path, SkipReason::Protected)));
+    deletions.extend(locks.deletions);
+    result.skipped_files.extend(unmatched.iter().map(|path| SkippedFile::new(fs, path, SkipReason::NoRule)));
+    result.skipped_files.extend(conflicts.iter().map(|(path, _)| SkippedFile::new(fs, path, SkipReason::Conflict)));
+    result
+        .skipped_files
+        .extend(retained.iter().chain(&locks.retained).map(|path| SkippedFile::new(fs, path, SkipReason::Retained)));
+    result
+        .skipped_files
+        .extend(kept.iter().chain(&locks.kept).map(|path| SkippedFile::new(fs, path, SkipReason::Kept)));
+    // Simulated, nothing is queued, but earlier rejections still count
+    let propose = |plan: &[PlannedMove]| match settings.simulation_mode {
+        true => approvals::rejected(conn, plan),
+        false => approvals::propose(conn, plan),
+    };
+    match settings.large_folders {
+        large_folders::LargeFolderPolicy::Skip => result
+            .skipped_files
+            .extend(large_folders.iter().map(|m| SkippedFile::new(fs, &m.source, SkipReason::TooLarge))),
+        large_folders::LargeFolderPolicy::Ask => {
+            let rejected = propose(&large_folders)?;
+            result.pending += large_folders.len() - rejected.len();
+            result.skipped_files.extend(rejected.iter().map(|path| SkippedFile::new(fs, path, SkipReason::Rejected)));
+        }
+    }
+    // Queued entries no rule or mapping sends anywhere any more aren't
+    // failed moves
+    let planned_sources: HashSet<&Path> = plan.iter().map(|m| m.source.as_path()).collect();
+    if !settings.simulation_mode {
+        retries::forget(conn, scanned.iter().map(PathBuf::as_path).filter(|s| !planned_sources.contains(s)))?;
+    }
+
+    // The inbox always reflects the latest full scan
+    if scan.complete {
+        conn.execute("DELETE FROM conflicts", [])?;
+    }
+    for (source, candidates) in &conflicts {
+        conn.execute(
+            "INSERT OR REPLACE INTO conflicts (source, candidates, detected_at) VALUES (?, ?, ?)",
+            params![
+                source.to_string_lossy(),
+                serde_json::to_string(candidates).unwrap_or_default(),
+                chrono::Utc::now().timestamp()
+            ],
+        )?;
+    }
+    // Simulated sorts leave the statistics alone
+    if !settings.simulation_mode {
+        record_matches(conn, &plan)?;
+        record_matches(conn, &deletions)?;
+    }
+    result.decisions.extend(plan.iter().map(|m| MatchDecision {
+        source: m.source.display().to_string(),
+        target_dir: Some(m.target_dir.display().to_string()),
+        semantics: settings.rule_semantics,
+        decided_by: m.decided_by.clone(),
+    }));
+    result.decisions.extend(conflicts.into_iter().map(|(source, candidates)| MatchDecision {
+        source: source.display().to_string(),
+        target_dir: None,
+        semantics: settings.rule_semantics,
+        decided_by: format!(
+            "conflicting matches: {}",
+            candidates
+                .iter()
+                .map(|c| format!("{} -> {}", c.matched_by, c.target_dir.display()))
+                .collect::<Vec<_>>()
+                .join("; ")
+        ),
+    }));
+
+    // Deletions go to the trash, where they can be restored from, so they
+    // don't wait for staging or approval like moves do
+    result.trashed = trash::trash_all(trash::disposal(&settings), &deletions, &mut result.errors);
+
+    if trigger == history::Trigger::Staged {
+        staging::stage(conn, &*ctx.fs, &get_desktop_path()?, &plan, started_at, &mut result)?;
+        result.skipped = result.skipped_files.len();
+        result.duration_ms = started.elapsed().as_millis() as u64;
+        tracing::info!("Staged {} moves", result.staged);
+        return Ok(result);
+    }
+
+    // Moves the user asked to approve first wait in the queue instead
+    if trigger == history::Trigger::Manual && settings.approve_moves {
+        approvals::prune(conn)?;
+        let rejected = propose(&plan)?;
+        result.pending += plan.len() - rejected.len();
+        result.skipped_files.extend(rejected.iter().map(|path| SkippedFile::new(fs, path, SkipReason::Rejected)));
+        if !result.trashed.is_empty() || !result.errors.is_empty() {
+            let mut entries: Vec<_> = result.trashed.iter().map(history::Entry::trashed).collect();
+            entries.extend(result.errors.iter().map(history::Entry::failed));
+            result.session_id = history::record_session(conn, trigger, started_at, &entries)?;
+        }
+        result.skipped = result.skipped_files.len();
+        result.duration_ms = started.elapsed().as_millis() as u64;
+        tracing::info!("Queued {} moves for approval", result.pending);
+        return Ok(result);
+    }
+
+    // Only now, so copies waiting for approval or in staging aren't deleted
+    result.discarded = duplicates::consolidate(
+        settings.duplicate_copies,
+        &*ctx.fs,
+        trash::disposal(&settings),
+        &mut plan,
+    );
+    execute_plan(conn, ctx, plan, trigger, (started, started_at), result)
+}
+
+/// Carries out `plan` and records it as a history session. `result` holds
+/// what planning found, `started` when the run began.
+fn execute_plan(
+    conn: &Connection,
+    ctx: &SortContext,
+    plan: Vec<PlannedMove>,
+    trigger: history::Trigger,
+    (started, started_at): (std::time::Instant, i64),
+    mut result: SortResult,
+) -> Result<SortResult, Error> {
+    let settings = Settings::load(conn)?;
+    let mappings = &ctx.mappings;
+    let totals = PlanTotals {
+        total_items: plan.len(),
+        total_bytes: plan.iter().map(|m| m.size).sum(),
+        hydrate_bytes: plan
+            .iter()
+            .filter(|m| ctx.fs.is_placeholder(&m.source))
+            .map(|m| m.size)
+            .sum(),
+    };
+    ctx.events.plan_ready(&totals);
+
+    // Refuse to start rather than run out of space halfway through a copy
+    let shortages = mover::check_free_space(
+        &*ctx.fs,
+        plan.iter().map(|m| (m.source.as_path(), m.target_dir.as_path())),
+    );
+    if !shortages.is_empty() {
+        result.errors.extend(
+            shortages
+                .into_iter()
+                .map(|message| SortError::new(FailedStep::FreeSpace, message)),
+        );
+        let entries: Vec<_> = result.errors.iter().map(history::Entry::failed).collect();
+        result.session_id = history::record_session(conn, trigger, started_at, &entries)?;
+        result
+            .skipped_files
+            .extend(plan.iter().map(|m| SkippedFile::new(&*ctx.fs, &m.source, SkipReason::NoSpace)));
+        result.skipped = result.skipped_files.len();
+        result.duration_ms = started.elapsed().as_millis() as u64;
+        return Ok(result);
+    }
+
+    // Execution pass
+    let mapping_categories: HashMap<String, String> = conn
+        .prepare("SELECT extension, category FROM path_mappings WHERE category IS NOT NULL")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    // Size, reason and category of each move, for the history and totals
+    let planned: HashMap<PathBuf, (u64, String, String)> = plan
+        .iter()
+        .map(|m| {
+            let category = match &m.matched {
+                MatchSource::Mapping(extension) => mapping_categories.get(extension).cloned(),
+                MatchSource::Rule(_)
+                | MatchSource::SourceMapping(..)
+                | MatchSource::Archive
+                | MatchSource::Mime(_)
+                | MatchSource::Project(_) => None,
+                MatchSource::CameraImport => mappings
+                    .camera_import_dir
+                    .as_deref()
+                    .map(|dir| category_for_target(&dir.to_string_lossy())),
+            }
+            .unwrap_or_else(|| category_for_target(&m.target_dir.to_string_lossy()));
+            (m.source.clone(), (m.size, m.decided_by.clone(), category))
+        })
+        .collect();
+    let options = ExecutionOptions::from_settings(&settings);
+    ctx.activity.begin();
+    let report = executor::execute(
+        &*ctx.fs,
+        plan,
+        &options,
+        &Progress::new(ctx.events, &totals),
+    );
+    ctx.activity.end();
+
+    for (from, to) in &report.rotated {
+        result.moved_files.push(
+            Message::new("sort.rotated")
+                .arg("from", from.display())
+                .arg("to", to.display()),
+        );
+    }
+    for (source, final_path) in &report.moved {
+        if !settings.simulation_mode {
+            conn.execute(
+                "DELETE FROM deferred_moves WHERE source = ?",
+                params![source.to_string_lossy()],
+            )?;
+        }
+        result.moved_files.push(
+            Message::new("sort.moved")
+                .arg("from", source.display())
+                .arg("to", final_path.display()),
+        );
+    }
+    result.errors.extend(report.errors);
+    result.warnings.extend(report.warnings);
+    if !settings.simulation_mode {
+        retries::forget(conn, report.moved.iter().chain(&report.queued).map(|(source, _)| source.as_path()))?;
+        retries::record_failures(conn, &report.failed)?;
+    }
+
+    let mut offline_shares: HashMap<PathBuf, usize> = HashMap::new();
+    for (source, target_dir) in &report.queued {
+        if !settings.simulation_mode {
+            conn.execute(
+                "INSERT OR REPLACE INTO deferred_moves (source, target_dir, reason, queued_at)
+                 VALUES (?, ?, ?, ?)",
+                params![
+                    source.to_string_lossy(),
+                    target_dir.to_string_lossy(),
+                    "share offline",
+                    chrono::Utc::now().timestamp()
+                ],
+            )?;
+        }
+        let root = mover::share_root(target_dir).unwrap_or_else(|| target_dir.clone());
+        *offline_shares.entry(root).or_default() += 1;
+    }
+
+    ctx.events.progress(&SortProgress::new(&totals, totals.total_items, totals.total_bytes, None, None));
+
+    for (root, count) in offline_shares {
+        result.queued_files.push(
+            Message::new("sort.share_offline")
+                .arg("path", root.display())
+                .arg("count", count),
+        );
+    }
+
+    let mut entries = Vec::new();
+    for (source, final_path) in &report.moved {
+        let (size, decided_by, category) = &planned[source];
+        entries.push(history::Entry::moved(source, final_path, *size, decided_by));
+        result.bytes_moved += size;
+        *result.categories.entry(category.clone()).or_default() += 1;
+    }
+    entries.extend(report.rotated.iter().map(|(from, to)| history::Entry::rotated(from, to)));
+    entries.extend(report.queued.iter().map(|(source, target_dir)| history::Entry::queued(source, target_dir)));
+    entries.extend(result.discarded.iter().map(history::Entry::discarded));
+    entries.extend(result.trashed.iter().map(history::Entry::trashed));
+    entries.extend(result.errors.iter().map(history::Entry::failed));
+    result.session_id = history::record_session(conn, trigger, started_at, &entries)?;
+    result.skipped = result.skipped_files.len();
+    result.duration_ms = started.elapsed().as_millis() as u64;
+
+    Ok(result)
+}
+
+/// A single desktop entry and where the current mappings send it.
+pub struct PlannedMove {
+    source: PathBuf,
+    target_dir: PathBuf,
+    quota: Quota,
+    size: u64,
+    /// The rule or mapping that picked the target, and why it won.
+    decided_by: String,
+    matched: MatchSource,
+}
+
+#[derive(Serialize)]
+pub struct SortResult {
+    moved_files: Vec<Message>,
+    errors: Vec<SortError>,
+    /// Moves that went through but couldn't keep all extended attributes or
+    /// ACLs, e.g. on a drive that doesn't support them.
+    warnings: Vec<String>,
+    queued_files: Vec<Message>,
+    /// How every matched entry was routed.
+    decisions: Vec<MatchDecision>,
+    /// History session this run was recorded as.
+    pub session_id: i64,
+    bytes_moved: u64,
+    /// Wall-clock time from scan to the last move.
+    duration_ms: u64,
+    /// Moved entries per category.
+    categories: BTreeMap<String, usize>,
+    /// Number of `skipped_files`.
+    skipped: usize,
+    /// Planned moves waiting for the user's approval instead of carried out.
+    pending: usize,
+    /// Entries moved to the staging folder, waiting for `commit_sort`.
+    staged: usize,
+    /// Copies sent to the trash for being identical to their original.
+    discarded: Vec<duplicates::Discarded>,
+    /// Entries rules that delete sent to the trash.
+    trashed: Vec<trash::Trashed>,
+    /// Scanned entries left where they are, and why.
+    skipped_files: Vec<SkippedFile>,
+}
+
+impl SortResult {
+    fn new(errors: Vec<SortError>, skipped_files: Vec<SkippedFile>) -> Self {
+        SortResult {
+            moved_files: Vec::new(),
+            errors,
+            warnings: Vec::new(),
+            queued_files: Vec::new(),
+            decisions: Vec::new(),
+            session_id: 0,
+            bytes_moved: 0,
+            duration_ms: 0,
+            categories: BTreeMap::new(),
+            skipped: 0,
+            pending: 0,
+            staged: 0,
+            discarded: Vec::new(),
+            trashed: Vec::new(),
+            skipped_files,
+        }
+    }
+}
+
+/// Which rule or mapping decided an entry's target under the configured semantics.
+#[derive(Serialize)]
+pub struct MatchDecision {
+    source: String,
+    /// Unset for entries sent to the conflict inbox.
+    target_dir: Option<String>,
+    semantics: RuleSemantics,
+    decided_by: String,
+}
+
+/// An entry in the conflict inbox, with every rule or mapping that matched it.
+#[derive(Serialize)]
+pub struct Conflict {
+    source: String,
+    candidates: Vec<Candidate>,
+    detected_at: i64,
+}
+
+/// Entries the last sort left alone because their matches disagree.
+pub fn conflicts(conn: &Connection) -> Result<Vec<Conflict>, Error> {
+    let mut stmt = conn.prepare("SELECT source, candidates, detected_at FROM conflicts ORDER BY source")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, i64>(2)?,
+        ))
+    })?;
+
+    let mut result = Vec::new();
+    for row in rows {
+        let (source, candidates, detected_at) = row?;
+        result.push(Conflict {
+            source,
+            candidates: serde_json::from_str(&candidates).unwrap_or_default(),
+            detected_at,
+        });
+    }
+    Ok(result)
+}
+
+/// Payload of the `sort-plan` event, emitted once planning is done and before
+/// the first item is moved.
+#[derive(Serialize, Clone)]
+pub struct PlanTotals {
+    total_items: usize,
+    total_bytes: u64,
+    /// Size of the online-only files in the plan, which the sync client
+    /// downloads when they are hydrated or copied to another drive.
+    hydrate_bytes: u64,
+}
+
+/// Payload of the `sort-progress` event, emitted before each item is processed
+/// and once more when the run is complete (with `current` unset).
+#[derive(Serialize, Clone)]
+pub struct SortProgress {
+    processed_items: usize,
+    total_items: usize,
+    processed_bytes: u64,
+    total_bytes: u64,
+    percent: f64,
+    current: Option<String>,
+    /// Estimated seconds until the run is done; unset until there is enough
+    /// to go on.
+    eta_secs: Option<u64>,
+}
+
+impl SortProgress {
+    fn new(
+        totals: &PlanTotals,
+        processed_items: usize,
+        processed_bytes: u64,
+        current: Option<&Path>,
+        eta_secs: Option<u64>,
+    ) -> Self {
+        // Bytes are the better measure, but a plan of empty files or folders has none
+        let percent = if totals.total_bytes > 0 {
+            processed_bytes as f64 / totals.total_bytes as f64 * 100.0
+        } else if totals.total_items > 0 {
+            processed_items as f64 / totals.total_items as f64 * 100.0
+        } else {
+            100.0
+        };
+
+        SortProgress {
+            processed_items,
+            total_items: totals.total_items,
+            processed_bytes,
+            total_bytes: totals.total_bytes,
+            percent,
+            current: current.map(|p| p.display().to_string()),
+            eta_secs,
+        }
+    }
+}
+
+/// Payload of the `file-progress` event, emitted while an item is copied
+/// across volumes. For folders, `file` is the file inside it being copied.
+#[derive(Serialize, Clone)]
+pub struct FileProgress {
+    source: String,
+    file: String,
+    copied: u64,
+    total: u64,
+    /// Estimated seconds until the whole run is done, as in `SortProgress`.
+    eta_secs: Option<u64>,
+}
+
+/// What a sort needs from whoever runs it.
+pub struct SortContext<'a> {
+    pub mappings: Arc<MappingCache>,
+    /// What entries are scanned and moved on; the disk outside of tests.
+    pub fs: Arc<dyn FileSystem>,
+    /// Marks the moves as DeskSort's own while they run, so they aren't
+    /// learned from.
+    pub activity: &'a learning::SortActivity,
+    pub events: &'a dyn SortEvents,
+}
+
+/// Receives the progress of a sort, e.g. to show it in the frontend.
+pub trait SortEvents: Sync {
+    /// Called once planning is done, before the first item is moved.
+    fn plan_ready(&self, totals: &PlanTotals);
+
+    /// Called before each item is processed and once more when the run is
+    /// complete.
+    fn progress(&self, progress: &SortProgress);
+
+    /// Called while an item is copied across volumes.
+    fn file_progress(&self, progress: &FileProgress);
+}
+
+/// Forwards executor progress to `events`.
+struct Progress<'a> {
+    events: &'a dyn SortEvents,
+    totals: &'a PlanTotals,
+    eta: std::sync::Mutex<Eta>,
+}
+
+impl<'a> Progress<'a> {
+    fn new(events: &'a dyn SortEvents, totals: &'a PlanTotals) -> Self {
+        Progress {
+            events,
+            totals,
+            eta: std::sync::Mutex::new(Eta::new()),
+        }
+    }
+
+    /// Seconds left with `done` of the run's bytes (or items, for a plan
+    /// without bytes) behind it.
+    fn eta_secs(&self, done_bytes: u64, done_items: usize) -> Option<u64> {
+        let (done, total) = if self.totals.total_bytes > 0 {
+            (done_bytes, self.totals.total_bytes)
+        } else {
+            (done_items as u64, self.totals.total_items as u64)
+        };
+        self.eta.lock().unwrap().update(done, total)
+    }
+}
+
+impl ProgressSink for Progress<'_> {
+    fn item_started(&self, processed_items: usize, processed_bytes: u64, source: &Path) {
+        self.eta.lock().unwrap().item_started(processed_bytes, processed_items);
+        let eta_secs = self.eta_secs(processed_bytes, processed_items);
+        self.events.progress(&SortProgress::new(
+            self.totals,
+            processed_items,
+            processed_bytes,
+            Some(source),
+            eta_secs,
+        ));
+    }
+
+    fn file_progress(&self, source: &Path, file: &Path, copied: u64, total: u64) {
+        let (base_bytes, items) = self.eta.lock().unwrap().base;
+        self.events.file_progress(&FileProgress {
+            source: source.display().to_string(),
+            file: file.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            copied,
+            total,
+            eta_secs: self.eta_secs(base_bytes + copied, items),
+        });
+    }
+}
+
+/// Weight of the newest throughput sample in the moving average.
+const ETA_SMOOTHING: f64 = 0.3;
+/// Samples closer together than this are too noisy to average in.
+const ETA_MIN_SAMPLE: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Estimates the time a run has left from a moving average of its throughput,
+/// so a few fast renames or one slow file don't swing the countdown.
+struct Eta {
+    /// Bytes and items of the items started before the current one; copy
+    /// progress within it comes on top.
+    base: (u64, usize),
+    /// Time and amount done at the last sample.
+    last: Option<(std::time::Instant, u64)>,
+    /// Units per second.
+    rate: Option<f64>,
+}
+
+impl Eta {
+    fn new() -> Self {
+        Eta {
+            base: (0, 0),
+            last: None,
+            rate: None,
+        }
+    }
+
+    fn item_started(&mut self, processed_bytes: u64, processed_items: usize) {
+        self.base = (processed_bytes, processed_items);
+    }
+
+    fn update(&mut self, done: u64, total: u64) -> Option<u64> {
+        let now = std::time::Instant::now();
+        match self.last {
+            None => self.last = Some((now, done)),
+            Some((at, before)) if now.duration_since(at) >= ETA_MIN_SAMPLE && done >= before => {
+                let sample = (done - before) as f64 / now.duration_since(at).as_secs_f64();
+                self.rate = Some(match self.rate {
+                    Some(rate) => ETA_SMOOTHING * sample + (1.0 - ETA_SMOOTHING) * rate,
+                    None => sample,
+                });
+                self.last = Some((now, done));
+            }
+            Some(_) => {}
+        }
+        let rate = self.rate.filter(|rate| *rate > 0.0)?;
+        Some((total.saturating_sub(done) as f64 / rate).ceil() as u64)
+    }
+}
+
+/// Files of one extension on the desktop that no rule or mapping handles.
+#[derive(Serialize)]
+pub struct UnmatchedExtension {
+    extension: String,
+    count: usize,
+    total_bytes: u64,
+}
+
+/// Scans the sources for file types no rule or mapping handles, most common
+/// first.
+pub fn unmatched_extensions(
+    conn: &Connection,
+    fs: &Arc<dyn FileSystem>,
+    mappings: &MappingCache,
+) -> Result<Vec<UnmatchedExtension>, Error> {
+    let settings = Settings::load(conn)?;
+    let now = chrono::Local::now().naive_local();
+
+    let mut unmatched: HashMap<String, UnmatchedExtension> = HashMap::new();
+    for entry in scan_sources(conn, fs, mappings, &mut Vec::new(), &mut Vec::new())? {
+        if !matches!(mappings.lookup(&entry, settings.rule_semantics, now), Decision::Unmatched) {
+            continue;
+        }
+        let extension = unmatched.entry(entry.key.clone()).or_insert_with(|| UnmatchedExtension {
+            extension: entry.key.clone(),
+            count: 0,
+            total_bytes: 0,
+        });
+        extension.count += 1;
+        extension.total_bytes += entry.size();
+    }
+
+    let mut unmatched: Vec<_> = unmatched.into_values().collect();
+    unmatched.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.extension.cmp(&b.extension)));
+    Ok(unmatched)
+}
+
+/// A desktop item waiting for its (network) target to become reachable again.
+#[derive(Serialize)]
+pub struct QueuedMove {
+    source: String,
+    target_dir: String,
+    reason: String,
+    queued_at: i64,
+}
+
+pub fn queued_moves(conn: &Connection) -> Result<Vec<QueuedMove>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT source, target_dir, reason, queued_at FROM deferred_moves ORDER BY queued_at",
+    )?;
+    let queued = stmt.query_map([], |row| {
+        Ok(QueuedMove {
+            source: row.get(0)?,
+            target_dir: row.get(1)?,
+            reason: row.get(2)?,
+            queued_at: row.get(3)?,
+        })
+    })?;
+
+    let mut result = Vec::new();
+    for item in queued {
+        result.push(item?);
+    }
+    Ok(result)
+}
+
+/// Renames the default category folders mappings point at when the folder
+/// language changes between `previous` and `settings`. Returns the number of
+/// mappings changed.
+pub fn rename_default_folders(conn: &Connection, previous: &Settings, settings: &Settings) -> Result<usize, Error> {
+    let (from, to) = (previous.folder_language(), settings.folder_language());
+    if from == to {
+        return Ok(0);
+    }
+    let renamed = defaults::rename_default_folders(conn, &get_desktop_path()?.join("Sorted"), from, to)?;
+    tracing::info!("Renamed {} default folders from {} to {}", renamed, from, to);
+    Ok(renamed)
+}
+
+/// Loads `desksort.toml` into `conn`, following a change of folder language.
+pub fn load_config_file(conn: &mut Connection) -> Result<Settings, Error> {
+    let path = config_file::path()?;
+    let previous = Settings::load(conn)?;
+    let settings = config_file::apply(conn, &path)?;
+    // The file should show the renamed folders too
+    if rename_default_folders(conn, &previous, &settings)? > 0 {
+        config_file::write(conn, &path)?;
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filesystem::MemoryFileSystem;
+
+    struct NoEvents;
+
+    impl SortEvents for NoEvents {
+        fn plan_ready(&self, _: &PlanTotals) {}
+        fn progress(&self, _: &SortProgress) {}
+        fn file_progress(&self, _: &FileProgress) {}
+    }
+
+    /// A database mapping `.pdf` to `target_dir`, sorting files of any age.
+    fn test_db(target_dir: &str) -> Connection {
+        test_db_with(target_dir, serde_json::Map::new())
+    }
+
+    fn test_db_with(target_dir: &str, mut changes: serde_json::Map<String, serde_json::Value>) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // A mapping in place before init keeps it from looking for the desktop
+        conn.execute("CREATE TABLE path_mappings (extension TEXT PRIMARY KEY, target_path TEXT NOT NULL)", [])
+            .unwrap();
+        conn.execute("INSERT INTO path_mappings (extension, target_path) VALUES ('.pdf', ?)", [target_dir])
+            .unwrap();
+        init_db(&mut conn).unwrap();
+        changes.insert("min_age_secs".to_string(), 0.into());
+        Settings::update(&conn, changes).unwrap();
+        conn
+    }
+
+    fn sort(conn: &Connection, fs: &Arc<impl FileSystem + 'static>, paths: &[&str]) -> SortResult {
+        // Loading the mappings resolves the Sorted folder, so any real
+        // folder stands in for the desktop; nothing is written to it
+        desktop::set_override(Some(std::env::temp_dir()));
+        let activity = learning::SortActivity::default();
+        let ctx = SortContext {
+            mappings: Arc::new(MappingCache::load(conn).unwrap()),
+            fs: fs.clone(),
+            activity: &activity,
+            events: &NoEvents,
+        };
+        sort_paths(conn, &ctx, paths.iter().map(PathBuf::from), history::Trigger::Manual).unwrap()
+    }
+
+    #[test]
+    fn sorts_next_to_entries_with_the_same_name() {
+        let conn = test_db("/sorted/Documents");
+        let fs = Arc::new(MemoryFileSystem::new());
+        fs.add_file(Path::new("/sorted/Documents/report.pdf"), "old");
+        fs.add_file(Path::new("/desktop/report.pdf"), "new");
+
+        let result = sort(&conn, &fs, &["/desktop/report.pdf"]);
+
+        assert!(result.errors.is_empty());
+        assert_eq!(result.moved_files.len(), 1);
+        assert_eq!(fs.contents(Path::new("/sorted/Documents/report.pdf")).as_deref(), Some(&b"old"[..]));
+        assert_eq!(fs.contents(Path::new("/sorted/Documents/report_1.pdf")).as_deref(), Some(&b"new"[..]));
+        assert!(!fs.exists(Path::new("/desktop/report.pdf")));
+    }
+
+    #[test]
+    fn sorts_across_volumes() {
+        let conn = test_db("/archive/Documents");
+        let fs = Arc::new(MemoryFileSystem::with_volumes([PathBuf::from("/desktop"), PathBuf::from("/archive")]));
+        fs.add_file(Path::new("/desktop/report.pdf"), "report");
+
+        let result = sort(&conn, &fs, &["/desktop/report.pdf"]);
+
+        assert!(result.errors.is_empty());
+        assert_eq!(fs.contents(Path::new("/archive/Documents/report.pdf")).as_deref(), Some(&b"report"[..]));
+        assert!(!fs.exists(Path::new("/desktop/report.pdf")));
+    }
+
+    #[test]
+    fn retries_moves_a_failed_copy_left_behind() {
+        let conn = test_db("/archive/Documents");
+        let fs = Arc::new(MemoryFileSystem::with_volumes([PathBuf::from("/desktop"), PathBuf::from("/archive")]));
+        fs.add_file(Path::new("/desktop/report.pdf"), "the whole report");
+        fs.fail_writes_after(Some(4));
+
+        let result = sort(&conn, &fs, &["/desktop/report.pdf"]);
+
+        assert_eq!(result.errors.len(), 1);
+        assert!(fs.exists(Path::new("/desktop/report.pdf")));
+        assert!(!fs.exists(Path::new("/archive/Documents/report.pdf")));
+        assert!(retries::sources(&conn).unwrap().contains(Path::new("/desktop/report.pdf")));
+
+        fs.fail_writes_after(None);
+        let result = sort(&conn, &fs, &["/desktop/report.pdf"]);
+
+        assert!(result.errors.is_empty());
+        assert_eq!(
+            fs.contents(Path::new("/archive/Documents/report.pdf")).as_deref(),
+            Some(&b"the whole report"[..])
+        );
+        assert!(!fs.exists(Path::new("/desktop/report.pdf")));
+        assert!(retries::sources(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn simulated_sorts_report_moves_to_approve_without_queuing_them() {
+        let mut changes = serde_json::Map::new();
+        changes.insert("approve_moves".to_string(), true.into());
+        changes.insert("simulation_mode".to_string(), true.into());
+        let conn = test_db_with("/sorted/Documents", changes);
+        let fs = Arc::new(MemoryFileSystem::new());
+        fs.add_file(Path::new("/desktop/report.pdf"), "report");
+
+        let result = sort(&conn, &fs, &["/desktop/report.pdf"]);
+
+        assert_eq!(result.pending, 1);
+        assert!(approvals::load(&conn).unwrap().is_empty());
+        assert!(fs.exists(Path::new("/desktop/report.pdf")));
+    }
+
+    #[test]
+    fn simulated_merges_leave_the_disk_alone() {
+        let root = std::env::temp_dir().join(format!("desksort-merge-{}", std::process::id()));
+        let (desktop, target) = (root.join("desktop"), root.join("sorted").join("Documents"));
+        fs::create_dir_all(&desktop).unwrap();
+        let copy = desktop.join("report (1).pdf");
+        fs::write(&copy, "report").unwrap();
+        let mut changes = serde_json::Map::new();
+        changes.insert("duplicate_copies".to_string(), "merge".into());
+        changes.insert("simulation_mode".to_string(), true.into());
+        let conn = test_db_with(target.to_str().unwrap(), changes);
+        let simulated = Arc::new(filesystem::SimulatedFileSystem::new());
+
+        let result = sort(&conn, &simulated, &[copy.to_str().unwrap()]);
+
+        let on_disk: Vec<PathBuf> = walkdir::WalkDir::new(&root).into_iter().map(|e| e.unwrap().into_path()).collect();
+        let (sorted, copy_left) = (simulated.exists(&target.join("report.pdf")), simulated.exists(&copy));
+        fs::remove_dir_all(&root).unwrap();
+        assert!(result.errors.is_empty());
+        assert!(sorted);
+        assert!(!copy_left);
+        assert_eq!(on_disk, vec![root.clone(), desktop, copy]);
+    }
+
+    #[test]
+    fn weekly_reports_leave_out_simulated_sorts() {
+        let mut changes = serde_json::Map::new();
+        changes.insert("simulation_mode".to_string(), true.into());
+        let conn = test_db_with("/sorted/Documents", changes);
+        let fs = Arc::new(MemoryFileSystem::new());
+        fs.add_file(Path::new("/desktop/simulated.pdf"), "simulated");
+        fs.add_file(Path::new("/desktop/report.pdf"), "report");
+
+        sort(&conn, &fs, &["/desktop/simulated.pdf"]);
+        let mut changes = serde_json::Map::new();
+        changes.insert("simulation_mode".to_string(), false.into());
+        Settings::update(&conn, changes).unwrap();
+        sort(&conn, &fs, &["/desktop/report.pdf"]);
+
+        let report = report::past_week(&conn).unwrap();
+        assert_eq!(report.files_sorted, 1);
+        assert_eq!(report.bytes_organized, 6);
+    }
+
+    #[test]
+    fn existing_installs_keep_the_previous_defaults() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE path_mappings (extension TEXT PRIMARY KEY, target_path TEXT NOT NULL)", [])
+            .unwrap();
+        conn.execute("INSERT INTO path_mappings (extension, target_path) VALUES ('.pdf', '/sorted/Documents')", [])
+            .unwrap();
+        init_db(&mut conn).unwrap();
+
+        let settings = Settings::load(&conn).unwrap();
+
+        assert!(!settings.parallel_moves);
+        assert_eq!(settings.retry_attempts, 0);
+        assert_eq!(settings.min_age_secs, 0);
+        assert!(!settings.camera_imports);
+        assert_eq!(settings.stale_after_days, None);
+        assert_eq!(settings.history_keep_days, None);
+        assert!(settings.project_markers.is_empty());
+        assert_eq!((settings.max_folder_bytes, settings.max_folder_files), (None, None));
+        assert_eq!(settings.cloud_placeholders, placeholders::PlaceholderPolicy::MoveStub);
+    }
+
+    #[test]
+    fn fresh_installs_get_the_current_defaults() {
+        desktop::set_override(Some(std::env::temp_dir()));
+        let mut conn = Connection::open_in_memory().unwrap();
+        init_db(&mut conn).unwrap();
+
+        let settings = Settings::load(&conn).unwrap();
+
+        assert!(settings.parallel_moves);
+        assert_eq!(settings.retry_attempts, 3);
+        assert_eq!(settings.min_age_secs, 10);
+        assert!(settings.camera_imports);
+        assert_eq!(settings.stale_after_days, Some(30));
+        assert_eq!(settings.history_keep_days, Some(365));
+    }
+}
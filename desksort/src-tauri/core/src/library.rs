@@ -0,0 +1,105 @@
+use crate::{
+    camera, filesystem::FileSystem, history, mover, plan_moves, quota, rules::RuleSemantics, settings::Settings, skipped, sort_paths, Error,
+    MappingCache, ScannedEntry, SortContext, SortResult,
+};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// An entry of the library the current rules and mappings send elsewhere.
+#[derive(Serialize)]
+pub struct Move {
+    pub source: String,
+    pub target_dir: String,
+    pub decided_by: String,
+}
+
+#[derive(Serialize)]
+pub struct Resort {
+    /// Entries that are, or on a dry run would be, moved.
+    pub moves: Vec<Move>,
+    /// Entries examined, including the ones already in place.
+    pub examined: usize,
+    /// Unset on a dry run.
+    pub result: Option<SortResult>,
+}
+
+/// Folders sorted entries sit in: every mapping and rule target, and the day
+/// folders below the camera import folder.
+fn placement_dirs(mappings: &MappingCache) -> BTreeSet<PathBuf> {
+    let mut dirs: BTreeSet<PathBuf> = mappings.target_dirs().map(Path::to_path_buf).collect();
+    if let Some(root) = &mappings.camera_import_dir {
+        dirs.extend(camera::day_dirs(root));
+    }
+    dirs
+}
+
+/// Every entry DeskSort placed in the library. Folders holding other targets,
+/// like `Images/Screenshots` inside `Images`, quota overflow folders, hidden
+/// entries and unfinished copies are left out.
+pub fn scan(fs: &Arc<dyn FileSystem>, mappings: &MappingCache) -> Vec<ScannedEntry> {
+    let dirs = placement_dirs(mappings);
+    let mut entries = Vec::new();
+    for dir in &dirs {
+        let Ok(read) = fs.read_dir(dir) else {
+            continue;
+        };
+        for entry in read {
+            let path = entry.path;
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let is_dir = fs.is_dir(&path);
+            if skipped::is_hidden(&path)
+                || mover::is_partial(&name)
+                || (is_dir && quota::is_overflow_dir(&name))
+                || (is_dir && dirs.iter().any(|target| target.starts_with(&path)))
+            {
+                continue;
+            }
+            entries.push(ScannedEntry::new(fs, path));
+        }
+    }
+    entries
+}
+
+/// The entries of `entries` whose target is no longer the folder they are in.
+pub fn plan(mappings: &MappingCache, semantics: RuleSemantics, entries: Vec<ScannedEntry>) -> Result<Vec<Move>, Error> {
+    let plan = plan_moves(mappings, semantics, entries)?;
+    Ok(plan
+        .moves
+        .into_iter()
+        .filter(|planned| planned.source.parent() != Some(planned.target_dir.as_path()))
+        .map(|planned| Move {
+            source: planned.source.display().to_string(),
+            target_dir: planned.target_dir.display().to_string(),
+            decided_by: planned.decided_by,
+        })
+        .collect())
+}
+
+/// Re-applies the current rules and mappings to everything already sorted and
+/// moves what now belongs elsewhere. With `dry_run` the moves are only listed.
+pub fn resort(conn: &Connection, ctx: &SortContext, dry_run: bool) -> Result<Resort, Error> {
+    let entries = scan(&ctx.fs, &ctx.mappings);
+    let examined = entries.len();
+    let moves = plan(&ctx.mappings, Settings::load(conn)?.rule_semantics, entries)?;
+    tracing::info!("{} of {} sorted entries belong elsewhere now", moves.len(), examined);
+    if dry_run || moves.is_empty() {
+        return Ok(Resort {
+            moves,
+            examined,
+            result: None,
+        });
+    }
+
+    let sources: Vec<PathBuf> = moves.iter().map(|m| PathBuf::from(&m.source)).collect();
+    let result = sort_paths(conn, ctx, sources, history::Trigger::Resort)?;
+    Ok(Resort {
+        moves,
+        examined,
+        result: Some(result),
+    })
+}
@@ -0,0 +1,37 @@
+use crate::Error;
+use std::{str::FromStr, sync::OnceLock};
+use tracing_subscriber::{filter::LevelFilter, prelude::*, reload, Registry};
+
+static LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Prints the log, including the scan, match and move spans, to stdout at
+/// `info` until [`set_level`] says otherwise. Only the first call has an
+/// effect.
+pub fn init() {
+    if LEVEL.get().is_some() {
+        return;
+    }
+    let (filter, handle) = reload::Layer::new(LevelFilter::INFO);
+    let installed = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_target(false))
+        .try_init();
+    if installed.is_ok() {
+        let _ = LEVEL.set(handle);
+    }
+}
+
+/// Changes what is logged until the app quits: `error`, `warn`, `info`,
+/// `debug`, `trace` or `off`. `debug` shows why each entry went where it did.
+pub fn set_level(level: &str) -> Result<(), Error> {
+    let filter = LevelFilter::from_str(level.trim())
+        .map_err(|_| Error::InvalidSetting(format!("unknown log level {}", level)))?;
+    let Some(handle) = LEVEL.get() else {
+        return Err(Error::InvalidSetting(String::from("logging isn't set up")));
+    };
+    handle
+        .modify(|current| *current = filter)
+        .map_err(|e| Error::InvalidSetting(format!("failed to change the log level: {}", e)))?;
+    tracing::info!("Log level set to {}", filter);
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use crate::{
+    categories, category_for_target, defaults, escape_like, extension_groups, Error, MappingPage, MappingQuery, PathMapping,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+/// Columns `from_row` reads, in order.
+const COLUMNS: &str = "extension, target_path, max_files, max_bytes, category, category_id, match_count,
+    last_matched_at, enabled, min_size, max_size, description";
+
+/// Most matches `search` returns when no limit is given.
+const SEARCH_LIMIT: usize = 50;
+
+/// Part of a mapping a search matched.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchedField {
+    Extension,
+    TargetPath,
+    Category,
+    Description,
+}
+
+#[derive(Serialize)]
+pub struct MappingMatch {
+    pub mapping: PathMapping,
+    /// Higher is a better match.
+    pub score: u32,
+    pub fields: Vec<MatchedField>,
+}
+
+fn from_row(row: &rusqlite::Row) -> rusqlite::Result<PathMapping> {
+    Ok(PathMapping {
+        extension: row.get(0)?,
+        target_path: row.get(1)?,
+        max_files: row.get(2)?,
+        max_bytes: row.get(3)?,
+        category: row.get(4)?,
+        category_id: row.get(5)?,
+        match_count: row.get(6)?,
+        last_matched_at: row.get(7)?,
+        enabled: row.get(8)?,
+        min_size: row.get(9)?,
+        max_size: row.get(10)?,
+        description: row.get(11)?,
+    })
+}
+
+/// Where `extension` is mapped to, if anywhere.
+pub fn target(conn: &Connection, extension: &str) -> Result<Option<String>, Error> {
+    Ok(conn
+        .query_row(
+            "SELECT target_path FROM path_mappings WHERE extension = ?",
+            params![extension],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// Maps `extension` to `target_path`, filed under `category` or the target's
+/// folder name.
+pub fn save(conn: &Connection, extension: &str, target_path: &str, category: Option<String>) -> Result<(), Error> {
+    if extension_groups::is_group(extension) && extension_groups::extensions(extension).is_none() {
+        return Err(Error::InvalidRule(format!("no extension group `{}`", extension)));
+    }
+    tracing::info!("Setting path mapping: {} -> {}", extension, target_path);
+    let category = category.unwrap_or_else(|| category_for_target(target_path));
+    conn.execute(
+        "INSERT INTO path_mappings (extension, target_path, category, category_id) VALUES (?, ?, ?, ?)
+         ON CONFLICT(extension) DO UPDATE SET
+            target_path = excluded.target_path,
+            category = excluded.category,
+            category_id = excluded.category_id",
+        params![extension, target_path, category, defaults::category_id(&category)],
+    )?;
+    categories::ensure(conn, &defaults::category_id(&category), &category, target_path)
+}
+
+/// Turns the mapping of `extension` off without forgetting it, or back on.
+pub fn set_enabled(conn: &Connection, extension: &str, enabled: bool) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE path_mappings SET enabled = ? WHERE extension = ?",
+        params![enabled, extension],
+    )?;
+    Ok(())
+}
+
+pub fn set_quota(conn: &Connection, extension: &str, max_files: Option<u64>, max_bytes: Option<u64>) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE path_mappings SET max_files = ?, max_bytes = ? WHERE extension = ?",
+        params![max_files, max_bytes, extension],
+    )?;
+    Ok(())
+}
+
+/// Notes why the mapping of `extension` exists, e.g. "client X wants PSDs
+/// on the shared drive". Blank notes are removed.
+pub fn set_description(conn: &Connection, extension: &str, description: Option<&str>) -> Result<(), Error> {
+    let description = description.map(str::trim).filter(|description| !description.is_empty());
+    conn.execute(
+        "UPDATE path_mappings SET description = ? WHERE extension = ?",
+        params![description, extension],
+    )?;
+    Ok(())
+}
+
+/// Limits the mapping of `extension` to files of at least `min_size` and at
+/// most `max_size` bytes.
+pub fn set_size_bounds(
+    conn: &Connection,
+    extension: &str,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+) -> Result<(), Error> {
+    if let (Some(min), Some(max)) = (min_size, max_size) {
+        if min > max {
+            return Err(Error::InvalidRule(format!(
+                "minimum size of {} is above its maximum size",
+                extension
+            )));
+        }
+    }
+    conn.execute(
+        "UPDATE path_mappings SET min_size = ?, max_size = ? WHERE extension = ?",
+        params![min_size, max_size, extension],
+    )?;
+    Ok(())
+}
+
+/// The mappings `query` selects, with how many there are without paging.
+pub fn page(conn: &Connection, query: &MappingQuery) -> Result<MappingPage, Error> {
+    let mut filter = String::from(" WHERE 1 = 1");
+    let mut args: Vec<rusqlite::types::Value> = Vec::new();
+    if let Some(search) = query.search.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+        filter.push_str(
+            " AND (extension LIKE ? ESCAPE '\\' OR target_path LIKE ? ESCAPE '\\'
+                   OR category LIKE ? ESCAPE '\\')",
+        );
+        let pattern = format!("%{}%", escape_like(search));
+        args.extend(std::iter::repeat_n(pattern.into(), 3));
+    }
+    if let Some(category) = &query.category {
+        filter.push_str(" AND category = ?");
+        args.push(category.clone().into());
+    }
+
+    let total: usize = conn.query_row(
+        &format!("SELECT COUNT(*) FROM path_mappings{}", filter),
+        rusqlite::params_from_iter(&args),
+        |row| row.get(0),
+    )?;
+
+    let sql = format!(
+        "SELECT {} FROM path_mappings{} ORDER BY {} {}, extension LIMIT ? OFFSET ?",
+        COLUMNS,
+        filter,
+        query.sort_by.column(),
+        if query.descending { "DESC" } else { "ASC" }
+    );
+    args.push(query.limit.map_or(-1, |limit| limit as i64).into());
+    args.push((query.offset as i64).into());
+
+    let mut stmt = conn.prepare(&sql)?;
+    let mappings = stmt.query_map(rusqlite::params_from_iter(&args), from_row)?;
+
+    let mut result = Vec::new();
+    for mapping in mappings {
+        result.push(mapping?);
+    }
+    tracing::info!("Found {} of {} mappings", result.len(), total);
+    Ok(MappingPage {
+        mappings: result,
+        total,
+    })
+}
+
+/// How well `value` matches the lowercase `text`: fully, at its start or
+/// anywhere, weighted by how telling the field is.
+fn field_score(value: &str, text: &str, weight: u32) -> u32 {
+    let value = value.to_lowercase();
+    if value == text {
+        weight
+    } else if value.starts_with(text) {
+        weight * 2 / 3
+    } else if value.contains(text) {
+        weight / 3
+    } else {
+        0
+    }
+}
+
+/// Mappings whose extension, target path, category or note contain `text`,
+/// best first: an exact extension beats a category, which beats a folder
+/// name, which beats a note.
+pub fn search(conn: &Connection, text: &str, limit: Option<usize>) -> Result<Vec<MappingMatch>, Error> {
+    let text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return Ok(Vec::new());
+    }
+    let pattern = format!("%{}%", escape_like(&text));
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM path_mappings
+         WHERE extension LIKE ?1 ESCAPE '\\' OR target_path LIKE ?1 ESCAPE '\\' OR category LIKE ?1 ESCAPE '\\'
+            OR category_id LIKE ?1 ESCAPE '\\' OR description LIKE ?1 ESCAPE '\\'",
+        COLUMNS
+    ))?;
+    let mappings = stmt.query_map(params![pattern], from_row)?.collect::<Result<Vec<_>, _>>()?;
+
+    // `pdf` and `.pdf` both find the `.pdf` mapping
+    let bare = text.trim_start_matches('.');
+    let mut matches: Vec<MappingMatch> = mappings
+        .into_iter()
+        .map(|mapping| {
+            let folder = std::path::Path::new(&mapping.target_path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let scores = [
+                (MatchedField::Extension, field_score(mapping.extension.trim_start_matches('.'), bare, 100)),
+                (
+                    MatchedField::Category,
+                    field_score(mapping.category.as_deref().unwrap_or_default(), &text, 60)
+                        .max(field_score(mapping.category_id.as_deref().unwrap_or_default(), &text, 60)),
+                ),
+                (
+                    MatchedField::TargetPath,
+                    field_score(&folder, &text, 45).max(field_score(&mapping.target_path, &text, 30)),
+                ),
+                (
+                    MatchedField::Description,
+                    field_score(mapping.description.as_deref().unwrap_or_default(), &text, 30),
+                ),
+            ];
+            MappingMatch {
+                score: scores.iter().map(|(_, score)| score).sum(),
+                fields: scores.iter().filter(|(_, score)| *score > 0).map(|(field, _)| *field).collect(),
+                mapping,
+            }
+        })
+        .filter(|found| found.score > 0)
+        .collect();
+    matches.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.mapping.extension.cmp(&b.mapping.extension)));
+    matches.truncate(limit.unwrap_or(SEARCH_LIMIT));
+    Ok(matches)
+}
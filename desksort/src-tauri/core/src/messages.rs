@@ -0,0 +1,123 @@
+use serde::{Serialize, Serializer};
+use std::{collections::BTreeMap, fmt};
+
+/// Text meant for the user, as a key into the frontend's message catalog
+/// and the values its `{placeholders}` are filled with. Serialized with the
+/// English text too, for clients without a catalog entry for the key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub key: &'static str,
+    pub args: BTreeMap<&'static str, String>,
+}
+
+impl Message {
+    pub fn new(key: &'static str) -> Self {
+        Message {
+            key,
+            args: BTreeMap::new(),
+        }
+    }
+
+    pub fn arg(mut self, name: &'static str, value: impl fmt::Display) -> Self {
+        self.args.insert(name, value.to_string());
+        self
+    }
+}
+
+/// English text of every key, for logs, the history and as the fallback the
+/// frontend shows for keys its catalog lacks.
+const ENGLISH: &[(&str, &str)] = &[
+    ("error.io", "IO error: {detail}"),
+    ("error.db", "Database error: {detail}"),
+    ("error.desktop_not_found", "Desktop path not found"),
+    ("error.config_dir_not_found", "Config directory not found"),
+    ("error.invalid_setting", "Invalid setting: {detail}"),
+    ("error.invalid_rule", "Invalid rule: {detail}"),
+    ("error.invalid_link", "Invalid link: {detail}"),
+    ("error.invalid_archive", "Invalid export file: {detail}"),
+    ("error.encryption", "Encryption error: {detail}"),
+    ("error.app_locked", "Enter the PIN to make changes"),
+    ("error.wrong_pin", "Wrong PIN"),
+    ("error.pin_attempts", "Too many wrong PINs, try again in {secs} seconds"),
+    ("error.invalid_config_file", "Invalid {file}: {detail}"),
+    ("error.invalid_category", "Invalid category: {detail}"),
+    ("error.invalid_source", "Invalid source: {detail}"),
+    ("error.not_restorable", "Cannot restore: {detail}"),
+    ("error.invalid_ruleset", "Invalid ruleset: {detail}"),
+    ("sort.moved", "Moved {from} to {to}"),
+    ("sort.rotated", "Rotated {from} to {to}"),
+    ("sort.staged", "Staged {from} for {to}"),
+    ("sort.put_back", "Put {path} back"),
+    ("sort.share_offline", "{path} is unreachable, queued {count} item(s) until it is back"),
+    ("sort.scan_failed", "Failed to read {path}: {detail}"),
+    ("sort.create_dir_failed", "Failed to create target directory {path}: {detail}"),
+    ("sort.rotate_failed", "Failed to rotate {path} into overflow: {detail}"),
+    ("sort.move_failed", "Failed to move {path}: {detail}"),
+    ("sort.trash_failed", "Failed to move {path} to the trash: {detail}"),
+    ("sort.stage_failed", "Failed to stage {path}: {detail}"),
+    ("sort.put_back_failed", "Failed to put {path} back: {detail}"),
+    ("sort.not_enough_space", "Not enough free space for {path}: {needed} needed, {available} available"),
+    ("sort.free_space_unknown", "Failed to check free space for {path}: {detail}"),
+    ("duplicates.changed", "Left {path} alone, it no longer matches {original}"),
+    ("links.not_broken", "Left {path} alone, it points to something again"),
+    ("report.weekly", "This week: {files} file(s) sorted, {bytes} organized, {streak} week(s) in a row"),
+    ("stale.reminder", "{count} file(s) have been on your desktop for over {days} days"),
+    (
+        "remediation.read_only_source",
+        "The file is on read-only media, so it can be copied but not moved away. Copy it manually or remount the drive as writable.",
+    ),
+    (
+        "remediation.read_only_target",
+        "The target is on read-only media (write-protected drive, mounted image or read-only share). Pick a target on a writable drive.",
+    ),
+    (
+        "remediation.read_source",
+        "DeskSort is not allowed to read this file. Check its permissions, or whether another program has it locked.",
+    ),
+    (
+        "remediation.remove_source",
+        "The file can be read but not removed from its current folder. Check that folder's permissions, or close programs using the file.",
+    ),
+    (
+        "remediation.create_dir",
+        "The target folder could not be created. Grant write access to its parent folder or choose another target in settings.",
+    ),
+    (
+        "remediation.rotate",
+        "Older files could not be moved into the overflow folder. Check write access to the target folder.",
+    ),
+    (
+        "remediation.write_target",
+        "The target folder is not writable. Grant write access to it or choose another target in settings.",
+    ),
+];
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some((_, template)) = ENGLISH.iter().find(|(key, _)| *key == self.key) else {
+            return f.write_str(self.key);
+        };
+        let mut text = template.to_string();
+        for (name, value) in &self.args {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        f.write_str(&text)
+    }
+}
+
+impl Serialize for Message {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Serialized<'a> {
+            key: &'a str,
+            args: &'a BTreeMap<&'static str, String>,
+            text: String,
+        }
+        Serialized {
+            key: self.key,
+            args: &self.args,
+            text: self.to_string(),
+        }
+        .serialize(serializer)
+    }
+}
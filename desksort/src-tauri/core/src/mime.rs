@@ -0,0 +1,185 @@
+use crate::Error;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+/// Bytes read from the start of a file to recognize its type.
+pub const HEADER_LEN: usize = 64;
+
+/// Signatures at the start of a file, with the offset they sit at.
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"BM", "image/bmp"),
+    (0, b"II*\0", "image/tiff"),
+    (0, b"MM\0*", "image/tiff"),
+    (0, b"\0\0\x01\0", "image/x-icon"),
+    (4, b"ftypheic", "image/heic"),
+    (4, b"ftypheix", "image/heic"),
+    (4, b"ftypmif1", "image/heif"),
+    (4, b"ftypavif", "image/avif"),
+    (4, b"ftypqt", "video/quicktime"),
+    (4, b"ftypM4A", "audio/mp4"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"\x1a\x45\xdf\xa3", "video/x-matroska"),
+    (0, b"fLaC", "audio/flac"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"\xff\xfb", "audio/mpeg"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"Rar!\x1a\x07", "application/vnd.rar"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"MZ", "application/x-msdownload"),
+    (0, b"\x7fELF", "application/x-executable"),
+    (0, b"SQLite format 3\0", "application/vnd.sqlite3"),
+    (0, b"{\\rtf", "application/rtf"),
+];
+
+/// What the first bytes of a file say it is. RIFF containers are told apart
+/// by their form type; anything else that is valid UTF-8 without NULs counts
+/// as text.
+pub fn sniff(header: &[u8]) -> Option<&'static str> {
+    if header.len() >= 12 && header.starts_with(b"RIFF") {
+        return match &header[8..12] {
+            b"WEBP" => Some("image/webp"),
+            b"WAVE" => Some("audio/wav"),
+            b"AVI " => Some("video/x-msvideo"),
+            _ => None,
+        };
+    }
+    if let Some((_, _, mime_type)) = SIGNATURES
+        .iter()
+        .find(|(offset, signature, _)| header.get(*offset..).is_some_and(|rest| rest.starts_with(signature)))
+    {
+        return Some(mime_type);
+    }
+    // A multi-byte character may be cut off at the end of the header
+    let text = match std::str::from_utf8(header) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    (!header.is_empty() && text && !header.contains(&0)).then_some("text/plain")
+}
+
+/// The extension files of `mime_type` usually have, for files that lack one.
+/// Plain text is left out, as it could be anything from a `Makefile` to a
+/// licence.
+pub fn extension(mime_type: &str) -> Option<&'static str> {
+    let extension = match mime_type {
+        "image/png" => ".png",
+        "image/jpeg" => ".jpg",
+        "image/gif" => ".gif",
+        "image/bmp" => ".bmp",
+        "image/tiff" => ".tiff",
+        "image/x-icon" => ".ico",
+        "image/heic" | "image/heif" => ".heic",
+        "image/avif" => ".avif",
+        "image/webp" => ".webp",
+        "video/quicktime" => ".mov",
+        "video/mp4" => ".mp4",
+        "video/x-matroska" => ".mkv",
+        "video/x-msvideo" => ".avi",
+        "audio/mp4" => ".m4a",
+        "audio/flac" => ".flac",
+        "audio/ogg" => ".ogg",
+        "audio/mpeg" => ".mp3",
+        "audio/wav" => ".wav",
+        "application/pdf" => ".pdf",
+        "application/zip" => ".zip",
+        "application/vnd.rar" => ".rar",
+        "application/x-7z-compressed" => ".7z",
+        "application/gzip" => ".gz",
+        "application/x-msdownload" => ".exe",
+        "application/vnd.sqlite3" => ".sqlite",
+        "application/rtf" => ".rtf",
+        _ => return None,
+    };
+    Some(extension)
+}
+
+/// Whether `pattern`, a type like `application/pdf` or a family like
+/// `image/*`, covers `mime_type`.
+pub fn matches(pattern: &str, mime_type: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(family) => mime_type
+            .split_once('/')
+            .is_some_and(|(top, _)| top.eq_ignore_ascii_case(family)),
+        None => pattern.eq_ignore_ascii_case(mime_type),
+    }
+}
+
+/// `pattern` lowercased, if it has the shape of a MIME type or family.
+fn normalize(pattern: &str) -> Result<String, Error> {
+    let pattern = pattern.trim().to_lowercase();
+    let valid = pattern.split_once('/').is_some_and(|(top, sub)| {
+        let token = |part: &str| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+        };
+        token(top) && (sub == "*" || token(sub))
+    });
+    if !valid {
+        return Err(Error::InvalidRule(format!(
+            "`{}` is not a MIME type like `application/pdf` or `image/*`",
+            pattern
+        )));
+    }
+    Ok(pattern)
+}
+
+/// Sends files of a MIME type or family somewhere when no extension mapping
+/// matches them.
+#[derive(Serialize)]
+pub struct MimeMapping {
+    pub mime_type: String,
+    pub target_path: String,
+    pub match_count: u64,
+    pub last_matched_at: Option<i64>,
+}
+
+/// Exact types first, as they win over families.
+pub fn load(conn: &Connection) -> Result<Vec<MimeMapping>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT mime_type, target_path, match_count, last_matched_at FROM mime_mappings
+         ORDER BY mime_type LIKE '%/*', mime_type",
+    )?;
+    let mappings = stmt
+        .query_map([], |row| {
+            Ok(MimeMapping {
+                mime_type: row.get(0)?,
+                target_path: row.get(1)?,
+                match_count: row.get(2)?,
+                last_matched_at: row.get(3)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(mappings)
+}
+
+/// Maps `mime_type` to `target_path` and returns the type as stored.
+pub fn save(conn: &Connection, mime_type: &str, target_path: &str) -> Result<String, Error> {
+    let mime_type = normalize(mime_type)?;
+    if target_path.trim().is_empty() {
+        return Err(Error::InvalidRule("target path must not be empty".to_string()));
+    }
+    tracing::info!("Setting MIME mapping: {} -> {}", mime_type, target_path);
+    conn.execute(
+        "INSERT INTO mime_mappings (mime_type, target_path) VALUES (?, ?)
+         ON CONFLICT(mime_type) DO UPDATE SET target_path = excluded.target_path",
+        params![mime_type, target_path],
+    )?;
+    Ok(mime_type)
+}
+
+/// Returns whether `mime_type` was mapped.
+pub fn remove(conn: &Connection, mime_type: &str) -> Result<bool, Error> {
+    Ok(conn.execute(
+        "DELETE FROM mime_mappings WHERE mime_type = ?",
+        params![mime_type.trim().to_lowercase()],
+    )? > 0)
+}
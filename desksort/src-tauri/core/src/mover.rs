@@ -0,0 +1,564 @@
+use crate::{
+    filesystem::FileSystem,
+    messages::Message,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(unix)]
+pub type VolumeId = u64;
+#[cfg(windows)]
+pub type VolumeId = std::ffi::OsString;
+
+/// Converts an absolute Windows path to its `\\?\` extended-length form so file
+/// APIs accept it beyond the 260 character `MAX_PATH` limit. Relative and
+/// already-verbatim paths are returned unchanged.
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> PathBuf {
+    use std::{
+        ffi::OsString,
+        path::{Component, Prefix},
+    };
+
+    let mut components = path.components();
+    let mut result = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(drive) => PathBuf::from(format!(r"\\?\{}:\", drive as char)),
+            Prefix::UNC(server, share) => {
+                let mut root = OsString::from(r"\\?\UNC\");
+                root.push(server);
+                root.push(r"\");
+                root.push(share);
+                root.push(r"\");
+                PathBuf::from(root)
+            }
+            _ => return path.to_path_buf(),
+        },
+        _ => return path.to_path_buf(),
+    };
+
+    // Verbatim paths skip normalization, so `.` and `..` must be resolved here
+    for component in components {
+        match component {
+            Component::ParentDir => {
+                result.pop();
+            }
+            Component::Normal(name) => result.push(name),
+            _ => {}
+        }
+    }
+    result
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Appended to the name of a copy while it is being written, so readers of
+/// the target folder never see a half-written file.
+const PARTIAL_SUFFIX: &str = ".desksort.partial";
+
+/// Whether `name` is that of a copy a move didn't finish, e.g. because the
+/// app was closed halfway through.
+pub fn is_partial(name: &str) -> bool {
+    name.ends_with(PARTIAL_SUFFIX)
+}
+
+/// Size of the buffer used when a move has to fall back to copying.
+const COPY_CHUNK: usize = 1024 * 1024;
+
+/// Called with (file being copied, bytes copied so far, file size) during the
+/// copy fallback of a move.
+pub type Progress<'a> = &'a mut dyn FnMut(&Path, u64, u64);
+
+/// Caps the throughput of copy fallbacks. One instance is shared by all
+/// workers, so the limit applies to the run as a whole.
+pub struct Throttle {
+    bytes_per_sec: u64,
+    window: Mutex<(Instant, u64)>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Throttle {
+            bytes_per_sec: bytes_per_sec.max(1),
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Accounts for `bytes` just written and sleeps for as long as needed to
+    /// stay under the limit. Sleeping under the lock is deliberate: it holds
+    /// back the other workers too.
+    fn consume(&self, bytes: u64) {
+        let mut window = self.window.lock().unwrap();
+        window.1 += bytes;
+
+        let allowed = Duration::from_secs_f64(window.1 as f64 / self.bytes_per_sec as f64);
+        let elapsed = window.0.elapsed();
+        if allowed > elapsed {
+            thread::sleep(allowed - elapsed);
+        }
+
+        // Start a fresh window every second so idle time doesn't build up a burst allowance
+        if window.0.elapsed() >= Duration::from_secs(1) {
+            *window = (Instant::now(), 0);
+        }
+    }
+}
+
+/// Moves `from` to `to`, falling back to copy-then-delete when the two paths
+/// live on different volumes and a plain rename is impossible. The copy is
+/// written under a temporary name next to `to` and only renamed into place
+/// once it is complete and matches the original, so an interrupted move
+/// leaves at most a partial copy behind. Returns a
+/// warning when the copy couldn't keep all timestamps, extended attributes or
+/// ACLs.
+pub fn move_path(
+    fs: &dyn FileSystem,
+    from: &Path,
+    to: &Path,
+    progress: Progress,
+    throttle: Option<&Throttle>,
+) -> io::Result<Option<String>> {
+    let (from, to) = (&long_path(from), &long_path(to));
+    match fs.rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            tracing::debug!(from = %from.display(), to = %to.display(), "different volumes, copying instead");
+            let mut partial = to.as_os_str().to_owned();
+            partial.push(PARTIAL_SUFFIX);
+            let partial = PathBuf::from(partial);
+            let mut lost = Vec::new();
+            let copied = copy_recursive(fs, from, &partial, progress, throttle, &mut lost)
+                .and_then(|()| fs.rename(&partial, to));
+            if let Err(e) = copied {
+                let _ = if fs.is_dir(&partial) {
+                    fs.remove_dir_all(&partial)
+                } else {
+                    fs.remove_file(&partial)
+                };
+                return Err(e);
+            }
+            // Warnings name the copy as it was called while being written
+            let (partial, to_name) = (partial.display().to_string(), to.display().to_string());
+            let mut lost: Vec<String> = lost.into_iter().map(|warning| warning.replace(&partial, &to_name)).collect();
+            if fs.is_dir(from) {
+                fs.remove_dir_all(from)?;
+            } else {
+                fs.remove_file(from)?;
+            }
+            Ok(match lost.len() {
+                0 => None,
+                1 => lost.pop(),
+                n => Some(format!("{} (and {} more in {})", lost.swap_remove(0), n - 1, to.display())),
+            })
+        }
+        other => other.map(|()| None),
+    }
+}
+
+/// Copies `from` to `to`, collecting in `lost` what couldn't be kept of each
+/// copy's timestamps and attributes.
+fn copy_recursive(
+    fs: &dyn FileSystem,
+    from: &Path,
+    to: &Path,
+    progress: Progress,
+    throttle: Option<&Throttle>,
+    lost: &mut Vec<String>,
+) -> io::Result<()> {
+    if fs.is_dir(from) {
+        fs.create_dir_all(to)?;
+        for entry in fs.read_dir(from)? {
+            let name = entry.path.file_name().unwrap();
+            copy_recursive(fs, &entry.path, &to.join(name), progress, throttle, lost)?;
+        }
+    } else {
+        copy_file(fs, from, to, progress, throttle)?;
+    }
+    lost.extend(fs.copy_attributes(from, to)?);
+    Ok(())
+}
+
+/// Chunked replacement for `fs::copy` that reports progress after every chunk,
+/// so multi-gigabyte files don't look stuck. The copy is read back and
+/// compared with a checksum of what was read from `from`.
+fn copy_file(
+    fs: &dyn FileSystem,
+    from: &Path,
+    to: &Path,
+    progress: Progress,
+    throttle: Option<&Throttle>,
+) -> io::Result<()> {
+    let total = fs.metadata(from)?.len;
+    let mut reader = fs.open(from)?;
+    let mut writer = fs.create(to)?;
+
+    let mut buffer = vec![0; COPY_CHUNK];
+    let mut hasher = Sha256::new();
+    let mut copied = 0;
+    progress(from, copied, total);
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buffer[..read]);
+        writer.write_all(&buffer[..read])?;
+        if let Some(throttle) = throttle {
+            throttle.consume(read as u64);
+        }
+        copied += read as u64;
+        progress(from, copied, total);
+    }
+
+    writer.flush()?;
+    drop(writer);
+    if hash_reader(fs.open(to)?)? != <[u8; 32]>::from(hasher.finalize()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the copy of {} doesn't match the original", from.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// SHA-256 of the contents of the file at `path`.
+pub fn hash_file(fs: &dyn FileSystem, path: &Path) -> io::Result<[u8; 32]> {
+    hash_reader(fs.open(path)?)
+}
+
+fn hash_reader(mut file: impl Read) -> io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; COPY_CHUNK];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => hasher.update(&buffer[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hasher.finalize().into())
+}
+
+/// Total size in bytes of a file, or of everything below a directory.
+pub fn entry_size(fs: &dyn FileSystem, path: &Path) -> u64 {
+    let path = &long_path(path);
+    if fs.is_dir(path) {
+        fs.read_dir(path)
+            .unwrap_or_default()
+            .iter()
+            .map(|entry| match entry.is_dir {
+                true => entry_size(fs, &entry.path),
+                false => fs.metadata(&entry.path).map(|m| m.len).unwrap_or(0),
+            })
+            .sum()
+    } else {
+        fs.metadata(path).map(|m| m.len).unwrap_or(0)
+    }
+}
+
+/// Closest ancestor of `path` (including itself) that exists on disk; target
+/// folders are often only created during the move itself.
+fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|p| p.exists())
+}
+
+#[cfg(unix)]
+pub fn volume_id(path: &Path) -> Option<VolumeId> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(existing_ancestor(path)?).ok().map(|m| m.dev())
+}
+
+#[cfg(windows)]
+pub fn volume_id(path: &Path) -> Option<VolumeId> {
+    use std::path::Component;
+    let path = existing_ancestor(path)?.canonicalize().ok()?;
+    match path.components().next()? {
+        Component::Prefix(prefix) => Some(prefix.as_os_str().to_os_string()),
+        _ => None,
+    }
+}
+
+/// Sums the bytes that will have to be copied (rather than renamed) onto each
+/// destination volume and returns a message for every volume without enough
+/// free space. `moves` yields (source, target directory) pairs.
+pub fn check_free_space<'a>(fs: &dyn FileSystem, moves: impl Iterator<Item = (&'a Path, &'a Path)>) -> Vec<Message> {
+    let mut needed: HashMap<VolumeId, (PathBuf, u64)> = HashMap::new();
+    for (source, target_dir) in moves {
+        let (Some(source_volume), Some(target_volume)) = (volume_id(source), volume_id(target_dir)) else {
+            continue;
+        };
+        if source_volume != target_volume {
+            let (_, bytes) = needed
+                .entry(target_volume)
+                .or_insert_with(|| (target_dir.to_path_buf(), 0));
+            *bytes += entry_size(fs, source);
+        }
+    }
+
+    let mut shortages = Vec::new();
+    for (dir, bytes) in needed.into_values() {
+        let Some(probe) = existing_ancestor(&dir) else {
+            continue;
+        };
+        match fs2::available_space(probe) {
+            Ok(free) if free < bytes => shortages.push(
+                Message::new("sort.not_enough_space")
+                    .arg("path", dir.display())
+                    .arg("needed", format_size(bytes))
+                    .arg("available", format_size(free)),
+            ),
+            Ok(_) => {}
+            Err(e) => shortages.push(
+                Message::new("sort.free_space_unknown")
+                    .arg("path", dir.display())
+                    .arg("detail", e),
+            ),
+        }
+    }
+    shortages
+}
+
+pub fn format_size(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1_048_576.0)
+}
+
+/// Attempts made for a move that keeps failing with transient (usually network) errors.
+const MOVE_ATTEMPTS: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Like [`move_path`], but retries transient failures with exponential backoff.
+pub fn move_path_with_retry(
+    fs: &dyn FileSystem,
+    from: &Path,
+    to: &Path,
+    progress: Progress,
+    throttle: Option<&Throttle>,
+) -> io::Result<Option<String>> {
+    let mut attempt = 1;
+    loop {
+        match move_path(fs, from, to, progress, throttle) {
+            Err(e) if attempt < MOVE_ATTEMPTS && is_transient(&e) => {
+                let delay = RETRY_BASE_DELAY * 2u32.pow(attempt - 1);
+                tracing::warn!(
+                    "Transient error moving {} (attempt {}): {}, retrying in {:?}",
+                    from.display(),
+                    attempt,
+                    e,
+                    delay
+                );
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            other => return other,
+        }
+    }
+}
+
+/// Whether `e` is likely to go away on its own, like a network hiccup.
+pub fn is_transient(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    if matches!(
+        e.kind(),
+        TimedOut | Interrupted | WouldBlock | ConnectionReset | ConnectionAborted | NotConnected | BrokenPipe
+    ) {
+        return true;
+    }
+
+    // ERROR_BAD_NETPATH, ERROR_UNEXP_NET_ERR, ERROR_NETNAME_DELETED, ERROR_SEM_TIMEOUT,
+    // and ERROR_CLOUD_FILE_PROVIDER_NOT_RUNNING, ERROR_CLOUD_FILE_NETWORK_UNAVAILABLE
+    // and ERROR_CLOUD_FILE_REQUEST_ABORTED from a sync client like OneDrive
+    #[cfg(windows)]
+    if matches!(e.raw_os_error(), Some(53 | 59 | 64 | 121 | 362 | 389 | 394)) {
+        return true;
+    }
+    // EIO, EHOSTDOWN/EHOSTUNREACH as reported by stale NFS/SMB mounts
+    #[cfg(target_os = "linux")]
+    if matches!(e.raw_os_error(), Some(5 | 112 | 113)) {
+        return true;
+    }
+
+    false
+}
+
+/// Root of the network share `path` lives on, if it is on one: `\\server\share\`
+/// for UNC paths, or the mount point of an NFS/SMB filesystem.
+#[cfg(windows)]
+pub fn share_root(path: &Path) -> Option<PathBuf> {
+    use std::path::{Component, Prefix};
+    match path.components().next()? {
+        Component::Prefix(prefix) => match prefix.kind() {
+            Prefix::UNC(..) | Prefix::VerbatimUNC(..) => {
+                let mut root = PathBuf::from(prefix.as_os_str());
+                root.push(std::path::MAIN_SEPARATOR_STR);
+                Some(root)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn share_root(path: &Path) -> Option<PathBuf> {
+    const NETWORK_FS: &[&str] = &["nfs", "nfs4", "cifs", "smbfs", "smb3", "fuse.sshfs", "9p"];
+
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mount_point = fields.nth(1)?.replace("\\040", " ");
+            let fs_type = fields.next()?;
+            NETWORK_FS.contains(&fs_type).then(|| PathBuf::from(mount_point))
+        })
+        .filter(|mount_point| path.starts_with(mount_point))
+        .max_by_key(|mount_point| mount_point.as_os_str().len())
+}
+
+#[cfg(target_os = "macos")]
+pub fn share_root(path: &Path) -> Option<PathBuf> {
+    // Shares mount under /Volumes; local disks there are treated the same way,
+    // which only means an unplugged drive is queued rather than reported.
+    let mut components = path.components();
+    match (components.next(), components.next(), components.next()) {
+        (Some(root), Some(volumes), Some(name)) if volumes.as_os_str() == "Volumes" => {
+            Some(Path::new(root.as_os_str()).join(volumes).join(name))
+        }
+        _ => None,
+    }
+}
+
+/// Whether `path` is on a network share that currently can't be reached.
+pub fn is_share_offline(path: &Path) -> bool {
+    share_root(path).is_some_and(|root| !root.exists())
+}
+
+/// Whether names in `dir` are case-sensitive, found by creating a probe file and
+/// looking it up under different casing. Results are cached per directory since
+/// mounted filesystems (SMB, exFAT, ...) don't follow the platform default. When
+/// the probe can't be written the directory is assumed case-insensitive, which
+/// at worst produces an unnecessary `_1` suffix instead of an overwrite.
+pub fn is_case_sensitive(dir: &Path) -> bool {
+    static CACHE: OnceLock<Mutex<HashMap<PathBuf, bool>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some(&sensitive) = cache.lock().unwrap().get(dir) {
+        return sensitive;
+    }
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let probe = dir.join(format!(".desksort-case-probe-{}-{}", std::process::id(), nanos));
+    let sensitive = match fs::File::create(long_path(&probe)) {
+        Ok(_) => {
+            let upper = dir.join(probe.file_name().unwrap().to_string_lossy().to_uppercase());
+            let sensitive = fs::symlink_metadata(long_path(&upper)).is_err();
+            let _ = fs::remove_file(long_path(&probe));
+            sensitive
+        }
+        Err(_) => return false,
+    };
+
+    cache.lock().unwrap().insert(dir.to_path_buf(), sensitive);
+    sensitive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::MemoryFileSystem;
+
+    /// A folder `depth` levels of 20-character names below `root`.
+    fn deep_dir(root: &Path, depth: usize) -> PathBuf {
+        (0..depth).fold(root.to_path_buf(), |dir, i| dir.join(format!("nested-folder-{:06}", i)))
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_prefixes_drive_paths() {
+        assert_eq!(long_path(Path::new(r"C:\Users\me\Desktop")), PathBuf::from(r"\\?\C:\Users\me\Desktop"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_prefixes_unc_paths() {
+        assert_eq!(
+            long_path(Path::new(r"\\server\share\Sorted\a.txt")),
+            PathBuf::from(r"\\?\UNC\server\share\Sorted\a.txt")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_leaves_prefixed_paths_alone() {
+        for path in [r"\\?\C:\Users\me", r"\\?\UNC\server\share\a.txt"] {
+            assert_eq!(long_path(Path::new(path)), PathBuf::from(path));
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_handles_paths_over_max_path() {
+        let path = deep_dir(Path::new(r"C:\Users\me\Desktop"), 20).join("report.pdf");
+        assert!(path.as_os_str().len() > 260);
+        let long = long_path(&path);
+        assert!(long.as_os_str().to_string_lossy().starts_with(r"\\?\C:\"));
+        assert!(long.ends_with(path.strip_prefix(r"C:\").unwrap()));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_resolves_dots() {
+        assert_eq!(long_path(Path::new(r"C:\a\.\b\..\c")), PathBuf::from(r"\\?\C:\a\c"));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn long_path_is_unchanged_elsewhere() {
+        let path = deep_dir(Path::new("/home/me/Desktop"), 20).join("report.pdf");
+        assert_eq!(long_path(&path), path);
+    }
+
+    #[test]
+    fn moves_into_folders_deeper_than_max_path() {
+        let fs = MemoryFileSystem::new();
+        let from = Path::new("/desktop/report.pdf");
+        let to = deep_dir(Path::new("/desktop/Sorted"), 20).join("report.pdf");
+        assert!(to.as_os_str().len() > 260);
+        fs.add_file(from, "contents");
+        fs.create_dir_all(to.parent().unwrap()).unwrap();
+
+        move_path(&fs, from, &to, &mut |_, _, _| {}, None).unwrap();
+
+        assert_eq!(fs.contents(&long_path(&to)).as_deref(), Some(&b"contents"[..]));
+        assert!(!fs.exists(from));
+    }
+
+    #[test]
+    fn copies_across_volumes_into_folders_deeper_than_max_path() {
+        let fs = MemoryFileSystem::with_volumes([PathBuf::from("/desktop"), PathBuf::from("/archive")]);
+        let from = Path::new("/desktop/report.pdf");
+        let to = deep_dir(Path::new("/archive"), 20).join("report.pdf");
+        fs.add_file(from, "contents");
+        fs.create_dir_all(to.parent().unwrap()).unwrap();
+
+        move_path(&fs, from, &to, &mut |_, _, _| {}, None).unwrap();
+
+        assert_eq!(fs.contents(&long_path(&to)).as_deref(), Some(&b"contents"[..]));
+        assert!(!fs.exists(from));
+    }
+}
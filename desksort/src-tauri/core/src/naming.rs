@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Extensions made of two parts that belong together, so a collision renames
+/// `backup.tar.gz` to `backup_1.tar.gz` rather than `backup.tar_1.gz`.
+const COMPOUND_EXTENSIONS: &[&str] = &[".tar.gz", ".tar.bz2", ".tar.xz", ".tar.zst", ".tar.lz", ".tar.lzma"];
+
+/// How an entry is renamed when its target folder already holds one of the
+/// same name.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionNaming {
+    /// `report_1.pdf`, `report_2.pdf`...
+    #[default]
+    Counter,
+    /// `report (1).pdf`, the way Windows Explorer does it.
+    Windows,
+    /// `report_20240501-101530.pdf`, the time of the move.
+    Timestamp,
+    /// `report_3fa9c1e.pdf`, a short id that is different every time.
+    ShortHash,
+}
+
+impl CollisionNaming {
+    /// The `attempt`th (from 1) alternative name for a file called `name`.
+    pub fn alternative(self, name: &str, attempt: u32) -> String {
+        let (stem, extension) = split_name(name);
+        match self {
+            CollisionNaming::Counter => format!("{}_{}{}", stem, attempt, extension),
+            CollisionNaming::Windows => format!("{} ({}){}", stem, attempt, extension),
+            CollisionNaming::Timestamp => {
+                let now = chrono::Local::now().format("%Y%m%d-%H%M%S");
+                // Another collision within the same second gets a counter too
+                match attempt {
+                    1 => format!("{}_{}{}", stem, now, extension),
+                    _ => format!("{}_{}_{}{}", stem, now, attempt, extension),
+                }
+            }
+            CollisionNaming::ShortHash => {
+                let mut hasher = Sha256::new();
+                hasher.update(name.as_bytes());
+                hasher.update(attempt.to_le_bytes());
+                hasher.update(chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default().to_le_bytes());
+                let hash: String = hasher.finalize()[..4].iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!("{}_{}{}", stem, &hash[..7], extension)
+            }
+        }
+    }
+}
+
+/// Splits `name` into its stem and its extension, dot included. Compound
+/// extensions like `.tar.gz` stay whole, and dotfiles like `.env` have none.
+pub fn split_name(name: &str) -> (&str, &str) {
+    let lower = name.to_lowercase();
+    if let Some(compound) = COMPOUND_EXTENSIONS.iter().find(|compound| lower.ends_with(*compound)) {
+        let split = name.len() - compound.len();
+        if split > 0 && name.is_char_boundary(split) {
+            return name.split_at(split);
+        }
+    }
+    match name.rfind('.') {
+        Some(0) | None => (name, ""),
+        Some(dot) => name.split_at(dot),
+    }
+}
@@ -0,0 +1,175 @@
+use std::{io, path::Path};
+
+/// Where the file at `path` was downloaded from, as recorded by the browser.
+/// Windows keeps it in the file's `Zone.Identifier` stream.
+#[cfg(windows)]
+pub fn source_url(path: &Path) -> Option<String> {
+    let contents = std::fs::read(zone_identifier(path)).ok()?;
+    let contents = String::from_utf8_lossy(&contents);
+    let field = |name: &str| {
+        contents.lines().find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == name).then(|| value.trim().to_string())
+        })
+    };
+    // Files saved from a page with no address of their own only have the referrer
+    field("HostUrl")
+        .filter(|url| domain(url).is_some())
+        .or_else(|| field("ReferrerUrl"))
+}
+
+/// macOS keeps the addresses in the `kMDItemWhereFroms` metadata, and in the
+/// quarantine event the `com.apple.quarantine` attribute points to.
+#[cfg(target_os = "macos")]
+pub fn source_url(path: &Path) -> Option<String> {
+    where_froms(path).or_else(|| quarantine_url(path))
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn source_url(_path: &Path) -> Option<String> {
+    None
+}
+
+/// The lowercased host of `url`, without port or credentials. `None` for
+/// placeholders like `about:internet` that browsers write for unknown sources.
+pub fn domain(url: &str) -> Option<String> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = match host.strip_prefix('[') {
+        // IPv6 literal
+        Some(literal) => literal.split(']').next()?,
+        None => host.split(':').next()?,
+    };
+    let host = host.trim_end_matches('.').to_lowercase();
+    (!host.is_empty()).then_some(host)
+}
+
+/// The domain the file at `path` was downloaded from.
+pub fn source_domain(path: &Path) -> Option<String> {
+    domain(&source_url(path)?)
+}
+
+/// Whether `domain` is `wanted` or one of its subdomains: `github.com`
+/// covers `codeload.github.com` but not `notgithub.com`.
+pub fn matches_domain(domain: &str, wanted: &str) -> bool {
+    domain == wanted
+        || domain
+            .strip_suffix(wanted)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// Copies the download origin of `from` to `to`, which a plain copy of the
+/// file contents drops. Elsewhere it is an extended attribute, copied along
+/// with the others.
+#[cfg(windows)]
+pub fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    match std::fs::copy(zone_identifier(from), zone_identifier(to)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other.map(|_| ()),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn copy(_from: &Path, _to: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Forgets where `path`, or everything inside it, was downloaded from. Windows
+/// stops warning before opening such files, and Gatekeeper stops checking them.
+#[cfg(windows)]
+pub fn strip(path: &Path) -> io::Result<()> {
+    let remove = |file: &Path| match std::fs::remove_file(zone_identifier(file)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    };
+    if path.is_dir() {
+        for entry in walkdir::WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_file() {
+                remove(entry.path())?;
+            }
+        }
+        Ok(())
+    } else {
+        remove(path)
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn strip(path: &Path) -> io::Result<()> {
+    for name in ORIGIN_ATTRIBUTES {
+        // Fails for files that don't have the attribute, which is fine
+        xattr(&["-r", "-d", name], path)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn strip(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// The NTFS alternate data stream browsers record a download's origin in.
+#[cfg(windows)]
+fn zone_identifier(path: &Path) -> std::path::PathBuf {
+    let mut stream = crate::mover::long_path(path).into_os_string();
+    stream.push(":Zone.Identifier");
+    stream.into()
+}
+
+/// Attributes macOS records a download's origin in. The quarantine one is
+/// what makes Gatekeeper check a file before it is first opened.
+#[cfg(target_os = "macos")]
+const ORIGIN_ATTRIBUTES: [&str; 2] = ["com.apple.quarantine", "com.apple.metadata:kMDItemWhereFroms"];
+
+/// Runs `xattr` with `args` on `path`. Returns its output, or `None` when it
+/// failed, usually because the attribute isn't set.
+#[cfg(target_os = "macos")]
+fn xattr(args: &[&str], path: &Path) -> io::Result<Option<Vec<u8>>> {
+    let output = std::process::Command::new("xattr").args(args).arg(path).output()?;
+    Ok(output.status.success().then_some(output.stdout))
+}
+
+/// The first address in the file's `kMDItemWhereFroms`, which `mdls` prints as
+/// a parenthesized list of quoted strings: the download, then the page it was
+/// on.
+#[cfg(target_os = "macos")]
+fn where_froms(path: &Path) -> Option<String> {
+    let output = std::process::Command::new("mdls")
+        .args(["-raw", "-name", "kMDItemWhereFroms"])
+        .arg(path)
+        .output()
+        .ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    output.lines().find_map(|line| {
+        let url = line.trim().trim_end_matches(',').trim_matches('"');
+        domain(url).map(|_| url.to_string())
+    })
+}
+
+/// The address the quarantine event of `path` recorded. The attribute reads
+/// `flags;timestamp;agent;event id`, and Launch Services keeps the events in
+/// a database in the user's preferences.
+#[cfg(target_os = "macos")]
+fn quarantine_url(path: &Path) -> Option<String> {
+    use rusqlite::{Connection, OpenFlags, OptionalExtension};
+
+    let attribute = xattr(&["-p", "com.apple.quarantine"], path).ok()??;
+    let attribute = String::from_utf8_lossy(&attribute);
+    let event_id = attribute.trim().split(';').nth(3).filter(|id| !id.is_empty())?;
+
+    let events = dirs::home_dir()?.join("Library/Preferences/com.apple.LaunchServices.QuarantineEventsV2");
+    let conn = Connection::open_with_flags(events, OpenFlags::SQLITE_OPEN_READ_ONLY).ok()?;
+    let (data_url, origin_url): (Option<String>, Option<String>) = conn
+        .query_row(
+            "SELECT LSQuarantineDataURLString, LSQuarantineOriginURLString
+             FROM LSQuarantineEvent WHERE LSQuarantineEventIdentifier = ?",
+            [event_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .ok()??;
+    data_url
+        .filter(|url| domain(url).is_some())
+        .or(origin_url)
+}
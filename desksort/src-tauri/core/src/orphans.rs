@@ -0,0 +1,176 @@
+use crate::{mover, Error};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Why a target can't be sorted into.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanReason {
+    /// The drive or volume it is on isn't connected.
+    DriveMissing,
+    /// The network share it is on can't be reached. Sorts queue entries for
+    /// such targets until it is back.
+    ShareOffline,
+    /// The folder it lives in was deleted. Sorting would recreate it.
+    ParentMissing,
+}
+
+/// What sends entries to an orphaned target.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Owner {
+    Mapping { extension: String },
+    Rule { id: i64, name: String },
+}
+
+#[derive(Serialize)]
+pub struct Orphan {
+    pub owner: Owner,
+    pub target_path: String,
+    pub reason: OrphanReason,
+}
+
+/// The drive `path` is on, for drives that can be disconnected.
+#[cfg(windows)]
+fn volume_root(path: &Path) -> Option<PathBuf> {
+    use std::path::{Component, Prefix};
+    match path.components().next()? {
+        Component::Prefix(prefix) => match prefix.kind() {
+            Prefix::Disk(_) | Prefix::VerbatimDisk(_) => {
+                let mut root = PathBuf::from(prefix.as_os_str());
+                root.push(std::path::MAIN_SEPARATOR_STR);
+                Some(root)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn volume_root(path: &Path) -> Option<PathBuf> {
+    let mut components = path.components();
+    match (components.next(), components.next(), components.next()) {
+        (Some(root), Some(volumes), Some(name)) if volumes.as_os_str() == "Volumes" => {
+            Some(Path::new(root.as_os_str()).join(volumes).join(name))
+        }
+        _ => None,
+    }
+}
+
+/// Removable drives are mounted under `/media/<user>`, `/run/media/<user>`
+/// or `/mnt`.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn volume_root(path: &Path) -> Option<PathBuf> {
+    let depth = if path.starts_with("/media") {
+        3
+    } else if path.starts_with("/run/media") {
+        4
+    } else if path.starts_with("/mnt") {
+        2
+    } else {
+        return None;
+    };
+    let root: PathBuf = path.components().take(depth + 1).collect();
+    (root.components().count() == depth + 1).then_some(root)
+}
+
+/// Whether `target` is on a drive or share that isn't there right now.
+pub fn is_disconnected(target: &Path) -> bool {
+    volume_root(target).is_some_and(|root| !root.exists()) || mover::is_share_offline(target)
+}
+
+/// Why entries can't be sorted into `target`, if they can't. Folders below
+/// `sorted_dir` don't count as deleted; DeskSort creates them as needed.
+pub fn check(target: &Path, sorted_dir: &Path) -> Option<OrphanReason> {
+    if volume_root(target).is_some_and(|root| !root.exists()) {
+        return Some(OrphanReason::DriveMissing);
+    }
+    if mover::is_share_offline(target) {
+        return Some(OrphanReason::ShareOffline);
+    }
+    let parent = target.parent()?;
+    if !target.exists() && !parent.exists() && !parent.starts_with(sorted_dir) {
+        return Some(OrphanReason::ParentMissing);
+    }
+    None
+}
+
+/// Every mapping and rule whose target can't be sorted into.
+pub fn find(conn: &Connection, sorted_dir: &Path) -> Result<Vec<Orphan>, Error> {
+    let mut orphans = Vec::new();
+
+    let mappings: Vec<(String, String)> = conn
+        .prepare("SELECT extension, target_path FROM path_mappings WHERE enabled = 1 ORDER BY extension")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    for (extension, target_path) in mappings {
+        if let Some(reason) = check(Path::new(&target_path), sorted_dir) {
+            orphans.push(Orphan {
+                owner: Owner::Mapping { extension },
+                target_path,
+                reason,
+            });
+        }
+    }
+
+    let rules: Vec<(i64, String, String)> = conn
+        .prepare(
+            "SELECT id, name, target_path FROM rules WHERE enabled = 1 AND target_path != ''
+             ORDER BY priority DESC, id",
+        )?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+    for (id, name, target_path) in rules {
+        if let Some(reason) = check(Path::new(&target_path), sorted_dir) {
+            orphans.push(Orphan {
+                owner: Owner::Rule { id, name },
+                target_path,
+                reason,
+            });
+        }
+    }
+
+    Ok(orphans)
+}
+
+/// Turns off the mappings and rules behind `orphans`. Returns how many were
+/// turned off.
+pub fn disable(conn: &Connection, orphans: &[Orphan]) -> Result<usize, Error> {
+    let mut disabled = 0;
+    for orphan in orphans {
+        disabled += match &orphan.owner {
+            Owner::Mapping { extension } => conn.execute(
+                "UPDATE path_mappings SET enabled = 0 WHERE extension = ?",
+                params![extension],
+            )?,
+            Owner::Rule { id, .. } => conn.execute("UPDATE rules SET enabled = 0 WHERE id = ?", params![id])?,
+        };
+    }
+    Ok(disabled)
+}
+
+/// Points every mapping and rule target inside `from` at the same place inside
+/// `to`, e.g. after a drive got a new letter. Returns how many were changed.
+pub fn retarget(conn: &mut Connection, from: &Path, to: &Path) -> Result<usize, Error> {
+    let tx = conn.transaction()?;
+    let mut changed = 0;
+    for (table, key) in [("path_mappings", "extension"), ("rules", "id")] {
+        let targets: Vec<(rusqlite::types::Value, String)> = tx
+            .prepare(&format!("SELECT {}, target_path FROM {}", key, table))?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        for (id, target_path) in targets {
+            let Ok(rest) = Path::new(&target_path).strip_prefix(from) else {
+                continue;
+            };
+            changed += tx.execute(
+                &format!("UPDATE {} SET target_path = ? WHERE {} = ?", table, key),
+                params![to.join(rest).to_string_lossy(), id],
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(changed)
+}
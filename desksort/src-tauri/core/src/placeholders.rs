@@ -0,0 +1,59 @@
+use crate::filesystem::FileSystem;
+use serde::{Deserialize, Serialize};
+use std::{io, path::Path};
+
+/// What happens to online-only files of OneDrive, Dropbox or iCloud, which
+/// are stubs on disk until something reads them.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaceholderPolicy {
+    /// Left where they are.
+    #[default]
+    Skip,
+    /// Downloaded in full before they are moved, so a move to another drive
+    /// doesn't fail halfway.
+    Hydrate,
+    /// Moved as they are. Within the synced folder that keeps them online
+    /// only; anywhere else the sync client downloads them during the copy.
+    MoveStub,
+}
+
+/// FILE_ATTRIBUTE_OFFLINE, FILE_ATTRIBUTE_RECALL_ON_OPEN and
+/// FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS, set by cloud file providers.
+#[cfg(windows)]
+const PLACEHOLDER_ATTRIBUTES: u32 = 0x1000 | 0x40000 | 0x400000;
+
+/// SF_DATALESS, set on files whose contents File Provider fetches on access.
+#[cfg(target_os = "macos")]
+const SF_DATALESS: u32 = 0x40000000;
+
+/// Whether the entry at `path` is an online-only stub.
+#[cfg(windows)]
+pub fn is_placeholder(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_attributes() & PLACEHOLDER_ATTRIBUTES != 0)
+}
+
+#[cfg(target_os = "macos")]
+pub fn is_placeholder(path: &Path) -> bool {
+    use std::os::macos::fs::MetadataExt;
+    std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.st_flags() & SF_DATALESS != 0)
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn is_placeholder(_path: &Path) -> bool {
+    false
+}
+
+/// Reads every online-only file at or below `path` once, which makes the
+/// sync client download it.
+pub fn hydrate(fs: &dyn FileSystem, path: &Path) -> io::Result<()> {
+    if fs.is_dir(path) {
+        for entry in fs.read_dir(path)? {
+            hydrate(fs, &entry.path)?;
+        }
+    } else if fs.is_placeholder(path) {
+        io::copy(&mut fs.open(path)?, &mut io::sink())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use crate::{rules::Candidate, Error, ScannedEntry};
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// What happens to a folder a project marker is found in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectAction {
+    /// Left where it is, whatever the `folder` mapping says.
+    Protect,
+    /// Moved to the projects folder.
+    Move,
+}
+
+/// Entries that give a development project away, by the name of an entry
+/// directly inside it. Moving a checkout breaks its build paths and editor
+/// state, so all of them protect it until told otherwise.
+pub fn default_markers() -> BTreeMap<String, ProjectAction> {
+    [".git", "Cargo.toml", "package.json", "*.sln"]
+        .into_iter()
+        .map(|marker| (marker.to_string(), ProjectAction::Protect))
+        .collect()
+}
+
+/// Checks that every marker is a usable glob.
+pub fn validate(markers: &BTreeMap<String, ProjectAction>) -> Result<(), Error> {
+    for marker in markers.keys() {
+        if marker.trim().is_empty() {
+            return Err(Error::InvalidSetting("project markers must not be empty".to_string()));
+        }
+        Pattern::new(marker)
+            .map_err(|e| Error::InvalidSetting(format!("bad project marker `{}`: {}", marker, e)))?;
+    }
+    Ok(())
+}
+
+/// The project markers, compiled, and where projects that are moved go.
+pub struct ProjectDetector {
+    markers: Vec<(String, Pattern, ProjectAction)>,
+    target_dir: PathBuf,
+}
+
+impl ProjectDetector {
+    /// `None` without any markers. Markers that aren't valid globs are
+    /// skipped; settings are validated when saved.
+    pub fn new(markers: &BTreeMap<String, ProjectAction>, target_dir: PathBuf) -> Option<Self> {
+        let mut markers: Vec<_> = markers
+            .iter()
+            .filter_map(|(marker, action)| Some((marker.clone(), Pattern::new(marker).ok()?, *action)))
+            .collect();
+        // A marker that protects wins over one that moves
+        markers.sort_by_key(|(_, _, action)| *action != ProjectAction::Protect);
+        (!markers.is_empty()).then_some(ProjectDetector { markers, target_dir })
+    }
+
+    /// Where projects go, if any marker moves them.
+    pub fn target_dir(&self) -> Option<&Path> {
+        self.markers
+            .iter()
+            .any(|(_, _, action)| *action == ProjectAction::Move)
+            .then_some(self.target_dir.as_path())
+    }
+
+    /// What to do with `entry` if it is a project folder.
+    pub fn candidate(&self, entry: &ScannedEntry) -> Option<Candidate> {
+        if !entry.is_dir {
+            return None;
+        }
+        let contents = entry.contents();
+        let (marker, _, action) = self.markers.iter().find(|(_, pattern, _)| contents.contains(pattern))?;
+        Some(Candidate::project(marker, *action, self.target_dir.clone()))
+    }
+}
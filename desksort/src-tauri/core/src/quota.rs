@@ -0,0 +1,174 @@
+use crate::{filesystem::FileSystem, mover::long_path, naming::CollisionNaming, unique_target_path, Error};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Limits configured on a single mapping's target folder.
+#[derive(Default, Clone, Copy)]
+pub struct Quota {
+    pub max_files: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl Quota {
+    pub fn is_set(&self) -> bool {
+        self.max_files.is_some() || self.max_bytes.is_some()
+    }
+
+    fn exceeded(&self, files: u64, bytes: u64) -> bool {
+        self.max_files.is_some_and(|max| files > max) || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+}
+
+struct UsageEntry {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+    /// Reserved for an item still being moved in, under its source path.
+    /// It counts against the quota but can't be rotated out yet.
+    in_flight: bool,
+}
+
+/// Tracks the contents of quota-limited target folders for the duration of a sort,
+/// so each folder is only read from disk once. Items make room and reserve
+/// their slot in one step, so parallel moves can't both count on the same
+/// free space.
+#[derive(Default)]
+pub struct QuotaTracker {
+    folders: HashMap<PathBuf, (Quota, Vec<UsageEntry>)>,
+}
+
+impl QuotaTracker {
+    /// Rolls the oldest entries of `dir` into a dated overflow subfolder until
+    /// the item at `incoming` of `incoming_size` bytes fits within `quota`,
+    /// renaming them as `naming` says when the overflow folder has one of the
+    /// same name, and reserves the item's slot. The slot is kept by `record`
+    /// once the item is moved in, or given back by `release`. Returns the
+    /// rotated (from, to) pairs.
+    pub fn make_room(
+        &mut self,
+        fs: &dyn FileSystem,
+        dir: &Path,
+        quota: &Quota,
+        incoming: &Path,
+        incoming_size: u64,
+        naming: CollisionNaming,
+    ) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+        if !quota.is_set() {
+            return Ok(Vec::new());
+        }
+
+        let (quota, entries) = match self.folders.entry(dir.to_path_buf()) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert((*quota, read_usage(fs, dir)?)),
+        };
+        // Reserved first, so the slot is held even if rotating fails
+        entries.push(UsageEntry {
+            path: incoming.to_path_buf(),
+            size: incoming_size,
+            modified: SystemTime::now(),
+            in_flight: true,
+        });
+        rotate(fs, dir, quota, entries, naming)
+    }
+
+    /// Keeps the slot `make_room` reserved for `incoming`, which was just
+    /// moved into `dir` as `path`. Items that were still moving in when room
+    /// was made for it couldn't be rotated out then, so `dir` is brought back
+    /// within its quota now. Returns the rotated (from, to) pairs.
+    pub fn record(
+        &mut self,
+        fs: &dyn FileSystem,
+        dir: &Path,
+        incoming: &Path,
+        path: &Path,
+        naming: CollisionNaming,
+    ) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+        let Some((quota, entries)) = self.folders.get_mut(dir) else {
+            return Ok(Vec::new());
+        };
+        let Some(entry) = entries.iter_mut().find(|e| e.in_flight && e.path == incoming) else {
+            return Ok(Vec::new());
+        };
+        entry.path = path.to_path_buf();
+        entry.modified = SystemTime::now();
+        entry.in_flight = false;
+        rotate(fs, dir, quota, entries, naming)
+    }
+
+    /// Gives back the slot `make_room` reserved for `incoming`, which didn't
+    /// make it into `dir`.
+    pub fn release(&mut self, dir: &Path, incoming: &Path) {
+        if let Some((_, entries)) = self.folders.get_mut(dir) {
+            entries.retain(|e| !(e.in_flight && e.path == incoming));
+        }
+    }
+}
+
+/// Rolls the oldest of `entries` into the overflow folder of `dir` until
+/// they fit within `quota`. Items still moving in stay until they have
+/// arrived.
+fn rotate(
+    fs: &dyn FileSystem,
+    dir: &Path,
+    quota: &Quota,
+    entries: &mut Vec<UsageEntry>,
+    naming: CollisionNaming,
+) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+    let overflow_dir = dir.join(overflow_dir_name());
+    let mut files = entries.len() as u64;
+    let mut bytes = entries.iter().map(|e| e.size).sum::<u64>();
+    let mut rotated = Vec::new();
+    while quota.exceeded(files, bytes) {
+        let Some(oldest) = entries.iter().position(|e| !e.in_flight) else {
+            break;
+        };
+        let oldest = entries.remove(oldest);
+        fs.create_dir_all(&long_path(&overflow_dir))?;
+        let file_name = oldest.path.file_name().unwrap();
+        let target = unique_target_path(fs, &overflow_dir, file_name, &HashSet::new(), naming);
+        fs.rename(&long_path(&oldest.path), &long_path(&target))?;
+
+        files -= 1;
+        bytes -= oldest.size;
+        rotated.push((oldest.path, target));
+    }
+    Ok(rotated)
+}
+
+/// Whether `name` is one of the folders quotas roll old entries into.
+pub fn is_overflow_dir(name: &str) -> bool {
+    name.starts_with("overflow-")
+}
+
+fn overflow_dir_name() -> String {
+    format!("overflow-{}", chrono::Local::now().format("%Y-%m"))
+}
+
+fn read_usage(fs: &dyn FileSystem, dir: &Path) -> Result<Vec<UsageEntry>, Error> {
+    let mut entries = Vec::new();
+    if !fs.exists(&long_path(dir)) {
+        return Ok(entries);
+    }
+
+    for entry in fs.read_dir(&long_path(dir))? {
+        let name = entry.path.file_name().unwrap_or_default();
+        if name.to_string_lossy().starts_with('.') || is_overflow_dir(&name.to_string_lossy()) {
+            continue;
+        }
+
+        let metadata = fs.metadata(&entry.path)?;
+        entries.push(UsageEntry {
+            path: dir.join(name),
+            size: metadata.len,
+            modified: metadata.modified.unwrap_or(SystemTime::UNIX_EPOCH),
+            in_flight: false,
+        });
+    }
+
+    // Oldest first, so rotation always takes from the front
+    entries.sort_by_key(|e| e.modified);
+    Ok(entries)
+}
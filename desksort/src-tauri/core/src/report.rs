@@ -0,0 +1,154 @@
+use crate::{categories, messages::Message, mover, Error};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+const WEEK_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Categories a report lists.
+const TOP_CATEGORIES: usize = 5;
+
+#[derive(Serialize)]
+pub struct CategoryTotal {
+    pub name: String,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// What DeskSort did during the seven days up to `to`.
+#[derive(Serialize)]
+pub struct WeeklyReport {
+    /// Unix times the week starts and ends at.
+    pub from: i64,
+    pub to: i64,
+    pub files_sorted: u64,
+    pub bytes_organized: u64,
+    /// Categories that received the most files, most first. Files outside
+    /// every category's folder count as `Other`.
+    pub top_categories: Vec<CategoryTotal>,
+    /// Entries moved to the trash, as duplicates or by rules that delete.
+    pub trashed: u64,
+    pub errors: u64,
+    /// Weeks in a row, this one included, in which anything was sorted.
+    pub streak_weeks: u32,
+    /// Files sorted the week before, to compare against.
+    pub previous_files_sorted: u64,
+}
+
+impl WeeklyReport {
+    /// One-line summary for a notification.
+    pub fn summary(&self) -> Message {
+        Message::new("report.weekly")
+            .arg("files", self.files_sorted)
+            .arg("bytes", mover::format_size(self.bytes_organized))
+            .arg("streak", self.streak_weeks)
+    }
+}
+
+/// Whether a week has passed since the last weekly report went out. The
+/// first check starts the week instead, so no report covers time before
+/// the user asked for them.
+pub fn is_due(conn: &Connection) -> Result<bool, Error> {
+    let now = chrono::Utc::now().timestamp();
+    let last_sent: Option<i64> = conn.query_row("SELECT MAX(sent_at) FROM weekly_reports", [], |row| row.get(0))?;
+    match last_sent {
+        Some(last_sent) => Ok(now - last_sent >= WEEK_SECS),
+        None => {
+            mark_sent(conn, now)?;
+            Ok(false)
+        }
+    }
+}
+
+/// Records that a weekly report went out at `at`.
+pub fn mark_sent(conn: &Connection, at: i64) -> Result<(), Error> {
+    conn.execute("INSERT INTO weekly_reports (sent_at) VALUES (?)", params![at])?;
+    Ok(())
+}
+
+/// The report of the seven days up to now.
+pub fn past_week(conn: &Connection) -> Result<WeeklyReport, Error> {
+    weekly(conn, chrono::Utc::now().timestamp())
+}
+
+/// Sums up the history of the week ending at `to`. Simulated sorts changed
+/// nothing, so they are left out.
+pub fn weekly(conn: &Connection, to: i64) -> Result<WeeklyReport, Error> {
+    let from = to - WEEK_SECS;
+    let roots: Vec<(String, String)> = categories::load(conn)?
+        .into_iter()
+        .filter(|category| category.parent_id.is_none())
+        .map(|category| (category.name, category.target_path))
+        .collect();
+
+    let mut stmt = conn.prepare(
+        "SELECT h.action, h.destination, h.size FROM history h JOIN sessions s ON s.id = h.session_id
+         WHERE NOT s.simulated AND h.created_at > ? AND h.created_at <= ?",
+    )?;
+    let mut rows = stmt.query(params![from, to])?;
+    let (mut files_sorted, mut bytes_organized, mut trashed, mut errors) = (0, 0, 0, 0);
+    let mut totals: HashMap<String, CategoryTotal> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let action: String = row.get(0)?;
+        match action.as_str() {
+            "moved" => {
+                let destination: Option<String> = row.get(1)?;
+                let size = row.get::<_, Option<u64>>(2)?.unwrap_or(0);
+                files_sorted += 1;
+                bytes_organized += size;
+                let name = destination
+                    .and_then(|destination| {
+                        roots
+                            .iter()
+                            .find(|(_, root)| Path::new(&destination).starts_with(root))
+                            .map(|(name, _)| name.clone())
+                    })
+                    .unwrap_or_else(|| "Other".to_string());
+                let total = totals.entry(name.clone()).or_insert(CategoryTotal {
+                    name,
+                    files: 0,
+                    bytes: 0,
+                });
+                total.files += 1;
+                total.bytes += size;
+            }
+            "trashed" | "discarded" => trashed += 1,
+            "failed" => errors += 1,
+            _ => {}
+        }
+    }
+    let mut top_categories: Vec<CategoryTotal> = totals.into_values().collect();
+    top_categories.sort_by(|a, b| b.files.cmp(&a.files).then_with(|| a.name.cmp(&b.name)));
+    top_categories.truncate(TOP_CATEGORIES);
+
+    // Weeks back from `to` anything was moved in
+    let weeks: BTreeSet<i64> = conn
+        .prepare(
+            "SELECT DISTINCT (? - h.created_at) / ? FROM history h JOIN sessions s ON s.id = h.session_id
+             WHERE NOT s.simulated AND h.action = 'moved' AND h.created_at <= ?",
+        )?
+        .query_map(params![to, WEEK_SECS, to], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    let streak_weeks = (0..).take_while(|week| weeks.contains(week)).count() as u32;
+    let previous_files_sorted = conn.query_row(
+        "SELECT COUNT(*) FROM history h JOIN sessions s ON s.id = h.session_id
+         WHERE NOT s.simulated AND h.action = 'moved' AND h.created_at > ? AND h.created_at <= ?",
+        params![from - WEEK_SECS, from],
+        |row| row.get(0),
+    )?;
+
+    Ok(WeeklyReport {
+        from,
+        to,
+        files_sorted,
+        bytes_organized,
+        top_categories,
+        trashed,
+        errors,
+        streak_weeks,
+        previous_files_sorted,
+    })
+}
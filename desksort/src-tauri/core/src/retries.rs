@@ -0,0 +1,103 @@
+use crate::{diagnostics::FailureKind, executor::FailedMove, Error};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// A move that failed and is waiting to be tried again.
+#[derive(Serialize)]
+pub struct PendingRetry {
+    pub source: String,
+    pub target_dir: String,
+    pub error_kind: FailureKind,
+    pub message: String,
+    /// Failed attempts so far, counting the first.
+    pub attempts: u32,
+    pub last_attempt_at: i64,
+}
+
+/// Adds `failed` to the queue, or counts another attempt for entries already
+/// in it.
+pub fn record_failures(conn: &Connection, failed: &[FailedMove]) -> Result<(), Error> {
+    let now = chrono::Utc::now().timestamp();
+    for failure in failed {
+        conn.execute(
+            "INSERT INTO pending_retries (source, target_dir, error_kind, message, attempts, last_attempt_at)
+             VALUES (?, ?, ?, ?, 1, ?)
+             ON CONFLICT(source) DO UPDATE SET
+                target_dir = excluded.target_dir,
+                error_kind = excluded.error_kind,
+                message = excluded.message,
+                attempts = attempts + 1,
+                last_attempt_at = excluded.last_attempt_at",
+            params![
+                failure.source.to_string_lossy(),
+                failure.target_dir.to_string_lossy(),
+                failure.kind.as_str(),
+                failure.message,
+                now
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Takes `sources` off the queue, e.g. once they were moved.
+pub fn forget<'a>(conn: &Connection, sources: impl IntoIterator<Item = &'a Path>) -> Result<(), Error> {
+    for source in sources {
+        conn.execute(
+            "DELETE FROM pending_retries WHERE source = ?",
+            params![source.to_string_lossy()],
+        )?;
+    }
+    Ok(())
+}
+
+/// Drops entries the user has since moved or deleted themselves.
+pub fn prune(conn: &Connection) -> Result<(), Error> {
+    let gone: Vec<PathBuf> = sources(conn)?.into_iter().filter(|s| !s.exists()).collect();
+    forget(conn, gone.iter().map(PathBuf::as_path))
+}
+
+/// Sources of every queued entry.
+pub fn sources(conn: &Connection) -> Result<HashSet<PathBuf>, Error> {
+    let sources = conn
+        .prepare("SELECT source FROM pending_retries")?
+        .query_map([], |row| row.get::<_, String>(0).map(PathBuf::from))?
+        .collect::<Result<_, _>>()?;
+    Ok(sources)
+}
+
+pub fn load(conn: &Connection) -> Result<Vec<PendingRetry>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT source, target_dir, error_kind, message, attempts, last_attempt_at
+         FROM pending_retries ORDER BY last_attempt_at",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(PendingRetry {
+            source: row.get(0)?,
+            target_dir: row.get(1)?,
+            error_kind: FailureKind::parse(&row.get::<_, String>(2)?),
+            message: row.get(3)?,
+            attempts: row.get(4)?,
+            last_attempt_at: row.get(5)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Sources worth retrying without asking: transient failures that have had
+/// fewer than `max_attempts` attempts.
+pub fn due(conn: &Connection, max_attempts: u32) -> Result<Vec<PathBuf>, Error> {
+    Ok(load(conn)?
+        .into_iter()
+        .filter(|retry| retry.error_kind.is_transient() && retry.attempts < max_attempts)
+        .map(|retry| PathBuf::from(retry.source))
+        .collect())
+}
+
+pub fn clear(conn: &Connection) -> Result<usize, Error> {
+    Ok(conn.execute("DELETE FROM pending_retries", [])?)
+}
@@ -0,0 +1,824 @@
+use crate::{
+    categories, extension_groups, origin, projects::ProjectAction, quota::Quota, Error, ScannedEntry, NO_EXTENSION,
+};
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime};
+use glob::{MatchOptions, Pattern};
+use regex::Regex;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration as StdDuration,
+};
+
+/// A boolean tree of tests on a desktop entry, stored as JSON in `rules.condition`,
+/// e.g. `{"type": "all", "conditions": [{"type": "extension", "value": ".pdf"},
+/// {"type": "name_glob", "pattern": "invoice*"}, {"type": "size_below", "bytes": 5242880}]}`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    /// True when every child is; an empty list always matches.
+    All { conditions: Vec<Condition> },
+    /// True when any child is; an empty list never matches.
+    Any { conditions: Vec<Condition> },
+    Not { condition: Box<Condition> },
+    /// Mapping key of the entry: `.pdf` (case-insensitive, dot optional),
+    /// `folder`, or a built-in group like `@images`.
+    Extension { value: String },
+    /// Shell-style pattern on the file name, case-insensitive.
+    NameGlob { pattern: String },
+    /// Regular expression searched in the file name.
+    NameRegex { pattern: String },
+    /// Size in bytes, recursive for folders.
+    SizeBelow { bytes: u64 },
+    SizeAtLeast { bytes: u64 },
+    IsFolder,
+    /// Domain a downloaded file came from, subdomains included, e.g.
+    /// `github.com`. Files without a recorded origin never match.
+    SourceDomain { domain: String },
+    /// Folder with an entry directly inside named like `name`, a glob, e.g.
+    /// `.git`. Files never match.
+    Contains { name: String },
+    /// Folder with nothing inside.
+    IsEmpty,
+    /// Folder where at least `percent` of the files below have the mapping
+    /// key `value`, which may be a group, e.g. `@images`.
+    MostlyExtension {
+        value: String,
+        #[serde(default = "default_percent")]
+        percent: u8,
+    },
+}
+
+fn default_percent() -> u8 {
+    60
+}
+
+/// A user rule. Enabled rules are tried by descending priority before the
+/// extension mappings; the first one whose condition holds picks the target.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Rule {
+    /// Unset when creating a rule.
+    #[serde(default)]
+    pub id: Option<i64>,
+    pub name: String,
+    pub condition: Condition,
+    /// Ignored when `category_id` is set; the rule then follows its
+    /// category's folder. Unused by rules that delete or skip.
+    #[serde(default)]
+    pub target_path: String,
+    /// Category, often a subcategory like `images/screenshots`, the rule
+    /// sends entries to.
+    #[serde(default)]
+    pub category_id: Option<String>,
+    #[serde(default)]
+    pub priority: i64,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    /// Rules in a disabled group are skipped regardless of `enabled`.
+    #[serde(default)]
+    pub group_id: Option<i64>,
+    /// When the rule applies; unset means always.
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    /// What happens to the entries it matches.
+    #[serde(default)]
+    pub action: RuleAction,
+    /// Number of entries this rule has routed, and when it last did.
+    #[serde(default, skip_deserializing)]
+    pub match_count: u64,
+    #[serde(default, skip_deserializing)]
+    pub last_matched_at: Option<i64>,
+}
+
+/// What a rule does with the entries it matches.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Moves them to its target.
+    #[default]
+    Move,
+    /// Sends them to the trash once they have gone `after_days` days without
+    /// changes, e.g. leftover `.tmp` files. Until then they stay put.
+    Delete { after_days: u32 },
+    /// Leaves them where they are, e.g. folders holding a `.git`.
+    Skip,
+}
+
+impl RuleAction {
+    /// How long matched entries are kept before they are deleted, for rules
+    /// that delete.
+    fn retention(self) -> Option<StdDuration> {
+        match self {
+            RuleAction::Move | RuleAction::Skip => None,
+            RuleAction::Delete { after_days } => Some(StdDuration::from_secs(u64::from(after_days) * 24 * 60 * 60)),
+        }
+    }
+}
+
+/// Days and hours a rule is active, in local time, e.g. Fridays from 18:00
+/// to 23:59 for `{"days": [5], "start": "18:00", "end": "23:59"}`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Schedule {
+    /// ISO weekdays, 1 (Monday) to 7 (Sunday); empty means every day.
+    #[serde(default)]
+    pub days: Vec<u32>,
+    /// `HH:MM` bounds of the daily window; both or neither must be set. A
+    /// window whose start is after its end runs past midnight and belongs to
+    /// the day it started on.
+    #[serde(default)]
+    pub start: Option<String>,
+    #[serde(default)]
+    pub end: Option<String>,
+}
+
+struct CompiledSchedule {
+    days: Vec<u32>,
+    window: Option<(NaiveTime, NaiveTime)>,
+}
+
+impl Schedule {
+    fn compile(&self) -> Result<CompiledSchedule, Error> {
+        if let Some(day) = self.days.iter().find(|day| !(1..=7).contains(*day)) {
+            return Err(Error::InvalidRule(format!("schedule day {} is not between 1 and 7", day)));
+        }
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|_| Error::InvalidRule(format!("schedule time `{}` is not HH:MM", time)))
+        };
+        let window = match (&self.start, &self.end) {
+            (Some(start), Some(end)) => Some((parse(start)?, parse(end)?)),
+            (None, None) => None,
+            _ => {
+                return Err(Error::InvalidRule(
+                    "schedule needs both a start and an end time".to_string(),
+                ))
+            }
+        };
+        Ok(CompiledSchedule {
+            days: self.days.clone(),
+            window,
+        })
+    }
+}
+
+impl CompiledSchedule {
+    fn is_active(&self, now: NaiveDateTime) -> bool {
+        let on_day = |at: NaiveDateTime| self.days.is_empty() || self.days.contains(&at.weekday().number_from_monday());
+        let time = now.time();
+        match self.window {
+            None => on_day(now),
+            Some((start, end)) if start <= end => on_day(now) && start <= time && time < end,
+            Some((start, end)) => {
+                (on_day(now) && time >= start) || (on_day(now - Duration::days(1)) && time < end)
+            }
+        }
+    }
+}
+
+/// A named set of rules that can be switched on and off together.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RuleGroup {
+    #[serde(default)]
+    pub id: Option<i64>,
+    pub name: String,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    /// Filled in when listing groups.
+    #[serde(default, skip_deserializing)]
+    pub rule_count: usize,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// How an entry matched by several rules (or a rule and its extension mapping)
+/// picks its target.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleSemantics {
+    /// The highest priority rule wins; mappings come after every rule.
+    #[default]
+    FirstMatch,
+    /// The match with the most specific condition wins: a regex beats an
+    /// extension, which beats a glob. Ties fall back to priority.
+    MostSpecific,
+    /// When matches disagree on the target, nothing is moved and the entry is
+    /// put in the conflict inbox for the user to decide.
+    ReportAmbiguity,
+}
+
+/// The rule or mapping behind a candidate, for match counters.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchSource {
+    Rule(i64),
+    Mapping(String),
+    /// Photos and clips recognized as copied off a camera.
+    CameraImport,
+    /// The mapping of an extension for one source, by source id.
+    SourceMapping(i64, String),
+    /// Entries nothing else matched, archived for being left untouched.
+    Archive,
+    /// The mapping of a MIME type or family, like `image/*`.
+    Mime(String),
+    /// A development project, by the marker found in it.
+    Project(String),
+}
+
+/// A rule or mapping matching a scanned entry.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Candidate {
+    pub source: MatchSource,
+    /// `rule "Invoices"` or `mapping .pdf`.
+    pub matched_by: String,
+    pub target_dir: PathBuf,
+    #[serde(skip)]
+    pub quota: Quota,
+    /// Set for rules that delete instead of moving: how long entries are
+    /// kept first. `target_dir` is empty then.
+    #[serde(skip)]
+    pub retention: Option<StdDuration>,
+    /// Set for rules that leave entries where they are.
+    #[serde(skip)]
+    pub skips: bool,
+    #[serde(skip)]
+    specificity: u8,
+}
+
+/// Specificity of an extension mapping, the same as an extension condition.
+const EXTENSION_SPECIFICITY: u8 = 2;
+
+impl Candidate {
+    pub fn mapping(extension: &str, target_dir: PathBuf, quota: Quota) -> Self {
+        Candidate {
+            source: MatchSource::Mapping(extension.to_string()),
+            matched_by: format!("mapping {}", extension),
+            target_dir,
+            quota,
+            retention: None,
+            skips: false,
+            specificity: EXTENSION_SPECIFICITY,
+        }
+    }
+}
+
+impl Candidate {
+    pub fn source_mapping(source_id: i64, extension: &str, target_dir: PathBuf) -> Self {
+        Candidate {
+            source: MatchSource::SourceMapping(source_id, extension.to_string()),
+            matched_by: format!("source mapping {}", extension),
+            target_dir,
+            quota: Quota::default(),
+            retention: None,
+            skips: false,
+            specificity: EXTENSION_SPECIFICITY,
+        }
+    }
+}
+
+impl Candidate {
+    /// Only ever used when nothing else matched, so it has no specificity.
+    pub fn archive(target_dir: PathBuf) -> Self {
+        Candidate {
+            source: MatchSource::Archive,
+            matched_by: "archive of untouched entries".to_string(),
+            target_dir,
+            quota: Quota::default(),
+            retention: None,
+            skips: false,
+            specificity: 0,
+        }
+    }
+}
+
+/// Sniffing the content says as little about a file as a glob on its name.
+const MIME_SPECIFICITY: u8 = 1;
+
+impl Candidate {
+    pub fn mime(pattern: &str, target_dir: PathBuf) -> Self {
+        Candidate {
+            source: MatchSource::Mime(pattern.to_string()),
+            matched_by: format!("MIME mapping {}", pattern),
+            target_dir,
+            quota: Quota::default(),
+            retention: None,
+            skips: false,
+            specificity: MIME_SPECIFICITY,
+        }
+    }
+}
+
+/// Project folders are recognized by what they hold, as narrowly as camera
+/// imports by their names.
+const PROJECT_SPECIFICITY: u8 = 3;
+
+impl Candidate {
+    /// Projects that are protected stay where they are.
+    pub fn project(marker: &str, action: ProjectAction, target_dir: PathBuf) -> Self {
+        let skips = action == ProjectAction::Protect;
+        Candidate {
+            source: MatchSource::Project(marker.to_string()),
+            matched_by: format!("project marker {}", marker),
+            target_dir: if skips { PathBuf::new() } else { target_dir },
+            quota: Quota::default(),
+            retention: None,
+            skips,
+            specificity: PROJECT_SPECIFICITY,
+        }
+    }
+}
+
+/// Camera imports are recognized by name patterns, as specific as a regex.
+const CAMERA_IMPORT_SPECIFICITY: u8 = 3;
+
+impl Candidate {
+    pub fn camera_import(target_dir: PathBuf) -> Self {
+        Candidate {
+            source: MatchSource::CameraImport,
+            matched_by: "camera import".to_string(),
+            target_dir,
+            quota: Quota::default(),
+            retention: None,
+            skips: false,
+            specificity: CAMERA_IMPORT_SPECIFICITY,
+        }
+    }
+}
+
+/// Where an entry goes under the configured semantics.
+pub enum Decision {
+    Unmatched,
+    /// The chosen match and why it was chosen.
+    Target(Candidate, &'static str),
+    Ambiguous(Vec<Candidate>),
+}
+
+impl RuleSemantics {
+    /// Picks among `candidates`, given in priority order.
+    pub fn decide(self, mut candidates: Vec<Candidate>) -> Decision {
+        if candidates.is_empty() {
+            return Decision::Unmatched;
+        }
+        match self {
+            RuleSemantics::FirstMatch => Decision::Target(candidates.swap_remove(0), "first match by priority"),
+            RuleSemantics::MostSpecific => {
+                // max_by_key keeps the last maximum, so search from the back to
+                // let priority break ties
+                let best = candidates
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .max_by_key(|(_, c)| c.specificity)
+                    .map(|(i, _)| i)
+                    .unwrap_or(0);
+                Decision::Target(candidates.swap_remove(best), "most specific match")
+            }
+            RuleSemantics::ReportAmbiguity => {
+                if candidates.iter().all(|c| c.target_dir == candidates[0].target_dir) {
+                    Decision::Target(candidates.swap_remove(0), "no conflicting match")
+                } else {
+                    Decision::Ambiguous(candidates)
+                }
+            }
+        }
+    }
+}
+
+/// A condition with its patterns parsed, ready to be evaluated many times.
+enum Compiled {
+    All(Vec<Compiled>),
+    Any(Vec<Compiled>),
+    Not(Box<Compiled>),
+    Extension(String),
+    NameGlob(Pattern),
+    NameRegex(Regex),
+    SizeBelow(u64),
+    SizeAtLeast(u64),
+    IsFolder,
+    SourceDomain(String),
+    Contains(Pattern),
+    IsEmpty,
+    MostlyExtension(Vec<String>, f64),
+}
+
+const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+impl Condition {
+    fn compile(&self) -> Result<Compiled, Error> {
+        Ok(match self {
+            Condition::All { conditions } => {
+                Compiled::All(conditions.iter().map(Condition::compile).collect::<Result<_, _>>()?)
+            }
+            Condition::Any { conditions } => {
+                Compiled::Any(conditions.iter().map(Condition::compile).collect::<Result<_, _>>()?)
+            }
+            Condition::Not { condition } => Compiled::Not(Box::new(condition.compile()?)),
+            Condition::Extension { value } => {
+                let mut keys = mapping_keys(value)?;
+                if keys.len() == 1 {
+                    Compiled::Extension(keys.remove(0))
+                } else {
+                    Compiled::Any(keys.into_iter().map(Compiled::Extension).collect())
+                }
+            }
+            Condition::NameGlob { pattern } => Compiled::NameGlob(
+                Pattern::new(pattern)
+                    .map_err(|e| Error::InvalidRule(format!("bad pattern `{}`: {}", pattern, e)))?,
+            ),
+            Condition::NameRegex { pattern } => Compiled::NameRegex(
+                Regex::new(pattern)
+                    .map_err(|e| Error::InvalidRule(format!("bad regular expression `{}`: {}", pattern, e)))?,
+            ),
+            Condition::SizeBelow { bytes } => Compiled::SizeBelow(*bytes),
+            Condition::SizeAtLeast { bytes } => Compiled::SizeAtLeast(*bytes),
+            Condition::IsFolder => Compiled::IsFolder,
+            Condition::SourceDomain { domain } => {
+                let domain = domain.trim().trim_start_matches("*.").trim_matches('.').to_lowercase();
+                if domain.is_empty() || domain.contains(['/', ':']) {
+                    return Err(Error::InvalidRule(format!("`{}` is not a domain", domain)));
+                }
+                Compiled::SourceDomain(domain)
+            }
+            Condition::Contains { name } => Compiled::Contains(
+                Pattern::new(name).map_err(|e| Error::InvalidRule(format!("bad pattern `{}`: {}", name, e)))?,
+            ),
+            Condition::IsEmpty => Compiled::IsEmpty,
+            Condition::MostlyExtension { value, percent } => {
+                if !(1..=100).contains(percent) {
+                    return Err(Error::InvalidRule(format!("{}% is not between 1 and 100", percent)));
+                }
+                Compiled::MostlyExtension(mapping_keys(value)?, f64::from(*percent) / 100.0)
+            }
+        })
+    }
+}
+
+/// The mapping keys an extension condition's `value` stands for: the
+/// extensions of a group, or `value` itself with a dot added if missing.
+fn mapping_keys(value: &str) -> Result<Vec<String>, Error> {
+    let value = value.trim().to_lowercase();
+    if extension_groups::is_group(&value) {
+        let extensions = extension_groups::extensions(&value)
+            .ok_or_else(|| Error::InvalidRule(format!("no extension group `{}`", value)))?;
+        Ok(extensions.iter().map(|extension| extension.to_string()).collect())
+    } else if value == "folder" || value == NO_EXTENSION || value.starts_with('.') {
+        Ok(vec![value])
+    } else {
+        Ok(vec![format!(".{}", value)])
+    }
+}
+
+impl Compiled {
+    /// Ranks how narrowly a condition picks files: regex 3, extension, source
+    /// domain and the contents of a folder 2, glob 1, anything else 0. Looking
+    /// inside a folder says as much about it as an extension about a file,
+    /// so such rules win over the `folder` mapping. A conjunction is as specific as its most
+    /// specific part, a disjunction as its least specific one.
+    fn specificity(&self) -> u8 {
+        match self {
+            Compiled::All(conditions) => conditions.iter().map(Compiled::specificity).max().unwrap_or(0),
+            Compiled::Any(conditions) => conditions.iter().map(Compiled::specificity).min().unwrap_or(0),
+            Compiled::NameRegex(_) => 3,
+            Compiled::Extension(_)
+            | Compiled::SourceDomain(_)
+            | Compiled::Contains(_)
+            | Compiled::IsEmpty
+            | Compiled::MostlyExtension(..) => EXTENSION_SPECIFICITY,
+            Compiled::NameGlob(_) => 1,
+            Compiled::Not(_) | Compiled::SizeBelow(_) | Compiled::SizeAtLeast(_) | Compiled::IsFolder => 0,
+        }
+    }
+}
+
+impl Compiled {
+    fn matches(&self, entry: &ScannedEntry) -> bool {
+        match self {
+            Compiled::All(conditions) => conditions.iter().all(|c| c.matches(entry)),
+            Compiled::Any(conditions) => conditions.iter().any(|c| c.matches(entry)),
+            Compiled::Not(condition) => !condition.matches(entry),
+            Compiled::Extension(value) => entry.key == *value,
+            Compiled::NameGlob(pattern) => pattern.matches_with(&entry.name(), GLOB_OPTIONS),
+            Compiled::NameRegex(regex) => regex.is_match(&entry.name()),
+            Compiled::SizeBelow(bytes) => entry.size() < *bytes,
+            Compiled::SizeAtLeast(bytes) => entry.size() >= *bytes,
+            Compiled::IsFolder => entry.is_dir,
+            Compiled::SourceDomain(domain) => entry
+                .source_domain()
+                .is_some_and(|source| origin::matches_domain(source, domain)),
+            Compiled::Contains(pattern) => entry.is_dir && entry.contents().contains(pattern),
+            Compiled::IsEmpty => entry.is_dir && entry.contents().is_empty(),
+            Compiled::MostlyExtension(keys, share) => entry.is_dir && entry.contents().share_of(keys) >= *share,
+        }
+    }
+}
+
+struct CompiledRule {
+    id: i64,
+    name: String,
+    condition: Compiled,
+    schedule: Option<CompiledSchedule>,
+    target_dir: PathBuf,
+    retention: Option<StdDuration>,
+    skips: bool,
+}
+
+impl CompiledRule {
+    fn new(rule: Rule) -> Result<Self, Error> {
+        Ok(CompiledRule {
+            condition: rule.condition.compile()?,
+            schedule: rule.schedule.as_ref().map(Schedule::compile).transpose()?,
+            id: rule.id.unwrap_or_default(),
+            name: rule.name,
+            target_dir: PathBuf::from(rule.target_path),
+            retention: rule.action.retention(),
+            skips: rule.action == RuleAction::Skip,
+        })
+    }
+
+    fn matches(&self, entry: &ScannedEntry, now: NaiveDateTime) -> bool {
+        self.schedule.as_ref().is_none_or(|schedule| schedule.is_active(now)) && self.condition.matches(entry)
+    }
+
+    fn candidate(&self) -> Candidate {
+        Candidate {
+            source: MatchSource::Rule(self.id),
+            matched_by: format!("rule \"{}\"", self.name),
+            target_dir: self.target_dir.clone(),
+            quota: Quota::default(),
+            retention: self.retention,
+            skips: self.skips,
+            specificity: self.condition.specificity(),
+        }
+    }
+}
+
+/// The enabled rules in evaluation order, compiled.
+#[derive(Default)]
+pub struct RuleSet {
+    rules: Vec<CompiledRule>,
+}
+
+impl RuleSet {
+    pub fn load(conn: &Connection) -> Result<Self, Error> {
+        let disabled_groups: Vec<i64> = conn
+            .prepare("SELECT id FROM rule_groups WHERE enabled = 0")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        let mut rules = Vec::new();
+        let active = |rule: &Rule| rule.enabled && !rule.group_id.is_some_and(|id| disabled_groups.contains(&id));
+        for rule in load_rules(conn)?.into_iter().filter(active) {
+            // Rules are validated when saved; this only trips on hand-edited rows
+            let name = rule.name.clone();
+            match CompiledRule::new(rule) {
+                Ok(rule) => rules.push(rule),
+                Err(e) => tracing::warn!("Skipping rule {}: {}", name, e),
+            }
+        }
+        Ok(RuleSet { rules })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn target_dirs(&self) -> impl Iterator<Item = &Path> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.retention.is_none() && !rule.skips)
+            .map(|rule| rule.target_dir.as_path())
+    }
+
+    /// The rule matching `entry` first. Rules whose schedule excludes `now` are skipped.
+    pub fn first_match(&self, entry: &ScannedEntry, now: NaiveDateTime) -> Option<Candidate> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(entry, now))
+            .map(CompiledRule::candidate)
+    }
+
+    /// Every rule matching `entry` at `now`, in priority order.
+    pub fn all_matches(&self, entry: &ScannedEntry, now: NaiveDateTime) -> Vec<Candidate> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(entry, now))
+            .map(CompiledRule::candidate)
+            .collect()
+    }
+}
+
+/// All rules, in evaluation order.
+pub fn load_rules(conn: &Connection) -> Result<Vec<Rule>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT id, name, condition, target_path, priority, enabled, group_id, schedule,
+                match_count, last_matched_at, category_id, action
+         FROM rules ORDER BY priority DESC, id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let name: String = row.get(1)?;
+        let condition = serde_json::from_str(&row.get::<_, String>(2)?);
+        let schedule = row
+            .get::<_, Option<String>>(7)?
+            .map(|schedule| serde_json::from_str(&schedule))
+            .transpose();
+        let action = row
+            .get::<_, Option<String>>(11)?
+            .map(|action| serde_json::from_str(&action))
+            .transpose();
+        Ok(match (condition, schedule, action) {
+            (Ok(condition), Ok(schedule), Ok(action)) => Ok(Rule {
+                id: Some(row.get(0)?),
+                name,
+                condition,
+                target_path: row.get(3)?,
+                priority: row.get(4)?,
+                enabled: row.get(5)?,
+                group_id: row.get(6)?,
+                schedule,
+                action: action.unwrap_or_default(),
+                match_count: row.get(8)?,
+                last_matched_at: row.get(9)?,
+                category_id: row.get(10)?,
+            }),
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => Err(format!("Ignoring unreadable rule {}: {}", name, e)),
+        })
+    })?;
+
+    let mut rules = Vec::new();
+    for row in rows {
+        match row? {
+            Ok(rule) => rules.push(rule),
+            Err(message) => tracing::warn!("{}", message),
+        }
+    }
+    Ok(rules)
+}
+
+/// Inserts `rule`, or updates it when it has an id, and returns it as stored.
+pub fn save_rule(conn: &Connection, mut rule: Rule) -> Result<Rule, Error> {
+    if rule.name.trim().is_empty() {
+        return Err(Error::InvalidRule("name must not be empty".to_string()));
+    }
+    let moves = rule.action == RuleAction::Move;
+    if !moves {
+        rule.target_path = String::new();
+        rule.category_id = None;
+    }
+    if let Some(category_id) = &rule.category_id {
+        rule.target_path = categories::target_path(conn, category_id)?
+            .ok_or_else(|| Error::InvalidRule(format!("no category `{}`", category_id)))?;
+    }
+    if moves && rule.target_path.trim().is_empty() {
+        return Err(Error::InvalidRule("target path must not be empty".to_string()));
+    }
+    rule.condition.compile()?;
+    if let Some(schedule) = &rule.schedule {
+        schedule.compile()?;
+    }
+    let schedule = rule.schedule.as_ref().map(|schedule| serde_json::to_string(schedule).unwrap_or_default());
+    if let Some(group_id) = rule.group_id {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM rule_groups WHERE id = ?)",
+            params![group_id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Err(Error::InvalidRule(format!("no rule group with id {}", group_id)));
+        }
+    }
+    let condition = serde_json::to_string(&rule.condition).map_err(|e| Error::InvalidRule(e.to_string()))?;
+    let action = serde_json::to_string(&rule.action).map_err(|e| Error::InvalidRule(e.to_string()))?;
+
+    match rule.id {
+        Some(id) => {
+            let updated = conn.execute(
+                "UPDATE rules SET name = ?, condition = ?, target_path = ?, category_id = ?, priority = ?,
+                    enabled = ?, group_id = ?, schedule = ?, action = ?
+                 WHERE id = ?",
+                params![
+                    rule.name,
+                    condition,
+                    rule.target_path,
+                    rule.category_id,
+                    rule.priority,
+                    rule.enabled,
+                    rule.group_id,
+                    schedule,
+                    action,
+                    id
+                ],
+            )?;
+            if updated == 0 {
+                return Err(Error::InvalidRule(format!("no rule with id {}", id)));
+            }
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO rules (name, condition, target_path, category_id, priority, enabled, group_id, schedule,
+                    action)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    rule.name,
+                    condition,
+                    rule.target_path,
+                    rule.category_id,
+                    rule.priority,
+                    rule.enabled,
+                    rule.group_id,
+                    schedule,
+                    action
+                ],
+            )?;
+            rule.id = Some(conn.last_insert_rowid());
+        }
+    }
+    Ok(rule)
+}
+
+/// Returns whether a rule with `id` existed.
+pub fn delete_rule(conn: &Connection, id: i64) -> Result<bool, Error> {
+    Ok(conn.execute("DELETE FROM rules WHERE id = ?", params![id])? > 0)
+}
+
+pub fn load_groups(conn: &Connection) -> Result<Vec<RuleGroup>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT g.id, g.name, g.enabled, COUNT(r.id) FROM rule_groups g
+         LEFT JOIN rules r ON r.group_id = g.id
+         GROUP BY g.id ORDER BY g.name",
+    )?;
+    let groups = stmt.query_map([], |row| {
+        Ok(RuleGroup {
+            id: Some(row.get(0)?),
+            name: row.get(1)?,
+            enabled: row.get(2)?,
+            rule_count: row.get(3)?,
+        })
+    })?;
+    Ok(groups.collect::<Result<_, _>>()?)
+}
+
+/// Inserts `group`, or renames and enables/disables it when it has an id.
+pub fn save_group(conn: &Connection, mut group: RuleGroup) -> Result<RuleGroup, Error> {
+    if group.name.trim().is_empty() {
+        return Err(Error::InvalidRule("group name must not be empty".to_string()));
+    }
+    let taken: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM rule_groups WHERE name = ? AND id IS NOT ?)",
+        params![group.name, group.id],
+        |row| row.get(0),
+    )?;
+    if taken {
+        return Err(Error::InvalidRule(format!("a group named `{}` already exists", group.name)));
+    }
+
+    match group.id {
+        Some(id) => {
+            let updated = conn.execute(
+                "UPDATE rule_groups SET name = ?, enabled = ? WHERE id = ?",
+                params![group.name, group.enabled, id],
+            )?;
+            if updated == 0 {
+                return Err(Error::InvalidRule(format!("no rule group with id {}", id)));
+            }
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO rule_groups (name, enabled) VALUES (?, ?)",
+                params![group.name, group.enabled],
+            )?;
+            group.id = Some(conn.last_insert_rowid());
+        }
+    }
+    Ok(group)
+}
+
+/// Enables or disables every rule of group `id` at once; flips the current
+/// state when `enabled` is unset. Returns the new state.
+pub fn toggle_group(conn: &Connection, id: i64, enabled: Option<bool>) -> Result<bool, Error> {
+    let current: Option<bool> = conn
+        .query_row("SELECT enabled FROM rule_groups WHERE id = ?", params![id], |row| row.get(0))
+        .optional()?;
+    let Some(current) = current else {
+        return Err(Error::InvalidRule(format!("no rule group with id {}", id)));
+    };
+
+    let enabled = enabled.unwrap_or(!current);
+    conn.execute("UPDATE rule_groups SET enabled = ? WHERE id = ?", params![enabled, id])?;
+    Ok(enabled)
+}
+
+/// Deletes group `id`. Its rules are kept and become ungrouped.
+pub fn delete_group(conn: &Connection, id: i64) -> Result<bool, Error> {
+    conn.execute("UPDATE rules SET group_id = NULL WHERE group_id = ?", params![id])?;
+    Ok(conn.execute("DELETE FROM rule_groups WHERE id = ?", params![id])? > 0)
+}
@@ -0,0 +1,779 @@
+use crate::{
+    categories, defaults, get_desktop_path, mappings,
+    rules::{self, Condition, Rule, RuleAction, Schedule},
+    sources, Error,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Value of `format` in every ruleset document.
+pub const FORMAT: &str = "desksort-ruleset";
+
+/// Version of the documents `export` writes. Version 1 had no `version`
+/// and only mappings and rules; 2 added metadata, categories and ignore
+/// patterns.
+pub const VERSION: u32 = 2;
+
+/// Extension of ruleset files, which DeskSort is registered to open.
+pub const EXTENSION: &str = "desksortrules";
+
+/// A rule as it travels between machines: what it matches and where it sends
+/// entries, without local ids, groups or counters. Rules are told apart by
+/// name.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SharedRule {
+    pub name: String,
+    pub condition: Condition,
+    /// Relative to the Sorted folder when it is inside it.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub target: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category_id: Option<String>,
+    #[serde(default)]
+    pub priority: i64,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Schedule>,
+    #[serde(default)]
+    pub action: RuleAction,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// A category rules can target by id. Subcategories go into their parent's
+/// folder wherever that is locally.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SharedCategory {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    pub name: String,
+    /// Relative to the Sorted folder when it is inside it.
+    #[serde(default)]
+    pub target: String,
+}
+
+/// Who made a ruleset and what it is for, shown before it is imported.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Unix time it was exported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exported_at: Option<i64>,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+/// Mappings and rules in a form that can be shared, e.g. as a team's gist
+/// or a `.desksortrules` file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Ruleset {
+    pub format: String,
+    #[serde(default = "first_version")]
+    pub version: u32,
+    #[serde(default)]
+    pub metadata: Metadata,
+    /// Parents before their children.
+    #[serde(default)]
+    pub categories: Vec<SharedCategory>,
+    /// Glob patterns for desktop entries to leave alone.
+    #[serde(default)]
+    pub ignore_patterns: Vec<String>,
+    /// Extension to target folder, relative to the Sorted folder when it is
+    /// inside it.
+    #[serde(default)]
+    pub mappings: BTreeMap<String, String>,
+    #[serde(default)]
+    pub rules: Vec<SharedRule>,
+}
+
+impl Default for Ruleset {
+    /// An empty ruleset of the current version.
+    fn default() -> Self {
+        Ruleset {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            metadata: Metadata::default(),
+            categories: Vec::new(),
+            ignore_patterns: Vec::new(),
+            mappings: BTreeMap::new(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// What to do when a ruleset and the local setup disagree. None removes
+/// anything the ruleset lacks.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// Only add what is missing locally.
+    #[default]
+    KeepLocal,
+    /// Also overwrite local mappings and rules that differ.
+    PreferImported,
+    /// Add what is missing and settle each difference as the resolutions
+    /// say, leaving the rest for the user to decide.
+    Interactive,
+}
+
+impl Strategy {
+    fn as_str(self) -> &'static str {
+        match self {
+            Strategy::KeepLocal => "keep_local",
+            Strategy::PreferImported => "prefer_imported",
+            Strategy::Interactive => "interactive",
+        }
+    }
+
+    fn parse(value: &str) -> Strategy {
+        match value {
+            "prefer_imported" => Strategy::PreferImported,
+            "interactive" => Strategy::Interactive,
+            _ => Strategy::KeepLocal,
+        }
+    }
+}
+
+/// Which side of a difference wins.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Local,
+    /// The imported or upstream version.
+    Remote,
+}
+
+/// How the user settled a difference an earlier preview or sync reported.
+#[derive(Deserialize, Debug)]
+pub struct Resolution {
+    pub item: Item,
+    pub keep: Side,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemKind {
+    Mapping,
+    Rule,
+    Category,
+    IgnorePattern,
+}
+
+/// A mapping, by extension, a rule, by name, a category, by id, or an
+/// ignore pattern, by itself.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Item {
+    pub kind: ItemKind,
+    pub key: String,
+}
+
+/// A mapping or rule in a ruleset that isn't the same locally. Mapping
+/// values are target folders, rule values `SharedRule`s, category values
+/// `SharedCategory`s and ignore pattern values the patterns.
+#[derive(Serialize, Debug)]
+pub struct Change {
+    pub item: Item,
+    /// Unset when it doesn't exist locally.
+    pub local: Option<Value>,
+    pub imported: Value,
+}
+
+/// What applying a ruleset does, or did.
+#[derive(Serialize, Default, Debug)]
+pub struct Changes {
+    /// Missing locally, so added.
+    pub added: Vec<Change>,
+    /// Overwritten with the imported version.
+    pub changed: Vec<Change>,
+    /// Different locally and left that way, by the strategy or because the
+    /// user hasn't decided yet.
+    pub conflicting: Vec<Change>,
+    /// Of the imported ruleset.
+    pub metadata: Metadata,
+}
+
+impl Changes {
+    /// Whether nothing local changes.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty()
+    }
+}
+
+pub(crate) fn sorted_dir() -> Result<PathBuf, Error> {
+    Ok(get_desktop_path()?.join("Sorted"))
+}
+
+/// `target` relative to `sorted` with `/` separators if it is inside it, so
+/// it means the same on a machine with another user name or platform.
+pub(crate) fn portable(target: &str, sorted: &Path) -> String {
+    match Path::new(target).strip_prefix(sorted) {
+        Ok(relative) => relative
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        Err(_) => target.to_string(),
+    }
+}
+
+pub(crate) fn resolve(target: &str, sorted: &Path) -> String {
+    if target.is_empty() || Path::new(target).is_absolute() {
+        return target.to_string();
+    }
+    target.split('/').fold(sorted.to_path_buf(), |dir, part| dir.join(part)).display().to_string()
+}
+
+pub(crate) fn share(rule: &Rule, sorted: &Path) -> SharedRule {
+    SharedRule {
+        name: rule.name.clone(),
+        condition: rule.condition.clone(),
+        target: if rule.category_id.is_some() {
+            String::new()
+        } else {
+            portable(&rule.target_path, sorted)
+        },
+        category_id: rule.category_id.clone(),
+        priority: rule.priority,
+        enabled: rule.enabled,
+        schedule: rule.schedule.clone(),
+        action: rule.action,
+    }
+}
+
+/// Whether two values serialize alike; conditions and schedules have no
+/// `PartialEq`.
+fn same<T: Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+/// Saves `shared` as a local rule, over `local` if given, keeping its group.
+pub(crate) fn save_shared(conn: &Connection, shared: &SharedRule, local: Option<&Rule>, sorted: &Path) -> Result<(), Error> {
+    let rule = Rule {
+        id: local.and_then(|local| local.id),
+        name: shared.name.clone(),
+        condition: shared.condition.clone(),
+        target_path: resolve(&shared.target, sorted),
+        category_id: shared.category_id.clone(),
+        priority: shared.priority,
+        enabled: shared.enabled,
+        group_id: local.and_then(|local| local.group_id),
+        schedule: shared.schedule.clone(),
+        action: shared.action,
+        match_count: 0,
+        last_matched_at: None,
+    };
+    rules::save_rule(conn, rule).map_err(|e| match e {
+        Error::InvalidRule(detail) => Error::InvalidRuleset(format!("rule `{}`: {}", shared.name, detail)),
+        e => e,
+    })?;
+    Ok(())
+}
+
+/// The desktop source, whose ignore patterns travel with rulesets.
+fn desktop_source(conn: &Connection) -> Result<Option<sources::Source>, Error> {
+    Ok(sources::load(conn)?.into_iter().find(|source| source.is_desktop))
+}
+
+/// The local categories, mappings, rules and desktop ignore patterns as a
+/// ruleset.
+pub fn current(conn: &Connection) -> Result<Ruleset, Error> {
+    let sorted = sorted_dir()?;
+    let categories = categories::load(conn)?
+        .into_iter()
+        .map(|category| SharedCategory {
+            target: portable(&category.target_path, &sorted),
+            id: category.id,
+            parent_id: category.parent_id,
+            name: category.name,
+        })
+        .collect();
+    let mappings = conn
+        .prepare("SELECT extension, target_path FROM path_mappings")?
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .map(|row| row.map(|(extension, target)| (extension, portable(&target, &sorted))))
+        .collect::<Result<_, _>>()?;
+    let rules = rules::load_rules(conn)?.iter().map(|rule| share(rule, &sorted)).collect();
+    Ok(Ruleset {
+        categories,
+        ignore_patterns: desktop_source(conn)?.map(|source| source.ignore_patterns).unwrap_or_default(),
+        mappings,
+        rules,
+        ..Ruleset::default()
+    })
+}
+
+/// Reads a ruleset document, rejecting other JSON, versions newer than this
+/// build understands, rules without a unique name and categories that come
+/// twice or before their parent.
+pub fn parse(data: &[u8]) -> Result<Ruleset, Error> {
+    let ruleset: Ruleset = serde_json::from_slice(data).map_err(|e| Error::InvalidRuleset(e.to_string()))?;
+    if ruleset.format != FORMAT {
+        return Err(Error::InvalidRuleset(format!("format is not `{}`", FORMAT)));
+    }
+    if ruleset.version > VERSION {
+        return Err(Error::InvalidRuleset(format!(
+            "version {} needs a newer DeskSort, this one reads up to {}",
+            ruleset.version, VERSION
+        )));
+    }
+    let mut ids = HashSet::new();
+    for category in &ruleset.categories {
+        if category.parent_id.as_ref().is_some_and(|parent| !ids.contains(parent.as_str())) {
+            return Err(Error::InvalidRuleset(format!("category `{}` comes before its parent", category.id)));
+        }
+        if category.parent_id.is_none() && category.target.is_empty() {
+            return Err(Error::InvalidRuleset(format!("category `{}` has no target", category.id)));
+        }
+        if !ids.insert(category.id.as_str()) {
+            return Err(Error::InvalidRuleset(format!("two categories have the id `{}`", category.id)));
+        }
+    }
+    if let Some(pattern) = ruleset.ignore_patterns.iter().find(|p| glob::Pattern::new(p).is_err()) {
+        return Err(Error::InvalidRuleset(format!("`{}` is not a valid glob pattern", pattern)));
+    }
+    let mut names = HashSet::new();
+    for rule in &ruleset.rules {
+        if rule.name.trim().is_empty() {
+            return Err(Error::InvalidRuleset("a rule has no name".to_string()));
+        }
+        if !names.insert(rule.name.as_str()) {
+            return Err(Error::InvalidRuleset(format!("two rules are named `{}`", rule.name)));
+        }
+    }
+    Ok(ruleset)
+}
+
+/// Merges `ruleset` into the local mappings and rules by `strategy`, all or
+/// nothing. `resolutions` only count for `Strategy::Interactive`. Missing
+/// categories and ignore patterns are added whatever the strategy; existing
+/// categories are left as they are, as changing them would move folders.
+/// With `dry_run` nothing is saved, but the changes are checked all the
+/// same.
+pub fn apply(
+    conn: &Connection,
+    ruleset: &Ruleset,
+    strategy: Strategy,
+    resolutions: &[Resolution],
+    dry_run: bool,
+) -> Result<Changes, Error> {
+    let sorted = sorted_dir()?;
+    let tx = conn.unchecked_transaction()?;
+    let mut changes = Changes {
+        metadata: ruleset.metadata.clone(),
+        ..Changes::default()
+    };
+    let overwrite = |item: &Item| match strategy {
+        Strategy::KeepLocal => false,
+        Strategy::PreferImported => true,
+        Strategy::Interactive => resolutions
+            .iter()
+            .any(|resolution| &resolution.item == item && resolution.keep == Side::Remote),
+    };
+
+    // First, so rules can target them
+    for category in &ruleset.categories {
+        let target = resolve(&category.target, &sorted);
+        if categories::import(&tx, &category.id, category.parent_id.as_deref(), &category.name, &target)? {
+            changes.added.push(Change {
+                item: Item {
+                    kind: ItemKind::Category,
+                    key: category.id.clone(),
+                },
+                local: None,
+                imported: serde_json::to_value(category).unwrap_or_default(),
+            });
+        }
+    }
+
+    for (extension, target) in &ruleset.mappings {
+        let item = Item {
+            kind: ItemKind::Mapping,
+            key: extension.clone(),
+        };
+        let local = mappings::target(&tx, extension)?.map(|local| portable(&local, &sorted));
+        if local.as_ref() == Some(target) {
+            continue;
+        }
+        let change = Change {
+            local: local.map(Value::String),
+            imported: Value::String(target.clone()),
+            item,
+        };
+        if change.local.is_some() && !overwrite(&change.item) {
+            changes.conflicting.push(change);
+            continue;
+        }
+        mappings::save(&tx, extension, &resolve(target, &sorted), None)?;
+        if change.local.is_some() {
+            changes.changed.push(change);
+        } else {
+            changes.added.push(change);
+        }
+    }
+
+    let local_rules = rules::load_rules(&tx)?;
+    for shared in &ruleset.rules {
+        let item = Item {
+            kind: ItemKind::Rule,
+            key: shared.name.clone(),
+        };
+        let local = local_rules.iter().find(|rule| rule.name == shared.name);
+        let local_shared = local.map(|local| share(local, &sorted));
+        if local_shared.as_ref().is_some_and(|local| same(local, shared)) {
+            continue;
+        }
+        let change = Change {
+            local: local_shared.and_then(|local| serde_json::to_value(local).ok()),
+            imported: serde_json::to_value(shared).unwrap_or_default(),
+            item,
+        };
+        if local.is_some() && !overwrite(&change.item) {
+            changes.conflicting.push(change);
+            continue;
+        }
+        save_shared(&tx, shared, local, &sorted)?;
+        if local.is_some() {
+            changes.changed.push(change);
+        } else {
+            changes.added.push(change);
+        }
+    }
+
+    if let Some(desktop) = desktop_source(&tx)? {
+        let mut patterns = desktop.ignore_patterns.clone();
+        for pattern in &ruleset.ignore_patterns {
+            if patterns.contains(pattern) {
+                continue;
+            }
+            patterns.push(pattern.clone());
+            changes.added.push(Change {
+                item: Item {
+                    kind: ItemKind::IgnorePattern,
+                    key: pattern.clone(),
+                },
+                local: None,
+                imported: Value::String(pattern.clone()),
+            });
+        }
+        if patterns.len() > desktop.ignore_patterns.len() {
+            sources::update(
+                &tx,
+                desktop.id,
+                desktop.enabled,
+                desktop.depth,
+                desktop.include_hidden,
+                &patterns,
+                &desktop.include_patterns,
+            )?;
+        }
+    }
+
+    if !dry_run {
+        tx.commit()?;
+    }
+    Ok(changes)
+}
+
+/// Where to import a ruleset from.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImportSource {
+    /// A ruleset JSON file, e.g. one saved with `export`.
+    File { path: String },
+    /// The mappings a new database starts with, in folders named in the
+    /// user's language.
+    Preset,
+    /// A ruleset at an `http(s)://` URL.
+    Url { url: String },
+}
+
+/// Reads the ruleset `source` stands for. `language` names the folders of the
+/// preset.
+pub fn load(source: &ImportSource, language: &str) -> Result<Ruleset, Error> {
+    match source {
+        ImportSource::File { path } => parse(&fs::read(path)?),
+        ImportSource::Url { url } => parse(&download(url)?),
+        ImportSource::Preset => {
+            let sorted = sorted_dir()?;
+            Ok(Ruleset {
+                mappings: defaults::default_mappings(&sorted, language)
+                    .into_iter()
+                    .map(|mapping| {
+                        let target = portable(&mapping.target.display().to_string(), &sorted);
+                        (mapping.extension.to_string(), target)
+                    })
+                    .collect(),
+                ..Ruleset::default()
+            })
+        }
+    }
+}
+
+/// Saves the local setup as a ruleset file others can import or subscribe
+/// to, described by `metadata`.
+pub fn export(conn: &Connection, path: &Path, metadata: Metadata) -> Result<(), Error> {
+    let ruleset = Ruleset {
+        metadata: Metadata {
+            exported_at: Some(chrono::Utc::now().timestamp()),
+            ..metadata
+        },
+        ..current(conn)?
+    };
+    let document = serde_json::to_vec_pretty(&ruleset).map_err(|e| Error::InvalidRuleset(e.to_string()))?;
+    fs::write(path, document)?;
+    Ok(())
+}
+
+/// A ruleset DeskSort fetches and merges on a schedule.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Subscription {
+    /// Unset when subscribing.
+    #[serde(default)]
+    pub id: Option<i64>,
+    /// An `http(s)://` URL, or a `file://` URL or path for a shared folder.
+    pub url: String,
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// Hex SHA-256 the document must have, to pin one version.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Key of the HMAC-SHA256, in hex at `<url>.sig`, the document must be
+    /// signed with. Never sent back to the frontend.
+    #[serde(default, skip_serializing)]
+    pub secret: Option<String>,
+    #[serde(default, skip_deserializing)]
+    pub signed: bool,
+    /// SHA-256 of the document last applied.
+    #[serde(default, skip_deserializing)]
+    pub last_hash: Option<String>,
+    #[serde(default, skip_deserializing)]
+    pub checked_at: Option<i64>,
+    #[serde(default, skip_deserializing)]
+    pub last_error: Option<String>,
+}
+
+/// Outcome of refreshing one subscription.
+#[derive(Serialize, Debug)]
+pub struct Refresh {
+    pub subscription_id: i64,
+    pub url: String,
+    /// Unset when the document hasn't changed since it was last applied, or
+    /// couldn't be applied.
+    pub changes: Option<Changes>,
+    pub error: Option<String>,
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn is_sha256(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub(crate) fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    const BLOCK: usize = 64;
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let inner = Sha256::new()
+        .chain_update(block.map(|byte| byte ^ 0x36))
+        .chain_update(data)
+        .finalize();
+    Sha256::new()
+        .chain_update(block.map(|byte| byte ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+/// Downloads `url` with curl, which every supported platform ships, or reads
+/// it as a file.
+fn download(url: &str) -> io::Result<Vec<u8>> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        let output = Command::new("curl")
+            .args(["-fsSL", "--max-time", "30", "--", url])
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "curl failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        return Ok(output.stdout);
+    }
+    fs::read(url.strip_prefix("file://").unwrap_or(url))
+}
+
+/// A downloaded ruleset document that passed its subscription's checks.
+pub struct Fetched {
+    data: Vec<u8>,
+    /// Its SHA-256, in hex.
+    hash: String,
+}
+
+/// Downloads the document of `subscription` and checks it against the pinned
+/// hash and signature, if any. Doesn't need the database, so it can be held
+/// only while applying the result with `update`.
+pub fn fetch(subscription: &Subscription) -> Result<Fetched, Error> {
+    let data = download(&subscription.url)?;
+    let hash = hex(&Sha256::digest(&data));
+    if let Some(pinned) = &subscription.sha256 {
+        if !pinned.eq_ignore_ascii_case(&hash) {
+            return Err(Error::InvalidRuleset(format!("SHA-256 is {}, expected {}", hash, pinned)));
+        }
+    }
+    if let Some(secret) = &subscription.secret {
+        let signature = download(&format!("{}.sig", subscription.url))?;
+        let expected = hex(&hmac_sha256(secret.as_bytes(), &data));
+        if !String::from_utf8_lossy(&signature).trim().eq_ignore_ascii_case(&expected) {
+            return Err(Error::InvalidRuleset("signature doesn't match".to_string()));
+        }
+    }
+    Ok(Fetched { data, hash })
+}
+
+fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Subscription> {
+    let secret: Option<String> = row.get(4)?;
+    Ok(Subscription {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        strategy: Strategy::parse(&row.get::<_, String>(2)?),
+        sha256: row.get(3)?,
+        signed: secret.is_some(),
+        secret,
+        last_hash: row.get(5)?,
+        checked_at: row.get(6)?,
+        last_error: row.get(7)?,
+    })
+}
+
+const COLUMNS: &str = "id, url, strategy, sha256, secret, last_hash, checked_at, last_error";
+
+pub fn subscriptions(conn: &Connection) -> Result<Vec<Subscription>, Error> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM ruleset_subscriptions ORDER BY id", COLUMNS))?;
+    let subscriptions = stmt.query_map([], from_row)?.collect::<Result<_, _>>()?;
+    Ok(subscriptions)
+}
+
+pub fn subscription(conn: &Connection, id: i64) -> Result<Option<Subscription>, Error> {
+    Ok(conn
+        .query_row(
+            &format!("SELECT {} FROM ruleset_subscriptions WHERE id = ?", COLUMNS),
+            params![id],
+            from_row,
+        )
+        .optional()?)
+}
+
+/// Adds `subscription`, or updates it when it has an id, and returns it as
+/// stored. It is fetched on the next refresh.
+pub fn subscribe(conn: &Connection, mut new: Subscription) -> Result<Subscription, Error> {
+    new.url = new.url.trim().to_string();
+    if new.url.is_empty() {
+        return Err(Error::InvalidRuleset("URL must not be empty".to_string()));
+    }
+    new.sha256 = new.sha256.map(|hash| hash.trim().to_string()).filter(|hash| !hash.is_empty());
+    if new.sha256.as_deref().is_some_and(|hash| !is_sha256(hash)) {
+        return Err(Error::InvalidRuleset("SHA-256 must be 64 hex digits".to_string()));
+    }
+    new.secret = new.secret.filter(|secret| !secret.is_empty());
+    let strategy = new.strategy.as_str();
+    let id = match new.id {
+        Some(id) => {
+            // Applied again on the next refresh, since it may have to pass
+            // new checks
+            let updated = conn.execute(
+                "UPDATE ruleset_subscriptions SET url = ?, strategy = ?, sha256 = ?, secret = ?, last_hash = NULL
+                 WHERE id = ?",
+                params![new.url, strategy, new.sha256, new.secret, id],
+            )?;
+            if updated == 0 {
+                return Err(Error::InvalidRuleset(format!("no subscription with id {}", id)));
+            }
+            id
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO ruleset_subscriptions (url, strategy, sha256, secret) VALUES (?, ?, ?, ?)",
+                params![new.url, strategy, new.sha256, new.secret],
+            )?;
+            conn.last_insert_rowid()
+        }
+    };
+    subscription(conn, id)?.ok_or_else(|| Error::InvalidRuleset(format!("no subscription with id {}", id)))
+}
+
+/// Returns whether a subscription with `id` existed. What it brought in
+/// stays.
+pub fn unsubscribe(conn: &Connection, id: i64) -> Result<bool, Error> {
+    Ok(conn.execute("DELETE FROM ruleset_subscriptions WHERE id = ?", params![id])? > 0)
+}
+
+/// Applies what `fetch` got for `subscription`, unless the document is the
+/// one applied last time, and notes the outcome on the subscription. A
+/// document that can't be fetched or applied is an error of the refresh, not
+/// of this call.
+pub fn update(conn: &Connection, subscription: &Subscription, fetched: Result<Fetched, Error>) -> Result<Refresh, Error> {
+    let subscription_id = subscription
+        .id
+        .ok_or_else(|| Error::InvalidRuleset("subscription has no id".to_string()))?;
+    let outcome = fetched.and_then(|fetched| {
+        if subscription.last_hash.as_deref() == Some(fetched.hash.as_str()) {
+            return Ok((None, fetched.hash));
+        }
+        let changes = apply(conn, &parse(&fetched.data)?, subscription.strategy, &[], false)?;
+        Ok((Some(changes), fetched.hash))
+    });
+    let now = chrono::Utc::now().timestamp();
+    match outcome {
+        Ok((changes, hash)) => {
+            conn.execute(
+                "UPDATE ruleset_subscriptions SET last_hash = ?, checked_at = ?, last_error = NULL WHERE id = ?",
+                params![hash, now, subscription_id],
+            )?;
+            Ok(Refresh {
+                subscription_id,
+                url: subscription.url.clone(),
+                changes,
+                error: None,
+            })
+        }
+        Err(e) => {
+            let error = e.to_string();
+            tracing::warn!("Ruleset {} not applied: {}", subscription.url, error);
+            conn.execute(
+                "UPDATE ruleset_subscriptions SET checked_at = ?, last_error = ? WHERE id = ?",
+                params![now, error, subscription_id],
+            )?;
+            Ok(Refresh {
+                subscription_id,
+                url: subscription.url.clone(),
+                changes: None,
+                error: Some(error),
+            })
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use crate::{
+    config_file::ConfigBackend,
+    defaults,
+    duplicates::DuplicatePolicy,
+    large_folders::LargeFolderPolicy,
+    naming::CollisionNaming,
+    placeholders::PlaceholderPolicy,
+    projects::{self, ProjectAction},
+    rules::RuleSemantics,
+    Error,
+};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Global preferences, stored one JSON value per key in the `settings` table.
+/// Keys missing from the table fall back to `Default`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// Rewrite moved file names to Unicode NFC, so files coming from macOS (NFD)
+    /// don't end up as visually identical duplicates in synced folders.
+    pub normalize_filenames: bool,
+    /// Move several items at once. Worth it on SSDs, counterproductive on
+    /// spinning disks where seeks dominate.
+    pub parallel_moves: bool,
+    /// Upper bound on simultaneous moves when `parallel_moves` is on.
+    pub max_parallel_moves: usize,
+    /// Keep sorting from competing with foreground disk use: copies are rate
+    /// limited and every move is followed by a short pause.
+    pub low_impact_mode: bool,
+    /// Copy bandwidth in low impact mode.
+    pub low_impact_bytes_per_sec: u64,
+    /// Pause after each move in low impact mode.
+    pub low_impact_pause_ms: u64,
+    /// How an entry matched by several rules picks its target.
+    pub rule_semantics: RuleSemantics,
+    /// Watch for files the user files into the sorted tree by hand and
+    /// propose mappings from them.
+    pub learn_from_moves: bool,
+    /// Whether mappings and settings are edited in `desksort.toml`.
+    pub config_backend: ConfigBackend,
+    /// Language default category folders are named in, as an ISO 639-1
+    /// code. Follows the system language when unset.
+    pub language: Option<String>,
+    /// Give category folders DeskSort creates their category's icon, or a
+    /// Finder color on macOS.
+    pub folder_icons: bool,
+    /// How often a move that failed for a passing reason (a dropped
+    /// connection, a file held open) is attempted before it waits for the
+    /// user. 0 turns automatic retries off.
+    pub retry_attempts: u32,
+    /// Leave entries modified less than this many seconds ago, which may
+    /// still be being written.
+    pub min_age_secs: u64,
+    /// Create every target folder as soon as a mapping or rule points at
+    /// it, rather than on the first move into it.
+    pub create_target_dirs: bool,
+    /// Send photos and clips copied off a camera or phone (`IMG_1234.jpg`, a
+    /// `DCIM` folder) to dated folders instead of the image and video
+    /// mappings.
+    pub camera_imports: bool,
+    /// Folder camera imports are filed below by date. Defaults to a Photos
+    /// folder next to the other categories.
+    pub camera_import_dir: Option<String>,
+    /// Keep the record of where a downloaded file came from (its
+    /// `Zone.Identifier` stream on Windows, its quarantine and where-from
+    /// attributes on macOS) when moving it. Without it, Windows no longer
+    /// warns before opening the file and Gatekeeper no longer checks it.
+    pub keep_download_origin: bool,
+    /// Queue the moves a sort plans for the user to approve or reject
+    /// instead of carrying them out.
+    pub approve_moves: bool,
+    /// How a moved entry is renamed when its target folder already holds one
+    /// of the same name.
+    pub collision_naming: CollisionNaming,
+    /// What happens to files named like a copy of another, such as
+    /// `report (1).pdf` or `report - Copy.pdf`.
+    pub duplicate_copies: DuplicatePolicy,
+    /// Sweep entries no rule or mapping handles into dated archive folders
+    /// once they have gone this many days without changes. Unset leaves them
+    /// on the desktop.
+    pub archive_after_days: Option<u32>,
+    /// Folder archived entries are filed below by month. Defaults to an
+    /// Archived folder next to the other categories, apart from the
+    /// Archives category of zip files.
+    pub archive_dir: Option<String>,
+    /// Remind the user of files no rule or mapping handles once they have sat
+    /// on the desktop this many days. Unset turns the reminders off.
+    pub stale_after_days: Option<u32>,
+    /// Show a summary of the past week's sorting once a week.
+    pub weekly_report: bool,
+    /// Days sorts are kept in the history, and can be restored from the
+    /// trash through it. Unset keeps them for good.
+    pub history_keep_days: Option<u32>,
+    /// Most sorts kept in the history, the latest ones. Unset keeps all.
+    pub history_keep_sessions: Option<u32>,
+    /// Folder, file or URL the team's shared ruleset is synced with every
+    /// hour. Unset turns syncing off.
+    pub team_ruleset: Option<String>,
+    /// Names that give a development project folder away, like `.git` or
+    /// `*.sln`, and whether such folders are left alone or moved to
+    /// `project_dir`.
+    pub project_markers: BTreeMap<String, ProjectAction>,
+    /// Folder projects with a marker that moves them go to. Defaults to a
+    /// Projects folder inside the Code category.
+    pub project_dir: Option<String>,
+    /// Folders bigger than this many bytes, or holding more than
+    /// `max_folder_files` files, aren't moved like other entries but
+    /// handled as `large_folders` says. Unset means no limit.
+    pub max_folder_bytes: Option<u64>,
+    pub max_folder_files: Option<u64>,
+    pub large_folders: LargeFolderPolicy,
+    /// What happens to online-only files of OneDrive, Dropbox or iCloud.
+    pub cloud_placeholders: PlaceholderPolicy,
+    /// Folder sorted as the desktop instead of the one the system reports.
+    pub desktop_override: Option<String>,
+    /// Sort the Windows public desktop along with the desktop, as its
+    /// entries show up there too.
+    pub public_desktop: bool,
+    /// Salted hash of the PIN that guards changes to mappings and rules and
+    /// the destructive tools. Never sent to the UI, and only changed through
+    /// `app_lock::set`.
+    #[serde(skip_serializing)]
+    pub app_lock: Option<String>,
+    /// Run sorts and the tools built on them without changing anything on
+    /// disk, recording what they would do in the history, flagged as
+    /// simulated.
+    pub simulation_mode: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            normalize_filenames: false,
+            parallel_moves: true,
+            max_parallel_moves: 4,
+            low_impact_mode: false,
+            low_impact_bytes_per_sec: 10 * 1024 * 1024,
+            low_impact_pause_ms: 50,
+            rule_semantics: RuleSemantics::FirstMatch,
+            learn_from_moves: false,
+            config_backend: ConfigBackend::Database,
+            language: None,
+            folder_icons: false,
+            retry_attempts: 3,
+            min_age_secs: 10,
+            create_target_dirs: false,
+            camera_imports: true,
+            camera_import_dir: None,
+            keep_download_origin: true,
+            approve_moves: false,
+            collision_naming: CollisionNaming::Counter,
+            duplicate_copies: DuplicatePolicy::Keep,
+            archive_after_days: None,
+            archive_dir: None,
+            stale_after_days: Some(30),
+            weekly_report: false,
+            history_keep_days: Some(365),
+            history_keep_sessions: None,
+            team_ruleset: None,
+            project_markers: projects::default_markers(),
+            project_dir: None,
+            max_folder_bytes: Some(10 * 1024 * 1024 * 1024),
+            max_folder_files: Some(10_000),
+            large_folders: LargeFolderPolicy::Ask,
+            cloud_placeholders: PlaceholderPolicy::Skip,
+            desktop_override: None,
+            public_desktop: false,
+            app_lock: None,
+            simulation_mode: false,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(conn: &Connection) -> Result<Self, Error> {
+        let mut stmt = conn.prepare("SELECT key, value FROM settings")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+        let mut values = Map::new();
+        for row in rows {
+            let (key, value) = row?;
+            match serde_json::from_str(&value) {
+                Ok(value) => {
+                    values.insert(key, value);
+                }
+                Err(e) => tracing::warn!("Ignoring unreadable setting {}: {}", key, e),
+            }
+        }
+
+        serde_json::from_value(Value::Object(values)).map_err(|e| Error::InvalidSetting(e.to_string()))
+    }
+
+    /// Stores `hash` as the app lock, or removes the lock. `update` can't
+    /// reach it, as it isn't serialized.
+    pub(crate) fn store_app_lock(conn: &Connection, hash: Option<&str>) -> Result<(), Error> {
+        match hash {
+            Some(hash) => conn.execute(
+                "INSERT INTO settings (key, value) VALUES ('app_lock', ?)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![Value::from(hash).to_string()],
+            )?,
+            None => conn.execute("DELETE FROM settings WHERE key = 'app_lock'", [])?,
+        };
+        Ok(())
+    }
+
+    /// Applies `changes` on top of the stored settings. The merged result is
+    /// validated as a whole before anything is written.
+    pub fn update(conn: &Connection, changes: Map<String, Value>) -> Result<Self, Error> {
+        let mut merged = match serde_json::to_value(Self::load(conn)?) {
+            Ok(Value::Object(map)) => map,
+            _ => Map::new(),
+        };
+        for (key, value) in &changes {
+            if !merged.contains_key(key) {
+                return Err(Error::InvalidSetting(format!("unknown setting `{}`", key)));
+            }
+            merged.insert(key.clone(), value.clone());
+        }
+
+        let settings: Settings = serde_json::from_value(Value::Object(merged))
+            .map_err(|e| Error::InvalidSetting(e.to_string()))?;
+        settings.validate()?;
+
+        for (key, value) in changes {
+            conn.execute(
+                "INSERT INTO settings (key, value) VALUES (?, ?)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![key, value.to_string()],
+            )?;
+        }
+
+        Ok(settings)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if !(1..=64).contains(&self.max_parallel_moves) {
+            return Err(Error::InvalidSetting(
+                "max_parallel_moves must be between 1 and 64".to_string(),
+            ));
+        }
+        if self.low_impact_bytes_per_sec == 0 {
+            return Err(Error::InvalidSetting(
+                "low_impact_bytes_per_sec must be greater than 0".to_string(),
+            ));
+        }
+        if self.retry_attempts > 20 {
+            return Err(Error::InvalidSetting(
+                "retry_attempts must be between 0 and 20".to_string(),
+            ));
+        }
+        if self.camera_import_dir.as_deref().is_some_and(|dir| dir.trim().is_empty()) {
+            return Err(Error::InvalidSetting(
+                "camera_import_dir must not be empty".to_string(),
+            ));
+        }
+        if self.archive_after_days == Some(0) {
+            return Err(Error::InvalidSetting(
+                "archive_after_days must be at least 1".to_string(),
+            ));
+        }
+        if self.archive_dir.as_deref().is_some_and(|dir| dir.trim().is_empty()) {
+            return Err(Error::InvalidSetting(
+                "archive_dir must not be empty".to_string(),
+            ));
+        }
+        if self.team_ruleset.as_deref().is_some_and(|location| location.trim().is_empty()) {
+            return Err(Error::InvalidSetting(
+                "team_ruleset must not be empty".to_string(),
+            ));
+        }
+        if self.desktop_override.as_deref().is_some_and(|dir| !Path::new(dir).is_absolute()) {
+            return Err(Error::InvalidSetting(
+                "desktop_override must be an absolute path".to_string(),
+            ));
+        }
+        if self.project_dir.as_deref().is_some_and(|dir| dir.trim().is_empty()) {
+            return Err(Error::InvalidSetting(
+                "project_dir must not be empty".to_string(),
+            ));
+        }
+        projects::validate(&self.project_markers)?;
+        if self.max_folder_bytes == Some(0) || self.max_folder_files == Some(0) {
+            return Err(Error::InvalidSetting(
+                "max_folder_bytes and max_folder_files must be greater than 0".to_string(),
+            ));
+        }
+        if self.history_keep_days == Some(0) || self.history_keep_sessions == Some(0) {
+            return Err(Error::InvalidSetting(
+                "history_keep_days and history_keep_sessions must be at least 1".to_string(),
+            ));
+        }
+        if self.stale_after_days == Some(0) {
+            return Err(Error::InvalidSetting(
+                "stale_after_days must be at least 1".to_string(),
+            ));
+        }
+        if let Some(language) = &self.language {
+            if !defaults::LANGUAGES.contains(&language.as_str()) {
+                return Err(Error::InvalidSetting(format!(
+                    "language must be one of {}",
+                    defaults::LANGUAGES.join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The language default category folders are named in.
+    pub fn folder_language(&self) -> &'static str {
+        defaults::language(self.language.as_deref())
+    }
+
+    /// Where camera imports go when they are recognized at all.
+    pub fn camera_import_dir(&self, sorted_dir: &Path) -> Option<PathBuf> {
+        if !self.camera_imports {
+            return None;
+        }
+        Some(match &self.camera_import_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => sorted_dir.join(defaults::folder_name("photos", self.folder_language()).unwrap_or("Photos")),
+        })
+    }
+
+    /// Where untouched entries are archived, and after how long, when
+    /// archiving is on.
+    pub fn archive(&self, sorted_dir: &Path) -> Option<(PathBuf, Duration)> {
+        let days = self.archive_after_days?;
+        Some((self.archive_dir(sorted_dir), Duration::from_secs(u64::from(days) * 24 * 60 * 60)))
+    }
+
+    /// Folder projects that are moved go to.
+    pub fn project_dir(&self, sorted_dir: &Path) -> PathBuf {
+        match &self.project_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => sorted_dir
+                .join(defaults::folder_name("code", self.folder_language()).unwrap_or("Code"))
+                .join("Projects"),
+        }
+    }
+
+    /// The folder archived entries go below, whether archiving is on or not.
+    pub fn archive_dir(&self, sorted_dir: &Path) -> PathBuf {
+        match &self.archive_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => sorted_dir.join("Archived"),
+        }
+    }
+}
+
+/// Settings whose defaults changed once installs were already out there,
+/// with the value those installs had. Fresh installs get the new defaults.
+const PREVIOUS_DEFAULTS: &[(&str, &str)] = &[
+    ("parallel_moves", "false"),
+    ("retry_attempts", "0"),
+    ("min_age_secs", "0"),
+    ("camera_imports", "false"),
+    ("stale_after_days", "null"),
+    ("history_keep_days", "null"),
+    ("project_markers", "{}"),
+    // No limits, so large folders move like any other
+    ("max_folder_bytes", "null"),
+    ("max_folder_files", "null"),
+    ("cloud_placeholders", "\"move_stub\""),
+];
+
+/// Pins the settings of `PREVIOUS_DEFAULTS` an existing install never set to
+/// the value it had, so upgrading doesn't change what a sort moves, or start
+/// retrying, reminding of stale files or pruning the history on its own. An install without any
+/// mappings yet is a fresh one and is left alone.
+pub(crate) fn keep_previous_defaults(conn: &Connection) -> Result<(), Error> {
+    let mappings: i64 = conn.query_row("SELECT COUNT(*) FROM path_mappings", [], |row| row.get(0))?;
+    if mappings == 0 {
+        return Ok(());
+    }
+    for (key, value) in PREVIOUS_DEFAULTS {
+        conn.execute("INSERT OR IGNORE INTO settings (key, value) VALUES (?, ?)", params![key, value])?;
+    }
+    Ok(())
+}
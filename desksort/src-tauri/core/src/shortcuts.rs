@@ -0,0 +1,225 @@
+use crate::{
+    diagnostics::{FailedStep, SortError},
+    history,
+    messages::Message,
+    orphans, sources,
+    trash::{Disposal, Trashed},
+    Error, MappingCache,
+};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    /// A symbolic link.
+    Symlink,
+    /// A Windows `.lnk` shortcut.
+    Shortcut,
+}
+
+impl LinkKind {
+    fn decided_by(self) -> &'static str {
+        match self {
+            LinkKind::Symlink => "broken symlink",
+            LinkKind::Shortcut => "broken shortcut",
+        }
+    }
+}
+
+/// A link whose destination is gone.
+#[derive(Serialize)]
+pub struct BrokenLink {
+    pub path: String,
+    pub kind: LinkKind,
+    /// Where it points.
+    pub target: String,
+}
+
+/// Broken links found in the sources and targets, for the user to confirm
+/// before `trash` throws them away.
+#[derive(Serialize)]
+pub struct CleanupPlan {
+    pub links: Vec<BrokenLink>,
+    /// Folders that were looked through.
+    pub folders: Vec<String>,
+}
+
+/// Outcome of throwing broken links away.
+#[derive(Serialize)]
+pub struct LinkCleanup {
+    pub trashed: Vec<Trashed>,
+    /// Links that couldn't be thrown away or work again.
+    pub errors: Vec<SortError>,
+    pub session_id: i64,
+}
+
+fn read_u16(data: &[u8], at: usize) -> Option<usize> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?) as usize)
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<usize> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?) as usize)
+}
+
+/// The NUL-terminated string at `at`, in the system code page. Only ASCII is
+/// decoded faithfully, which is enough to tell whether the path exists for
+/// most shortcuts; the Unicode copy is preferred where there is one.
+fn read_ansi(data: &[u8], at: usize) -> Option<String> {
+    let bytes = data.get(at..)?;
+    let end = bytes.iter().position(|&b| b == 0)?;
+    Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+fn read_unicode(data: &[u8], at: usize) -> Option<String> {
+    let units: Vec<u16> = data
+        .get(at..)?
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect();
+    Some(String::from_utf16_lossy(&units))
+}
+
+/// The local path a shell link (`.lnk`) points to, from its LinkInfo
+/// structure. `None` for links to network shares, URLs or shell items like
+/// the Control Panel, which have no local path to check.
+fn shortcut_target(data: &[u8]) -> Option<String> {
+    const HEADER_SIZE: usize = 0x4c;
+    const HAS_ID_LIST: usize = 0x1;
+    const HAS_LINK_INFO: usize = 0x2;
+    const VOLUME_ID_AND_LOCAL_BASE_PATH: usize = 0x1;
+
+    if read_u32(data, 0)? != HEADER_SIZE {
+        return None;
+    }
+    let flags = read_u32(data, 0x14)?;
+    let mut at = HEADER_SIZE;
+    if flags & HAS_ID_LIST != 0 {
+        at += 2 + read_u16(data, at)?;
+    }
+    if flags & HAS_LINK_INFO == 0 {
+        return None;
+    }
+    let info = data.get(at..)?;
+    let header_size = read_u32(info, 0x4)?;
+    if read_u32(info, 0x8)? & VOLUME_ID_AND_LOCAL_BASE_PATH == 0 {
+        return None;
+    }
+    let (base, suffix) = if header_size >= 0x24 {
+        (read_unicode(info, read_u32(info, 0x1c)?)?, read_unicode(info, read_u32(info, 0x20)?)?)
+    } else {
+        (read_ansi(info, read_u32(info, 0x10)?)?, read_ansi(info, read_u32(info, 0x18)?)?)
+    };
+    Some(base + &suffix)
+}
+
+/// What `path` links to and how, if it is a link whose destination doesn't
+/// exist. Destinations on drives or shares that aren't connected might come
+/// back, and shortcuts to paths of another platform can't be checked, so
+/// neither counts as broken.
+fn broken_link(path: &Path) -> Option<(LinkKind, PathBuf)> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    let (kind, target) = if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path).ok()?;
+        // Relative links are relative to the folder they are in
+        let resolved = path.parent().map(|dir| dir.join(&target)).unwrap_or(target);
+        (LinkKind::Symlink, resolved)
+    } else if metadata.is_file()
+        && path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("lnk"))
+    {
+        let target = PathBuf::from(shortcut_target(&fs::read(path).ok()?)?);
+        if !target.is_absolute() {
+            return None;
+        }
+        (LinkKind::Shortcut, target)
+    } else {
+        return None;
+    };
+    if fs::metadata(&target).is_ok() || orphans::is_disconnected(&target) {
+        return None;
+    }
+    Some((kind, target))
+}
+
+/// Looks for broken symlinks and shortcuts in the enabled sources, as deep as
+/// each is sorted, and anywhere below the target folders of `mappings`.
+pub fn find_broken(conn: &Connection, mappings: &MappingCache) -> Result<CleanupPlan, Error> {
+    let mut folders: Vec<(PathBuf, usize)> = sources::enabled(conn)?
+        .into_iter()
+        .map(|source| (source.path, source.depth as usize))
+        .collect();
+    let targets: BTreeSet<&Path> = mappings.target_dirs().collect();
+    folders.extend(targets.into_iter().map(|dir| (dir.to_path_buf(), usize::MAX)));
+
+    let mut seen = BTreeSet::new();
+    let mut links = Vec::new();
+    for (dir, depth) in &folders {
+        let entries = WalkDir::new(dir).min_depth(1).max_depth(*depth).into_iter().filter_map(|entry| entry.ok());
+        for entry in entries {
+            if !seen.insert(entry.path().to_path_buf()) {
+                continue;
+            }
+            if let Some((kind, target)) = broken_link(entry.path()) {
+                links.push(BrokenLink {
+                    path: entry.path().display().to_string(),
+                    kind,
+                    target: target.display().to_string(),
+                });
+            }
+        }
+    }
+    Ok(CleanupPlan {
+        links,
+        folders: folders.iter().map(|(dir, _)| dir.display().to_string()).collect(),
+    })
+}
+
+/// Hands the links at `paths` the user confirmed to `disposal` and records
+/// them in the history. Links that work again by now are left alone.
+pub fn trash(conn: &Connection, disposal: &dyn Disposal, paths: &[PathBuf]) -> Result<LinkCleanup, Error> {
+    let started_at = chrono::Utc::now().timestamp();
+    let mut trashed = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        let Some((kind, _)) = broken_link(path) else {
+            let message = Message::new("links.not_broken").arg("path", path.display());
+            let mut error = SortError::new(FailedStep::RemoveSource, message);
+            error.path = Some(path.display().to_string());
+            errors.push(error);
+            continue;
+        };
+        let size = fs::symlink_metadata(path).map(|m| m.len()).unwrap_or(0);
+        match disposal.dispose(path) {
+            Ok(location) => trashed.push(Trashed {
+                path: path.display().to_string(),
+                location: location.map(|location| location.display().to_string()),
+                size,
+                decided_by: kind.decided_by().to_string(),
+            }),
+            Err(e) => {
+                let message = Message::new("sort.trash_failed").arg("path", path.display()).arg("detail", &e);
+                errors.push(SortError::from_io(FailedStep::RemoveSource, path, &e, message));
+            }
+        }
+    }
+    let entries: Vec<history::Entry> = trashed
+        .iter()
+        .map(history::Entry::trashed)
+        .chain(errors.iter().map(history::Entry::failed))
+        .collect();
+    let session_id = history::record_session(conn, history::Trigger::Cleanup, started_at, &entries)?;
+    Ok(LinkCleanup {
+        trashed,
+        errors,
+        session_id,
+    })
+}
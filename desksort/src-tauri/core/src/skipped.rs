@@ -0,0 +1,171 @@
+use crate::{filesystem::FileSystem, mapping_key, mover, staging, ScannedEntry};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Why a scanned entry was left where it is.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// No rule or mapping matches it.
+    NoRule,
+    /// Several rules match and disagree; it waits in the conflict inbox.
+    Conflict,
+    /// Belongs to the system or to DeskSort, like `desktop.ini` or a folder
+    /// DeskSort sorts into.
+    Protected,
+    /// Modified too recently; it may still be being written.
+    TooNew,
+    /// A dotfile, or hidden through its attributes on Windows.
+    Hidden,
+    /// A browser or torrent download that hasn't finished.
+    Downloading,
+    /// Its target didn't have room, so the sort didn't start.
+    NoSpace,
+    /// The user turned down its planned move.
+    Rejected,
+    /// Its name matches an ignore pattern of its source.
+    Ignored,
+    /// Its source only sorts names matching its include patterns, and this
+    /// one matches none.
+    NotIncluded,
+    /// A rule deletes it once its retention period is over.
+    Retained,
+    /// A rule says to leave it alone.
+    Kept,
+    /// A folder over the size or file count limit for moves.
+    TooLarge,
+    /// An online-only file of a sync client, left until it is downloaded.
+    OnlineOnly,
+    /// On the public desktop, and moving it takes administrator rights.
+    NeedsElevation,
+}
+
+/// An entry a sort examined but didn't move.
+#[derive(Serialize)]
+pub struct SkippedFile {
+    pub path: String,
+    /// The key a mapping for it would use, e.g. `.pdf`, `folder` or `noext`.
+    pub extension: String,
+    pub reason: SkipReason,
+}
+
+impl SkippedFile {
+    /// `path` as `fs` sees it, which tells folders from files.
+    pub fn new(fs: &dyn FileSystem, path: &Path, reason: SkipReason) -> Self {
+        SkippedFile {
+            path: path.display().to_string(),
+            extension: mapping_key(fs, path),
+            reason,
+        }
+    }
+}
+
+/// Extensions browsers and download managers give files until they are complete.
+const DOWNLOAD_EXTENSIONS: &[&str] = &[".crdownload", ".part", ".partial", ".download", ".opdownload", ".!qb", ".!ut"];
+
+/// Files the system keeps on the desktop for itself.
+const SYSTEM_FILES: &[&str] = &["desktop.ini", "thumbs.db", "$recycle.bin", "icon\r"];
+
+#[cfg(windows)]
+fn has_attribute(path: &Path, attribute: u32) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_attributes() & attribute != 0)
+}
+
+/// Whether the entry at `path` is hidden from the user's file manager.
+pub fn is_hidden(path: &Path) -> bool {
+    if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+        return true;
+    }
+    // FILE_ATTRIBUTE_HIDDEN
+    #[cfg(windows)]
+    if has_attribute(path, 0x2) {
+        return true;
+    }
+    false
+}
+
+/// Whether `name` is one of Office's locks on an open document, like
+/// `~$report.docx`. Stale ones are only ever deleted by rules that delete.
+pub fn is_lock_file(name: &str) -> bool {
+    name.starts_with("~$")
+}
+
+/// Checks every scan entry must pass before it is matched.
+pub struct Filter {
+    /// Folders DeskSort sorts into; they and their parents stay put.
+    targets: Vec<PathBuf>,
+    min_age: Duration,
+    /// Names in the scan, to spot files whose download is still running
+    /// beside them.
+    names: HashSet<String>,
+}
+
+impl Filter {
+    pub fn new<'a>(
+        targets: impl IntoIterator<Item = &'a Path>,
+        min_age: Duration,
+        entries: &[ScannedEntry],
+    ) -> Self {
+        Filter {
+            targets: targets.into_iter().map(Path::to_path_buf).collect(),
+            min_age,
+            names: entries.iter().map(|entry| entry.name().into_owned()).collect(),
+        }
+    }
+
+    /// Why `entry` must not be sorted, if it mustn't.
+    pub fn check(&self, entry: &ScannedEntry) -> Option<SkipReason> {
+        let name = entry.name();
+        if self.is_protected(entry, &name) {
+            return Some(SkipReason::Protected);
+        }
+        // Firefox reserves the final name next to the `.part` file it writes
+        if DOWNLOAD_EXTENSIONS.contains(&entry.key.as_str())
+            || DOWNLOAD_EXTENSIONS
+                .iter()
+                .any(|extension| self.names.contains(&format!("{}{}", name, extension)))
+        {
+            return Some(SkipReason::Downloading);
+        }
+        if self.is_too_new(&entry.path) {
+            return Some(SkipReason::TooNew);
+        }
+        None
+    }
+
+    fn is_protected(&self, entry: &ScannedEntry, name: &str) -> bool {
+        let lowercase = name.to_lowercase();
+        if SYSTEM_FILES.contains(&lowercase.as_str()) || is_lock_file(name) {
+            return true;
+        }
+        // DeskSort's own, in case hidden entries are sorted
+        if name == staging::STAGING_DIR || mover::is_partial(name) {
+            return true;
+        }
+        // FILE_ATTRIBUTE_SYSTEM
+        #[cfg(windows)]
+        if has_attribute(&entry.path, 0x4) {
+            return true;
+        }
+        entry.is_dir && self.targets.iter().any(|target| target.starts_with(&entry.path))
+    }
+
+    fn is_too_new(&self, path: &Path) -> bool {
+        if self.min_age.is_zero() {
+            return false;
+        }
+        let modified = fs::symlink_metadata(path).and_then(|metadata| metadata.modified());
+        // A timestamp in the future counts as just written
+        modified.is_ok_and(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .map_or(true, |age| age < self.min_age)
+        })
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{get_desktop_path, Error, NO_EXTENSION};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Deepest level `depth` may reach into a source.
+const MAX_DEPTH: u32 = 16;
+
+/// A folder DeskSort sorts entries out of.
+#[derive(Serialize, Clone)]
+pub struct Source {
+    pub id: i64,
+    pub path: PathBuf,
+    /// The user's desktop, wherever it currently is. It can be turned off
+    /// but not removed.
+    pub is_desktop: bool,
+    pub enabled: bool,
+    /// Levels of the folder entries are picked up from. At 1 only its own
+    /// entries are, and folders are moved whole; deeper, the folders above
+    /// the last level are looked into instead of moved.
+    pub depth: u32,
+    /// Sort hidden entries too, rather than leaving them alone.
+    pub include_hidden: bool,
+    /// Glob patterns, like `*.iso` or `node_modules`, for names to leave alone.
+    pub ignore_patterns: Vec<String>,
+    /// Glob patterns, like `*.pdf`, that entries must match to be sorted.
+    /// Empty lets everything through. Folders above the last level are still
+    /// looked into.
+    pub include_patterns: Vec<String>,
+}
+
+impl Source {
+    /// Options a folder is scanned with when it has none of its own: the
+    /// desktop's defaults.
+    pub fn at(path: &Path) -> Self {
+        Source {
+            id: 0,
+            path: path.to_path_buf(),
+            is_desktop: false,
+            enabled: true,
+            depth: 1,
+            include_hidden: false,
+            ignore_patterns: Vec::new(),
+            include_patterns: Vec::new(),
+        }
+    }
+
+    /// Whether the entry called `name` matches one of `ignore_patterns`.
+    pub fn ignores(&self, name: &str) -> bool {
+        matches_any(&self.ignore_patterns, name)
+    }
+
+    /// Whether the entry called `name` passes `include_patterns`.
+    pub fn includes(&self, name: &str) -> bool {
+        self.include_patterns.is_empty() || matches_any(&self.include_patterns, name)
+    }
+}
+
+fn matches_any(patterns: &[String], name: &str) -> bool {
+    patterns
+        .iter()
+        .filter_map(|pattern| glob::Pattern::new(pattern).ok())
+        .any(|pattern| pattern.matches(name))
+}
+
+/// Adds the desktop as the first source. Its row has no path, so it follows
+/// the desktop wherever the system puts it.
+pub fn seed(conn: &Connection) -> Result<(), Error> {
+    conn.execute("INSERT INTO sources (path) VALUES (NULL)", [])?;
+    Ok(())
+}
+
+/// A `sources` row: id, path, enabled, depth, include_hidden and the two
+/// pattern lists as JSON.
+type SourceRow = (i64, Option<String>, bool, u32, bool, String, String);
+
+/// Every source, the desktop first. Sources whose folder can't be resolved,
+/// like the desktop on a system without one, are left out.
+pub fn load(conn: &Connection) -> Result<Vec<Source>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT id, path, enabled, depth, include_hidden, ignore_patterns, include_patterns FROM sources
+         ORDER BY path IS NOT NULL, id",
+    )?;
+    let rows: Vec<SourceRow> = stmt
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?))
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(id, path, enabled, depth, include_hidden, ignore_patterns, include_patterns)| {
+            let (path, is_desktop) = match path {
+                Some(path) => (PathBuf::from(path), false),
+                None => (get_desktop_path().ok()?, true),
+            };
+            Some(Source {
+                id,
+                path,
+                is_desktop,
+                enabled,
+                depth,
+                include_hidden,
+                ignore_patterns: serde_json::from_str(&ignore_patterns).unwrap_or_default(),
+                include_patterns: serde_json::from_str(&include_patterns).unwrap_or_default(),
+            })
+        })
+        .collect())
+}
+
+/// The sources that are turned on.
+pub fn enabled(conn: &Connection) -> Result<Vec<Source>, Error> {
+    Ok(load(conn)?.into_iter().filter(|source| source.enabled).collect())
+}
+
+/// Adds `path` as a source with the desktop's defaults. Returns its id.
+pub fn add(conn: &Connection, path: &Path) -> Result<i64, Error> {
+    if !path.is_dir() {
+        return Err(Error::InvalidSource(format!("{} is not a folder", path.display())));
+    }
+    if load(conn)?.iter().any(|source| source.path == path) {
+        return Err(Error::InvalidSource(format!("{} is already a source", path.display())));
+    }
+    conn.execute(
+        "INSERT INTO sources (path) VALUES (?)",
+        params![path.to_string_lossy()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Replaces the scan options of source `id`.
+pub fn update(
+    conn: &Connection,
+    id: i64,
+    enabled: bool,
+    depth: u32,
+    include_hidden: bool,
+    ignore_patterns: &[String],
+    include_patterns: &[String],
+) -> Result<(), Error> {
+    if !(1..=MAX_DEPTH).contains(&depth) {
+        return Err(Error::InvalidSource(format!("depth must be between 1 and {}", MAX_DEPTH)));
+    }
+    let mut patterns = ignore_patterns.iter().chain(include_patterns);
+    if let Some(pattern) = patterns.find(|pattern| glob::Pattern::new(pattern).is_err()) {
+        return Err(Error::InvalidSource(format!("`{}` is not a valid glob pattern", pattern)));
+    }
+    let updated = conn.execute(
+        "UPDATE sources SET enabled = ?, depth = ?, include_hidden = ?, ignore_patterns = ?, include_patterns = ?
+         WHERE id = ?",
+        params![
+            enabled,
+            depth,
+            include_hidden,
+            serde_json::to_string(ignore_patterns).unwrap_or_default(),
+            serde_json::to_string(include_patterns).unwrap_or_default(),
+            id
+        ],
+    )?;
+    if updated == 0 {
+        return Err(Error::InvalidSource(format!("no source {}", id)));
+    }
+    Ok(())
+}
+
+/// A mapping that applies to the entries of one source only, ahead of every
+/// rule and global mapping, e.g. `.exe` to `Installers` for Downloads.
+#[derive(Serialize)]
+pub struct SourceMapping {
+    pub source_id: i64,
+    pub extension: String,
+    pub target_path: String,
+}
+
+/// Every source mapping, by source and extension.
+pub fn load_mappings(conn: &Connection) -> Result<Vec<SourceMapping>, Error> {
+    let mut stmt =
+        conn.prepare("SELECT source_id, extension, target_path FROM source_mappings ORDER BY source_id, extension")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(SourceMapping {
+            source_id: row.get(0)?,
+            extension: row.get(1)?,
+            target_path: row.get(2)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Sends `extension` entries of source `source_id` to `target_path`,
+/// whatever the global mapping says.
+pub fn set_mapping(conn: &Connection, source_id: i64, extension: &str, target_path: &str) -> Result<(), Error> {
+    if target_path.trim().is_empty() {
+        return Err(Error::InvalidSource("target path must not be empty".to_string()));
+    }
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sources WHERE id = ?)",
+        params![source_id],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Err(Error::InvalidSource(format!("no source {}", source_id)));
+    }
+    // Keyed like `path_mappings`: `folder`, `noext`, or a lowercase extension with its dot
+    let extension = extension.trim().trim_start_matches('.').to_lowercase();
+    if extension.is_empty() {
+        return Err(Error::InvalidSource("extension must not be empty".to_string()));
+    }
+    let extension = if extension == "folder" || extension == NO_EXTENSION {
+        extension
+    } else {
+        format!(".{}", extension)
+    };
+    conn.execute(
+        "INSERT INTO source_mappings (source_id, extension, target_path) VALUES (?, ?, ?)
+         ON CONFLICT(source_id, extension) DO UPDATE SET target_path = excluded.target_path",
+        params![source_id, extension, target_path],
+    )?;
+    Ok(())
+}
+
+/// Returns whether source `source_id` had a mapping for `extension`.
+pub fn remove_mapping(conn: &Connection, source_id: i64, extension: &str) -> Result<bool, Error> {
+    Ok(conn.execute(
+        "DELETE FROM source_mappings WHERE source_id = ? AND extension = ?",
+        params![source_id, extension],
+    )? > 0)
+}
+
+/// Stops sorting source `id`, and forgets its mappings. The desktop can only
+/// be turned off.
+pub fn remove(conn: &Connection, id: i64) -> Result<(), Error> {
+    let path: Option<Option<String>> = conn
+        .query_row("SELECT path FROM sources WHERE id = ?", params![id], |row| row.get(0))
+        .optional()?;
+    match path {
+        None => Err(Error::InvalidSource(format!("no source {}", id))),
+        Some(None) => Err(Error::InvalidSource("the desktop can't be removed, only turned off".to_string())),
+        Some(Some(_)) => {
+            conn.execute("DELETE FROM source_mappings WHERE source_id = ?", params![id])?;
+            conn.execute("DELETE FROM sources WHERE id = ?", params![id])?;
+            Ok(())
+        }
+    }
+}
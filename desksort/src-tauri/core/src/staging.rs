@@ -0,0 +1,266 @@
+use crate::{
+    diagnostics::SortError, execute_plan, filesystem::FileSystem, history, messages::Message, mover, quota::Quota,
+    rules::MatchSource, settings::Settings, unique_target_path, Error, PlannedMove, SortContext, SortResult,
+};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Folder on the desktop staged entries wait in. Being on the same volume as
+/// the desktop, staging and restoring are plain renames.
+pub const STAGING_DIR: &str = ".desksort-staging";
+
+/// An entry moved to the staging folder, waiting to be committed or put back.
+#[derive(Serialize)]
+pub struct StagedMove {
+    /// Where it was on the desktop.
+    pub source: String,
+    pub target_dir: String,
+    pub decided_by: String,
+    pub size: u64,
+    pub staged_at: i64,
+}
+
+pub fn staging_dir(desktop: &Path) -> PathBuf {
+    desktop.join(STAGING_DIR)
+}
+
+/// Creates the staging folder, hidden from Explorer on Windows. The leading
+/// dot hides it elsewhere, and keeps scans out of it.
+fn create_staging_dir(fs: &dyn FileSystem, dir: &Path) -> io::Result<()> {
+    let long_dir = &mover::long_path(dir);
+    if fs.exists(long_dir) {
+        return Ok(());
+    }
+    fs.create_dir_all(long_dir)?;
+    if cfg!(windows) {
+        if let Err(e) = std::process::Command::new("attrib").arg("+h").arg(dir).status() {
+            tracing::warn!("Failed to hide {}: {}", dir.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// Moves every entry of `plan` into a slot of its own in the staging folder,
+/// keeping its name, and records where it goes unless simulated. Fills
+/// `result` and records the staging as a history session.
+pub fn stage(
+    conn: &Connection,
+    fs: &dyn FileSystem,
+    desktop: &Path,
+    plan: &[PlannedMove],
+    started_at: i64,
+    result: &mut SortResult,
+) -> Result<(), Error> {
+    let simulated = Settings::load(conn)?.simulation_mode;
+    let dir = staging_dir(desktop);
+    create_staging_dir(fs, &dir)?;
+    let now = chrono::Utc::now().timestamp();
+    let mut staged = Vec::new();
+    let mut slot = 0;
+    for planned in plan {
+        while fs.exists(&dir.join(slot.to_string())) {
+            slot += 1;
+        }
+        let slot_dir = dir.join(slot.to_string());
+        let staged_path = slot_dir.join(planned.source.file_name().unwrap());
+        let outcome = fs
+            .create_dir_all(&slot_dir)
+            .and_then(|()| mover::move_path(fs, &planned.source, &staged_path, &mut |_, _, _| {}, None));
+        match outcome {
+            Ok(_) => {
+                if !simulated {
+                    conn.execute(
+                        "INSERT INTO staged_moves
+                            (staged_path, source, target_dir, decided_by, matched, size, staged_at)
+                         VALUES (?, ?, ?, ?, ?, ?, ?)",
+                        params![
+                            staged_path.to_string_lossy(),
+                            planned.source.to_string_lossy(),
+                            planned.target_dir.to_string_lossy(),
+                            planned.decided_by,
+                            serde_json::to_string(&planned.matched).unwrap_or_default(),
+                            planned.size,
+                            now
+                        ],
+                    )?;
+                }
+                result.moved_files.push(
+                    Message::new("sort.staged")
+                        .arg("from", planned.source.display())
+                        .arg("to", planned.target_dir.display()),
+                );
+                result.bytes_moved += planned.size;
+                staged.push((planned, staged_path));
+            }
+            Err(e) => {
+                let _ = fs.remove_dir_all(&slot_dir);
+                let message = Message::new("sort.stage_failed")
+                    .arg("path", planned.source.display())
+                    .arg("detail", &e);
+                result.errors.push(SortError::from_move(&planned.source, &dir, &e, message));
+            }
+        }
+    }
+    result.staged = staged.len();
+
+    let mut entries: Vec<_> = staged
+        .iter()
+        .map(|(planned, staged_path)| {
+            history::Entry::moved(&planned.source, staged_path, planned.size, &planned.decided_by)
+        })
+        .collect();
+    entries.extend(result.trashed.iter().map(history::Entry::trashed));
+    entries.extend(result.errors.iter().map(history::Entry::failed));
+    result.session_id = history::record_session(conn, history::Trigger::Staged, started_at, &entries)?;
+    Ok(())
+}
+
+/// Drops entries that left the staging folder some other way, and removes
+/// the slots and staging folder they leave empty. Simulated, the entries
+/// stay recorded.
+pub fn prune(conn: &Connection, fs: &dyn FileSystem, desktop: &Path) -> Result<(), Error> {
+    let simulated = Settings::load(conn)?.simulation_mode;
+    let staged: Vec<String> = conn
+        .prepare("SELECT staged_path FROM staged_moves")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for staged_path in staged.iter().filter(|path| !simulated && !fs.exists(Path::new(path))) {
+        conn.execute("DELETE FROM staged_moves WHERE staged_path = ?", params![staged_path])?;
+    }
+
+    let dir = staging_dir(desktop);
+    let is_empty = |dir: &Path| fs.read_dir(dir).is_ok_and(|entries| entries.is_empty());
+    if let Ok(slots) = fs.read_dir(&dir) {
+        // Slots that still hold their entry stay
+        for slot in slots.iter().filter(|slot| slot.is_dir && is_empty(&slot.path)) {
+            let _ = fs.remove_dir_all(&slot.path);
+        }
+    }
+    if is_empty(&dir) {
+        let _ = fs.remove_dir_all(&dir);
+    }
+    Ok(())
+}
+
+/// Staged entries, oldest first.
+pub fn load(conn: &Connection) -> Result<Vec<StagedMove>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT source, target_dir, decided_by, size, staged_at
+         FROM staged_moves ORDER BY staged_at, source",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(StagedMove {
+            source: row.get(0)?,
+            target_dir: row.get(1)?,
+            decided_by: row.get(2)?,
+            size: row.get(3)?,
+            staged_at: row.get(4)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// The moves that finish the staged sort, from the staging folder to the
+/// targets. Entries stay recorded until `prune` sees they are gone, so
+/// failed ones can still be committed again or put back.
+pub fn plan(conn: &Connection, quota: impl Fn(&MatchSource) -> Quota) -> Result<Vec<PlannedMove>, Error> {
+    let rows: Vec<(String, String, String, String, u64)> = conn
+        .prepare("SELECT staged_path, target_dir, decided_by, matched, size FROM staged_moves ORDER BY staged_at")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(staged_path, target_dir, decided_by, matched, size)| {
+            let matched = serde_json::from_str::<MatchSource>(&matched).ok()?;
+            Some(PlannedMove {
+                source: PathBuf::from(staged_path),
+                target_dir: PathBuf::from(target_dir),
+                quota: quota(&matched),
+                size,
+                decided_by,
+                matched,
+            })
+        })
+        .collect())
+}
+
+/// Puts every staged entry back where it was on the desktop, under a new
+/// name if something has taken its old one since. Fills `result` and records
+/// the restore as a history session.
+pub fn restore(conn: &Connection, fs: &dyn FileSystem, started_at: i64, result: &mut SortResult) -> Result<(), Error> {
+    let rows: Vec<(String, String, u64)> = conn
+        .prepare("SELECT staged_path, source, size FROM staged_moves ORDER BY staged_at")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+    let settings = Settings::load(conn)?;
+    let mut restored = Vec::new();
+    for (staged_path, source, size) in rows {
+        let (staged_path, source) = (PathBuf::from(staged_path), PathBuf::from(source));
+        let Some(desktop_dir) = source.parent() else {
+            continue;
+        };
+        let name = source.file_name().unwrap();
+        let restored_path = unique_target_path(fs, desktop_dir, name, &HashSet::new(), settings.collision_naming);
+        match mover::move_path(fs, &staged_path, &restored_path, &mut |_, _, _| {}, None) {
+            Ok(_) => {
+                if !settings.simulation_mode {
+                    conn.execute(
+                        "DELETE FROM staged_moves WHERE staged_path = ?",
+                        params![staged_path.to_string_lossy()],
+                    )?;
+                }
+                result.moved_files.push(Message::new("sort.put_back").arg("path", restored_path.display()));
+                result.bytes_moved += size;
+                restored.push((staged_path, restored_path, size));
+            }
+            Err(e) => {
+                let message = Message::new("sort.put_back_failed").arg("path", source.display()).arg("detail", &e);
+                result.errors.push(SortError::from_move(&staged_path, desktop_dir, &e, message));
+            }
+        }
+    }
+
+    let mut entries: Vec<_> = restored
+        .iter()
+        .map(|(staged_path, restored_path, size)| history::Entry::moved(staged_path, restored_path, *size, "staging aborted"))
+        .collect();
+    entries.extend(result.errors.iter().map(history::Entry::failed));
+    result.session_id = history::record_session(conn, history::Trigger::Restored, started_at, &entries)?;
+    Ok(())
+}
+
+/// Moves the staged entries on to the targets they were staged for. Entries
+/// that fail stay staged.
+pub fn commit(conn: &Connection, ctx: &SortContext, desktop: &Path) -> Result<SortResult, Error> {
+    let started = (std::time::Instant::now(), chrono::Utc::now().timestamp());
+    prune(conn, &*ctx.fs, desktop)?;
+    let moves = plan(conn, |matched| ctx.mappings.quota(matched))?;
+    tracing::info!("Committing {} staged moves", moves.len());
+    let result = execute_plan(
+        conn,
+        ctx,
+        moves,
+        history::Trigger::Manual,
+        started,
+        SortResult::new(Vec::new(), Vec::new()),
+    )?;
+    prune(conn, &*ctx.fs, desktop)?;
+    Ok(result)
+}
+
+/// Puts every staged entry back on the desktop.
+pub fn abort(conn: &Connection, fs: &dyn FileSystem, desktop: &Path) -> Result<SortResult, Error> {
+    let started = std::time::Instant::now();
+    prune(conn, fs, desktop)?;
+    let mut result = SortResult::new(Vec::new(), Vec::new());
+    restore(conn, fs, chrono::Utc::now().timestamp(), &mut result)?;
+    prune(conn, fs, desktop)?;
+    result.duration_ms = started.elapsed().as_millis() as u64;
+    tracing::info!("Put {} staged entries back", result.moved_files.len());
+    Ok(result)
+}
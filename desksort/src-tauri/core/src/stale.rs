@@ -0,0 +1,138 @@
+use crate::{
+    archival, execute_plan,
+    filesystem::{FileSystem, OsFileSystem},
+    get_desktop_path, history,
+    quota::Quota,
+    rules::MatchSource,
+    scan_entries,
+    settings::Settings,
+    skipped, sources, Decision, Error, MappingCache, PlannedMove, ScannedEntry, SortContext, SortResult,
+};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Least time between two reminders of stale files.
+const REMINDER_INTERVAL_SECS: i64 = 24 * 60 * 60;
+
+/// A desktop entry no rule or mapping handles, and how long it has been
+/// there.
+#[derive(Serialize)]
+pub struct StaleFile {
+    pub path: String,
+    pub size: u64,
+    /// Unix time DeskSort first saw it on the desktop.
+    pub first_seen: i64,
+    pub days: i64,
+}
+
+/// Notes which unmatched entries are on the desktop now, forgetting the ones
+/// that left, and returns those first seen at least `after_days` ago, longest
+/// there first.
+pub fn track(conn: &Connection, mappings: &MappingCache, after_days: u32) -> Result<Vec<StaleFile>, Error> {
+    let settings = Settings::load(conn)?;
+    let Some(desktop) = sources::load(conn)?.into_iter().find(|source| source.is_desktop) else {
+        return Ok(Vec::new());
+    };
+    let targets: Vec<&Path> = mappings.target_dirs().collect();
+    let fs: Arc<dyn FileSystem> = Arc::new(OsFileSystem);
+    let entries = scan_entries(&fs, &desktop, &targets, &mut Vec::new(), &mut Vec::new());
+    let filter = skipped::Filter::new(targets.iter().copied(), Duration::ZERO, &entries);
+    let now = chrono::Local::now();
+    let unmatched: Vec<&ScannedEntry> = entries
+        .iter()
+        .filter(|entry| filter.check(entry).is_none())
+        .filter(|entry| {
+            matches!(
+                mappings.lookup(entry, settings.rule_semantics, now.naive_local()),
+                Decision::Unmatched
+            )
+        })
+        .collect();
+
+    let tx = conn.unchecked_transaction()?;
+    let present: HashSet<String> = unmatched.iter().map(|entry| entry.path.to_string_lossy().into_owned()).collect();
+    let known: Vec<String> = tx
+        .prepare("SELECT path FROM desktop_sightings")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for path in known.iter().filter(|path| !present.contains(*path)) {
+        tx.execute("DELETE FROM desktop_sightings WHERE path = ?", params![path])?;
+    }
+    for path in &present {
+        tx.execute(
+            "INSERT OR IGNORE INTO desktop_sightings (path, first_seen) VALUES (?, ?)",
+            params![path, now.timestamp()],
+        )?;
+    }
+    tx.commit()?;
+
+    let cutoff = now.timestamp() - i64::from(after_days) * 24 * 60 * 60;
+    let mut stale = Vec::new();
+    for entry in unmatched {
+        let first_seen: i64 = conn.query_row(
+            "SELECT first_seen FROM desktop_sightings WHERE path = ?",
+            params![entry.path.to_string_lossy()],
+            |row| row.get(0),
+        )?;
+        if first_seen <= cutoff {
+            stale.push(StaleFile {
+                path: entry.path.display().to_string(),
+                size: entry.size(),
+                first_seen,
+                days: (now.timestamp() - first_seen) / (24 * 60 * 60),
+            });
+        }
+    }
+    stale.sort_by(|a, b| a.first_seen.cmp(&b.first_seen).then_with(|| a.path.cmp(&b.path)));
+    Ok(stale)
+}
+
+/// Whether a day has passed since the user was last reminded of stale
+/// files, or they never were.
+pub fn is_reminder_due(conn: &Connection) -> Result<bool, Error> {
+    let now = chrono::Utc::now().timestamp();
+    let last: Option<i64> = conn.query_row("SELECT MAX(reminded_at) FROM stale_reminders", [], |row| row.get(0))?;
+    Ok(last.is_none_or(|last| now - last >= REMINDER_INTERVAL_SECS))
+}
+
+/// Records that the user was just reminded of stale files.
+pub fn mark_reminded(conn: &Connection) -> Result<(), Error> {
+    let now = chrono::Utc::now().timestamp();
+    conn.execute("INSERT INTO stale_reminders (reminded_at) VALUES (?)", params![now])?;
+    Ok(())
+}
+
+/// Moves the entries at `paths` into the archive folder, each into the month
+/// it was last changed in, whether or not archiving is turned on.
+pub fn archive(conn: &Connection, ctx: &SortContext, paths: &[PathBuf]) -> Result<SortResult, Error> {
+    let started = (std::time::Instant::now(), chrono::Utc::now().timestamp());
+    let root = Settings::load(conn)?.archive_dir(&get_desktop_path()?.join("Sorted"));
+    let plan = paths
+        .iter()
+        .filter_map(|path| {
+            let entry = ScannedEntry::new(&ctx.fs, path.clone());
+            Some(PlannedMove {
+                target_dir: archival::month_dir(&root, path)?,
+                quota: Quota::default(),
+                size: entry.size(),
+                decided_by: "archived from the stale files reminder".to_string(),
+                matched: MatchSource::Archive,
+                source: entry.path,
+            })
+        })
+        .collect();
+    execute_plan(
+        conn,
+        ctx,
+        plan,
+        history::Trigger::Archived,
+        started,
+        SortResult::new(Vec::new(), Vec::new()),
+    )
+}
@@ -0,0 +1,162 @@
+use crate::{defaults, filesystem::FileSystem, settings::Settings, unmatched_extensions, Error, MappingCache};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+/// Extensions DeskSort has no default mapping for, and the category they
+/// usually belong in.
+const KNOWN_EXTENSIONS: &[(&str, &str)] = &[
+    (".heic", "Images"),
+    (".heif", "Images"),
+    (".avif", "Images"),
+    (".svg", "Images"),
+    (".ico", "Images"),
+    (".raw", "Images"),
+    (".cr2", "Images"),
+    (".nef", "Images"),
+    (".arw", "Images"),
+    (".dng", "Images"),
+    (".psd", "Design"),
+    (".ai", "Design"),
+    (".xcf", "Design"),
+    (".fig", "Design"),
+    (".sketch", "Design"),
+    (".indd", "Design"),
+    (".epub", "Books"),
+    (".mobi", "Books"),
+    (".azw", "Books"),
+    (".azw3", "Books"),
+    (".djvu", "Books"),
+    (".cbz", "Books"),
+    (".cbr", "Books"),
+    (".md", "Documents"),
+    (".pages", "Documents"),
+    (".tex", "Documents"),
+    (".xps", "Documents"),
+    (".numbers", "Spreadsheets"),
+    (".tsv", "Spreadsheets"),
+    (".ppt", "Presentations"),
+    (".m4v", "Videos"),
+    (".mpg", "Videos"),
+    (".mpeg", "Videos"),
+    (".3gp", "Videos"),
+    (".m4a", "Audio"),
+    (".opus", "Audio"),
+    (".wma", "Audio"),
+    (".aiff", "Audio"),
+    (".mid", "Audio"),
+    (".bz2", "Archives"),
+    (".xz", "Archives"),
+    (".zst", "Archives"),
+    (".tgz", "Archives"),
+    (".iso", "Disk Images"),
+    (".dmg", "Disk Images"),
+    (".img", "Disk Images"),
+    (".vhd", "Disk Images"),
+    (".deb", "Installers"),
+    (".rpm", "Installers"),
+    (".pkg", "Installers"),
+    (".apk", "Installers"),
+    (".appimage", "Executables"),
+    (".ttf", "Fonts"),
+    (".otf", "Fonts"),
+    (".woff", "Fonts"),
+    (".woff2", "Fonts"),
+    (".c", "Code"),
+    (".h", "Code"),
+    (".go", "Code"),
+    (".rb", "Code"),
+    (".php", "Code"),
+    (".kt", "Code"),
+    (".swift", "Code"),
+    (".cs", "Code"),
+    (".tsx", "Code"),
+    (".jsx", "Code"),
+    (".yaml", "Code"),
+    (".yml", "Code"),
+    (".toml", "Code"),
+    (".xml", "Code"),
+    (".sql", "Code"),
+    (".ipynb", "Code"),
+    (".stl", "3D Models"),
+    (".obj", "3D Models"),
+    (".blend", "3D Models"),
+    (".fbx", "3D Models"),
+    (".ics", "Calendar"),
+    (".vcf", "Contacts"),
+    (".torrent", "Downloads"),
+];
+
+/// A mapping the UI can offer to create in one click.
+#[derive(Serialize)]
+pub struct MappingSuggestion {
+    pub extension: String,
+    pub category: String,
+    pub target_path: String,
+    /// Number of unmatched desktop entries with this extension.
+    pub file_count: usize,
+}
+
+/// Suggests mappings for `unmatched` (extension -> entry count). Categories
+/// that already have a folder in `category_targets` (by category id) reuse it;
+/// others get a new folder under `sorted_dir`, named in `language` where
+/// DeskSort knows the category. Extensions the knowledge base doesn't know
+/// are left out. Most frequent first.
+pub fn suggest(
+    unmatched: &HashMap<String, usize>,
+    category_targets: &HashMap<String, String>,
+    sorted_dir: &Path,
+    language: &str,
+) -> Vec<MappingSuggestion> {
+    let mut suggestions: Vec<MappingSuggestion> = unmatched
+        .iter()
+        .filter_map(|(extension, &file_count)| {
+            let (_, category) = KNOWN_EXTENSIONS.iter().find(|(known, _)| known == extension)?;
+            let id = defaults::category_id(category);
+            let category = defaults::folder_name(&id, language).unwrap_or(category);
+            let target_path = category_targets
+                .get(&id)
+                .cloned()
+                .unwrap_or_else(|| sorted_dir.join(category).to_string_lossy().into_owned());
+            Some(MappingSuggestion {
+                extension: extension.clone(),
+                category: category.to_string(),
+                target_path,
+                file_count,
+            })
+        })
+        .collect();
+    suggestions.sort_by(|a, b| b.file_count.cmp(&a.file_count).then_with(|| a.extension.cmp(&b.extension)));
+    suggestions
+}
+
+/// Scans the sources and suggests mappings for the extensions that no rule
+/// or mapping handles yet, with folders under `sorted_dir`.
+pub fn for_sources(
+    conn: &Connection,
+    fs: &Arc<dyn FileSystem>,
+    mappings: &MappingCache,
+    sorted_dir: &Path,
+) -> Result<Vec<MappingSuggestion>, Error> {
+    let unmatched: HashMap<String, usize> = unmatched_extensions(conn, fs, mappings)?
+        .into_iter()
+        .map(|unmatched| (unmatched.extension, unmatched.count))
+        .collect();
+    let category_targets: HashMap<String, String> = conn
+        .prepare("SELECT category_id, target_path FROM path_mappings WHERE category_id IS NOT NULL")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+
+    let suggestions = suggest(
+        &unmatched,
+        &category_targets,
+        sorted_dir,
+        Settings::load(conn)?.folder_language(),
+    );
+    tracing::info!(
+        "{} unmatched extensions, {} suggestions",
+        unmatched.len(),
+        suggestions.len()
+    );
+    Ok(suggestions)
+}
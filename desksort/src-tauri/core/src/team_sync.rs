@@ -0,0 +1,307 @@
+use crate::{
+    mappings,
+    ruleset::{self, Item, ItemKind, Resolution, Ruleset, SharedRule, Side},
+    rules::{self, Rule},
+    Error,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// Name of the ruleset file when the shared location is a folder.
+pub const FILE_NAME: &str = "desksort-ruleset.json";
+
+/// Mappings and rules by item, as JSON so both kinds compare alike.
+type Entries = BTreeMap<Item, Value>;
+
+/// A mapping or rule changed both here and upstream since the last sync.
+/// Missing values mean it doesn't exist on that side.
+#[derive(Serialize, Debug)]
+pub struct Conflict {
+    pub item: Item,
+    pub base: Option<Value>,
+    pub local: Option<Value>,
+    pub remote: Option<Value>,
+}
+
+/// What a sync did.
+#[derive(Serialize, Debug)]
+pub struct SyncReport {
+    pub location: String,
+    /// Upstream changes applied here.
+    pub pulled: Vec<Item>,
+    /// Local changes written upstream.
+    pub pushed: Vec<Item>,
+    /// Left as they are on both sides until resolved.
+    pub conflicts: Vec<Conflict>,
+    pub synced_at: i64,
+}
+
+fn entries(ruleset: &Ruleset) -> Entries {
+    let mappings = ruleset.mappings.iter().map(|(extension, target)| {
+        let item = Item {
+            kind: ItemKind::Mapping,
+            key: extension.clone(),
+        };
+        (item, Value::String(target.clone()))
+    });
+    let rules = ruleset.rules.iter().map(|rule| {
+        let item = Item {
+            kind: ItemKind::Rule,
+            key: rule.name.clone(),
+        };
+        (item, serde_json::to_value(rule).unwrap_or_default())
+    });
+    mappings.chain(rules).collect()
+}
+
+fn to_ruleset(entries: &Entries) -> Result<Ruleset, Error> {
+    let mut ruleset = Ruleset::default();
+    for (item, value) in entries {
+        match item.kind {
+            ItemKind::Mapping => {
+                ruleset
+                    .mappings
+                    .insert(item.key.clone(), value.as_str().unwrap_or_default().to_string());
+            }
+            ItemKind::Rule => ruleset.rules.push(
+                serde_json::from_value(value.clone()).map_err(|e| Error::InvalidRuleset(e.to_string()))?,
+            ),
+            // Only mappings and rules are synced with the team
+            ItemKind::Category | ItemKind::IgnorePattern => {}
+        }
+    }
+    Ok(ruleset)
+}
+
+/// The ruleset file `location` stands for: a URL, or a file or folder path.
+fn file_path(location: &str) -> PathBuf {
+    let path = PathBuf::from(location.strip_prefix("file://").unwrap_or(location));
+    if path.is_dir() {
+        path.join(FILE_NAME)
+    } else {
+        path
+    }
+}
+
+fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// Reads the shared ruleset at `location`, or `None` if nobody pushed one yet.
+fn read(location: &str) -> io::Result<Option<Vec<u8>>> {
+    if !is_url(location) {
+        return match fs::read(file_path(location)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        };
+    }
+    // Without -f, so a 404 can be told from a failure
+    let download = std::env::temp_dir().join(format!("desksort-ruleset-{}.json", std::process::id()));
+    let output = Command::new("curl")
+        .args(["-sSL", "--max-time", "30", "-w", "%{http_code}", "-o"])
+        .arg(&download)
+        .args(["--", location])
+        .output()?;
+    let status = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let data = fs::read(&download);
+    let _ = fs::remove_file(&download);
+    match status.as_str() {
+        _ if !output.status.success() => Err(io::Error::other(format!(
+            "curl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+        "404" | "410" => Ok(None),
+        _ if status.starts_with('2') => data.map(Some),
+        _ => Err(io::Error::other(format!("{} answered HTTP {}", location, status))),
+    }
+}
+
+/// Replaces the shared ruleset at `location` with `data`: uploaded with a PUT
+/// for URLs, or written next to the file and renamed over it so readers
+/// never see half of it.
+fn write(location: &str, data: &[u8]) -> io::Result<()> {
+    if !is_url(location) {
+        let path = file_path(location);
+        let partial = path.with_extension("json.partial");
+        fs::write(&partial, data)?;
+        return fs::rename(&partial, &path);
+    }
+    let mut child = Command::new("curl")
+        .args(["-fsS", "--max-time", "30", "--upload-file", "-", "--", location])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(data)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "curl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// The shared ruleset at `location` as it is now, `None` if there is none
+/// yet. Doesn't need the database, so it can be held only for `sync`.
+pub fn fetch(location: &str) -> Result<Option<Ruleset>, Error> {
+    read(location)?.map(|data| ruleset::parse(&data)).transpose()
+}
+
+/// What both sides agreed on after the last sync with `location`.
+fn base(conn: &Connection, location: &str) -> Result<Entries, Error> {
+    let base: Option<String> = conn
+        .query_row(
+            "SELECT base FROM ruleset_sync WHERE location = ?",
+            params![location],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match base {
+        Some(base) => Ok(entries(&ruleset::parse(base.as_bytes())?)),
+        None => Ok(Entries::new()),
+    }
+}
+
+/// Sets the local mapping or rule `item` to `value`, removing it when unset.
+fn write_local(conn: &Connection, item: &Item, value: Option<&Value>, local_rules: &[Rule]) -> Result<(), Error> {
+    let sorted = ruleset::sorted_dir()?;
+    match (item.kind, value) {
+        (ItemKind::Mapping, Some(target)) => {
+            let target = ruleset::resolve(target.as_str().unwrap_or_default(), &sorted);
+            mappings::save(conn, &item.key, &target, None)
+        }
+        (ItemKind::Mapping, None) => {
+            conn.execute("DELETE FROM path_mappings WHERE extension = ?", params![item.key])?;
+            Ok(())
+        }
+        (ItemKind::Rule, value) => {
+            let local = local_rules.iter().find(|rule| rule.name == item.key);
+            match (value, local) {
+                (Some(value), local) => {
+                    let shared: SharedRule =
+                        serde_json::from_value(value.clone()).map_err(|e| Error::InvalidRuleset(e.to_string()))?;
+                    ruleset::save_shared(conn, &shared, local, &sorted)
+                }
+                (None, Some(Rule { id: Some(id), .. })) => rules::delete_rule(conn, *id).map(|_| ()),
+                (None, _) => Ok(()),
+            }
+        }
+        (ItemKind::Category | ItemKind::IgnorePattern, _) => Ok(()),
+    }
+}
+
+/// Three-way merges the local mappings and rules with `remote`, what `fetch`
+/// got from `location`, against what both agreed on last time. Changes made
+/// on one side only, deletions included, are carried over to the other.
+/// Items changed on both sides differently are reported as conflicts and
+/// left alone, unless `resolutions` says which side wins. The local changes
+/// are undone if `location` can't be written.
+pub fn sync(
+    conn: &Connection,
+    location: &str,
+    remote: Option<Ruleset>,
+    resolutions: &[Resolution],
+) -> Result<SyncReport, Error> {
+    let base = base(conn, location)?;
+    let local = entries(&ruleset::current(conn)?);
+    let remote_entries = remote.as_ref().map(entries).unwrap_or_default();
+    let keys: BTreeSet<&Item> = base.keys().chain(local.keys()).chain(remote_entries.keys()).collect();
+
+    let (mut new_local, mut new_remote, mut new_base) = (Entries::new(), Entries::new(), Entries::new());
+    let mut conflicts = Vec::new();
+    for item in keys {
+        let (b, l, r) = (base.get(item), local.get(item), remote_entries.get(item));
+        let merged = if l == r {
+            l
+        } else if l == b {
+            r
+        } else if r == b {
+            l
+        } else {
+            match resolutions.iter().find(|resolution| &resolution.item == item).map(|resolution| resolution.keep) {
+                Some(Side::Local) => l,
+                Some(Side::Remote) => r,
+                None => {
+                    conflicts.push(Conflict {
+                        item: item.clone(),
+                        base: b.cloned(),
+                        local: l.cloned(),
+                        remote: r.cloned(),
+                    });
+                    // Both keep their version, and the base stays, so it
+                    // comes up again next time
+                    for (entries, value) in [(&mut new_local, l), (&mut new_remote, r), (&mut new_base, b)] {
+                        if let Some(value) = value {
+                            entries.insert(item.clone(), value.clone());
+                        }
+                    }
+                    continue;
+                }
+            }
+        };
+        if let Some(value) = merged {
+            for entries in [&mut new_local, &mut new_remote, &mut new_base] {
+                entries.insert(item.clone(), value.clone());
+            }
+        }
+    }
+
+    let changed = |from: &Entries, to: &Entries| -> Vec<Item> {
+        from.keys()
+            .chain(to.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter(|item| from.get(*item) != to.get(*item))
+            .cloned()
+            .collect()
+    };
+    let pulled = changed(&local, &new_local);
+    let pushed = changed(&remote_entries, &new_remote);
+
+    let tx = conn.unchecked_transaction()?;
+    let local_rules = rules::load_rules(&tx)?;
+    for item in &pulled {
+        write_local(&tx, item, new_local.get(item), &local_rules)?;
+    }
+    let synced_at = chrono::Utc::now().timestamp();
+    let base = serde_json::to_string_pretty(&to_ruleset(&new_base)?).map_err(|e| Error::InvalidRuleset(e.to_string()))?;
+    tx.execute(
+        "INSERT INTO ruleset_sync (location, base, synced_at) VALUES (?, ?, ?)
+         ON CONFLICT(location) DO UPDATE SET base = excluded.base, synced_at = excluded.synced_at",
+        params![location, base, synced_at],
+    )?;
+    if !pushed.is_empty() || remote.is_none() {
+        let document =
+            serde_json::to_vec_pretty(&to_ruleset(&new_remote)?).map_err(|e| Error::InvalidRuleset(e.to_string()))?;
+        write(location, &document)?;
+    }
+    tx.commit()?;
+
+    tracing::info!(
+        "Synced rules with {}: {} pulled, {} pushed, {} conflicts",
+        location,
+        pulled.len(),
+        pushed.len(),
+        conflicts.len()
+    );
+    Ok(SyncReport {
+        location: location.to_string(),
+        pulled,
+        pushed,
+        conflicts,
+        synced_at,
+    })
+}
@@ -0,0 +1,163 @@
+use crate::{
+    diagnostics::{FailedStep, SortError},
+    filesystem::FileSystem,
+    history,
+    messages::Message,
+    mover,
+    settings::Settings,
+    unique_target_path, Error, PlannedMove,
+};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// An entry DeskSort sent to the trash.
+#[derive(Serialize)]
+pub struct Trashed {
+    pub path: String,
+    /// Where it is now, on platforms that tell.
+    pub location: Option<String>,
+    pub size: u64,
+    pub decided_by: String,
+}
+
+/// How the pipeline gets rid of user files. Everything a sort deletes, from
+/// duplicate copies to matches of rules that delete, goes through one, so
+/// nothing is ever unlinked outright.
+pub trait Disposal: Sync {
+    /// Removes `path` from where it is. Returns where it can be recovered
+    /// from, when that is known.
+    fn dispose(&self, path: &Path) -> io::Result<Option<PathBuf>>;
+}
+
+/// The trash of the current user: the Recycle Bin on Windows, Finder's
+/// trash on macOS, the freedesktop.org trash elsewhere.
+pub struct SystemTrash;
+
+impl Disposal for SystemTrash {
+    fn dispose(&self, path: &Path) -> io::Result<Option<PathBuf>> {
+        if std::fs::symlink_metadata(path).is_err() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", path.display())));
+        }
+        let path = std::path::absolute(path)?;
+        ::trash::delete(&path).map_err(io::Error::other)?;
+        Ok(location(&path))
+    }
+}
+
+/// Leaves everything where it is, for simulation mode. Entries it disposes
+/// of can't be restored, having never gone anywhere.
+pub struct SimulatedTrash;
+
+impl Disposal for SimulatedTrash {
+    fn dispose(&self, path: &Path) -> io::Result<Option<PathBuf>> {
+        if std::fs::symlink_metadata(path).is_err() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", path.display())));
+        }
+        Ok(None)
+    }
+}
+
+/// The trash `settings` call for: the system's, or none in simulation mode.
+pub fn disposal(settings: &Settings) -> &'static dyn Disposal {
+    if settings.simulation_mode {
+        &SimulatedTrash
+    } else {
+        &SystemTrash
+    }
+}
+
+/// Disposes of the entries of `deletions`. Failures are added to `errors`;
+/// the entries then stay where they are.
+pub fn trash_all(disposal: &dyn Disposal, deletions: &[PlannedMove], errors: &mut Vec<SortError>) -> Vec<Trashed> {
+    let mut trashed = Vec::new();
+    for planned in deletions {
+        match disposal.dispose(&planned.source) {
+            Ok(location) => trashed.push(Trashed {
+                path: planned.source.display().to_string(),
+                location: location.map(|location| location.display().to_string()),
+                size: planned.size,
+                decided_by: planned.decided_by.clone(),
+            }),
+            Err(e) => {
+                let message = Message::new("sort.trash_failed")
+                    .arg("path", planned.source.display())
+                    .arg("detail", &e);
+                errors.push(SortError::from_io(FailedStep::RemoveSource, &planned.source, &e, message));
+            }
+        }
+    }
+    trashed
+}
+
+/// Moves an entry `dispose` put at `location` back to `to`, and drops what
+/// the trash kept about it.
+pub fn restore(fs: &dyn FileSystem, location: &Path, to: &Path) -> io::Result<()> {
+    mover::move_path(fs, location, to, &mut |_, _, _| {}, None)?;
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if let (Some(trash), Some(name)) = (location.parent().and_then(Path::parent), location.file_name()) {
+        let info = trash.join("info").join(format!("{}.trashinfo", name.to_string_lossy()));
+        if let Err(e) = fs.remove_file(&info) {
+            tracing::warn!("Failed to remove {}: {}", info.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// Takes the entry history entry `id` sent to the trash back to where it
+/// was, under a new name if that is taken, as a history session of its own.
+/// Returns the restored path. Simulated, the entry is still in the trash
+/// afterwards and remains restorable.
+pub fn restore_recorded(conn: &Connection, fs: &dyn FileSystem, id: i64) -> Result<PathBuf, Error> {
+    let started_at = chrono::Utc::now().timestamp();
+    let settings = Settings::load(conn)?;
+    let Some((source, size, location)) = history::trashed(conn, id)? else {
+        return Err(Error::NotRestorable(format!("history entry {} has no known place in the trash", id)));
+    };
+    if fs.symlink_metadata(&location).is_err() {
+        if !settings.simulation_mode {
+            history::forget_trash_location(conn, id)?;
+        }
+        return Err(Error::NotRestorable(format!("{} is no longer in the trash", source.display())));
+    }
+    let (Some(dir), Some(name)) = (source.parent(), source.file_name()) else {
+        return Err(Error::NotRestorable(format!("{} has no folder", source.display())));
+    };
+    fs.create_dir_all(&mover::long_path(dir))?;
+    let restored = unique_target_path(fs, dir, name, &HashSet::new(), settings.collision_naming);
+    tracing::info!("Restoring {} from the trash", restored.display());
+    restore(fs, &location, &restored)?;
+    if !settings.simulation_mode {
+        history::forget_trash_location(conn, id)?;
+    }
+    history::record_session(
+        conn,
+        history::Trigger::Restored,
+        started_at,
+        &[history::Entry::moved(&location, &restored, size, "restored from the trash")],
+    )?;
+    Ok(restored)
+}
+
+/// Where the freedesktop.org trash put the entry last deleted from `path`,
+/// found from the `.trashinfo` file it was listed with.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn location(path: &Path) -> Option<PathBuf> {
+    let item = ::trash::os_limited::list()
+        .ok()?
+        .into_iter()
+        .filter(|item| item.original_path() == path)
+        .max_by_key(|item| item.time_deleted)?;
+    let info = Path::new(&item.id);
+    Some(info.parent()?.parent()?.join("files").join(info.file_stem()?))
+}
+
+/// The Recycle Bin and Finder's trash don't say where entries went.
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn location(_path: &Path) -> Option<PathBuf> {
+    None
+}
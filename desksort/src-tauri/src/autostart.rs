@@ -0,0 +1,126 @@
+use crate::Error;
+use std::{env, io, path::PathBuf};
+
+#[cfg(not(target_os = "macos"))]
+const APP_NAME: &str = "DeskSort";
+/// Passed to the registered command so a login launch can tell itself apart
+/// from the user opening the app.
+pub const AUTOSTART_ARG: &str = "--autostart";
+
+fn current_exe() -> Result<PathBuf, Error> {
+    Ok(env::current_exe()?)
+}
+
+/// Whether DeskSort is registered to start at login.
+#[cfg(target_os = "windows")]
+pub fn is_enabled() -> Result<bool, Error> {
+    let status = std::process::Command::new("reg")
+        .args(["query", RUN_KEY, "/v", APP_NAME])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()?;
+    Ok(status.success())
+}
+
+#[cfg(target_os = "windows")]
+const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+
+/// Adds or removes the `Run` registry value for the current user.
+#[cfg(target_os = "windows")]
+pub fn set_enabled(enabled: bool) -> Result<(), Error> {
+    let mut command = std::process::Command::new("reg");
+    if enabled {
+        let value = format!("\"{}\" {}", current_exe()?.display(), AUTOSTART_ARG);
+        command.args(["add", RUN_KEY, "/v", APP_NAME, "/t", "REG_SZ", "/d", &value, "/f"]);
+    } else {
+        if !is_enabled()? {
+            return Ok(());
+        }
+        command.args(["delete", RUN_KEY, "/v", APP_NAME, "/f"]);
+    }
+
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(Error::Io(io::Error::other(format!(
+            "reg.exe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn entry_path() -> Result<PathBuf, Error> {
+    let home = dirs::home_dir().ok_or(Error::ConfigDirNotFound)?;
+    Ok(home.join("Library/LaunchAgents/com.desksort.app.plist"))
+}
+
+#[cfg(target_os = "macos")]
+fn entry_contents() -> Result<String, Error> {
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>com.desksort.app</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{}</string>
+        <string>{}</string>
+    </array>
+    <key>RunAtLoad</key>
+    <true/>
+</dict>
+</plist>
+"#,
+        xml_escape(&current_exe()?.to_string_lossy()),
+        AUTOSTART_ARG
+    ))
+}
+
+#[cfg(target_os = "macos")]
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn entry_path() -> Result<PathBuf, Error> {
+    let config_dir = dirs::config_dir().ok_or(Error::ConfigDirNotFound)?;
+    Ok(config_dir.join("autostart/desksort.desktop"))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn entry_contents() -> Result<String, Error> {
+    // Exec values quote arguments containing spaces, per the desktop entry spec
+    let exe = current_exe()?.to_string_lossy().replace('\\', "\\\\").replace('"', "\\\"");
+    Ok(format!(
+        "[Desktop Entry]\nType=Application\nName={}\nExec=\"{}\" {}\nX-GNOME-Autostart-enabled=true\nNoDisplay=true\n",
+        APP_NAME, exe, AUTOSTART_ARG
+    ))
+}
+
+/// Whether DeskSort is registered to start at login.
+#[cfg(unix)]
+pub fn is_enabled() -> Result<bool, Error> {
+    Ok(entry_path()?.exists())
+}
+
+/// Writes or removes the login item (a LaunchAgent on macOS, an XDG autostart
+/// entry elsewhere).
+#[cfg(unix)]
+pub fn set_enabled(enabled: bool) -> Result<(), Error> {
+    let path = entry_path()?;
+    if enabled {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, entry_contents()?)?;
+    } else {
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use crate::{ruleset, Error};
+use serde::Serialize;
+use std::path::PathBuf;
+
+pub const SCHEME: &str = "desksort";
+
+/// Registry class of `.desksortrules` files.
+#[cfg(target_os = "windows")]
+const FILE_CLASS: &str = "DeskSort.Rules";
+
+/// MIME type of `.desksortrules` files.
+#[cfg(all(unix, not(target_os = "macos")))]
+const FILE_MIME_TYPE: &str = "application/x-desksortrules";
+
+/// An action requested through a `desksort://` URL. Actions that change
+/// configuration are only forwarded to the UI, which asks the user first.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum DeepLink {
+    /// `desksort://sort`
+    Sort,
+    /// `desksort://add-rule?ext=.heic&target=/home/me/Pictures`
+    AddRule { extension: String, target: String },
+    /// A `.desksortrules` file opened with DeskSort, to preview and import.
+    ImportRules { path: String },
+}
+
+/// Whether `arg` looks like a `desksort://` URL.
+pub fn is_link(arg: &str) -> bool {
+    arg.get(..SCHEME.len()).is_some_and(|scheme| scheme.eq_ignore_ascii_case(SCHEME))
+        && arg[SCHEME.len()..].starts_with("://")
+}
+
+/// The ruleset file `arg` names, if it is one. Some Linux desktops pass
+/// opened files as `file://` URLs.
+pub fn rules_file(arg: &str) -> Option<PathBuf> {
+    let path = match arg.strip_prefix("file://") {
+        // A `+` in a file URL is no space
+        Some(url) => PathBuf::from(percent_decode(&url.replace('+', "%2B")).ok()?),
+        None => PathBuf::from(arg),
+    };
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(ruleset::EXTENSION))
+        .then_some(path)
+}
+
+pub fn parse(url: &str) -> Result<DeepLink, Error> {
+    if !is_link(url) {
+        return Err(Error::InvalidLink(format!("not a {}:// URL", SCHEME)));
+    }
+    let rest = &url[SCHEME.len() + 3..];
+    let (action, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+    let mut params = Vec::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        params.push((percent_decode(key)?, percent_decode(value)?));
+    }
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| Error::InvalidLink(format!("missing `{}`", name)))
+    };
+
+    match action.trim_matches('/').to_ascii_lowercase().as_str() {
+        "sort" => Ok(DeepLink::Sort),
+        "add-rule" => {
+            let extension = param("ext")?.to_lowercase();
+            Ok(DeepLink::AddRule {
+                extension: if extension.starts_with('.') || extension == "folder" {
+                    extension
+                } else {
+                    format!(".{}", extension)
+                },
+                target: param("target")?,
+            })
+        }
+        other => Err(Error::InvalidLink(format!("unknown action `{}`", other))),
+    }
+}
+
+fn percent_decode(text: &str) -> Result<String, Error> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let byte = text
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| Error::InvalidLink(format!("bad escape in `{}`", text)))?;
+                decoded.push(byte);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| Error::InvalidLink(format!("`{}` is not UTF-8", text)))
+}
+
+/// Registers this executable as the handler of `desksort://` URLs and
+/// `.desksortrules` files for the current user. Runs on every start so the
+/// registration follows the app when it is moved or updated.
+#[cfg(target_os = "windows")]
+pub fn register() -> Result<(), Error> {
+    let key = format!(r"HKCU\Software\Classes\{}", SCHEME);
+    let command = format!("\"{}\" \"%1\"", std::env::current_exe()?.display());
+    reg(&["add", &key, "/ve", "/d", "URL:DeskSort", "/f"])?;
+    reg(&["add", &key, "/v", "URL Protocol", "/d", "", "/f"])?;
+    reg(&["add", &format!(r"{}\shell\open\command", key), "/ve", "/d", &command, "/f"])?;
+
+    let class = format!(r"HKCU\Software\Classes\{}", FILE_CLASS);
+    let extension = format!(r"HKCU\Software\Classes\.{}", ruleset::EXTENSION);
+    reg(&["add", &extension, "/ve", "/d", FILE_CLASS, "/f"])?;
+    reg(&["add", &class, "/ve", "/d", "DeskSort rules", "/f"])?;
+    reg(&["add", &format!(r"{}\shell\open\command", class), "/ve", "/d", &command, "/f"])
+}
+
+#[cfg(target_os = "windows")]
+fn reg(args: &[&str]) -> Result<(), Error> {
+    let output = std::process::Command::new("reg").args(args).output()?;
+    if !output.status.success() {
+        return Err(Error::Io(std::io::Error::other(format!(
+            "reg.exe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))));
+    }
+    Ok(())
+}
+
+/// On macOS the scheme is declared in the bundle's Info.plist instead.
+/// Ruleset files aren't associated there, as Tauri doesn't pass on the
+/// files Finder opens an app with.
+#[cfg(target_os = "macos")]
+pub fn register() -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn register() -> Result<(), Error> {
+    let data_dir = dirs::data_dir().ok_or(Error::ConfigDirNotFound)?;
+    let applications = data_dir.join("applications");
+    std::fs::create_dir_all(&applications)?;
+
+    let exe = std::env::current_exe()?.to_string_lossy().replace('\\', "\\\\").replace('"', "\\\"");
+    let desktop_file = format!("{}-handler.desktop", SCHEME);
+    std::fs::write(
+        applications.join(&desktop_file),
+        format!(
+            "[Desktop Entry]\nType=Application\nName=DeskSort\nExec=\"{}\" %u\nMimeType=x-scheme-handler/{};{};\nNoDisplay=true\n",
+            exe, SCHEME, FILE_MIME_TYPE
+        ),
+    )?;
+
+    // Tells the desktop which files are ruleset files
+    let mime = data_dir.join("mime");
+    std::fs::create_dir_all(mime.join("packages"))?;
+    std::fs::write(
+        mime.join("packages").join("desksort.xml"),
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <mime-info xmlns=\"http://www.freedesktop.org/standards/shared-mime-info\">\n\
+             \x20 <mime-type type=\"{}\">\n\
+             \x20   <comment>DeskSort rules</comment>\n\
+             \x20   <glob pattern=\"*.{}\"/>\n\
+             \x20 </mime-type>\n\
+             </mime-info>\n",
+            FILE_MIME_TYPE,
+            ruleset::EXTENSION
+        ),
+    )?;
+
+    // Best effort: without xdg-utils the entry is still picked up on next login
+    let _ = std::process::Command::new("update-mime-database").arg(&mime).status();
+    for mime_type in [format!("x-scheme-handler/{}", SCHEME), FILE_MIME_TYPE.to_string()] {
+        let _ = std::process::Command::new("xdg-mime")
+            .args(["default", &desktop_file, &mime_type])
+            .status();
+    }
+    Ok(())
+}
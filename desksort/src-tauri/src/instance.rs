@@ -0,0 +1,151 @@
+use crate::{deeplink, get_db_path, Error};
+use fs2::FileExt;
+use std::{
+    collections::hash_map::RandomState,
+    fs::{self, File},
+    hash::{BuildHasher, Hasher},
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    path::PathBuf,
+    thread,
+    time::Duration,
+};
+
+/// How long a second instance waits for the first one to take its message.
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// What a second launch asks the running instance to do.
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    /// Bring the window to the front.
+    Activate,
+    /// Bring the window to the front and sort the desktop.
+    SortNow,
+    /// Handle a `desksort://` URL the OS launched us with.
+    OpenUrl(String),
+    /// Offer to import a `.desksortrules` file the user opened.
+    OpenRules(PathBuf),
+}
+
+impl Request {
+    /// The request implied by command line `args`.
+    pub fn from_args(args: &[String]) -> Self {
+        if let Some(url) = args.iter().find(|arg| deeplink::is_link(arg)) {
+            Request::OpenUrl(url.clone())
+        } else if let Some(path) = args.iter().find_map(|arg| deeplink::rules_file(arg)) {
+            Request::OpenRules(path)
+        } else if args.iter().any(|arg| arg == "--sort-now") {
+            Request::SortNow
+        } else {
+            Request::Activate
+        }
+    }
+
+    fn encode(&self) -> String {
+        match self {
+            Request::Activate => "activate".to_string(),
+            Request::SortNow => "sort".to_string(),
+            Request::OpenUrl(url) => format!("url {}", url),
+            Request::OpenRules(path) => format!("rules {}", path.display()),
+        }
+    }
+
+    fn decode(text: &str) -> Option<Self> {
+        match text.split_once(' ') {
+            Some(("url", url)) => Some(Request::OpenUrl(url.to_string())),
+            Some(("rules", path)) => Some(Request::OpenRules(PathBuf::from(path))),
+            _ => match text {
+                "activate" => Some(Request::Activate),
+                "sort" => Some(Request::SortNow),
+                _ => None,
+            },
+        }
+    }
+}
+
+pub enum Instance {
+    /// This process owns the database and serves requests from later launches.
+    Primary(PrimaryInstance),
+    /// Another process is already running.
+    Secondary,
+}
+
+pub struct PrimaryInstance {
+    /// Held for the life of the process; the OS drops the lock on exit.
+    _lock: File,
+    listener: TcpListener,
+    token: String,
+}
+
+/// Files next to the database: the lock, and the port and token of the
+/// running instance's loopback listener.
+fn lock_paths() -> Result<(PathBuf, PathBuf), Error> {
+    let dir = get_db_path()?.parent().map(PathBuf::from).ok_or(Error::ConfigDirNotFound)?;
+    Ok((dir.join("instance.lock"), dir.join("instance.port")))
+}
+
+/// Claims the single-instance lock, or reports that another instance has it.
+pub fn acquire() -> Result<Instance, Error> {
+    let (lock_path, port_path) = lock_paths()?;
+    let lock = File::create(&lock_path)?;
+    if lock.try_lock_exclusive().is_err() {
+        return Ok(Instance::Secondary);
+    }
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    // Only processes that can read our config directory may send requests
+    let token = format!("{:016x}{:016x}", random_u64(), random_u64());
+    fs::write(&port_path, format!("{} {}", listener.local_addr()?.port(), token))?;
+
+    Ok(Instance::Primary(PrimaryInstance {
+        _lock: lock,
+        listener,
+        token,
+    }))
+}
+
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Sends `request` to the running instance.
+pub fn forward(request: &Request) -> Result<(), Error> {
+    let (_, port_path) = lock_paths()?;
+    let contents = fs::read_to_string(port_path)?;
+    let (port, token) = contents
+        .trim()
+        .split_once(' ')
+        .and_then(|(port, token)| Some((port.parse::<u16>().ok()?, token)))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed instance.port"))?;
+
+    let mut stream = TcpStream::connect_timeout(&(Ipv4Addr::LOCALHOST, port).into(), FORWARD_TIMEOUT)?;
+    stream.set_write_timeout(Some(FORWARD_TIMEOUT))?;
+    writeln!(stream, "{} {}", token, request.encode())?;
+    Ok(())
+}
+
+impl PrimaryInstance {
+    /// Serves requests from later launches on a background thread.
+    pub fn listen(self, on_request: impl Fn(Request) + Send + 'static) {
+        thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let _ = stream.set_read_timeout(Some(FORWARD_TIMEOUT));
+                let mut line = String::new();
+                if BufReader::new(stream).read_line(&mut line).is_err() {
+                    continue;
+                }
+
+                match line.trim().split_once(' ') {
+                    Some((token, request)) if token == self.token => match Request::decode(request) {
+                        Some(request) => on_request(request),
+                        None => tracing::warn!("Ignoring unknown instance request: {}", request),
+                    },
+                    _ => tracing::warn!("Ignoring instance request with a bad token"),
+                }
+            }
+        });
+    }
+}
@@ -1,153 +1,186 @@
-use anyhow::Context;
-use rusqlite::{Connection, params};
-use serde::{Deserialize, Serialize};
+use rusqlite::Connection;
 use std::{
     fs,
     path::{Path, PathBuf},
     result::Result,
-    sync::Mutex,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
-use tauri::State;
-use walkdir::WalkDir;
-
-#[derive(Debug, thiserror::Error)]
-pub enum Error {
-    #[error("IO error: {0}")]
-    Io(#[from] std::io::Error),
-    #[error("Database error: {0}")]
-    Db(#[from] rusqlite::Error),
-    #[error("Desktop path not found")]
-    DesktopNotFound,
-    #[error("Config directory not found")]
-    ConfigDirNotFound,
-}
+use tauri::{Manager, State, Window};
 
-impl serde::Serialize for Error {
-    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        serializer.serialize_str(self.to_string().as_ref())
-    }
-}
+mod autostart;
+mod deeplink;
+mod instance;
 
-#[derive(Default, Serialize, Deserialize, Clone)]
-pub struct PathMapping {
-    extension: String,
-    target_path: String,
-}
+use desksort_core::{
+    app_lock, approvals, archive, benchmark, categories, config_file, conflicts, configure_connection,
+    create_target_dirs, desktop, duplicates, encryption, extension_groups,
+    filesystem::{FileSystem, OsFileSystem, SimulatedFileSystem},
+    folder_stats, get_db_path, get_desktop_path, health, history, init_db, large_files, learning, library,
+    load_config_file, logging, mappings, messages::Message, migrate_mapping, mime, open_in_file_manager, orphans,
+    queued_moves, rename_default_folders, report, retries, ruleset,
+    rules::{self, Rule, RuleGroup},
+    settings::Settings,
+    shortcuts, sort_extension, sort_paths, sort_sources, sources, staging, stale,
+    suggestions::{self, MappingSuggestion},
+    team_sync, trash, unmatched_extensions, Conflict, Error, FileProgress, MappingCache, MappingQuery, MappingPage,
+    PlanTotals, QueuedMove, SortContext, SortEvents, SortProgress, SortResult, TargetDirsReport, UnmatchedExtension,
+};
 
 pub struct AppState {
     db: Mutex<Connection>,
+    /// Loaded on the first sort and dropped whenever a mapping is written.
+    mapping_cache: Mutex<Option<Arc<MappingCache>>>,
+    sort_activity: learning::SortActivity,
+    /// Running while `learn_from_moves` is on.
+    move_watcher: Mutex<Option<learning::MoveWatcher>>,
+    /// A `desksort://` link that arrived before the UI could take it.
+    pending_link: Mutex<Option<deeplink::DeepLink>>,
+    /// The last reminder of stale files, until the UI takes it.
+    pending_stale_reminder: Mutex<Option<Message>>,
+    /// Summary of the last weekly report, until the UI takes it.
+    pending_weekly_report: Mutex<Option<Message>>,
+    /// Set while the database is encrypted and the passphrase hasn't been
+    /// entered; `db` is then an empty in-memory placeholder.
+    locked: AtomicBool,
+    /// Running while the TOML config backend is selected.
+    config_watcher: Mutex<Option<config_file::ConfigWatcher>>,
+    /// Counts of the target folders, kept until their contents change.
+    folder_stats: folder_stats::StatsCache,
+    /// Until when changes go through without the app lock PIN, after it was
+    /// entered.
+    unlocked_until: Mutex<Option<Instant>>,
 }
 
-fn init_db(conn: &mut Connection) -> Result<(), Error> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS path_mappings (
-            extension TEXT PRIMARY KEY,
-            target_path TEXT NOT NULL
-        )",
-        [],
-    )?;
-
-    // Check if we need to initialize default paths
-    let count: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM path_mappings",
-        [],
-        |row| row.get(0),
-    )?;
-
-    if count == 0 {
-        println!("Initializing default paths...");
-        let desktop = get_desktop_path()?;
-        let sorted_dir = desktop.join("Sorted");
-
-        let default_paths = [
-            (".pdf", sorted_dir.join("Documents")),
-            (".docx", sorted_dir.join("Documents")),
-            (".doc", sorted_dir.join("Documents")),
-            (".txt", sorted_dir.join("Documents")),
-            (".odt", sorted_dir.join("Documents")),
-            (".rtf", sorted_dir.join("Documents")),
-            (".xls", sorted_dir.join("Spreadsheets")),
-            (".xlsx", sorted_dir.join("Spreadsheets")),
-            (".csv", sorted_dir.join("Spreadsheets")),
-            (".ods", sorted_dir.join("Spreadsheets")),
-            (".pptx", sorted_dir.join("Presentations")),
-            (".odp", sorted_dir.join("Presentations")),
-            (".key", sorted_dir.join("Presentations")),
-            (".jpg", sorted_dir.join("Images")),
-            (".jpeg", sorted_dir.join("Images")),
-            (".png", sorted_dir.join("Images")),
-            (".gif", sorted_dir.join("Images")),
-            (".bmp", sorted_dir.join("Images")),
-            (".webp", sorted_dir.join("Images")),
-            (".tiff", sorted_dir.join("Images")),
-            (".mp4", sorted_dir.join("Videos")),
-            (".mkv", sorted_dir.join("Videos")),
-            (".avi", sorted_dir.join("Videos")),
-            (".mov", sorted_dir.join("Videos")),
-            (".webm", sorted_dir.join("Videos")),
-            (".flv", sorted_dir.join("Videos")),
-            (".wmv", sorted_dir.join("Videos")),
-            (".mp3", sorted_dir.join("Audio")),
-            (".wav", sorted_dir.join("Audio")),
-            (".aac", sorted_dir.join("Audio")),
-            (".ogg", sorted_dir.join("Audio")),
-            (".flac", sorted_dir.join("Audio")),
-            (".zip", sorted_dir.join("Archives")),
-            (".rar", sorted_dir.join("Archives")),
-            (".7z", sorted_dir.join("Archives")),
-            (".tar", sorted_dir.join("Archives")),
-            (".gz", sorted_dir.join("Archives")),
-            (".tar.gz", sorted_dir.join("Archives")),
-            (".exe", sorted_dir.join("Executables")),
-            (".msi", sorted_dir.join("Executables")),
-            (".sh", sorted_dir.join("Executables")),
-            (".bat", sorted_dir.join("Executables")),
-            (".AppImage", sorted_dir.join("Executables")),
-            (".js", sorted_dir.join("Code")),
-            (".py", sorted_dir.join("Code")),
-            (".rs", sorted_dir.join("Code")),
-            (".cpp", sorted_dir.join("Code")),
-            (".java", sorted_dir.join("Code")),
-            (".html", sorted_dir.join("Code")),
-            (".css", sorted_dir.join("Code")),
-            (".json", sorted_dir.join("Code")),
-            (".ts", sorted_dir.join("Code")),
-            ("folder", sorted_dir.join("Folders")),
-        ];
-
-        let tx = conn.transaction()?;
-        for (ext, path) in default_paths.iter() {
-            tx.execute(
-                "INSERT OR IGNORE INTO path_mappings (extension, target_path) VALUES (?, ?)",
-                params![ext, path.to_str().unwrap()],
-            )?;
-        }
-        tx.commit()?;
-        println!("Default paths initialized");
-    }
-
-    Ok(())
-}
+impl AppState {
+    /// The current mappings, read from `conn` if nothing is cached.
+    fn mappings(&self, conn: &Connection) -> Result<Arc<MappingCache>, Error> {
+        let mut cache = self.mapping_cache.lock().unwrap();
+        if let Some(mappings) = cache.as_ref() {
+            return Ok(mappings.clone());
+        }
 
-fn get_db_path() -> Result<PathBuf, Error> {
-    let config_dir = dirs::config_dir().ok_or(Error::ConfigDirNotFound)?;
-    let db_dir = config_dir.join("desksort");
-    fs::create_dir_all(&db_dir)?;
-    Ok(db_dir.join("settings.db"))
-}
+        let mappings = Arc::new(MappingCache::load(conn)?);
+        *cache = Some(mappings.clone());
+        Ok(mappings)
+    }
 
-fn get_desktop_path() -> Result<PathBuf, Error> {
-    dirs::desktop_dir().ok_or(Error::DesktopNotFound)
-}
+    /// The disk, or in simulation mode a view of it that only records what
+    /// would change.
+    fn filesystem(&self, conn: &Connection) -> Result<Arc<dyn FileSystem>, Error> {
+        Ok(if Settings::load(conn)?.simulation_mode {
+            Arc::new(SimulatedFileSystem::new())
+        } else {
+            Arc::new(OsFileSystem)
+        })
+    }
+
+    /// What a sort reporting to `events` runs with.
+    fn sort_context<'a>(&'a self, conn: &Connection, events: &'a WindowEvents) -> Result<SortContext<'a>, Error> {
+        Ok(SortContext {
+            mappings: self.mappings(conn)?,
+            fs: self.filesystem(conn)?,
+            activity: &self.sort_activity,
+            events,
+        })
+    }
+
+    /// Fails with `AppLocked` while an app lock is set and its PIN wasn't
+    /// entered within `UNLOCK_DURATION`.
+    fn check_app_lock(&self, conn: &Connection) -> Result<(), Error> {
+        if Settings::load(conn)?.app_lock.is_none() || self.is_unlocked() {
+            return Ok(());
+        }
+        Err(Error::AppLocked)
+    }
+
+    fn is_unlocked(&self) -> bool {
+        self.unlocked_until.lock().unwrap().is_some_and(|until| Instant::now() < until)
+    }
+
+    fn invalidate_mappings(&self) {
+        *self.mapping_cache.lock().unwrap() = None;
+    }
+
+    /// Applies the desktop override of `settings`, and starts or stops
+    /// watching for manual moves to follow them.
+    fn apply_learning(&self, settings: &Settings) {
+        let mut watcher = self.move_watcher.lock().unwrap();
+        // A running watcher still follows the previous desktop
+        if desktop::set_override(settings.desktop_override.as_ref().map(PathBuf::from)) {
+            *watcher = None;
+        }
+        if !settings.learn_from_moves {
+            *watcher = None;
+            return;
+        }
+        if watcher.is_some() {
+            return;
+        }
+
+        let started = get_desktop_path()
+            .and_then(|desktop| learning::watch(&desktop, &desktop.join("Sorted"), self.sort_activity.clone()));
+        match started {
+            Ok(started) => *watcher = Some(started),
+            Err(e) => tracing::warn!("Failed to watch for manual moves: {}", e),
+        }
+    }
+
+    /// Starts or stops following `desksort.toml` to match `settings`. When the
+    /// watcher starts, an existing file is loaded into `conn` first.
+    fn apply_config_backend(
+        &self,
+        app: &tauri::AppHandle,
+        conn: &mut Connection,
+        settings: &Settings,
+    ) -> Result<(), Error> {
+        let mut watcher = self.config_watcher.lock().unwrap();
+        if settings.config_backend != config_file::ConfigBackend::Toml {
+            *watcher = None;
+            return Ok(());
+        }
+        if watcher.is_some() {
+            return Ok(());
+        }
+
+        let path = config_file::path()?;
+        if path.exists() {
+            let settings = load_config_file(conn)?;
+            self.invalidate_mappings();
+            self.apply_learning(&settings);
+        } else {
+            config_file::write(conn, &path)?;
+        }
+        let handle = app.clone();
+        *watcher = Some(config_file::watch(&path, move || reload_config_file(&handle))?);
+        Ok(())
+    }
+
+    /// Creates every target folder when `create_target_dirs` is on. Called
+    /// after mappings or rules change; failures are only logged.
+    fn prepare_target_dirs(&self, conn: &Connection) -> Result<(), Error> {
+        let settings = Settings::load(conn)?;
+        if !settings.create_target_dirs || settings.simulation_mode {
+            return Ok(());
+        }
+        let report = create_target_dirs(&*self.filesystem(conn)?, &*self.mappings(conn)?, settings.folder_icons);
+        for error in &report.errors {
+            tracing::warn!("{}", error.message);
+        }
+        Ok(())
+    }
 
-fn ensure_dir_exists(path: &Path) -> std::io::Result<()> {
-    if !path.exists() {
-        fs::create_dir_all(path)?;
+    /// Mirrors mappings and settings written through the app into
+    /// `desksort.toml` when that is the config backend.
+    fn write_config_file(&self, conn: &Connection) -> Result<(), Error> {
+        if self.config_watcher.lock().unwrap().is_some() {
+            config_file::write(conn, &config_file::path()?)?;
+        }
+        Ok(())
     }
-    Ok(())
 }
 
 pub mod commands {
@@ -156,159 +189,1652 @@ pub mod commands {
     #[tauri::command]
     pub async fn get_path_mapping(extension: String, state: State<'_, AppState>) -> Result<Option<String>, Error> {
         let conn = state.db.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT target_path FROM path_mappings WHERE extension = ?")?;
-        let mut rows = stmt.query(params![extension])?;
-        
-        if let Some(row) = rows.next()? {
-            Ok(Some(row.get(0)?))
-        } else {
-            Ok(None)
+        mappings::target(&conn, &extension)
+    }
+
+    /// Maps `extension` to `target_path`. With `migrate`, entries already
+    /// sorted into the mapping's previous target are moved to the new one, as
+    /// a history session of their own.
+    #[tauri::command]
+    pub async fn set_path_mapping(
+        extension: String,
+        target_path: String,
+        category: Option<String>,
+        migrate: Option<bool>,
+        window: Window,
+        state: State<'_, AppState>,
+    ) -> Result<Option<SortResult>, Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let previous = mappings::target(&conn, &extension)?;
+        save_mapping(&conn, &state, &extension, &target_path, category)?;
+
+        match previous {
+            Some(previous) if migrate.unwrap_or(false) && Path::new(&previous) != Path::new(&target_path) => {
+                let events = WindowEvents(window);
+                let ctx = state.sort_context(&conn, &events)?;
+                migrate_mapping(&conn, &ctx, &extension, Path::new(&previous)).map(Some)
+            }
+            _ => Ok(None),
         }
     }
 
+    /// Maps `extension` to `target_path` and sorts the desktop entries it
+    /// matches right away, e.g. from the unhandled file types of a sort.
+    #[tauri::command]
+    pub async fn add_mapping_and_sort(
+        extension: String,
+        target_path: String,
+        category: Option<String>,
+        window: Window,
+        state: State<'_, AppState>,
+    ) -> Result<SortResult, Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        save_mapping(&conn, &state, &extension, &target_path, category)?;
+
+        let events = WindowEvents(window);
+        sort_extension(&conn, &state.sort_context(&conn, &events)?, &extension)
+    }
+
+    /// Turns a mapping off without forgetting it, or back on.
     #[tauri::command]
-    pub async fn set_path_mapping(extension: String, target_path: String, state: State<'_, AppState>) -> Result<(), Error> {
-        println!("Setting path mapping: {} -> {}", extension, target_path);
+    pub async fn set_mapping_enabled(extension: String, enabled: bool, state: State<'_, AppState>) -> Result<(), Error> {
+        tracing::info!("Mapping {} is now {}", extension, if enabled { "enabled" } else { "disabled" });
         let conn = state.db.lock().unwrap();
-        conn.execute(
-            "INSERT OR REPLACE INTO path_mappings (extension, target_path) VALUES (?, ?)",
-            params![extension, target_path],
-        )?;
+        state.check_app_lock(&conn)?;
+        mappings::set_enabled(&conn, &extension, enabled)?;
+        state.invalidate_mappings();
+        state.write_config_file(&conn)?;
+        state.prepare_target_dirs(&conn)?;
         Ok(())
     }
 
+    /// Mappings and rules whose target is on a disconnected drive or share, or
+    /// in a deleted folder.
+    #[tauri::command]
+    pub async fn find_orphaned_mappings(state: State<'_, AppState>) -> Result<Vec<orphans::Orphan>, Error> {
+        let conn = state.db.lock().unwrap();
+        orphans::find(&conn, &get_desktop_path()?.join("Sorted"))
+    }
+
+    /// The category tree, with how many mappings and rules use each category.
+    #[tauri::command]
+    pub async fn get_categories(state: State<'_, AppState>) -> Result<Vec<categories::Category>, Error> {
+        let conn = state.db.lock().unwrap();
+        categories::load(&conn)
+    }
+
+    /// The category tree nested, with the folder of each category and how
+    /// many rules target it and its subcategories.
+    #[tauri::command]
+    pub async fn get_category_tree(state: State<'_, AppState>) -> Result<Vec<categories::CategoryNode>, Error> {
+        categories::tree(&state.db.lock().unwrap())
+    }
+
+    /// File count, size and latest addition of every target folder, for the
+    /// home screen. Only folders that changed since the last call are walked.
+    #[tauri::command]
+    pub async fn get_folder_stats(state: State<'_, AppState>) -> Result<Vec<folder_stats::FolderStats>, Error> {
+        let conn = state.db.lock().unwrap();
+        folder_stats::folder_stats(&conn, &*state.mappings(&conn)?, &state.folder_stats)
+    }
+
+    /// The biggest files of at least `min_size` bytes in the sources, the
+    /// target folders or both, with their ages. The folders are walked
+    /// without holding the database.
+    #[tauri::command]
+    pub async fn find_large_files(
+        min_size: u64,
+        scope: large_files::Scope,
+        state: State<'_, AppState>,
+    ) -> Result<Vec<large_files::LargeFile>, Error> {
+        let (sources, mappings) = {
+            let conn = state.db.lock().unwrap();
+            (sources::enabled(&conn)?, state.mappings(&conn)?)
+        };
+        Ok(large_files::find(&sources, &mappings, min_size, scope))
+    }
+
+    /// Size, file count and largest files of every category's folder. The
+    /// folders are walked without holding the database.
+    #[tauri::command]
+    pub async fn get_category_disk_usage(state: State<'_, AppState>) -> Result<Vec<categories::DiskUsage>, Error> {
+        let categories = categories::load(&state.db.lock().unwrap())?;
+        Ok(categories::disk_usage(&categories))
+    }
+
+    /// Adds a folder inside a category that rules can send entries to, e.g.
+    /// `Screenshots` inside `images`.
     #[tauri::command]
-    pub async fn get_all_mappings(state: State<'_, AppState>) -> Result<Vec<PathMapping>, Error> {
-        println!("Getting all mappings...");
+    pub async fn add_subcategory(
+        parent_id: String,
+        name: String,
+        state: State<'_, AppState>,
+    ) -> Result<categories::Category, Error> {
+        tracing::info!("Adding subcategory {} to {}", name, parent_id);
         let conn = state.db.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT extension, target_path FROM path_mappings")?;
-        let mappings = stmt.query_map([], |row| {
-            Ok(PathMapping {
-                extension: row.get(0)?,
-                target_path: row.get(1)?,
-            })
-        })?;
+        state.check_app_lock(&conn)?;
+        let category = categories::add_subcategory(&conn, &parent_id, &name)?;
+        state.prepare_target_dirs(&conn)?;
+        Ok(category)
+    }
+
+    /// Renames category `id` and its folder. Returns the new folder.
+    #[tauri::command]
+    pub async fn rename_category(id: String, new_name: String, state: State<'_, AppState>) -> Result<String, Error> {
+        let mut conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let fs = state.filesystem(&conn)?;
+        let renamed = categories::rename(&mut conn, &*fs, &id, &new_name)?;
+        tracing::info!("Renamed category {} to {}", id, renamed.display());
+        state.invalidate_mappings();
+        state.write_config_file(&conn)?;
+        state.prepare_target_dirs(&conn)?;
+        Ok(renamed.display().to_string())
+    }
 
-        let mut result = Vec::new();
-        for mapping in mappings {
-            result.push(mapping?);
+    /// Folds category `from` into `to`, repointing its mappings and rules.
+    /// With `consolidate`, the entries in its folders are moved into the
+    /// matching folders of `to`, names that are taken getting a suffix as in
+    /// any sort, and the emptied folders are removed.
+    #[tauri::command]
+    pub async fn merge_categories(
+        from: String,
+        to: String,
+        consolidate: Option<bool>,
+        window: Window,
+        state: State<'_, AppState>,
+    ) -> Result<Option<SortResult>, Error> {
+        let mut conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let merged = categories::merge(&mut conn, &from, &to)?;
+        tracing::info!("Merged category {} into {}, {} mappings and rules changed", from, to, merged.changed);
+        state.invalidate_mappings();
+        state.write_config_file(&conn)?;
+        state.prepare_target_dirs(&conn)?;
+        if !consolidate.unwrap_or(false) {
+            return Ok(None);
         }
-        println!("Found {} mappings", result.len());
-        Ok(result)
+
+        let events = WindowEvents(window);
+        let ctx = state.sort_context(&conn, &events)?;
+        let decided_by = format!("category {} merged into {}", from, to);
+        categories::consolidate(&conn, &ctx, &merged, &decided_by).map(Some)
+    }
+
+    /// Creates every folder a mapping or rule sends entries to, so the sorted
+    /// tree exists before the first sort.
+    #[tauri::command]
+    pub async fn create_all_target_dirs(state: State<'_, AppState>) -> Result<TargetDirsReport, Error> {
+        let conn = state.db.lock().unwrap();
+        let settings = Settings::load(&conn)?;
+        Ok(create_target_dirs(&*state.filesystem(&conn)?, &*state.mappings(&conn)?, settings.folder_icons))
     }
 
+    /// Checks every mapping and rule for conflicts, unusable targets and
+    /// things that are switched off.
     #[tauri::command]
-    pub async fn scan_and_sort(state: State<'_, AppState>) -> Result<SortResult, Error> {
+    pub async fn check_mappings_health(state: State<'_, AppState>) -> Result<health::HealthReport, Error> {
         let desktop_path = get_desktop_path()?;
-        let mut result = SortResult {
-            moved_files: Vec::new(),
-            errors: Vec::new(),
-        };
+        let conn = state.db.lock().unwrap();
+        health::check(&conn, &desktop_path, &desktop_path.join("Sorted"))
+    }
+
+    /// Changes how much the scan, match and move phases log until the app
+    /// quits, e.g. `debug` to see why files went where they did.
+    #[tauri::command]
+    pub async fn set_log_level(level: String) -> Result<(), Error> {
+        logging::set_level(&level)
+    }
 
+    /// Disables every mapping and rule `find_orphaned_mappings` reports, so
+    /// sorts leave their entries alone instead of failing on them.
+    #[tauri::command]
+    pub async fn disable_orphaned_mappings(state: State<'_, AppState>) -> Result<usize, Error> {
         let conn = state.db.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT target_path FROM path_mappings WHERE extension = ?")?;
-
-        for entry in WalkDir::new(&desktop_path)
-            .min_depth(1)
-            .max_depth(1)
-            .into_iter()
-            .filter_entry(|e| {
-                !e.file_name()
-                    .to_str()
-                    .map(|s| s.starts_with('.'))
-                    .unwrap_or(false)
-            })
-        {
-            let entry = match entry {
-                Ok(entry) => entry,
-                Err(e) => {
-                    result.errors.push(format!("Failed to read entry: {}", e));
-                    continue;
-                }
-            };
-
-            let path = entry.path();
-            let extension = if path.is_dir() {
-                String::from("folder")
-            } else {
-                path.extension()
-                    .and_then(|e| e.to_str())
-                    .map(|e| format!(".{}", e.to_lowercase()))
-                    .unwrap_or_default()
-            };
-
-            let mut rows = stmt.query(params![extension])?;
-            if let Some(row) = rows.next()? {
-                let target_dir: String = row.get(0)?;
-                let target_dir = PathBuf::from(target_dir);
-
-                ensure_dir_exists(&target_dir)
-                    .with_context(|| {
-                        format!(
-                            "Failed to create target directory: {}",
-                            target_dir.display()
-                        )
-                    })
-                    .map_err(|e| {
-                        result.errors.push(e.to_string());
-                        return;
-                    })
-                    .ok();
-
-                let file_name = path.file_name().unwrap();
-                let target_path = target_dir.join(file_name);
-                let mut counter = 1;
-                let mut final_path = target_path.clone();
-
-                while final_path.exists() {
-                    let file_stem = target_path.file_stem().unwrap().to_str().unwrap();
-                    let extension = target_path
-                        .extension()
-                        .map(|ext| format!(".{}", ext.to_str().unwrap()))
-                        .unwrap_or_default();
-                    final_path = target_dir.join(format!("{}_{}{}", file_stem, counter, extension));
-                    counter += 1;
-                }
+        state.check_app_lock(&conn)?;
+        let found = orphans::find(&conn, &get_desktop_path()?.join("Sorted"))?;
+        let disabled = orphans::disable(&conn, &found)?;
+        tracing::info!("Disabled {} orphaned mappings and rules", disabled);
+        state.invalidate_mappings();
+        state.write_config_file(&conn)?;
+        Ok(disabled)
+    }
 
-                match fs::rename(path, &final_path) {
-                    Ok(_) => result.moved_files.push(format!(
-                        "Moved {} to {}",
-                        path.display(),
-                        final_path.display()
-                    )),
-                    Err(e) => result.errors.push(format!(
-                        "Failed to move {}: {}",
-                        path.display(),
-                        e
-                    )),
-                }
-            }
+    /// Moves every mapping and rule target below `from` to the same place below
+    /// `to`.
+    #[tauri::command]
+    pub async fn retarget_mappings(from: String, to: String, state: State<'_, AppState>) -> Result<usize, Error> {
+        let mut conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let changed = orphans::retarget(&mut conn, Path::new(&from), Path::new(&to))?;
+        tracing::info!("Retargeted {} mappings and rules from {} to {}", changed, from, to);
+        state.invalidate_mappings();
+        state.write_config_file(&conn)?;
+        state.prepare_target_dirs(&conn)?;
+        Ok(changed)
+    }
+
+    #[tauri::command]
+    pub async fn set_mapping_quota(
+        extension: String,
+        max_files: Option<u64>,
+        max_bytes: Option<u64>,
+        state: State<'_, AppState>,
+    ) -> Result<(), Error> {
+        tracing::info!("Setting quota for {}: files={:?} bytes={:?}", extension, max_files, max_bytes);
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        mappings::set_quota(&conn, &extension, max_files, max_bytes)?;
+        state.invalidate_mappings();
+        state.write_config_file(&conn)?;
+        Ok(())
+    }
+
+    #[tauri::command]
+    pub async fn set_mapping_description(
+        extension: String,
+        description: Option<String>,
+        state: State<'_, AppState>,
+    ) -> Result<(), Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        mappings::set_description(&conn, &extension, description.as_deref())?;
+        state.write_config_file(&conn)
+    }
+
+    /// Limits the mapping of `extension` to files of at least `min_size` and
+    /// at most `max_size` bytes, e.g. only `.log` files over 10 MB. Others
+    /// are left where they are as if it had no mapping.
+    #[tauri::command]
+    pub async fn set_mapping_size_bounds(
+        extension: String,
+        min_size: Option<u64>,
+        max_size: Option<u64>,
+        state: State<'_, AppState>,
+    ) -> Result<(), Error> {
+        tracing::info!("Setting size bounds for {}: min={:?} max={:?}", extension, min_size, max_size);
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        mappings::set_size_bounds(&conn, &extension, min_size, max_size)?;
+        state.invalidate_mappings();
+        state.write_config_file(&conn)?;
+        Ok(())
+    }
+
+    /// Scans the sources for file types no rule or mapping handles, most
+    /// common first.
+    #[tauri::command]
+    pub async fn list_unmatched_extensions(state: State<'_, AppState>) -> Result<Vec<UnmatchedExtension>, Error> {
+        let conn = state.db.lock().unwrap();
+        unmatched_extensions(&conn, &state.filesystem(&conn)?, &*state.mappings(&conn)?)
+    }
+
+    /// Scans the sources and suggests mappings for the extensions that no rule
+    /// or mapping handles yet.
+    #[tauri::command]
+    pub async fn get_mapping_suggestions(state: State<'_, AppState>) -> Result<Vec<MappingSuggestion>, Error> {
+        let desktop_path = get_desktop_path()?;
+        let conn = state.db.lock().unwrap();
+        let fs = state.filesystem(&conn)?;
+        suggestions::for_sources(&conn, &fs, &*state.mappings(&conn)?, &desktop_path.join("Sorted"))
+    }
+
+    #[tauri::command]
+    pub async fn get_all_mappings(
+        query: Option<MappingQuery>,
+        state: State<'_, AppState>,
+    ) -> Result<MappingPage, Error> {
+        tracing::info!("Getting all mappings...");
+        let conn = state.db.lock().unwrap();
+        mappings::page(&conn, &query.unwrap_or_default())
+    }
+
+    /// The built-in groups like `@images` a mapping or rule can name.
+    #[tauri::command]
+    pub async fn get_extension_groups() -> Vec<extension_groups::ExtensionGroup> {
+        extension_groups::all()
+    }
+
+    /// Mappings matching `text` by extension, target folder, category or
+    /// note, best first.
+    #[tauri::command]
+    pub async fn search_mappings(
+        text: String,
+        limit: Option<usize>,
+        state: State<'_, AppState>,
+    ) -> Result<Vec<mappings::MappingMatch>, Error> {
+        mappings::search(&state.db.lock().unwrap(), &text, limit)
+    }
+
+    #[tauri::command]
+    pub async fn get_rules(state: State<'_, AppState>) -> Result<Vec<Rule>, Error> {
+        let conn = state.db.lock().unwrap();
+        rules::load_rules(&conn)
+    }
+
+    /// Creates `rule`, or replaces the stored one when it carries an id.
+    #[tauri::command]
+    pub async fn save_rule(rule: Rule, state: State<'_, AppState>) -> Result<Rule, Error> {
+        tracing::info!("Saving rule: {} -> {}", rule.name, rule.target_path);
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let rule = rules::save_rule(&conn, rule)?;
+        state.invalidate_mappings();
+        state.prepare_target_dirs(&conn)?;
+        Ok(rule)
+    }
+
+    #[tauri::command]
+    pub async fn delete_rule(id: i64, state: State<'_, AppState>) -> Result<bool, Error> {
+        tracing::info!("Deleting rule {}", id);
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let deleted = rules::delete_rule(&conn, id)?;
+        state.invalidate_mappings();
+        Ok(deleted)
+    }
+
+    #[tauri::command]
+    pub async fn get_rule_groups(state: State<'_, AppState>) -> Result<Vec<RuleGroup>, Error> {
+        let conn = state.db.lock().unwrap();
+        rules::load_groups(&conn)
+    }
+
+    /// Creates `group`, or updates the stored one when it carries an id.
+    #[tauri::command]
+    pub async fn save_rule_group(group: RuleGroup, state: State<'_, AppState>) -> Result<RuleGroup, Error> {
+        tracing::info!("Saving rule group: {}", group.name);
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let group = rules::save_group(&conn, group)?;
+        state.invalidate_mappings();
+        Ok(group)
+    }
+
+    /// Switches a whole group of rules on or off; flips it when `enabled` is
+    /// omitted. Returns whether the group is now enabled.
+    #[tauri::command]
+    pub async fn toggle_rule_group(
+        id: i64,
+        enabled: Option<bool>,
+        state: State<'_, AppState>,
+    ) -> Result<bool, Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let enabled = rules::toggle_group(&conn, id, enabled)?;
+        tracing::info!("Rule group {} is now {}", id, if enabled { "enabled" } else { "disabled" });
+        state.invalidate_mappings();
+        Ok(enabled)
+    }
+
+    /// Deletes a group; its rules stay, ungrouped.
+    #[tauri::command]
+    pub async fn delete_rule_group(id: i64, state: State<'_, AppState>) -> Result<bool, Error> {
+        tracing::info!("Deleting rule group {}", id);
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let deleted = rules::delete_group(&conn, id)?;
+        state.invalidate_mappings();
+        Ok(deleted)
+    }
+
+    #[tauri::command]
+    pub async fn scan_and_sort(window: Window, state: State<'_, AppState>) -> Result<SortResult, Error> {
+        let conn = state.db.lock().unwrap();
+        let events = WindowEvents(window);
+        sort_sources(&conn, &state.sort_context(&conn, &events)?, history::Trigger::Manual)
+    }
+
+    /// Re-applies the current rules and mappings to everything already
+    /// sorted and moves what now belongs elsewhere, e.g. screenshots once a
+    /// Screenshots subcategory exists. With `dry_run` the moves are only
+    /// listed.
+    #[tauri::command]
+    pub async fn resort_library(
+        dry_run: bool,
+        window: Window,
+        state: State<'_, AppState>,
+    ) -> Result<library::Resort, Error> {
+        let conn = state.db.lock().unwrap();
+        if !dry_run {
+            state.check_app_lock(&conn)?;
         }
+        let events = WindowEvents(window);
+        library::resort(&conn, &state.sort_context(&conn, &events)?, dry_run)
+    }
+
+    /// The folders sorts take entries from, the desktop first.
+    #[tauri::command]
+    pub async fn get_sources(state: State<'_, AppState>) -> Result<Vec<sources::Source>, Error> {
+        let conn = state.db.lock().unwrap();
+        sources::load(&conn)
+    }
 
-        Ok(result)
+    /// Where the system puts the desktop and Downloads of the user.
+    #[tauri::command]
+    pub async fn get_source_paths() -> Result<desktop::SourcePaths, Error> {
+        Ok(desktop::source_paths())
     }
-}
 
-#[derive(Serialize)]
-pub struct SortResult {
-    moved_files: Vec<String>,
-    errors: Vec<String>,
-}
+    /// Sorts `path` too, one level deep like the desktop until its options
+    /// are changed.
+    #[tauri::command]
+    pub async fn add_source(path: String, state: State<'_, AppState>) -> Result<i64, Error> {
+        tracing::info!("Adding source {}", path);
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        sources::add(&conn, Path::new(&path))
+    }
 
-pub fn run() {
-    let db_path = get_db_path().expect("Failed to get database path");
-    let mut conn = Connection::open(db_path).expect("Failed to open database");
-    init_db(&mut conn).expect("Failed to initialize database");
+    /// Sets whether source `id` is sorted, how deep, whether hidden entries
+    /// are included, which names are left alone and which are the only ones
+    /// sorted.
+    #[tauri::command]
+    pub async fn update_source(
+        id: i64,
+        enabled: bool,
+        depth: u32,
+        include_hidden: bool,
+        ignore_patterns: Vec<String>,
+        include_patterns: Vec<String>,
+        state: State<'_, AppState>,
+    ) -> Result<(), Error> {
+        tracing::info!("Updating source {}", id);
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        sources::update(&conn, id, enabled, depth, include_hidden, &ignore_patterns, &include_patterns)
+    }
 
-    tauri::Builder::default()
-        .manage(AppState {
-            db: Mutex::new(conn),
+    #[tauri::command]
+    pub async fn remove_source(id: i64, state: State<'_, AppState>) -> Result<(), Error> {
+        tracing::info!("Removing source {}", id);
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        sources::remove(&conn, id)?;
+        state.invalidate_mappings();
+        Ok(())
+    }
+
+    #[tauri::command]
+    pub async fn get_source_mappings(state: State<'_, AppState>) -> Result<Vec<sources::SourceMapping>, Error> {
+        let conn = state.db.lock().unwrap();
+        sources::load_mappings(&conn)
+    }
+
+    /// Sends `extension` entries of source `source_id` to `target_path`,
+    /// ahead of every rule and the extension's own mapping.
+    #[tauri::command]
+    pub async fn set_source_mapping(
+        source_id: i64,
+        extension: String,
+        target_path: String,
+        state: State<'_, AppState>,
+    ) -> Result<(), Error> {
+        tracing::info!("Setting source {} mapping: {} -> {}", source_id, extension, target_path);
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        sources::set_mapping(&conn, source_id, &extension, &target_path)?;
+        state.invalidate_mappings();
+        state.prepare_target_dirs(&conn)
+    }
+
+    #[tauri::command]
+    pub async fn remove_source_mapping(
+        source_id: i64,
+        extension: String,
+        state: State<'_, AppState>,
+    ) -> Result<bool, Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let removed = sources::remove_mapping(&conn, source_id, &extension)?;
+        state.invalidate_mappings();
+        Ok(removed)
+    }
+
+    #[tauri::command]
+    pub async fn get_mime_mappings(state: State<'_, AppState>) -> Result<Vec<mime::MimeMapping>, Error> {
+        let conn = state.db.lock().unwrap();
+        mime::load(&conn)
+    }
+
+    /// Sends files whose content says they are `mime_type`, a type like
+    /// `application/pdf` or a family like `image/*`, to `target_path` when
+    /// no extension mapping takes them. Returns the type as stored.
+    #[tauri::command]
+    pub async fn set_mime_mapping(
+        mime_type: String,
+        target_path: String,
+        state: State<'_, AppState>,
+    ) -> Result<String, Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let mime_type = mime::save(&conn, &mime_type, &target_path)?;
+        state.invalidate_mappings();
+        state.prepare_target_dirs(&conn)?;
+        Ok(mime_type)
+    }
+
+    #[tauri::command]
+    pub async fn remove_mime_mapping(mime_type: String, state: State<'_, AppState>) -> Result<bool, Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let removed = mime::remove(&conn, &mime_type)?;
+        state.invalidate_mappings();
+        Ok(removed)
+    }
+
+    /// Takes the entry history entry `history_id` sent to the trash back to
+    /// where it was, under a new name if that is taken. Only possible where
+    /// the platform said where the entry went, so not on Windows. Returns the
+    /// restored path.
+    #[tauri::command]
+    pub async fn restore_trashed(history_id: i64, state: State<'_, AppState>) -> Result<String, Error> {
+        let conn = state.db.lock().unwrap();
+        Ok(trash::restore_recorded(&conn, &*state.filesystem(&conn)?, history_id)?.display().to_string())
+    }
+
+    /// Files in the target folders with identical contents, each group with
+    /// the file to `keep` and the rest suggested for deletion. The folders
+    /// are hashed without holding the database.
+    #[tauri::command]
+    pub async fn find_duplicates(
+        keep: duplicates::Keep,
+        state: State<'_, AppState>,
+    ) -> Result<Vec<duplicates::DuplicateGroup>, Error> {
+        let mappings = state.mappings(&state.db.lock().unwrap())?;
+        Ok(duplicates::find_in_targets(&mappings, keep))
+    }
+
+    /// Symlinks and `.lnk` shortcuts in the sources and targets whose
+    /// destination is gone. Nothing is removed until the user confirms the
+    /// plan through `trash_broken_links`.
+    #[tauri::command]
+    pub async fn find_broken_links(state: State<'_, AppState>) -> Result<shortcuts::CleanupPlan, Error> {
+        let conn = state.db.lock().unwrap();
+        shortcuts::find_broken(&conn, &*state.mappings(&conn)?)
+    }
+
+    /// Moves the broken links the user confirmed to the trash.
+    #[tauri::command]
+    pub async fn trash_broken_links(
+        paths: Vec<PathBuf>,
+        state: State<'_, AppState>,
+    ) -> Result<shortcuts::LinkCleanup, Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        shortcuts::trash(&conn, trash::disposal(&Settings::load(&conn)?), &paths)
+    }
+
+    /// Photos and screenshots in the Images folder that look alike, like
+    /// resized or re-saved copies, for the user to review. Decoded without
+    /// holding the database.
+    #[tauri::command]
+    pub async fn find_similar_images(state: State<'_, AppState>) -> Result<Vec<duplicates::SimilarImages>, Error> {
+        let Some(dir) = categories::target_path(&state.db.lock().unwrap(), "images")? else {
+            return Ok(Vec::new());
+        };
+        Ok(duplicates::find_similar_images(Path::new(&dir), duplicates::SIMILARITY_THRESHOLD))
+    }
+
+    /// What was sorted, organized, trashed and failed over the past seven
+    /// days, with the top categories and the streak of weeks with sorting.
+    #[tauri::command]
+    pub async fn get_weekly_report(state: State<'_, AppState>) -> Result<report::WeeklyReport, Error> {
+        report::past_week(&state.db.lock().unwrap())
+    }
+
+    /// Desktop entries no rule or mapping handles that have been there for
+    /// longer than the reminder setting allows, longest first. Empty while
+    /// reminders are off.
+    #[tauri::command]
+    pub async fn get_stale_files(state: State<'_, AppState>) -> Result<Vec<stale::StaleFile>, Error> {
+        let conn = state.db.lock().unwrap();
+        let Some(after_days) = Settings::load(&conn)?.stale_after_days else {
+            return Ok(Vec::new());
+        };
+        stale::track(&conn, &*state.mappings(&conn)?, after_days)
+    }
+
+    /// Moves the stale entries the user picked into the archive folder.
+    #[tauri::command]
+    pub async fn archive_stale_files(
+        paths: Vec<PathBuf>,
+        window: Window,
+        state: State<'_, AppState>,
+    ) -> Result<SortResult, Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let events = WindowEvents(window);
+        stale::archive(&conn, &state.sort_context(&conn, &events)?, &paths)
+    }
+
+    /// Moves the duplicates the user picked to the trash, leaving files that
+    /// changed since `find_duplicates` alone.
+    #[tauri::command]
+    pub async fn delete_duplicates(
+        deletions: Vec<duplicates::Deletion>,
+        state: State<'_, AppState>,
+    ) -> Result<duplicates::Cleanup, Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let fs = state.filesystem(&conn)?;
+        duplicates::delete(&conn, &*fs, trash::disposal(&Settings::load(&conn)?), &deletions)
+    }
+
+    /// First step of a sort with a grace period: moves what a sort would move
+    /// into a hidden staging folder on the desktop instead of its targets.
+    /// `commit_sort` finishes the moves and `abort_sort` puts everything back.
+    #[tauri::command]
+    pub async fn stage_sort(window: Window, state: State<'_, AppState>) -> Result<SortResult, Error> {
+        let conn = state.db.lock().unwrap();
+        let events = WindowEvents(window);
+        sort_sources(&conn, &state.sort_context(&conn, &events)?, history::Trigger::Staged)
+    }
+
+    /// Entries waiting in the staging folder.
+    #[tauri::command]
+    pub async fn get_staged_sort(state: State<'_, AppState>) -> Result<Vec<staging::StagedMove>, Error> {
+        let conn = state.db.lock().unwrap();
+        staging::prune(&conn, &*state.filesystem(&conn)?, &get_desktop_path()?)?;
+        staging::load(&conn)
+    }
+
+    /// Moves the staged entries on to the targets they were staged for.
+    /// Entries that fail stay staged.
+    #[tauri::command]
+    pub async fn commit_sort(window: Window, state: State<'_, AppState>) -> Result<SortResult, Error> {
+        let desktop_path = get_desktop_path()?;
+        let conn = state.db.lock().unwrap();
+        let events = WindowEvents(window);
+        staging::commit(&conn, &state.sort_context(&conn, &events)?, &desktop_path)
+    }
+
+    /// Puts every staged entry back on the desktop.
+    #[tauri::command]
+    pub async fn abort_sort(state: State<'_, AppState>) -> Result<SortResult, Error> {
+        let desktop_path = get_desktop_path()?;
+        let conn = state.db.lock().unwrap();
+        staging::abort(&conn, &*state.filesystem(&conn)?, &desktop_path)
+    }
+
+    /// Moves a sort planned while `approve_moves` is on, waiting for the
+    /// user's decision.
+    #[tauri::command]
+    pub async fn get_pending_moves(state: State<'_, AppState>) -> Result<Vec<approvals::PendingMove>, Error> {
+        let conn = state.db.lock().unwrap();
+        approvals::prune(&conn)?;
+        approvals::load(&conn)
+    }
+
+    /// Carries out the queued moves `ids`, to the targets they were proposed
+    /// with.
+    #[tauri::command]
+    pub async fn approve_moves(ids: Vec<i64>, window: Window, state: State<'_, AppState>) -> Result<SortResult, Error> {
+        let conn = state.db.lock().unwrap();
+        let events = WindowEvents(window);
+        approvals::approve(&conn, &state.sort_context(&conn, &events)?, &ids)
+    }
+
+    /// Turns down the queued moves `ids`. The entries stay where they are and
+    /// aren't proposed again for the same target.
+    #[tauri::command]
+    pub async fn reject_moves(ids: Vec<i64>, state: State<'_, AppState>) -> Result<usize, Error> {
+        let conn = state.db.lock().unwrap();
+        let rejected = approvals::reject(&conn, &ids)?;
+        tracing::info!("Rejected {} moves", rejected);
+        Ok(rejected)
+    }
+
+    /// Entries whose move failed, with why and how often it was tried.
+    #[tauri::command]
+    pub async fn get_retry_queue(state: State<'_, AppState>) -> Result<Vec<retries::PendingRetry>, Error> {
+        let conn = state.db.lock().unwrap();
+        retries::prune(&conn)?;
+        retries::load(&conn)
+    }
+
+    /// Tries every queued failed move again, whatever the reason it failed.
+    /// Entries go wherever the current rules and mappings send them.
+    #[tauri::command]
+    pub async fn retry_failed_moves(window: Window, state: State<'_, AppState>) -> Result<SortResult, Error> {
+        let conn = state.db.lock().unwrap();
+        retries::prune(&conn)?;
+        let sources = retries::sources(&conn)?;
+        tracing::info!("Retrying {} failed moves", sources.len());
+        let events = WindowEvents(window);
+        sort_paths(&conn, &state.sort_context(&conn, &events)?, sources, history::Trigger::Retry)
+    }
+
+    /// Gives up on every queued failed move. The entries stay where they are.
+    #[tauri::command]
+    pub async fn clear_retry_queue(state: State<'_, AppState>) -> Result<usize, Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let cleared = retries::clear(&conn)?;
+        tracing::info!("Cleared {} entries from the retry queue", cleared);
+        Ok(cleared)
+    }
+
+    /// Diagnostic: times scanning, matching and moving `file_count` generated
+    /// files in a scratch folder. Nothing outside that folder is touched.
+    #[tauri::command]
+    pub async fn benchmark_sort(
+        file_count: usize,
+        file_size: Option<u64>,
+        state: State<'_, AppState>,
+    ) -> Result<benchmark::BenchmarkResult, Error> {
+        tracing::info!("Running sort benchmark with {} files...", file_count);
+        let conn = state.db.lock().unwrap();
+        benchmark::run(&conn, file_count, file_size.unwrap_or(1024))
+    }
+
+    #[tauri::command]
+    pub async fn get_settings(state: State<'_, AppState>) -> Result<Settings, Error> {
+        let conn = state.db.lock().unwrap();
+        Settings::load(&conn)
+    }
+
+    #[tauri::command]
+    pub async fn update_settings(
+        changes: serde_json::Map<String, serde_json::Value>,
+        app: tauri::AppHandle,
+        state: State<'_, AppState>,
+    ) -> Result<Settings, Error> {
+        tracing::info!("Updating settings: {:?}", changes);
+        let mut conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let previous = Settings::load(&conn)?;
+        let settings = Settings::update(&conn, changes)?;
+        rename_default_folders(&conn, &previous, &settings)?;
+        state.invalidate_mappings();
+        state.apply_learning(&settings);
+        // Switching to the TOML backend may load settings from an existing file
+        state.apply_config_backend(&app, &mut conn, &settings)?;
+        state.write_config_file(&conn)?;
+        state.prepare_target_dirs(&conn)?;
+        Settings::load(&conn)
+    }
+
+    /// Sorts `path` as the desktop, for a desktop elsewhere than the system
+    /// says or none at all, or the system's desktop again when unset.
+    #[tauri::command]
+    pub async fn set_desktop_override(
+        path: Option<String>,
+        app: tauri::AppHandle,
+        state: State<'_, AppState>,
+    ) -> Result<Settings, Error> {
+        let path = path.filter(|p| !p.trim().is_empty());
+        if let Some(path) = &path {
+            desktop::validate_override(Path::new(path))?;
+        }
+        let mut changes = serde_json::Map::new();
+        changes.insert("desktop_override".to_string(), path.into());
+        update_settings(changes, app, state).await
+    }
+
+    /// The latest `limit` moves, queued items and errors across all sorts,
+    /// newest first.
+    #[tauri::command]
+    pub async fn get_recent_activity(limit: usize, state: State<'_, AppState>) -> Result<Vec<history::Activity>, Error> {
+        let conn = state.db.lock().unwrap();
+        history::recent(&conn, limit, Settings::load(&conn)?.history_keep_days)
+    }
+
+    /// Deletes the sorts older or further back than the history is kept
+    /// for. Returns how many were deleted.
+    #[tauri::command]
+    pub async fn prune_history(state: State<'_, AppState>) -> Result<usize, Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        let settings = Settings::load(&conn)?;
+        history::prune(&conn, settings.history_keep_days, settings.history_keep_sessions)
+    }
+
+    /// The last `desksort://` link not yet handled by the UI, if any.
+    #[tauri::command]
+    pub async fn take_pending_link(state: State<'_, AppState>) -> Result<Option<deeplink::DeepLink>, Error> {
+        Ok(state.pending_link.lock().unwrap().take())
+    }
+
+    /// The last reminder of stale files not yet shown by the UI, if any.
+    #[tauri::command]
+    pub async fn take_stale_reminder(state: State<'_, AppState>) -> Result<Option<Message>, Error> {
+        Ok(state.pending_stale_reminder.lock().unwrap().take())
+    }
+
+    /// Summary of the last weekly report not yet shown by the UI, if any.
+    #[tauri::command]
+    pub async fn take_weekly_report(state: State<'_, AppState>) -> Result<Option<Message>, Error> {
+        Ok(state.pending_weekly_report.lock().unwrap().take())
+    }
+
+    /// Writes mappings, rules, settings and learned moves to `path` as one JSON file.
+    #[tauri::command]
+    pub async fn export_database(path: String, state: State<'_, AppState>) -> Result<(), Error> {
+        tracing::info!("Exporting database to {}", path);
+        let conn = state.db.lock().unwrap();
+        archive::write(&conn, Path::new(&path))
+    }
+
+    /// Writes the history within `date_range`, all of it by default, to `path`
+    /// as CSV: when, what, from and to where, how large, which rule decided
+    /// and whether it was simulated.
+    /// Returns how many entries it wrote.
+    #[tauri::command]
+    pub async fn export_history_csv(
+        path: String,
+        date_range: Option<history::DateRange>,
+        state: State<'_, AppState>,
+    ) -> Result<usize, Error> {
+        tracing::info!("Exporting history to {}", path);
+        let mut out = std::io::BufWriter::new(fs::File::create(&path)?);
+        let conn = state.db.lock().unwrap();
+        let written = history::export_csv(&conn, &mut out, &date_range.unwrap_or_default())?;
+        std::io::Write::flush(&mut out)?;
+        Ok(written)
+    }
+
+    /// Replaces the configuration with the export at `path`. With `dry_run`
+    /// nothing is written and the report only lists what would change.
+    #[tauri::command]
+    pub async fn import_database(
+        path: String,
+        dry_run: bool,
+        state: State<'_, AppState>,
+    ) -> Result<archive::ImportReport, Error> {
+        tracing::info!("Importing database from {} (dry run: {})", path, dry_run);
+        let archive = archive::read(Path::new(&path))?;
+
+        let mut conn = state.db.lock().unwrap();
+        if !dry_run {
+            state.check_app_lock(&conn)?;
+        }
+        let report = archive::import(&mut conn, &archive, dry_run)?;
+        if !dry_run {
+            state.invalidate_mappings();
+            state.apply_learning(&Settings::load(&conn)?);
+            state.write_config_file(&conn)?;
+            state.prepare_target_dirs(&conn)?;
+        }
+        Ok(report)
+    }
+
+    /// Merges mappings and rules from `source` into the local ones by
+    /// `strategy`, or with `dry_run` only reports what that would do.
+    #[tauri::command]
+    pub async fn import_ruleset(
+        source: ruleset::ImportSource,
+        strategy: ruleset::Strategy,
+        resolutions: Vec<ruleset::Resolution>,
+        dry_run: bool,
+        state: State<'_, AppState>,
+    ) -> Result<ruleset::Changes, Error> {
+        let language = Settings::load(&state.db.lock().unwrap())?.folder_language();
+        // Fetched without holding the database
+        let imported = ruleset::load(&source, language)?;
+
+        let conn = state.db.lock().unwrap();
+        if !dry_run {
+            state.check_app_lock(&conn)?;
+        }
+        let changes = ruleset::apply(&conn, &imported, strategy, &resolutions, dry_run)?;
+        if !dry_run && !changes.is_empty() {
+            state.invalidate_mappings();
+            state.write_config_file(&conn)?;
+            state.prepare_target_dirs(&conn)?;
+        }
+        Ok(changes)
+    }
+
+    /// Saves the local setup as a ruleset file, usually `.desksortrules`,
+    /// described by `metadata`.
+    #[tauri::command]
+    pub async fn export_ruleset(
+        path: String,
+        metadata: Option<ruleset::Metadata>,
+        state: State<'_, AppState>,
+    ) -> Result<(), Error> {
+        ruleset::export(&state.db.lock().unwrap(), Path::new(&path), metadata.unwrap_or_default())
+    }
+
+    #[tauri::command]
+    pub async fn get_ruleset_subscriptions(state: State<'_, AppState>) -> Result<Vec<ruleset::Subscription>, Error> {
+        ruleset::subscriptions(&state.db.lock().unwrap())
+    }
+
+    /// Subscribes to a ruleset URL, or changes a subscription, and fetches
+    /// it right away.
+    #[tauri::command]
+    pub async fn subscribe_to_ruleset(
+        subscription: ruleset::Subscription,
+        state: State<'_, AppState>,
+        window: Window,
+    ) -> Result<ruleset::Refresh, Error> {
+        let subscription = {
+            let conn = state.db.lock().unwrap();
+            state.check_app_lock(&conn)?;
+            ruleset::subscribe(&conn, subscription)?
+        };
+        let mut refreshes = refresh_rulesets(&window.app_handle(), subscription.id)?;
+        refreshes
+            .pop()
+            .ok_or_else(|| Error::InvalidRuleset("subscription is gone".to_string()))
+    }
+
+    /// Stops fetching a ruleset; the mappings and rules it brought in stay.
+    #[tauri::command]
+    pub async fn unsubscribe_from_ruleset(id: i64, state: State<'_, AppState>) -> Result<bool, Error> {
+        let conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        ruleset::unsubscribe(&conn, id)
+    }
+
+    /// Fetches every subscribed ruleset now rather than on the next hourly
+    /// check.
+    #[tauri::command]
+    pub async fn refresh_ruleset_subscriptions(
+        state: State<'_, AppState>,
+        window: Window,
+    ) -> Result<Vec<ruleset::Refresh>, Error> {
+        state.check_app_lock(&state.db.lock().unwrap())?;
+        refresh_rulesets(&window.app_handle(), None)
+    }
+
+    /// Syncs with the team's shared ruleset now, settling the conflicts the
+    /// last sync reported as `resolutions` say.
+    #[tauri::command]
+    pub async fn sync_team_ruleset(
+        resolutions: Vec<ruleset::Resolution>,
+        state: State<'_, AppState>,
+        window: Window,
+    ) -> Result<Option<team_sync::SyncReport>, Error> {
+        state.check_app_lock(&state.db.lock().unwrap())?;
+        sync_team_ruleset_with(&window.app_handle(), &resolutions)
+    }
+
+    /// Reveals the folder holding `settings.db` in the file manager, and
+    /// returns its path for the UI to show.
+    #[tauri::command]
+    pub async fn open_config_directory() -> Result<String, Error> {
+        let db_path = get_db_path()?;
+        let dir = db_path.parent().ok_or(Error::ConfigDirNotFound)?;
+        tracing::info!("Opening config directory {}", dir.display());
+        open_in_file_manager(dir)?;
+        Ok(dir.display().to_string())
+    }
+
+    #[tauri::command]
+    pub async fn get_database_status(state: State<'_, AppState>) -> Result<encryption::DatabaseStatus, Error> {
+        Ok(encryption::DatabaseStatus {
+            encryption_available: encryption::is_available(),
+            encrypted: encryption::is_encrypted(&get_db_path()?),
+            locked: state.locked.load(Ordering::SeqCst),
+        })
+    }
+
+    /// Opens the encrypted database with `passphrase`.
+    #[tauri::command]
+    pub async fn unlock_database(
+        passphrase: String,
+        app: tauri::AppHandle,
+        state: State<'_, AppState>,
+    ) -> Result<(), Error> {
+        let mut conn = encryption::unlock(&get_db_path()?, &passphrase)?;
+        configure_connection(&conn)?;
+        init_db(&mut conn)?;
+        let settings = Settings::load(&conn)?;
+
+        let mut db = state.db.lock().unwrap();
+        *db = conn;
+        state.locked.store(false, Ordering::SeqCst);
+        state.invalidate_mappings();
+        state.apply_learning(&settings);
+        tracing::info!("Database unlocked");
+        state.apply_config_backend(&app, &mut db, &settings)?;
+        Ok(())
+    }
+
+    /// Encrypts the database with `passphrase`, changes the passphrase, or
+    /// decrypts the database when `passphrase` is unset.
+    #[tauri::command]
+    pub async fn set_database_passphrase(passphrase: Option<String>, state: State<'_, AppState>) -> Result<(), Error> {
+        if state.locked.load(Ordering::SeqCst) {
+            return Err(Error::Encryption("unlock the database first".to_string()));
+        }
+        let passphrase = passphrase.filter(|p| !p.is_empty());
+        let db_path = get_db_path()?;
+        let replacement = db_path.with_extension("db.new");
+
+        let mut conn = state.db.lock().unwrap();
+        state.check_app_lock(&conn)?;
+        encryption::export(&conn, &replacement, passphrase.as_deref())?;
+
+        // Every connection to the old file has to go before it is replaced
+        *state.move_watcher.lock().unwrap() = None;
+        *conn = Connection::open_in_memory()?;
+        encryption::replace_file(&replacement, &db_path)?;
+        encryption::set_passphrase(passphrase.clone());
+
+        let reopened = Connection::open(&db_path)?;
+        configure_connection(&reopened)?;
+        *conn = reopened;
+        state.apply_learning(&Settings::load(&conn)?);
+        tracing::info!(
+            "Database is now {}",
+            if passphrase.is_some() { "encrypted" } else { "unencrypted" }
+        );
+        Ok(())
+    }
+
+    #[tauri::command]
+    pub async fn get_app_lock_status(state: State<'_, AppState>) -> Result<app_lock::AppLockStatus, Error> {
+        let conn = state.db.lock().unwrap();
+        Ok(app_lock::AppLockStatus {
+            enabled: Settings::load(&conn)?.app_lock.is_some(),
+            unlocked: state.is_unlocked(),
+        })
+    }
+
+    /// Lets changes through for a while if `pin` is the app lock's.
+    #[tauri::command]
+    pub async fn unlock_app(pin: String, state: State<'_, AppState>) -> Result<(), Error> {
+        let conn = state.db.lock().unwrap();
+        app_lock::check(&conn, &pin)?;
+        *state.unlocked_until.lock().unwrap() = Some(Instant::now() + UNLOCK_DURATION);
+        Ok(())
+    }
+
+    /// Requires the PIN again right away.
+    #[tauri::command]
+    pub async fn lock_app(state: State<'_, AppState>) -> Result<(), Error> {
+        *state.unlocked_until.lock().unwrap() = None;
+        Ok(())
+    }
+
+    /// Guards changes with `pin`, or drops the guard when unset. A lock that
+    /// is already set takes its `current_pin`.
+    #[tauri::command]
+    pub async fn set_app_lock(
+        current_pin: Option<String>,
+        pin: Option<String>,
+        state: State<'_, AppState>,
+    ) -> Result<(), Error> {
+        let pin = pin.filter(|p| !p.is_empty());
+        let conn = state.db.lock().unwrap();
+        app_lock::set(&conn, current_pin.as_deref(), pin.as_deref())?;
+        *state.unlocked_until.lock().unwrap() = None;
+        tracing::info!("App lock {}", if pin.is_some() { "set" } else { "removed" });
+        Ok(())
+    }
+
+    #[tauri::command]
+    pub async fn get_autostart() -> Result<bool, Error> {
+        autostart::is_enabled()
+    }
+
+    /// Registers DeskSort to start at login, or removes the registration.
+    #[tauri::command]
+    pub async fn set_autostart(enabled: bool, state: State<'_, AppState>) -> Result<(), Error> {
+        state.check_app_lock(&state.db.lock().unwrap())?;
+        tracing::info!("Setting autostart: {}", enabled);
+        autostart::set_enabled(enabled)
+    }
+
+    /// Mappings proposed from repeated manual moves into the sorted tree.
+    #[tauri::command]
+    pub async fn get_learned_mappings(state: State<'_, AppState>) -> Result<Vec<learning::LearnedMapping>, Error> {
+        let conn = state.db.lock().unwrap();
+        learning::learned_mappings(&conn)
+    }
+
+    /// Forgets the manual moves behind a proposal the user turned down.
+    #[tauri::command]
+    pub async fn dismiss_learned_mapping(
+        extension: String,
+        target_path: String,
+        state: State<'_, AppState>,
+    ) -> Result<(), Error> {
+        let conn = state.db.lock().unwrap();
+        learning::dismiss(&conn, &extension, &target_path)
+    }
+
+    /// Entries the last sort left alone because their matches disagree.
+    #[tauri::command]
+    pub async fn get_conflicts(state: State<'_, AppState>) -> Result<Vec<Conflict>, Error> {
+        let conn = state.db.lock().unwrap();
+        conflicts(&conn)
+    }
+
+    #[tauri::command]
+    pub async fn get_queued_moves(state: State<'_, AppState>) -> Result<Vec<QueuedMove>, Error> {
+        let conn = state.db.lock().unwrap();
+        queued_moves(&conn)
+    }
+}
+
+/// Maps `extension` to `target_path`, filed under `category` or the target's
+/// folder name.
+fn save_mapping(
+    conn: &Connection,
+    state: &AppState,
+    extension: &str,
+    target_path: &str,
+    category: Option<String>,
+) -> Result<(), Error> {
+    mappings::save(conn, extension, target_path, category)?;
+    state.invalidate_mappings();
+    state.write_config_file(conn)?;
+    state.prepare_target_dirs(conn)
+}
+
+/// Forwards the progress of a sort to the frontend as `sort-plan`,
+/// `sort-progress` and `file-progress` events.
+struct WindowEvents(Window);
+
+impl SortEvents for WindowEvents {
+    fn plan_ready(&self, totals: &PlanTotals) {
+        let _ = self.0.emit("sort-plan", totals);
+    }
+
+    fn progress(&self, progress: &SortProgress) {
+        let _ = self.0.emit("sort-progress", progress);
+    }
+
+    fn file_progress(&self, progress: &FileProgress) {
+        let _ = self.0.emit("file-progress", progress);
+    }
+}
+
+/// Brings the main window to the front.
+fn show_main_window(app: &tauri::AppHandle) {
+    if let Some(window) = app.get_window("main") {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+}
+
+/// Loads `desksort.toml` after it changed on disk. A file that doesn't parse
+/// leaves the current configuration in place.
+fn reload_config_file(app: &tauri::AppHandle) {
+    let state = app.state::<AppState>();
+    let mut conn = state.db.lock().unwrap();
+    match load_config_file(&mut conn) {
+        Ok(settings) => {
+            tracing::info!("Reloaded {}", config_file::FILE_NAME);
+            state.invalidate_mappings();
+            state.apply_learning(&settings);
+            if let Err(e) = state.prepare_target_dirs(&conn) {
+                tracing::warn!("Failed to create target folders: {}", e);
+            }
+            let _ = app.emit_all("config-reloaded", ());
+        }
+        Err(e) => {
+            tracing::warn!("Ignoring changes to {}: {}", config_file::FILE_NAME, e);
+            let _ = app.emit_all("config-error", e.to_string());
+        }
+    }
+}
+
+/// How often failed moves are retried in the background.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+const DAILY_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often the desktop is checked for stale files.
+const STALE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often the app checks whether a weekly report is due.
+const REPORT_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often subscribed rulesets are fetched and the team ruleset synced.
+const RULESET_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long changes go through after the app lock PIN was entered.
+const UNLOCK_DURATION: Duration = Duration::from_secs(10 * 60);
+
+/// Keeps the database from growing without bound. Runs at startup and once a
+/// day; failures are only logged.
+fn maintain_database(app: &tauri::AppHandle) {
+    let state = app.state::<AppState>();
+    if state.locked.load(Ordering::SeqCst) {
+        return;
+    }
+    let conn = state.db.lock().unwrap();
+    let pruned = Settings::load(&conn)
+        .and_then(|settings| history::prune(&conn, settings.history_keep_days, settings.history_keep_sessions));
+    match pruned {
+        Ok(0) => {}
+        Ok(pruned) => tracing::info!("Pruned {} sorts from the history", pruned),
+        Err(e) => tracing::error!("Failed to prune the history: {}", e),
+    }
+}
+
+/// Parks a reminder message for the UI and emits `stale-files` when entries
+/// have sat on the desktop unsorted for longer than the user allows, at most
+/// once a day. Called at startup and every `STALE_CHECK_INTERVAL`, so
+/// reminders don't depend on the app running for a whole day.
+fn remind_of_stale_files(app: &tauri::AppHandle) {
+    let state = app.state::<AppState>();
+    if state.locked.load(Ordering::SeqCst) {
+        return;
+    }
+    let conn = state.db.lock().unwrap();
+    let stale = Settings::load(&conn).and_then(|settings| {
+        let Some(after_days) = settings.stale_after_days else {
+            return Ok(None);
+        };
+        // Tracked on every check, so first sightings are accurate
+        let stale = stale::track(&conn, &*state.mappings(&conn)?, after_days)?;
+        if stale.is_empty() || !stale::is_reminder_due(&conn)? {
+            return Ok(None);
+        }
+        Ok(Some((stale, after_days)))
+    });
+    match stale {
+        Ok(Some((stale, after_days))) => {
+            let message = Message::new("stale.reminder")
+                .arg("count", stale.len())
+                .arg("days", after_days);
+            tracing::info!("{}", message);
+            // Parked as well as emitted, so a reminder due at startup is
+            // shown once the page has loaded
+            *state.pending_stale_reminder.lock().unwrap() = Some(message.clone());
+            let _ = app.emit_all("stale-files", message);
+            if let Err(e) = stale::mark_reminded(&conn) {
+                tracing::warn!("Failed to record the stale files reminder: {}", e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => tracing::error!("Failed to look for stale files: {}", e),
+    }
+}
+
+/// Parks the summary of the past week's report for the UI and emits
+/// `weekly-report` once a week has passed since the last one, when the user
+/// asked for them. Called at startup and every `REPORT_CHECK_INTERVAL`, so a
+/// report missed while the app wasn't running goes out on the next start.
+fn send_weekly_report(app: &tauri::AppHandle) {
+    let state = app.state::<AppState>();
+    if state.locked.load(Ordering::SeqCst) {
+        return;
+    }
+    let conn = state.db.lock().unwrap();
+    let report = Settings::load(&conn).and_then(|settings| {
+        if !settings.weekly_report || !report::is_due(&conn)? {
+            return Ok(None);
+        }
+        report::past_week(&conn).map(Some)
+    });
+    match report {
+        Ok(Some(report)) => {
+            tracing::info!("{}", report.summary());
+            // Parked as well as emitted, so a report due at startup is shown
+            // once the page has loaded
+            *state.pending_weekly_report.lock().unwrap() = Some(report.summary());
+            let _ = app.emit_all("weekly-report", report.summary());
+            if let Err(e) = report::mark_sent(&conn, report.to) {
+                tracing::warn!("Failed to record the weekly report: {}", e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => tracing::error!("Failed to put together the weekly report: {}", e),
+    }
+}
+
+/// Fetches the subscribed rulesets, or the one with `id`, without holding the
+/// database, then applies those that changed. Emits `rulesets-updated` with
+/// the outcomes when anything changed.
+fn refresh_rulesets(app: &tauri::AppHandle, id: Option<i64>) -> Result<Vec<ruleset::Refresh>, Error> {
+    let state = app.state::<AppState>();
+    let subscriptions = {
+        let conn = state.db.lock().unwrap();
+        match id {
+            Some(id) => ruleset::subscription(&conn, id)?.into_iter().collect(),
+            None => ruleset::subscriptions(&conn)?,
+        }
+    };
+    let fetched: Vec<_> = subscriptions
+        .into_iter()
+        .map(|subscription| {
+            let fetched = ruleset::fetch(&subscription);
+            (subscription, fetched)
+        })
+        .collect();
+
+    let conn = state.db.lock().unwrap();
+    let refreshes = fetched
+        .into_iter()
+        .map(|(subscription, fetched)| ruleset::update(&conn, &subscription, fetched))
+        .collect::<Result<Vec<_>, _>>()?;
+    if refreshes
+        .iter()
+        .any(|refresh| refresh.changes.as_ref().is_some_and(|changes| !changes.is_empty()))
+    {
+        state.invalidate_mappings();
+        state.write_config_file(&conn)?;
+        state.prepare_target_dirs(&conn)?;
+        let _ = app.emit_all("rulesets-updated", &refreshes);
+    }
+    Ok(refreshes)
+}
+
+/// Syncs with the shared ruleset in the settings, if there is one, fetching
+/// it without holding the database. Emits `team-ruleset-synced` with the
+/// report when anything was pulled or conflicts.
+fn sync_team_ruleset_with(
+    app: &tauri::AppHandle,
+    resolutions: &[ruleset::Resolution],
+) -> Result<Option<team_sync::SyncReport>, Error> {
+    let state = app.state::<AppState>();
+    let Some(location) = Settings::load(&state.db.lock().unwrap())?.team_ruleset else {
+        return Ok(None);
+    };
+    let remote = team_sync::fetch(&location)?;
+
+    let conn = state.db.lock().unwrap();
+    let report = team_sync::sync(&conn, &location, remote, resolutions)?;
+    if !report.pulled.is_empty() {
+        state.invalidate_mappings();
+        state.write_config_file(&conn)?;
+        state.prepare_target_dirs(&conn)?;
+    }
+    if !report.pulled.is_empty() || !report.conflicts.is_empty() {
+        let _ = app.emit_all("team-ruleset-synced", &report);
+    }
+    Ok(Some(report))
+}
+
+/// Retries queued moves that failed for a passing reason and haven't used up
+/// their attempts. Emits `retry-finished` with the session id when anything
+/// was tried.
+fn retry_due_moves(app: &tauri::AppHandle) {
+    let state = app.state::<AppState>();
+    if state.locked.load(Ordering::SeqCst) {
+        return;
+    }
+    let Some(window) = app.get_window("main") else {
+        return;
+    };
+    let conn = state.db.lock().unwrap();
+    let due = Settings::load(&conn).and_then(|settings| {
+        retries::prune(&conn)?;
+        match settings.retry_attempts {
+            0 => Ok(Vec::new()),
+            max_attempts => retries::due(&conn, max_attempts),
+        }
+    });
+    let due = match due {
+        Ok(due) => due,
+        Err(e) => {
+            tracing::error!("Failed to read the retry queue: {}", e);
+            return;
+        }
+    };
+    if due.is_empty() {
+        return;
+    }
+
+    tracing::info!("Retrying {} failed moves", due.len());
+    let events = WindowEvents(window);
+    let result = state
+        .sort_context(&conn, &events)
+        .and_then(|ctx| sort_paths(&conn, &ctx, due, history::Trigger::Retry));
+    match result {
+        Ok(result) => {
+            let _ = app.emit_all("retry-finished", result.session_id);
+        }
+        Err(e) => tracing::error!("Retrying failed moves failed: {}", e),
+    }
+}
+
+/// Carries out a request from the command line or a later launch.
+fn handle_request(app: &tauri::AppHandle, request: instance::Request) {
+    show_main_window(app);
+    let link = match request {
+        instance::Request::Activate => return,
+        instance::Request::SortNow => deeplink::DeepLink::Sort,
+        instance::Request::OpenUrl(url) => match deeplink::parse(&url) {
+            Ok(link) => link,
+            Err(e) => {
+                tracing::warn!("Ignoring {}: {}", url, e);
+                return;
+            }
+        },
+        instance::Request::OpenRules(path) => deeplink::DeepLink::ImportRules {
+            path: path.display().to_string(),
+        },
+    };
+
+    // Parked as well as emitted, so a link that started the app is picked up
+    // once the page has loaded
+    *app.state::<AppState>().pending_link.lock().unwrap() = Some(link.clone());
+    let _ = app.emit_all("deep-link", link);
+}
+
+pub fn run() {
+    logging::init();
+    let args: Vec<String> = std::env::args().collect();
+    let autostarted = args.iter().any(|arg| arg == autostart::AUTOSTART_ARG);
+
+    // Only one process may own the database and the watcher; later launches
+    // hand their request to it and quit
+    let primary = match instance::acquire() {
+        Ok(instance::Instance::Primary(primary)) => Some(primary),
+        Ok(instance::Instance::Secondary) => {
+            if !autostarted {
+                if let Err(e) = instance::forward(&instance::Request::from_args(&args)) {
+                    tracing::warn!("Failed to reach the running instance: {}", e);
+                }
+            }
+            return;
+        }
+        Err(e) => {
+            tracing::warn!("Single instance check failed, starting anyway: {}", e);
+            None
+        }
+    };
+
+    let db_path = get_db_path().expect("Failed to get database path");
+    // An encrypted database stays closed until the UI supplies the passphrase
+    let locked = encryption::is_encrypted(&db_path);
+    let conn = if locked {
+        tracing::info!("Database is encrypted, waiting for the passphrase");
+        Connection::open_in_memory().expect("Failed to open database")
+    } else {
+        let mut conn = Connection::open(db_path).expect("Failed to open database");
+        configure_connection(&conn).expect("Failed to configure database");
+        init_db(&mut conn).expect("Failed to initialize database");
+        conn
+    };
+    let settings = if locked {
+        Settings::default()
+    } else {
+        Settings::load(&conn).unwrap_or_default()
+    };
+
+    let state = AppState {
+        db: Mutex::new(conn),
+        mapping_cache: Mutex::new(None),
+        sort_activity: learning::SortActivity::default(),
+        move_watcher: Mutex::new(None),
+        pending_link: Mutex::new(None),
+        pending_stale_reminder: Mutex::new(None),
+        pending_weekly_report: Mutex::new(None),
+        locked: AtomicBool::new(locked),
+        config_watcher: Mutex::new(None),
+        folder_stats: folder_stats::StatsCache::default(),
+        unlocked_until: Mutex::new(None),
+    };
+    state.apply_learning(&settings);
+
+    tauri::Builder::default()
+        .manage(state)
+        .setup(move |app| {
+            // Launched at login: stay out of the way
+            if autostarted {
+                if let Some(window) = app.get_window("main") {
+                    let _ = window.minimize();
+                }
+            }
+
+            if !locked {
+                let state = app.state::<AppState>();
+                let mut conn = state.db.lock().unwrap();
+                if let Err(e) = state.apply_config_backend(&app.handle(), &mut conn, &settings) {
+                    tracing::error!("Failed to load {}: {}", config_file::FILE_NAME, e);
+                }
+            }
+
+            let handle = app.handle();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(RETRY_INTERVAL);
+                retry_due_moves(&handle);
+            });
+            let handle = app.handle();
+            std::thread::spawn(move || loop {
+                maintain_database(&handle);
+                std::thread::sleep(DAILY_INTERVAL);
+            });
+            let handle = app.handle();
+            std::thread::spawn(move || loop {
+                remind_of_stale_files(&handle);
+                std::thread::sleep(STALE_CHECK_INTERVAL);
+            });
+            let handle = app.handle();
+            std::thread::spawn(move || loop {
+                send_weekly_report(&handle);
+                std::thread::sleep(REPORT_CHECK_INTERVAL);
+            });
+            let handle = app.handle();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(RULESET_INTERVAL);
+                if handle.state::<AppState>().locked.load(Ordering::SeqCst) {
+                    continue;
+                }
+                if let Err(e) = refresh_rulesets(&handle, None) {
+                    tracing::error!("Failed to refresh rulesets: {}", e);
+                }
+                if let Err(e) = sync_team_ruleset_with(&handle, &[]) {
+                    tracing::error!("Failed to sync the team ruleset: {}", e);
+                }
+            });
+
+            if let Err(e) = deeplink::register() {
+                tracing::warn!("Failed to register {}:// links: {}", deeplink::SCHEME, e);
+            }
+
+            let request = instance::Request::from_args(&args);
+            if matches!(request, instance::Request::OpenUrl(_) | instance::Request::OpenRules(_)) {
+                handle_request(&app.handle(), request);
+            }
+            if let Some(primary) = primary {
+                let handle = app.handle();
+                primary.listen(move |request| handle_request(&handle, request));
+            }
+            Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             commands::scan_and_sort,
             commands::get_path_mapping,
             commands::set_path_mapping,
-            commands::get_all_mappings
+            commands::add_mapping_and_sort,
+            commands::set_mapping_quota,
+            commands::set_mapping_description,
+            commands::set_mapping_size_bounds,
+            commands::set_mapping_enabled,
+            commands::find_orphaned_mappings,
+            commands::disable_orphaned_mappings,
+            commands::retarget_mappings,
+            commands::check_mappings_health,
+            commands::create_all_target_dirs,
+            commands::get_categories,
+            commands::get_category_tree,
+            commands::get_category_disk_usage,
+            commands::get_folder_stats,
+            commands::find_large_files,
+            commands::add_subcategory,
+            commands::merge_categories,
+            commands::rename_category,
+            commands::get_all_mappings,
+            commands::search_mappings,
+            commands::get_extension_groups,
+            commands::get_mapping_suggestions,
+            commands::list_unmatched_extensions,
+            commands::get_rules,
+            commands::save_rule,
+            commands::delete_rule,
+            commands::get_rule_groups,
+            commands::save_rule_group,
+            commands::toggle_rule_group,
+            commands::delete_rule_group,
+            commands::get_queued_moves,
+            commands::get_conflicts,
+            commands::benchmark_sort,
+            commands::get_settings,
+            commands::update_settings,
+            commands::get_learned_mappings,
+            commands::dismiss_learned_mapping,
+            commands::get_autostart,
+            commands::set_autostart,
+            commands::take_pending_link,
+            commands::take_stale_reminder,
+            commands::take_weekly_report,
+            commands::open_config_directory,
+            commands::export_database,
+            commands::export_history_csv,
+            commands::import_database,
+            commands::import_ruleset,
+            commands::export_ruleset,
+            commands::get_ruleset_subscriptions,
+            commands::subscribe_to_ruleset,
+            commands::unsubscribe_from_ruleset,
+            commands::refresh_ruleset_subscriptions,
+            commands::sync_team_ruleset,
+            commands::get_database_status,
+            commands::unlock_database,
+            commands::set_database_passphrase,
+            commands::get_app_lock_status,
+            commands::unlock_app,
+            commands::lock_app,
+            commands::set_app_lock,
+            commands::get_recent_activity,
+            commands::prune_history,
+            commands::get_sources,
+            commands::get_source_paths,
+            commands::set_desktop_override,
+            commands::add_source,
+            commands::update_source,
+            commands::remove_source,
+            commands::get_source_mappings,
+            commands::set_source_mapping,
+            commands::remove_source_mapping,
+            commands::get_mime_mappings,
+            commands::set_mime_mapping,
+            commands::remove_mime_mapping,
+            commands::restore_trashed,
+            commands::find_duplicates,
+            commands::find_similar_images,
+            commands::delete_duplicates,
+            commands::find_broken_links,
+            commands::get_stale_files,
+            commands::get_weekly_report,
+            commands::archive_stale_files,
+            commands::trash_broken_links,
+            commands::stage_sort,
+            commands::get_staged_sort,
+            commands::commit_sort,
+            commands::abort_sort,
+            commands::get_pending_moves,
+            commands::approve_moves,
+            commands::reject_moves,
+            commands::get_retry_queue,
+            commands::retry_failed_moves,
+            commands::resort_library,
+            commands::clear_retry_queue,
+            commands::set_log_level
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
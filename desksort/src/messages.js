@@ -0,0 +1,134 @@
+// Texts for the message keys the backend returns, by language. A key missing
+// here falls back to the English text the backend sends along with it.
+const MESSAGES = {
+    en: {
+        'error.io': 'IO error: {detail}',
+        'error.db': 'Database error: {detail}',
+        'error.desktop_not_found': 'Desktop path not found',
+        'error.config_dir_not_found': 'Config directory not found',
+        'error.invalid_setting': 'Invalid setting: {detail}',
+        'error.invalid_rule': 'Invalid rule: {detail}',
+        'error.invalid_link': 'Invalid link: {detail}',
+        'error.invalid_archive': 'Invalid export file: {detail}',
+        'error.encryption': 'Encryption error: {detail}',
+        'error.app_locked': 'Enter the PIN to make changes',
+        'error.wrong_pin': 'Wrong PIN',
+        'error.pin_attempts': 'Too many wrong PINs, try again in {secs} seconds',
+        'error.invalid_config_file': 'Invalid {file}: {detail}',
+        'error.invalid_category': 'Invalid category: {detail}',
+        'error.invalid_source': 'Invalid source: {detail}',
+        'error.not_restorable': 'Cannot restore: {detail}',
+        'error.invalid_ruleset': 'Invalid ruleset: {detail}',
+        'sort.moved': 'Moved {from} to {to}',
+        'sort.rotated': 'Rotated {from} to {to}',
+        'sort.staged': 'Staged {from} for {to}',
+        'sort.put_back': 'Put {path} back',
+        'sort.share_offline': '{path} is unreachable, queued {count} item(s) until it is back',
+        'sort.scan_failed': 'Failed to read {path}: {detail}',
+        'sort.create_dir_failed': 'Failed to create target directory {path}: {detail}',
+        'sort.rotate_failed': 'Failed to rotate {path} into overflow: {detail}',
+        'sort.move_failed': 'Failed to move {path}: {detail}',
+        'sort.trash_failed': 'Failed to move {path} to the trash: {detail}',
+        'sort.stage_failed': 'Failed to stage {path}: {detail}',
+        'sort.put_back_failed': 'Failed to put {path} back: {detail}',
+        'sort.not_enough_space': 'Not enough free space for {path}: {needed} needed, {available} available',
+        'sort.free_space_unknown': 'Failed to check free space for {path}: {detail}',
+        'duplicates.changed': 'Left {path} alone, it no longer matches {original}',
+        'links.not_broken': 'Left {path} alone, it points to something again',
+        'report.weekly': 'This week: {files} file(s) sorted, {bytes} organized, {streak} week(s) in a row',
+        'stale.reminder': '{count} file(s) have been on your desktop for over {days} days',
+        'remediation.read_only_source': 'The file is on read-only media, so it can be copied but not moved away. Copy it manually or remount the drive as writable.',
+        'remediation.read_only_target': 'The target is on read-only media (write-protected drive, mounted image or read-only share). Pick a target on a writable drive.',
+        'remediation.read_source': 'DeskSort is not allowed to read this file. Check its permissions, or whether another program has it locked.',
+        'remediation.remove_source': "The file can be read but not removed from its current folder. Check that folder's permissions, or close programs using the file.",
+        'remediation.create_dir': 'The target folder could not be created. Grant write access to its parent folder or choose another target in settings.',
+        'remediation.rotate': 'Older files could not be moved into the overflow folder. Check write access to the target folder.',
+        'remediation.write_target': 'The target folder is not writable. Grant write access to it or choose another target in settings.',
+        'skip.no_rule': 'no rule or mapping for it',
+        'skip.protected': 'it belongs to the system or to DeskSort',
+        'skip.too_new': 'it was changed moments ago',
+        'skip.downloading': 'its download has not finished',
+        'skip.no_space': 'not enough free space at its target',
+        'skip.rejected': 'you rejected its move',
+        'skip.ignored': 'it matches an ignore pattern of its folder',
+        'skip.not_included': 'its folder only sorts names matching its include patterns',
+        'skip.retained': 'a rule deletes it once it has been left alone long enough',
+        'skip.kept': 'a rule says to leave it alone',
+        'skip.too_large': 'the folder is larger than the limit for moves',
+        'skip.online_only': 'it is only available online',
+        'skip.needs_elevation': 'moving it takes administrator rights'
+    },
+    de: {
+        'error.io': 'E/A-Fehler: {detail}',
+        'error.db': 'Datenbankfehler: {detail}',
+        'error.desktop_not_found': 'Schreibtisch nicht gefunden',
+        'error.config_dir_not_found': 'Konfigurationsordner nicht gefunden',
+        'error.invalid_setting': 'Ungültige Einstellung: {detail}',
+        'error.invalid_rule': 'Ungültige Regel: {detail}',
+        'error.invalid_link': 'Ungültiger Link: {detail}',
+        'error.invalid_archive': 'Ungültige Exportdatei: {detail}',
+        'error.encryption': 'Verschlüsselungsfehler: {detail}',
+        'error.app_locked': 'Zum Ändern die PIN eingeben',
+        'error.wrong_pin': 'Falsche PIN',
+        'error.pin_attempts': 'Zu viele falsche PINs, in {secs} Sekunden erneut versuchen',
+        'error.invalid_config_file': 'Ungültige {file}: {detail}',
+        'error.invalid_category': 'Ungültige Kategorie: {detail}',
+        'error.invalid_source': 'Ungültiger Quellordner: {detail}',
+        'error.not_restorable': 'Wiederherstellen nicht möglich: {detail}',
+        'error.invalid_ruleset': 'Ungültiges Regelwerk: {detail}',
+        'sort.moved': '{from} nach {to} verschoben',
+        'sort.rotated': '{from} nach {to} ausgelagert',
+        'sort.staged': '{from} für {to} vorgemerkt',
+        'sort.put_back': '{path} zurückgelegt',
+        'sort.share_offline': '{path} ist nicht erreichbar, {count} Element(e) warten, bis es wieder da ist',
+        'sort.scan_failed': '{path} konnte nicht gelesen werden: {detail}',
+        'sort.create_dir_failed': 'Zielordner {path} konnte nicht angelegt werden: {detail}',
+        'sort.rotate_failed': 'Ältere Dateien in {path} konnten nicht ausgelagert werden: {detail}',
+        'sort.move_failed': '{path} konnte nicht verschoben werden: {detail}',
+        'sort.trash_failed': '{path} konnte nicht in den Papierkorb verschoben werden: {detail}',
+        'sort.stage_failed': '{path} konnte nicht vorgemerkt werden: {detail}',
+        'sort.put_back_failed': '{path} konnte nicht zurückgelegt werden: {detail}',
+        'sort.not_enough_space': 'Nicht genug freier Speicher für {path}: {needed} benötigt, {available} frei',
+        'sort.free_space_unknown': 'Freier Speicher für {path} konnte nicht ermittelt werden: {detail}',
+        'duplicates.changed': '{path} wurde behalten, es stimmt nicht mehr mit {original} überein',
+        'links.not_broken': '{path} wurde behalten, es zeigt wieder auf etwas',
+        'report.weekly': 'Diese Woche: {files} Datei(en) sortiert, {bytes} aufgeräumt, {streak} Woche(n) in Folge',
+        'stale.reminder': '{count} Datei(en) liegen seit über {days} Tagen auf deinem Schreibtisch',
+        'remediation.read_only_source': 'Die Datei liegt auf einem schreibgeschützten Datenträger und kann nur kopiert, nicht verschoben werden. Kopiere sie von Hand oder binde das Laufwerk beschreibbar ein.',
+        'remediation.read_only_target': 'Das Ziel liegt auf einem schreibgeschützten Datenträger (schreibgeschütztes Laufwerk, eingebundenes Abbild oder Freigabe ohne Schreibrecht). Wähle ein Ziel auf einem beschreibbaren Laufwerk.',
+        'remediation.read_source': 'DeskSort darf diese Datei nicht lesen. Prüfe ihre Berechtigungen oder ob ein anderes Programm sie gesperrt hat.',
+        'remediation.remove_source': 'Die Datei lässt sich lesen, aber nicht aus ihrem Ordner entfernen. Prüfe die Berechtigungen des Ordners oder schließe Programme, die sie verwenden.',
+        'remediation.create_dir': 'Der Zielordner konnte nicht angelegt werden. Erlaube Schreibzugriff auf den übergeordneten Ordner oder wähle in den Einstellungen ein anderes Ziel.',
+        'remediation.rotate': 'Ältere Dateien konnten nicht in den Überlaufordner verschoben werden. Prüfe den Schreibzugriff auf den Zielordner.',
+        'remediation.write_target': 'Der Zielordner ist nicht beschreibbar. Erlaube Schreibzugriff darauf oder wähle in den Einstellungen ein anderes Ziel.',
+        'skip.no_rule': 'keine Regel oder Zuordnung dafür',
+        'skip.protected': 'gehört zum System oder zu DeskSort',
+        'skip.too_new': 'wurde gerade erst geändert',
+        'skip.downloading': 'der Download ist noch nicht fertig',
+        'skip.no_space': 'nicht genug freier Speicher am Ziel',
+        'skip.rejected': 'du hast das Verschieben abgelehnt',
+        'skip.ignored': 'passt zu einem Ignoriermuster des Ordners',
+        'skip.not_included': 'der Ordner sortiert nur Namen, die zu seinen Einschlussmustern passen',
+        'skip.retained': 'eine Regel löscht es, sobald es lange genug unverändert ist',
+        'skip.kept': 'eine Regel lässt es, wo es ist',
+        'skip.too_large': 'der Ordner ist größer als die Grenze fürs Verschieben',
+        'skip.online_only': 'es ist nur online verfügbar',
+        'skip.needs_elevation': 'zum Verschieben sind Administratorrechte nötig'
+    }
+};
+
+const LANGUAGE = navigator.language.split('-')[0] in MESSAGES ? navigator.language.split('-')[0] : 'en';
+
+// Text of a backend message ({ key, args, text }) or of a bare key, in the
+// user's language. Plain strings, like errors Tauri raises itself, pass through.
+window.t = function (message, args = {}) {
+    if (typeof message === 'string' && !(message in MESSAGES.en)) {
+        return message;
+    }
+    const { key, args: messageArgs = args, text } = typeof message === 'string' ? { key: message } : message;
+    const template = MESSAGES[LANGUAGE][key] ?? MESSAGES.en[key];
+    if (template === undefined) {
+        return text ?? key;
+    }
+    return template.replace(/\{(\w+)\}/g, (match, name) => messageArgs[name] ?? match);
+};